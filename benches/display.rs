@@ -0,0 +1,33 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use chip8::display::Display;
+
+const ROUNDS: u32 = 100_000;
+
+// Average time of one call of f over ROUNDS calls
+fn time(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    println!("{:<24} {:>8.1} ns", name, start.elapsed().as_nanos() as f64 / ROUNDS as f64);
+}
+
+// Display::clear's single fill against writing every pixel in turn, at both resolutions
+fn main() {
+    for hires in [false, true] {
+        let mut display = Display::new();
+        display.set_hires(hires);
+        let label = if hires { "128x64" } else { "64x32" };
+
+        time(&format!("fill {}", label), || {
+            display.clear();
+            black_box(&mut display);
+        });
+        time(&format!("per pixel {}", label), || {
+            display.iter_mut().for_each(|pixel| *pixel = black_box(0));
+            black_box(&mut display);
+        });
+    }
+}
@@ -0,0 +1,56 @@
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::Size;
+use embedded_graphics_simulator::sdl2::Keycode;
+use embedded_graphics_simulator::{BinaryColorTheme, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window};
+
+use chip8::panel::graphics::BinaryTarget;
+use chip8::panel::Panel;
+use chip8::Chip8;
+
+// A ROM on a simulated 128x64 SSD1306, drawn through the panel renderer the way firmware would drive the
+// real one: cargo run --example panel_simulator --features embedded-graphics -- <rom_path>
+
+const PANEL: (u32, u32) = (128, 64);
+const CYCLES_PER_FRAME: usize = 12;     // About 700 instructions a second at 60 frames
+
+// Host keys laid out like the hex keypad, the frontend's default layout
+const KEYS: [(Keycode, usize); 16] = [
+    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4),    (Keycode::W, 0x5),    (Keycode::E, 0x6),    (Keycode::R, 0xD),
+    (Keycode::A, 0x7),    (Keycode::S, 0x8),    (Keycode::D, 0x9),    (Keycode::F, 0xE),
+    (Keycode::Z, 0xA),    (Keycode::X, 0x0),    (Keycode::C, 0xB),    (Keycode::V, 0xF),
+];
+
+fn main() -> Result<(), String> {
+    let rom_path = env::args().nth(1).ok_or("Usage: panel_simulator <rom_path>")?;
+    let rom = std::fs::read(&rom_path).map_err(|err| format!("could not read {}: {}", rom_path, err))?;
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&rom).map_err(|err| err.to_string())?;
+
+    let mut screen = SimulatorDisplay::<BinaryColor>::new(Size::new(PANEL.0, PANEL.1));
+    let settings = OutputSettingsBuilder::new().theme(BinaryColorTheme::OledBlue).scale(4).build();
+    let mut window = Window::new("CHIP-8 on an SSD1306", &settings);
+    let mut panel = Panel::new(PANEL.0, PANEL.1);
+
+    loop {
+        chip8.step_frame(CYCLES_PER_FRAME);
+        let Ok(()) = panel.draw(&chip8.display, &mut BinaryTarget(&mut screen));     // The simulator can't fail to draw
+        window.update(&screen);
+        for event in window.events() {
+            let (keycode, state) = match event {
+                SimulatorEvent::Quit => return Ok(()),
+                SimulatorEvent::KeyDown { keycode, .. } => (keycode, 1),
+                SimulatorEvent::KeyUp { keycode, .. } => (keycode, 0),
+                _ => continue,
+            };
+            if let Some(&(_, key)) = KEYS.iter().find(|(host, _)| *host == keycode) {
+                chip8.set_key(key, state);
+            }
+        }
+        thread::sleep(Duration::from_millis(16));
+    }
+}
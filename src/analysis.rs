@@ -0,0 +1,436 @@
+use std::fmt;
+
+use crate::chip8::{Chip8, Quirks, Variant};
+use crate::disasm::{opcode_at, Flow, Instruction};
+
+// Static ROM analysis for picking quirks when a ROM ships without metadata
+
+// How many instructions after an FX55/FX65 to look for code that relies on the new I
+const LOAD_STORE_WINDOW: usize = 4;
+
+// The headless run each candidate profile gets in simulate_profiles
+const SIMULATION_FRAMES: usize = 120;
+const SIMULATION_CYCLES: usize = 15;   // Instructions per simulated frame, 900 a second
+
+// Profiles simulated, in the order ties go to
+const CANDIDATES: [Platform; 3] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+    Chip8X,
+    Chip8E,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::SuperChip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+            Platform::Chip8X => write!(f, "CHIP-8X"),
+            Platform::Chip8E => write!(f, "CHIP-8E"),
+        }
+    }
+}
+
+impl Platform {
+    // A platform by the name given on the command line, chip8, schip, xochip, chip8x or chip8e
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "chip8" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::SuperChip),
+            "xochip" => Ok(Platform::XoChip),
+            "chip8x" => Ok(Platform::Chip8X),
+            "chip8e" => Ok(Platform::Chip8E),
+            _ => Err(format!("unknown platform '{}', expected chip8, schip, xochip, chip8x or chip8e", name)),
+        }
+    }
+
+    // Quirks the original interpreter for each platform behaves with
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks { clip_sprites: true, load_store_increment: true, shift_vy: true, index_width: 12, ..Quirks::default() },
+            Platform::SuperChip => Quirks { clip_sprites: true, load_store_increment: false, shift_vy: false, index_width: 12, ..Quirks::default() },
+            Platform::XoChip => Quirks { clip_sprites: false, load_store_increment: true, shift_vy: true, index_width: 16, adi_overflow_width: 16, ..Quirks::default() },
+            Platform::Chip8X => Quirks { variant: Variant::Chip8X, ..Platform::Chip8.quirks() },
+            Platform::Chip8E => Quirks { variant: Variant::Chip8E, ..Platform::Chip8.quirks() },
+        }
+    }
+}
+
+// Name of the preset the quirks match, for bug reports: a platform's name, default for Quirks::default(),
+// or custom once flags or detection moved them off every preset
+pub fn preset_name(quirks: &Quirks) -> String {
+    [Platform::Chip8, Platform::SuperChip, Platform::XoChip, Platform::Chip8X, Platform::Chip8E].into_iter()
+        .find(|platform| platform.quirks() == *quirks)
+        .map_or_else(|| if *quirks == Quirks::default() { "default" } else { "custom" }.to_string(), |platform| platform.to_string())
+}
+
+// Confidence from 0.0 (no evidence, default guessed) to 1.0 (unambiguous) for each recommended quirk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuirkConfidence {
+    pub clip_sprites: f32,
+    pub load_store_increment: f32,
+    pub shift_vy: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuirkReport {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub confidence: QuirkConfidence,
+    pub signals: Vec<String>,           // Human readable evidence, e.g. "0x204: shift 8126 with X != Y"
+}
+
+impl fmt::Display for QuirkReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Platform: {}", self.platform)?;
+        writeln!(f, "clip_sprites         = {:<5} ({:.0}%)", self.quirks.clip_sprites, self.confidence.clip_sprites * 100.0)?;
+        writeln!(f, "load_store_increment = {:<5} ({:.0}%)", self.quirks.load_store_increment, self.confidence.load_store_increment * 100.0)?;
+        writeln!(f, "shift_vy             = {:<5} ({:.0}%)", self.quirks.shift_vy, self.confidence.shift_vy * 100.0)?;
+        for signal in &self.signals {
+            writeln!(f, "  {}", signal)?;
+        }
+        Ok(())
+    }
+}
+
+// Scan the reachable code of a ROM image (loaded at 0x200) for opcodes that hint at the interpreter it was written for
+pub fn detect_quirks(rom: &[u8]) -> QuirkReport {
+    let opcodes: Vec<(u16, u16)> = reachable(rom).into_iter()
+        .map(|addr| (addr, opcode_at(rom, addr).unwrap_or(0)))
+        .collect();
+
+    let mut signals = Vec::new();
+    let mut schip = false;
+    let mut xochip = false;
+    let mut shift_xy = false;
+    let mut load_store_chain = false;
+
+    for (i, &(addr, opcode)) in opcodes.iter().enumerate() {
+        if is_xochip_opcode(opcode) {
+            xochip = true;
+            signals.push(format!("{:#05X}: XO-CHIP opcode {:04X}", addr, opcode));
+        } else if is_schip_opcode(opcode) {
+            schip = true;
+            signals.push(format!("{:#05X}: SUPER-CHIP opcode {:04X}", addr, opcode));
+        }
+
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        if matches!(opcode & 0xF00F, 0x8006 | 0x800E) && x != y {
+            shift_xy = true;
+            signals.push(format!("{:#05X}: shift {:04X} with X != Y", addr, opcode));
+        }
+
+        if opcode & 0xF000 == 0xB000 {
+            signals.push(format!("{:#05X}: computed jump {:04X} to {:#05X} + V0", addr, opcode, opcode & 0x0FFF));
+        }
+
+        // Another load/store or I adjustment before I is reloaded means the code expects I to have moved
+        if matches!(opcode & 0xF0FF, 0xF055 | 0xF065) {
+            for &(_, next) in opcodes.iter().skip(i + 1).take(LOAD_STORE_WINDOW) {
+                if next & 0xF000 == 0xA000 {
+                    break;
+                }
+                if matches!(next & 0xF0FF, 0xF055 | 0xF065) {
+                    load_store_chain = true;
+                    signals.push(format!("{:#05X}: {:04X} followed by {:04X} without reloading I", addr, opcode, next));
+                    break;
+                }
+            }
+        }
+    }
+
+    // Platform defaults, then the individual signals on top
+    let (platform, mut quirks, platform_confidence) = if xochip {
+        (Platform::XoChip, Platform::XoChip.quirks(), 0.9)
+    } else if schip {
+        (Platform::SuperChip, Platform::SuperChip.quirks(), 0.9)
+    } else {
+        (Platform::Chip8, Quirks::default(), 0.0)
+    };
+    let mut confidence = QuirkConfidence {
+        clip_sprites: platform_confidence,
+        load_store_increment: platform_confidence,
+        shift_vy: platform_confidence,
+    };
+
+    if shift_xy && !quirks.shift_vy {
+        quirks.shift_vy = true;
+        confidence.shift_vy = if schip { 0.4 } else { 0.7 };
+    }
+    if load_store_chain && !quirks.load_store_increment {
+        quirks.load_store_increment = true;
+        confidence.load_store_increment = if schip { 0.4 } else { 0.8 };
+    }
+
+    QuirkReport { platform, quirks, confidence, signals }
+}
+
+// How a profile fared running the ROM headless with no keys pressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileScore {
+    pub platform: Platform,
+    pub faults: usize,                  // Unknown opcodes run
+    pub first_fault: Option<String>,
+    pub fatal: Option<String>,          // What ended the run early: a stack under or overflow, or the PC leaving memory
+}
+
+impl ProfileScore {
+    // Lower is better, a fatal fault outweighs any number of others
+    fn cost(&self) -> (bool, usize) {
+        (self.fatal.is_some(), self.faults)
+    }
+}
+
+impl fmt::Display for ProfileScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.fatal, &self.first_fault) {
+            (Some(fatal), _) => write!(f, "{} stopped on {} after {} faults", self.platform, fatal, self.faults),
+            (None, Some(first)) => write!(f, "{} ran with {} faults, the first {}", self.platform, self.faults, first),
+            (None, None) => write!(f, "{} ran clean", self.platform),
+        }
+    }
+}
+
+// Run the ROM under quirks for a few seconds, stopping at what would crash the interpreter
+pub fn simulate(rom: &[u8], platform: Platform, quirks: Quirks) -> ProfileScore {
+    let mut chip8 = Chip8::new();
+    chip8.quirks = quirks;
+    let mut score = ProfileScore { platform, faults: 0, first_fault: None, fatal: None };
+    if let Err(err) = chip8.load_rom_bytes(rom) {
+        score.fatal = Some(err.to_string());
+        return score;
+    }
+
+    for frame in 1..=SIMULATION_FRAMES {
+        for _ in 0..SIMULATION_CYCLES {
+            let pc = chip8.pc();
+            let opcode = match (chip8.peek(pc as usize), chip8.peek(pc as usize + 1)) {
+                (Some(hi), Some(lo)) => Some((hi as u16) << 8 | lo as u16),
+                _ => None,
+            };
+            if opcode.is_none() {
+                score.fatal = Some(format!("the PC leaving memory at {:#05X} in frame {}", pc, frame));
+                return score;
+            }
+            if chip8.halted() {
+                return score;
+            }
+
+            chip8.cycle();
+            if let Some(fault) = chip8.fault() {
+                score.fatal = Some(format!("a {} at {:#05X} in frame {}", fault, pc, frame));
+                return score;
+            }
+            if opcode.is_some() && chip8.last_unknown_opcode() == opcode {
+                score.faults += 1;
+                score.first_fault.get_or_insert_with(|| format!("unknown opcode {:04X} at {:#05X} in frame {}", opcode.unwrap(), pc, frame));
+            }
+        }
+        chip8.tick_timers();
+    }
+    score
+}
+
+// Back the static guess with a short run under every candidate profile. When the guessed quirks fault
+// and another profile runs better, the report switches to that one; either way the outcomes join the
+// signals
+pub fn simulate_profiles(rom: &[u8], report: &mut QuirkReport) -> Vec<ProfileScore> {
+    let guessed = simulate(rom, report.platform, report.quirks);
+    let scores: Vec<ProfileScore> = CANDIDATES.iter()
+        .map(|&platform| match platform == report.platform {
+            true => guessed.clone(),
+            false => simulate(rom, platform, platform.quirks()),
+        })
+        .collect();
+
+    let best = scores.iter().min_by_key(|score| score.cost()).unwrap();
+    if best.cost() < guessed.cost() {
+        report.signals.push(format!("simulation: {} ran better than the guessed {}", best.platform, guessed.platform));
+        report.platform = best.platform;
+        report.quirks = best.platform.quirks();
+        report.confidence = QuirkConfidence { clip_sprites: 0.5, load_store_increment: 0.5, shift_vy: 0.5 };
+    }
+    for score in scores.iter().filter(|score| score.cost() != (false, 0)) {
+        report.signals.push(format!("simulation: {}", score));
+    }
+    scores
+}
+
+// Addresses of every instruction reachable from 0x200, in address order
+// Follows jumps, calls and both sides of skips; computed jumps (BNNN) end a path since the target is unknown
+pub fn reachable(rom: &[u8]) -> Vec<u16> {
+    let mut seen = vec![false; 0x1000];
+    let mut pending = vec![0x200u16];
+
+    while let Some(addr) = pending.pop() {
+        if addr >= 0x1000 || seen[addr as usize] {
+            continue;
+        }
+        let Some(opcode) = opcode_at(rom, addr) else {
+            continue;
+        };
+        seen[addr as usize] = true;
+
+        match Instruction::decode(opcode).flow() {
+            Flow::Next => pending.push(addr + 2),
+            Flow::Jump(target) => pending.push(target),
+            Flow::Call(target) => {
+                pending.push(target);
+                pending.push(addr + 2);
+            }
+            Flow::Skip => {
+                pending.push(addr + 2);
+                pending.push(addr + 4);
+            }
+            Flow::Return | Flow::Computed(_) | Flow::Halt => {}
+        }
+    }
+
+    (0..0x1000u16).filter(|&addr| seen[addr as usize]).collect()
+}
+
+// 00CN scroll, 00FB-00FF scroll/exit/resolution, FX30 big font, FX75/FX85 RPL flags
+fn is_schip_opcode(opcode: u16) -> bool {
+    opcode & 0xFFF0 == 0x00C0
+        || matches!(opcode, 0x00FB..=0x00FF)
+        || matches!(opcode & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+}
+
+// 00DN scroll up, 5XY2/5XY3 range save/load, F000 long I, F002 audio pattern, FN01 planes, FX3A pitch
+fn is_xochip_opcode(opcode: u16) -> bool {
+    opcode & 0xFFF0 == 0x00D0
+        || matches!(opcode & 0xF00F, 0x5002 | 0x5003)
+        || opcode == 0xF000
+        || opcode == 0xF002
+        || opcode & 0xF0FF == 0xF001
+        || opcode & 0xF0FF == 0xF03A
+}
+
+// Combine quirk sources, lowest priority first: the database platform, detected quirks with any confidence,
+// then quirks forced on the command line. The index width and the instruction set only come from a platform, overriding
+// them is up to the caller
+pub fn resolve_quirks(database: Option<Platform>, detected: Option<&QuirkReport>, explicit: Quirks) -> Quirks {
+    let mut quirks = database.map(Platform::quirks).unwrap_or_default();
+
+    if let Some(report) = detected {
+        if report.platform != Platform::Chip8 {
+            quirks.index_width = report.quirks.index_width;
+        }
+        if report.confidence.clip_sprites > 0.0 {
+            quirks.clip_sprites = report.quirks.clip_sprites;
+        }
+        if report.confidence.load_store_increment > 0.0 {
+            quirks.load_store_increment = report.quirks.load_store_increment;
+        }
+        if report.confidence.shift_vy > 0.0 {
+            quirks.shift_vy = report.quirks.shift_vy;
+        }
+    }
+
+    Quirks {
+        clip_sprites: quirks.clip_sprites || explicit.clip_sprites,
+        load_store_increment: quirks.load_store_increment || explicit.load_store_increment,
+        shift_vy: quirks.shift_vy || explicit.shift_vy,
+        index_width: quirks.index_width,
+        adi_overflow_vf: quirks.adi_overflow_vf || explicit.adi_overflow_vf,
+        adi_overflow_width: if explicit.adi_overflow_vf { explicit.adi_overflow_width } else { quirks.adi_overflow_width },
+        variant: quirks.variant,
+        collision_delay: quirks.collision_delay || explicit.collision_delay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_signal(report: &QuirkReport, text: &str) -> bool {
+        report.signals.iter().any(|signal| signal.contains(text))
+    }
+
+    #[test]
+    fn plain_roms_get_the_default_guess_with_no_confidence() {
+        let report = detect_quirks(&[0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(report.platform, Platform::Chip8);
+        assert_eq!(report.quirks, Quirks::default());
+        assert_eq!(report.confidence.shift_vy, 0.0);
+        assert!(report.signals.is_empty());
+    }
+
+    #[test]
+    fn superchip_opcodes_pick_superchip() {
+        let report = detect_quirks(&[0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(report.platform, Platform::SuperChip);
+        assert_eq!(report.quirks, Platform::SuperChip.quirks());
+        assert!(has_signal(&report, "0x200: SUPER-CHIP opcode 00FF"));
+    }
+
+    #[test]
+    fn xochip_opcodes_win_over_superchip_ones() {
+        let report = detect_quirks(&[0x00, 0xFF, 0x00, 0xD2, 0x12, 0x04]);
+        assert_eq!(report.platform, Platform::XoChip);
+        assert!(has_signal(&report, "0x202: XO-CHIP opcode 00D2"));
+    }
+
+    #[test]
+    fn shifts_between_registers_suggest_shift_vy() {
+        let report = detect_quirks(&[0x81, 0x26, 0x12, 0x02]);
+        assert!(report.quirks.shift_vy);
+        assert_eq!(report.confidence.shift_vy, 0.7);
+        assert!(has_signal(&report, "shift 8126 with X != Y"));
+        assert!(!detect_quirks(&[0x81, 0x16, 0x12, 0x02]).quirks.shift_vy, "X == Y says nothing");
+    }
+
+    #[test]
+    fn chained_loads_and_stores_suggest_the_increment() {
+        let chained = detect_quirks(&[0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65, 0x12, 0x06]);
+        assert!(chained.quirks.load_store_increment);
+        assert_eq!(chained.confidence.load_store_increment, 0.8);
+        assert!(has_signal(&chained, "F255 followed by F265 without reloading I"));
+
+        let reloaded = detect_quirks(&[0xA3, 0x00, 0xF2, 0x55, 0xA3, 0x00, 0xF2, 0x65, 0x12, 0x08]);
+        assert!(!reloaded.quirks.load_store_increment, "I is set again in between");
+    }
+
+    #[test]
+    fn computed_jumps_are_reported() {
+        let report = detect_quirks(&[0xB3, 0x00]);
+        assert!(has_signal(&report, "0x200: computed jump B300 to 0x300 + V0"));
+    }
+
+    #[test]
+    fn simulation_counts_unknown_opcodes() {
+        let score = simulate(&[0xF0, 0xE3, 0x12, 0x00], Platform::Chip8, Quirks::default());
+        assert_eq!(score.faults, SIMULATION_FRAMES * SIMULATION_CYCLES / 2);
+        assert_eq!(score.first_fault.as_deref(), Some("unknown opcode F0E3 at 0x200 in frame 1"));
+        assert_eq!(score.fatal, None);
+    }
+
+    #[test]
+    fn simulation_stops_at_stack_faults() {
+        let underflow = simulate(&[0x00, 0xEE], Platform::Chip8, Quirks::default());
+        assert_eq!(underflow.fatal.as_deref(), Some("a stack underflow at 0x200 in frame 1"));
+        let overflow = simulate(&[0x22, 0x00], Platform::Chip8, Quirks::default());
+        assert_eq!(overflow.fatal.as_deref(), Some("a stack overflow at 0x200 in frame 2"), "the 17th call");
+
+        let mut report = detect_quirks(&[0x00, 0xEE]);
+        simulate_profiles(&[0x00, 0xEE], &mut report);
+        assert_eq!(report.platform, Platform::Chip8, "nothing runs better");
+        assert!(has_signal(&report, "simulation: CHIP-8 stopped on a stack underflow"));
+    }
+
+    #[test]
+    fn clean_runs_leave_the_guess_alone() {
+        let rom = [0x00, 0xFF, 0x60, 0x01, 0x12, 0x02];
+        let mut report = detect_quirks(&rom);
+        let before = report.clone();
+        let scores = simulate_profiles(&rom, &mut report);
+        assert!(scores.iter().all(|score| score.cost() == (false, 0)), "{:?}", scores);
+        assert_eq!(report, before);
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+
+// Octo source to ROM, covering the subset decompile emits: labels, byte literals, the statements for
+// every CHIP-8 and SUPER-CHIP opcode Octo names, and a bare label calling it as a subroutine.
+// Labels may be used before they are defined, addresses are patched in once the whole source is read
+
+// Where the ROM is loaded, and so where the first assembled byte lands
+const ORIGIN: u16 = 0x200;
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+// A label use waiting for its address: the byte offset of the opcode and its top nibble
+struct Fixup<'a> {
+    offset: usize,
+    opcode: u16,
+    label: Token<'a>,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    rom: Vec<u8>,
+    labels: BTreeMap<&'a str, u16>,
+    fixups: Vec<Fixup<'a>>,
+}
+
+// Assemble Octo source, errors name the line they were found on
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let tokens = source.lines().enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |text| Token { text, line: i + 1 })
+        })
+        .collect();
+    let mut asm = Assembler { tokens, pos: 0, rom: Vec::new(), labels: BTreeMap::new(), fixups: Vec::new() };
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+
+    for fixup in &asm.fixups {
+        let addr = *asm.labels.get(fixup.label.text)
+            .ok_or_else(|| format!("line {}: undefined label '{}'", fixup.label.line, fixup.label.text))?;
+        let opcode = fixup.opcode | (addr & 0xFFF);
+        asm.rom[fixup.offset..fixup.offset + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+    Ok(asm.rom)
+}
+
+// Decimal, 0x hex or 0b binary
+fn number(text: &str) -> Option<u16> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// v0 to vf, either case
+fn register(text: &str) -> Option<u16> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u16::from_str_radix(digit, 16).ok()
+}
+
+impl<'a> Assembler<'a> {
+    fn next(&mut self) -> Result<&Token<'a>, String> {
+        let line = self.tokens.last().map_or(0, |token| token.line);
+        let token = self.tokens.get(self.pos).ok_or_else(|| format!("line {}: unexpected end of source", line))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.tokens[self.pos - 1].line, message)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        if self.next()?.text != text {
+            return Err(self.error(&format!("expected '{}'", text)));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let text = self.next()?.text;
+        register(text).ok_or_else(|| self.error(&format!("expected a register, found '{}'", text)))
+    }
+
+    fn constant(&mut self, max: u16) -> Result<u16, String> {
+        let text = self.next()?.text;
+        match number(text) {
+            Some(value) if value <= max => Ok(value),
+            Some(_) => Err(self.error(&format!("{} does not fit in {:#x}", text, max))),
+            None => Err(self.error(&format!("expected a number, found '{}'", text))),
+        }
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.rom.extend(opcode.to_be_bytes());
+    }
+
+    // An opcode taking a 12 bit address, given as a number or a label resolved at the end
+    fn emit_address(&mut self, opcode: u16) -> Result<(), String> {
+        let pos = self.pos;
+        let text = self.next()?.text;
+        match number(text) {
+            Some(addr) if addr <= 0xFFF => self.emit(opcode | addr),
+            Some(_) => return Err(self.error(&format!("address {} is out of range", text))),
+            None => {
+                let label = Token { text, line: self.tokens[pos].line };
+                self.fixups.push(Fixup { offset: self.rom.len(), opcode, label });
+                self.emit(opcode);
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let text = self.next()?.text;
+        match text {
+            ":" => {
+                let name = self.next()?.text;
+                let addr = ORIGIN + self.rom.len() as u16;
+                if self.labels.insert(name, addr).is_some() {
+                    return Err(self.error(&format!("label '{}' defined twice", name)));
+                }
+            }
+            "clear" => self.emit(0x00E0),
+            "return" => self.emit(0x00EE),
+            "scroll-down" => {
+                let n = self.constant(0xF)?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "if" => self.condition()?,
+            "sprite" => {
+                let (x, y) = (self.register()?, self.register()?);
+                let n = self.constant(0xF)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "audio" => self.emit(0xF002),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match text {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            }
+            "bcd" | "save" | "load" | "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                let low = match text {
+                    "bcd" => 0x33,
+                    "save" => 0x55,
+                    "load" => 0x65,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            }
+            "i" => self.index()?,
+            _ => {
+                if let Some(x) = register(text) {
+                    self.assignment(x)?;
+                } else if let Some(byte) = number(text) {
+                    if byte > 0xFF {
+                        return Err(self.error(&format!("byte {} is out of range", text)));
+                    }
+                    self.rom.push(byte as u8);
+                } else {
+                    // Naming a label calls it
+                    self.pos -= 1;
+                    self.emit_address(0x2000)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // if vX (== | !=) (NN | vY) then, and if vX [-]key then. The skip is the negation, it jumps over the
+    // statement that follows when the condition fails
+    fn condition(&mut self) -> Result<(), String> {
+        let x = self.register()?;
+        let op = self.next()?.text;
+        let opcode = match op {
+            "key" => 0xE0A1 | x << 8,
+            "-key" => 0xE09E | x << 8,
+            "==" | "!=" => {
+                let operand = self.next()?.text;
+                let equal = op == "==";
+                if let Some(y) = register(operand) {
+                    (if equal { 0x9000 } else { 0x5000 }) | x << 8 | y << 4
+                } else {
+                    let nn = number(operand).filter(|&nn| nn <= 0xFF)
+                        .ok_or_else(|| self.error(&format!("expected a byte or register, found '{}'", operand)))?;
+                    (if equal { 0x4000 } else { 0x3000 }) | x << 8 | nn
+                }
+            }
+            _ => return Err(self.error(&format!("unknown comparison '{}'", op))),
+        };
+        self.expect("then")?;
+        self.emit(opcode);
+        Ok(())
+    }
+
+    // i := NNN, i := label, i := hex vX and i += vX
+    fn index(&mut self) -> Result<(), String> {
+        match self.next()?.text {
+            ":=" => {
+                if self.tokens.get(self.pos).is_some_and(|token| token.text == "hex") {
+                    self.pos += 1;
+                    let x = self.register()?;
+                    self.emit(0xF029 | x << 8);
+                    Ok(())
+                } else {
+                    self.emit_address(0xA000)
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | x << 8);
+                Ok(())
+            }
+            op => Err(self.error(&format!("unknown index operation '{}'", op))),
+        }
+    }
+
+    // vX followed by an assignment operator and its source
+    fn assignment(&mut self, x: u16) -> Result<(), String> {
+        let op = self.next()?.text;
+        let operand = self.next()?.text;
+        let opcode = match (op, operand) {
+            (":=", "delay") => 0xF007 | x << 8,
+            (":=", "key") => 0xF00A | x << 8,
+            (":=", "random") => 0xC000 | x << 8 | self.constant(0xFF)?,
+            _ => {
+                let low = match op {
+                    ":=" => 0x0,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(self.error(&format!("unknown operator '{}'", op))),
+                };
+                match (register(operand), number(operand)) {
+                    (Some(y), _) => 0x8000 | x << 8 | y << 4 | low,
+                    (None, Some(nn)) if nn <= 0xFF && low == 0x0 => 0x6000 | x << 8 | nn,
+                    (None, Some(nn)) if nn <= 0xFF && low == 0x4 => 0x7000 | x << 8 | nn,
+                    _ => return Err(self.error(&format!("bad operand '{}' for {}", operand, op))),
+                }
+            }
+        };
+        self.emit(opcode);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::decompile::decompile;
+    use sha1::{Digest, Sha1};
+
+    const TEST_ROMS: [(&str, &[u8]); 7] = [
+        ("1-chip8-logo", include_bytes!("../testfiles/1-chip8-logo.ch8")),
+        ("2-ibm-logo", include_bytes!("../testfiles/2-ibm-logo.ch8")),
+        ("3-corax+", include_bytes!("../testfiles/3-corax+.ch8")),
+        ("4-flags", include_bytes!("../testfiles/4-flags.ch8")),
+        ("5-quirks", include_bytes!("../testfiles/5-quirks.ch8")),
+        ("6-keypad", include_bytes!("../testfiles/6-keypad.ch8")),
+        ("7-beep", include_bytes!("../testfiles/7-beep.ch8")),
+    ];
+
+    // Hash of the registers, memory and display after two seconds of headless running from a fixed seed
+    fn execution_hash(rom: &[u8]) -> [u8; 20] {
+        let mut chip8 = Chip8::new();
+        chip8.set_seed(1);
+        chip8.load_rom_bytes(rom).unwrap();
+        for _ in 0..120 {
+            chip8.step_frame(15);
+        }
+        Sha1::digest(chip8.save_state()).into()
+    }
+
+    #[test]
+    fn decompiled_test_roms_reassemble_to_the_same_execution() {
+        for (name, rom) in TEST_ROMS {
+            let source = decompile(rom);
+            let assembled = assemble(&source).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(execution_hash(&assembled), execution_hash(rom), "{} runs differently", name);
+            assert_eq!(assembled, rom, "{} reassembles to the same image", name);
+        }
+    }
+
+    #[test]
+    fn statements_assemble_to_their_opcodes() {
+        let source = "
+            : main
+                v0 := 3  v0 += 0x10  v1 := v0  v1 >>= v0  v2 =- v1
+                i := hex v2  i += v1  i := 0x300
+                if v3 != 7 then v3 := random 0xff
+                if v3 == v4 then clear
+                if v5 -key then return
+                delay := v0  v6 := delay  v7 := key
+                sprite v1 v2 5  bcd v3  save v4  loadflags v5
+                audio  pitch := v8
+        ";
+        let words: Vec<u16> = assemble(source).unwrap().chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(words, [
+            0x6003, 0x7010, 0x8100, 0x8106, 0x8217,
+            0xF229, 0xF11E, 0xA300,
+            0x3307, 0xC3FF,
+            0x9340, 0x00E0,
+            0xE59E, 0x00EE,
+            0xF015, 0xF607, 0xF70A,
+            0xD125, 0xF333, 0xF455, 0xF585,
+            0xF002, 0xF83A,
+        ]);
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let rom = assemble(": main sub jump main : sub i := data return : data 0xFF").unwrap();
+        assert_eq!(rom, [0x22, 0x04, 0x12, 0x00, 0xA2, 0x08, 0x00, 0xEE, 0xFF]);
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert_eq!(assemble(": main\n\tjump nowhere"), Err("line 2: undefined label 'nowhere'".to_string()));
+        assert_eq!(assemble("v0 := 300"), Err("line 1: bad operand '300' for :=".to_string()));
+        assert_eq!(assemble(": a\n: a"), Err("line 2: label 'a' defined twice".to_string()));
+        assert_eq!(assemble("if v0 != 1"), Err("line 1: unexpected end of source".to_string()));
+    }
+}
@@ -0,0 +1,366 @@
+use std::sync::{Arc, Mutex};
+
+use chip8::chip8::{DEFAULT_PITCH, PATTERN_SIZE};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+pub const SAMPLE_RATE: i32 = 44100;
+pub const TONE_HZ: f32 = 440.0;
+pub const VOLUME: f32 = 0.25;
+pub const SCOPE_MS: usize = 50;         // Audio the debug window's scope shows
+pub const PATTERN_BITS: usize = PATTERN_SIZE * 8;
+
+// Shape of the buzzer tone
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    Noise,                              // A new random level every period, pitched like the other shapes
+}
+
+impl Waveform {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+}
+
+// The newest samples played, a fixed number of them with the oldest overwritten first. The audio
+// callback writes it and the debug window reads it, through a mutex neither side holds for long
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleRing {
+    samples: Vec<f32>,
+    next: usize,                        // Where the next sample goes, the oldest one until it does
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        SampleRing { samples: vec![0.0; capacity], next: 0 }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        if self.samples.is_empty() {
+            return;
+        }
+        for &sample in samples {
+            self.samples[self.next] = sample;
+            self.next = (self.next + 1) % self.samples.len();
+        }
+    }
+
+    // Back to silence, for while the buzzer is off and the callback isn't writing
+    pub fn clear(&mut self) {
+        self.samples.fill(0.0);
+    }
+
+    // Every sample held, oldest first
+    pub fn snapshot(&self) -> Vec<f32> {
+        let (older, newer) = self.samples.split_at(self.next);
+        newer.iter().chain(older).copied().collect()
+    }
+}
+
+// The 128 samples of an XO-CHIP audio pattern, the top bit of the first byte first
+pub fn pattern_bits(pattern: &[u8; PATTERN_SIZE]) -> [bool; PATTERN_BITS] {
+    core::array::from_fn(|bit| pattern[bit / 8] & (0x80 >> (bit % 8)) != 0)
+}
+
+// Pattern bits played a second at an XO-CHIP pitch: 4000 at 64, doubling every 48 above it
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+}
+
+// Tone generator, independent of SDL so the same samples can be recorded
+pub struct Oscillator {
+    waveform: Waveform,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    noise: u32,                         // xorshift state
+    level: f32,                         // Current noise level
+    scope: Option<Arc<Mutex<SampleRing>>>,  // Where played samples are copied for the scope
+    sample_rate: f32,
+    pattern: Option<([bool; PATTERN_BITS], f32)>,   // XO-CHIP pattern played in place of the tone, with its bits per sample
+    position: f32,                      // Pattern bit playing, kept across pattern changes like the hardware
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, freq: f32, sample_rate: i32, volume: f32) -> Self {
+        Oscillator {
+            waveform,
+            phase_inc: freq / sample_rate as f32,
+            phase: 0.0,
+            volume,
+            noise: 0x2545F491,
+            level: 1.0,
+            scope: None,
+            sample_rate: sample_rate as f32,
+            pattern: None,
+            position: 0.0,
+        }
+    }
+
+    // Play an XO-CHIP pattern at pitch in place of the tone, or the tone again for None
+    pub fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, pitch: u8) {
+        self.pattern = pattern.map(|pattern| (pattern_bits(&pattern), pattern_rate(pitch) / self.sample_rate));
+    }
+
+    // The pattern bit playing next, None while the tone plays
+    pub fn pattern_position(&self) -> Option<usize> {
+        self.pattern.map(|_| self.position as usize)
+    }
+
+    // Copy every sample played into scope as well
+    pub fn with_scope(self, scope: Arc<Mutex<SampleRing>>) -> Self {
+        Oscillator { scope: Some(scope), ..self }
+    }
+
+    // Fill the buffer with the next samples, between -volume and volume
+    pub fn fill(&mut self, out: &mut [f32]) {
+        if let Some((bits, step)) = self.pattern {
+            for sample in out.iter_mut() {
+                *sample = if bits[self.position as usize] { self.volume } else { -self.volume };
+                self.position = (self.position + step) % PATTERN_BITS as f32;
+            }
+            return;
+        }
+        for sample in out.iter_mut() {
+            let shape = match self.waveform {
+                Waveform::Square => if self.phase <= 0.5 { 1.0 } else { -1.0 },
+                Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+                Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                Waveform::Noise => self.level,
+            };
+            *sample = shape * self.volume;
+
+            self.phase += self.phase_inc;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.level = (self.noise as f32 / u32::MAX as f32) * 2.0 - 1.0;
+            }
+        }
+    }
+}
+
+impl AudioCallback for Oscillator {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.fill(out);
+        // The audio thread never waits on the UI, a buffer the scope is busy reading is left out of it
+        if let Some(Ok(mut scope)) = self.scope.as_ref().map(|scope| scope.try_lock()) {
+            scope.push(out);
+        }
+    }
+}
+
+// Buzzer driven by the sound timer, silent when no audio device could be opened
+pub enum Beeper {
+    Device(AudioDevice<Oscillator>, Arc<Mutex<SampleRing>>),
+    Silent,
+}
+
+impl Beeper {
+    // Open the default playback device, falling back to a silent beeper so the video loop still runs
+    pub fn new(sdl_context: &Sdl, waveform: Waveform) -> Self {
+        let scope = Arc::new(Mutex::new(SampleRing::new(SAMPLE_RATE as usize * SCOPE_MS / 1000)));
+        Beeper::with_device(open_device(sdl_context, waveform, scope.clone()), scope)
+    }
+
+    // The beeper for however opening the device went
+    fn with_device(device: Result<AudioDevice<Oscillator>, String>, scope: Arc<Mutex<SampleRing>>) -> Self {
+        match device {
+            Ok(device) => Beeper::Device(device, scope),
+            Err(err) => {
+                log::warn!("audio unavailable ({}), running without sound", err);
+                Beeper::Silent
+            }
+        }
+    }
+
+    // Start or stop the tone
+    pub fn set_beeping(&self, beeping: bool) {
+        if let Beeper::Device(device, scope) = self {
+            if beeping {
+                device.resume();
+            } else {
+                device.pause();
+                if let Ok(mut scope) = scope.lock() {
+                    scope.clear();
+                }
+            }
+        }
+    }
+
+    // Play the ROM's XO-CHIP pattern, if it loaded one, in place of the tone
+    pub fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, pitch: u8) {
+        if let Beeper::Device(device, _) = self {
+            device.lock().set_pattern(pattern, pitch);
+        }
+    }
+
+    // The pattern bit playing next, None while the tone plays or without a device. Mutable only because
+    // reading it locks the device
+    pub fn pattern_position(&mut self) -> Option<usize> {
+        match self {
+            Beeper::Device(device, _) => device.lock().pattern_position(),
+            Beeper::Silent => None,
+        }
+    }
+
+    // The last SCOPE_MS of audio played, oldest first, none without a device
+    pub fn scope(&self) -> Vec<f32> {
+        match self {
+            Beeper::Device(_, scope) => scope.lock().map(|scope| scope.snapshot()).unwrap_or_default(),
+            Beeper::Silent => Vec::new(),
+        }
+    }
+}
+
+fn open_device(sdl_context: &Sdl, waveform: Waveform, scope: Arc<Mutex<SampleRing>>) -> Result<AudioDevice<Oscillator>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: None,
+    };
+
+    audio_subsystem.open_playback(None, &desired, |spec| {
+        if let Ok(mut ring) = scope.lock() {
+            *ring = SampleRing::new(spec.freq.max(0) as usize * SCOPE_MS / 1000);   // The device may not play at SAMPLE_RATE
+        }
+        Oscillator::new(waveform, TONE_HZ, spec.freq, VOLUME).with_scope(scope)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_missing_device_gives_a_silent_beeper() {
+        let scope = Arc::new(Mutex::new(SampleRing::new(16)));
+        let mut beeper = Beeper::with_device(Err("no audio device".to_string()), scope);
+        assert!(matches!(beeper, Beeper::Silent));
+        beeper.set_beeping(true);
+        beeper.set_beeping(false);
+        beeper.set_pattern(Some([0xFF; PATTERN_SIZE]), DEFAULT_PITCH);
+        assert!(beeper.scope().is_empty(), "nothing played");
+        assert_eq!(beeper.pattern_position(), None);
+    }
+
+    // Two periods of a 1hz tone sampled 8 times a second at full volume
+    fn two_periods(waveform: Waveform) -> Vec<f32> {
+        let mut samples = vec![0.0; 16];
+        Oscillator::new(waveform, 1.0, 8, 1.0).fill(&mut samples);
+        samples
+    }
+
+    #[test]
+    fn square_and_triangle_differ_in_shape_not_period() {
+        let square = two_periods(Waveform::Square);
+        let triangle = two_periods(Waveform::Triangle);
+        assert_eq!(square[..8], [1.0, 1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0]);
+        assert_eq!(triangle[..8], [-1.0, -0.5, 0.0, 0.5, 1.0, 0.5, 0.0, -0.5]);
+        assert_eq!(square[..8], square[8..], "square repeats every period");
+        assert_eq!(triangle[..8], triangle[8..], "triangle repeats every period");
+        assert_eq!(square.iter().zip(&triangle).filter(|(a, b)| a == b).count(), 2, "they only meet at the triangle's peak");
+    }
+
+    #[test]
+    fn volume_bounds_every_shape() {
+        for waveform in [Waveform::Square, Waveform::Sine, Waveform::Triangle, Waveform::Noise] {
+            let mut samples = vec![0.0; 2048];
+            Oscillator::new(waveform, TONE_HZ, SAMPLE_RATE, VOLUME).fill(&mut samples);
+            assert!(samples.iter().all(|sample| sample.abs() <= VOLUME), "{:?}", waveform);
+        }
+        let noise = two_periods(Waveform::Noise);
+        assert!(noise[..8].iter().all(|&sample| sample == noise[0]), "noise holds its level for a period");
+        assert_ne!(noise[8], noise[0], "and changes it on the next");
+        assert_eq!(Waveform::parse("triangle"), Some(Waveform::Triangle));
+        assert_eq!(Waveform::parse("sawtooth"), None);
+    }
+
+    #[test]
+    fn the_ring_keeps_the_newest_samples_oldest_first() {
+        let mut ring = SampleRing::new(4);
+        assert_eq!(ring.snapshot(), [0.0; 4], "silence before anything plays");
+        ring.push(&[1.0, 2.0, 3.0]);
+        assert_eq!(ring.snapshot(), [0.0, 1.0, 2.0, 3.0]);
+        ring.push(&[4.0, 5.0, 6.0]);
+        assert_eq!(ring.snapshot(), [3.0, 4.0, 5.0, 6.0], "the oldest are overwritten first");
+        ring.push(&[7.0, 8.0, 9.0, 10.0, 11.0]);
+        assert_eq!(ring.snapshot(), [8.0, 9.0, 10.0, 11.0], "a buffer larger than the ring keeps its end");
+        ring.clear();
+        assert_eq!(ring.snapshot(), [0.0; 4]);
+
+        let mut empty = SampleRing::new(0);
+        empty.push(&[1.0]);
+        assert!(empty.snapshot().is_empty());
+    }
+
+    #[test]
+    fn the_ring_is_shared_with_the_scope_reader() {
+        let scope = Arc::new(Mutex::new(SampleRing::new(8)));
+        let mut oscillator = Oscillator::new(Waveform::Square, 1.0, 8, 1.0).with_scope(scope.clone());
+        let mut buffer = [0.0; 8];
+        oscillator.callback(&mut buffer);
+        assert_eq!(scope.lock().unwrap().snapshot(), buffer);
+
+        let held = scope.lock().unwrap();
+        oscillator.callback(&mut buffer);
+        assert_eq!(held.snapshot()[..5], [1.0; 5], "a buffer played while the UI holds the ring is skipped, not waited for");
+    }
+
+    #[test]
+    fn patterns_unpack_top_bit_first() {
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern[0] = 0x80;
+        pattern[1] = 0x0F;
+        pattern[15] = 0x01;
+        let bits = pattern_bits(&pattern);
+        let lit: Vec<usize> = (0..PATTERN_BITS).filter(|&bit| bits[bit]).collect();
+        assert_eq!(lit, [0, 12, 13, 14, 15, 127]);
+        assert_eq!(pattern_bits(&[0xFF; PATTERN_SIZE]), [true; PATTERN_BITS]);
+    }
+
+    #[test]
+    fn pitch_doubles_the_rate_every_48_steps() {
+        assert_eq!(pattern_rate(DEFAULT_PITCH), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 0.01);
+        assert!((pattern_rate(16) - 2000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn patterns_play_in_place_of_the_tone() {
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern[0] = 0xF0;
+        let mut oscillator = Oscillator::new(Waveform::Sine, 440.0, 8000, 0.5);
+        assert_eq!(oscillator.pattern_position(), None);
+        oscillator.set_pattern(Some(pattern), DEFAULT_PITCH);
+        let mut samples = [0.0; 16];
+        oscillator.fill(&mut samples);
+        assert_eq!(samples[..8], [0.5; 8], "4000 bits a second at 8000 samples a second, two samples a bit");
+        assert_eq!(samples[8..], [-0.5; 8]);
+        assert_eq!(oscillator.pattern_position(), Some(8));
+
+        let mut rest = vec![0.0; 240];
+        oscillator.fill(&mut rest);
+        assert_eq!(oscillator.pattern_position(), Some(0), "the pattern loops after 128 bits");
+
+        oscillator.set_pattern(None, DEFAULT_PITCH);
+        assert_eq!(oscillator.pattern_position(), None);
+        oscillator.fill(&mut samples);
+        assert!(samples.iter().any(|&sample| sample.abs() != 0.5), "back to the sine tone");
+    }
+}
@@ -0,0 +1,404 @@
+use std::fmt;
+
+use crate::chip8::{Chip8, KeyObservation};
+use crate::condition::{self, Condition};
+
+const MEMORY_SIZE: usize = 4096;
+
+// Stop before executing the instruction at address, only when the condition holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Condition,
+}
+
+// Stop before executing any opcode with opcode & mask == value, wherever it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeBreak {
+    pub mask: u16,
+    pub value: u16,
+}
+
+impl OpcodeBreak {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+// Stop after the ROM checks a key with EX9E/EXA1 or FX0A returns one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyBreak {
+    pub key: Option<u8>,                // None watches every key
+    pub press_only: bool,               // Only when the key was actually down
+}
+
+impl KeyBreak {
+    pub fn matches(&self, observation: &KeyObservation) -> bool {
+        self.key.is_none_or(|key| key == observation.key) && (observation.pressed || !self.press_only)
+    }
+}
+
+// A debugger command that sets a breakpoint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Break(Breakpoint),
+    BreakOp(OpcodeBreak),
+    BreakKey(KeyBreak),
+}
+
+// Debugger command syntax:
+//   b 0x2A4
+//   b 0x2A4 if v3 == 0x10 && i >= 0x300
+//   b key 5
+//   b key any press
+//   break-op DXYN
+//   break-op mask=F000 value=D000
+pub fn parse_command(text: &str) -> Result<Command, String> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix("b key ") {
+        Ok(Command::BreakKey(parse_key(rest)?))
+    } else if let Some(rest) = text.strip_prefix("b ") {
+        Ok(Command::Break(parse(rest)?))
+    } else if let Some(rest) = text.strip_prefix("break-op ") {
+        Ok(Command::BreakOp(parse_opcode(rest)?))
+    } else {
+        Err("breakpoints are written b ADDR [if CONDITION], b key KEY|any [press] or break-op PATTERN".to_string())
+    }
+}
+
+// "ADDR [if CONDITION]", the part after the b command and the --break argument
+pub fn parse(text: &str) -> Result<Breakpoint, String> {
+    let (address, condition) = match text.trim().split_once(" if ") {
+        Some((address, condition)) => (address, condition::parse(condition)?),
+        None => (text.trim(), Condition::default()),
+    };
+    let address = address.trim();
+    let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+        .ok()
+        .filter(|&addr| (addr as usize) < MEMORY_SIZE)
+        .ok_or_else(|| format!("invalid breakpoint address '{}'", address))?;
+    Ok(Breakpoint { address, condition })
+}
+
+// Opcode pattern, the part after break-op and the --break-op argument: either four characters where
+// hex digits must match and X, Y, N or K match anything (DXYN, FX0A), or "mask=F000 value=D000"
+pub fn parse_opcode(text: &str) -> Result<OpcodeBreak, String> {
+    let text = text.trim();
+    if let Some((mask, value)) = text.split_once(' ') {
+        let field = |part: &str, name: &str| {
+            part.trim().strip_prefix(name)
+                .and_then(|hex| u16::from_str_radix(hex.trim_start_matches("0x"), 16).ok())
+                .ok_or_else(|| format!("expected {}HEX, got '{}'", name, part.trim()))
+        };
+        let (mask, value) = (field(mask, "mask=")?, field(value, "value=")?);
+        return Ok(OpcodeBreak { mask, value: value & mask });
+    }
+
+    if text.chars().count() != 4 {
+        return Err(format!("opcode pattern '{}' should be 4 characters like DXYN", text));
+    }
+    let (mut mask, mut value) = (0, 0);
+    for c in text.chars() {
+        mask <<= 4;
+        value <<= 4;
+        match c.to_ascii_uppercase() {
+            'X' | 'Y' | 'N' | 'K' => {}
+            c => {
+                let digit = c.to_digit(16).ok_or_else(|| format!("unexpected '{}' in opcode pattern '{}'", c, text))?;
+                mask |= 0xF;
+                value |= digit as u16;
+            }
+        }
+    }
+    Ok(OpcodeBreak { mask, value })
+}
+
+// "KEY|any [press]", the part after b key and the --break-key argument
+pub fn parse_key(text: &str) -> Result<KeyBreak, String> {
+    let mut words = text.split_whitespace();
+    let key = match words.next() {
+        Some("any") => None,
+        Some(key) => Some(u8::from_str_radix(key.trim_start_matches("0x"), 16).ok()
+            .filter(|&key| key < 16)
+            .ok_or_else(|| format!("invalid key '{}', expected 0-F or any", key))?),
+        None => return Err("expected a key 0-F or any".to_string()),
+    };
+    let press_only = match words.next() {
+        Some("press") => true,
+        Some(other) => return Err(format!("unexpected '{}' after the key, expected press", other)),
+        None => false,
+    };
+    Ok(KeyBreak { key, press_only })
+}
+
+// Why execution stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Hit {
+    Address(Breakpoint),
+    Opcode { pattern: OpcodeBreak, address: u16, opcode: u16 },
+    Key(KeyObservation),
+}
+
+impl Hit {
+    pub fn address(&self) -> u16 {
+        match self {
+            Hit::Address(breakpoint) => breakpoint.address,
+            Hit::Opcode { address, .. } => *address,
+            Hit::Key(observation) => observation.pc,
+        }
+    }
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hit::Address(breakpoint) if breakpoint.condition.all.is_empty() => write!(f, "Breakpoint at {:#05X}", breakpoint.address),
+            Hit::Address(breakpoint) => write!(f, "Breakpoint at {:#05X} if {}", breakpoint.address, breakpoint.condition),
+            Hit::Opcode { pattern, address, opcode } => {
+                write!(f, "Opcode break at {:#05X}: {:04X} (mask={:04X} value={:04X})", address, opcode, pattern.mask, pattern.value)
+            }
+            Hit::Key(observation) => write!(f, "Key break at {:#05X}: {:04X} checked key {:X} ({})", observation.pc, observation.opcode,
+                observation.key, if observation.pressed { "pressed" } else { "not pressed" }),
+        }
+    }
+}
+
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    patterns: Vec<OpcodeBreak>,
+    keys: Vec<KeyBreak>,
+    armed: Vec<bool>,                   // One flag per address, so unmarked addresses cost a single lookup
+    skip_once: bool,                    // Resuming lets the instruction it stopped on run
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints {
+            breakpoints: Vec::new(),
+            patterns: Vec::new(),
+            keys: Vec::new(),
+            armed: vec![false; MEMORY_SIZE],
+            skip_once: false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn patterns(&self) -> &[OpcodeBreak] {
+        &self.patterns
+    }
+
+    pub fn keys(&self) -> &[KeyBreak] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty() && self.patterns.is_empty() && self.keys.is_empty()
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        self.armed[breakpoint.address as usize] = true;
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn add_pattern(&mut self, pattern: OpcodeBreak) {
+        self.patterns.push(pattern);
+    }
+
+    pub fn add_key(&mut self, key_break: KeyBreak) {
+        self.keys.push(key_break);
+    }
+
+    // Watch a key, or stop watching it when it already is, returns whether it is now watched
+    pub fn toggle_key(&mut self, key: u8) -> bool {
+        match self.keys.iter().position(|key_break| key_break.key == Some(key)) {
+            Some(idx) => {
+                self.keys.remove(idx);
+                false
+            }
+            None => {
+                self.keys.push(KeyBreak { key: Some(key), press_only: false });
+                true
+            }
+        }
+    }
+
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Break(breakpoint) => self.add(breakpoint),
+            Command::BreakOp(pattern) => self.add_pattern(pattern),
+            Command::BreakKey(key_break) => self.add_key(key_break),
+        }
+    }
+
+    // Continue from a stop without hitting the same breakpoint again straight away
+    pub fn resume(&mut self) {
+        self.skip_once = true;
+    }
+
+    // What to stop on before the next instruction, if anything
+    pub fn check(&mut self, chip8: &Chip8) -> Option<Hit> {
+        if std::mem::take(&mut self.skip_once) {
+            return None;
+        }
+        let pc = chip8.pc();
+        if self.armed.get(pc as usize).copied().unwrap_or(false) {
+            if let Some(breakpoint) = self.breakpoints.iter().find(|breakpoint| breakpoint.address == pc && breakpoint.condition.holds(chip8)) {
+                return Some(Hit::Address(breakpoint.clone()));
+            }
+        }
+        if self.patterns.is_empty() {
+            return None;
+        }
+
+        // The opcode the core is about to fetch
+        let opcode = (chip8.peek(pc as usize)? as u16) << 8 | chip8.peek(pc as usize + 1)? as u16;
+        let pattern = *self.patterns.iter().find(|pattern| pattern.matches(opcode))?;
+        Some(Hit::Opcode { pattern, address: pc, opcode })
+    }
+
+    // Called after each instruction, stops when it made a key check that a key break watches
+    pub fn check_key(&self, chip8: &mut Chip8) -> Option<Hit> {
+        let observation = chip8.take_key_observation()?;
+        self.keys.iter().any(|key_break| key_break.matches(&observation)).then_some(Hit::Key(observation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v3 = 0, then v3 += 1 in a loop through 0x204
+    const COUNTER: [u8; 6] = [0x63, 0x00, 0x73, 0x01, 0x12, 0x02];
+
+    // Cycle until a breakpoint stops the machine, at most limit instructions
+    fn run_to_hit(chip8: &mut Chip8, breakpoints: &mut Breakpoints, limit: usize) -> Option<Hit> {
+        for _ in 0..limit {
+            if let Some(hit) = breakpoints.check(chip8) {
+                return Some(hit);
+            }
+            chip8.cycle();
+        }
+        None
+    }
+
+    #[test]
+    fn console_syntax_parses_into_commands() {
+        let Command::Break(breakpoint) = parse_command("b 0x2A4 if v3 == 0x10").unwrap() else {
+            panic!("expected an address breakpoint");
+        };
+        assert_eq!(breakpoint.address, 0x2A4);
+        assert_eq!(breakpoint.condition, condition::parse("v3 == 0x10").unwrap());
+        assert_eq!(parse_command("b 2a4").unwrap(), Command::Break(Breakpoint { address: 0x2A4, condition: Condition::default() }));
+        assert_eq!(parse_command("b 0x1000").unwrap_err(), "invalid breakpoint address '0x1000'");
+        assert!(parse_command("b 0x2A4 if v3 ==").is_err());
+        assert!(parse_command("x 0x2A4").unwrap_err().starts_with("breakpoints are written"));
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_only_when_the_condition_holds() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&COUNTER).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.apply(parse_command("b 0x204 if v3 == 0x10").unwrap());
+
+        let hit = run_to_hit(&mut chip8, &mut breakpoints, 1000).expect("the breakpoint is hit");
+        assert_eq!(hit.to_string(), "Breakpoint at 0x204 if v3 == 0x10");
+        assert_eq!((chip8.pc(), chip8.register(3)), (0x204, 0x10), "fifteen passes went through unstopped");
+
+        breakpoints.resume();
+        assert_eq!(breakpoints.check(&chip8), None, "resuming runs the instruction it stopped on");
+        chip8.cycle();
+        assert_eq!(run_to_hit(&mut chip8, &mut breakpoints, 100), None, "v3 has moved past 0x10");
+    }
+
+    #[test]
+    fn opcode_patterns_parse_from_shorthand_and_raw_masks() {
+        assert_eq!(parse_opcode("DXYN").unwrap(), OpcodeBreak { mask: 0xF000, value: 0xD000 });
+        assert_eq!(parse_opcode("fx0a").unwrap(), OpcodeBreak { mask: 0xF0FF, value: 0xF00A });
+        assert_eq!(parse_opcode("mask=F000 value=D000").unwrap(), parse_opcode("DXYN").unwrap());
+        assert_eq!(parse_command("break-op 00E0").unwrap(), Command::BreakOp(OpcodeBreak { mask: 0xFFFF, value: 0x00E0 }));
+        assert!(parse_opcode("DXY").is_err());
+        assert_eq!(parse_opcode("DXYZ").unwrap_err(), "unexpected 'Z' in opcode pattern 'DXYZ'");
+        assert_eq!(parse_opcode("mask=F000 D000").unwrap_err(), "expected value=HEX, got 'D000'");
+    }
+
+    #[test]
+    fn draw_patterns_stop_at_the_first_dxyn() {
+        // v0 = 0, v1 = 5, I = 0x20C, then draw at 0x206 and 0x208 in a loop back to 0x206
+        let rom = [0x60, 0x00, 0x61, 0x05, 0xA2, 0x0C, 0xD0, 0x15, 0xD1, 0x15, 0x12, 0x06, 0xF0, 0x90];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.apply(parse_command("break-op DXYN").unwrap());
+
+        let hit = run_to_hit(&mut chip8, &mut breakpoints, 100).expect("the draw is hit");
+        assert_eq!(hit, Hit::Opcode { pattern: parse_opcode("DXYN").unwrap(), address: 0x206, opcode: 0xD015 });
+        assert_eq!(hit.address(), 0x206);
+        assert_eq!(chip8.pc(), 0x206, "stopped before the draw ran");
+        assert_eq!(chip8.lit_pixels().count(), 0);
+
+        breakpoints.resume();
+        let hit = run_to_hit(&mut chip8, &mut breakpoints, 100).expect("the second draw is hit");
+        assert_eq!((hit.address(), chip8.pc()), (0x208, 0x208), "resuming ran exactly the draw it stopped on");
+        assert!(chip8.lit_pixels().count() > 0);
+    }
+
+    // Cycle like the frontend does, with key breaks checked after each instruction
+    fn run_to_key(chip8: &mut Chip8, breakpoints: &Breakpoints, limit: usize) -> Option<Hit> {
+        for _ in 0..limit {
+            chip8.cycle();
+            if let Some(hit) = breakpoints.check_key(chip8) {
+                return Some(hit);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn key_breaks_stop_when_the_watched_key_is_checked() {
+        // v0 = 5, then poll it with EX9E until it's down, then wait for any key with FX0A
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF3, 0x0A, 0x12, 0x08];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.apply(parse_command("b key 5 press").unwrap());
+        assert_eq!(breakpoints.keys(), [KeyBreak { key: Some(5), press_only: true }]);
+
+        assert_eq!(run_to_key(&mut chip8, &breakpoints, 100), None, "checks of a released key don't stop");
+        chip8.set_key(5, 1);
+        let hit = run_to_key(&mut chip8, &breakpoints, 10).expect("the press is seen");
+        assert_eq!(hit, Hit::Key(KeyObservation { pc: 0x202, opcode: 0xE09E, key: 5, pressed: true }));
+        assert_eq!(hit.to_string(), "Key break at 0x202: E09E checked key 5 (pressed)");
+
+        // FX0A only reports the key it returns
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.apply(parse_command("b key any").unwrap());
+        chip8.set_key(5, 0);
+        chip8.set_key(7, 1);
+        let hit = run_to_key(&mut chip8, &breakpoints, 10).expect("FX0A completes");
+        assert_eq!(hit, Hit::Key(KeyObservation { pc: 0x206, opcode: 0xF30A, key: 7, pressed: true }));
+        assert_eq!(chip8.register(3), 7);
+    }
+
+    #[test]
+    fn unpressed_checks_stop_without_press() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x05, 0xE0, 0xA1, 0x12, 0x02]).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.apply(parse_command("b key 5").unwrap());
+        let hit = run_to_key(&mut chip8, &breakpoints, 10).expect("the check is seen");
+        assert_eq!(hit.to_string(), "Key break at 0x202: E0A1 checked key 5 (not pressed)");
+        assert!(!breakpoints.toggle_key(5), "toggling an existing break removes it");
+        assert!(breakpoints.is_empty());
+        assert_eq!(parse_command("b key 16").unwrap_err(), "invalid key '16', expected 0-F or any");
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::analysis::reachable;
+use crate::disasm::{opcode_at, Flow, Instruction};
+
+// Control flow graph of the code reachable from 0x200, for exporting to Graphviz
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Node {
+    Block(u16),                         // Basic block by start address
+    Computed,                           // Stand-in for every BNNN target
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Taken,
+    Fallthrough,
+    Call,
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: u16,                      // Start of the source block
+    pub to: Node,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+}
+
+impl Block {
+    fn last(&self) -> (u16, Instruction) {
+        *self.instructions.last().unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,             // In address order
+    pub edges: Vec<Edge>,
+    pub data: Vec<(u16, u16)>,          // Inclusive address ranges no reachable instruction covers
+}
+
+pub fn build(rom: &[u8]) -> Cfg {
+    let addresses = reachable(rom);
+    let code: BTreeMap<u16, Instruction> = addresses.iter()
+        .map(|&addr| (addr, Instruction::decode(opcode_at(rom, addr).unwrap())))
+        .collect();
+
+    // Jumping into the middle of another instruction makes the two overlap, such instructions get a block of their own
+    let overlaps = |addr: u16| code.contains_key(&(addr.wrapping_sub(1))) || code.contains_key(&(addr + 1));
+
+    let mut leaders = BTreeSet::from([0x200]);
+    for (&addr, instruction) in &code {
+        match instruction.flow() {
+            Flow::Jump(target) => { leaders.insert(target); }
+            Flow::Call(target) => {
+                leaders.insert(target);
+                leaders.insert(addr + 2);
+            }
+            Flow::Skip => {
+                leaders.insert(addr + 2);
+                leaders.insert(addr + 4);
+            }
+            _ => {}
+        }
+        if overlaps(addr) {
+            leaders.insert(addr);
+            leaders.insert(addr + 2);
+        }
+    }
+
+    // Each block runs from a leader until the next leader or a control flow instruction
+    let mut blocks = Vec::new();
+    for &start in leaders.iter().filter(|addr| code.contains_key(addr)) {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        while let Some(&instruction) = code.get(&addr) {
+            if addr != start && leaders.contains(&addr) {
+                break;
+            }
+            instructions.push((addr, instruction));
+            if instruction.flow() != Flow::Next || overlaps(addr) {
+                break;
+            }
+            addr += 2;
+        }
+        blocks.push(Block { start, instructions });
+    }
+
+    let mut edges = BTreeSet::new();
+    let mut link = |from: u16, to: Node, kind: EdgeKind| {
+        if matches!(to, Node::Block(addr) if !code.contains_key(&addr)) {
+            return;                     // Target outside the ROM
+        }
+        edges.insert(Edge { from, to, kind });
+    };
+    for block in &blocks {
+        let (addr, instruction) = block.last();
+        match instruction.flow() {
+            Flow::Next => link(block.start, Node::Block(addr + 2), EdgeKind::Fallthrough),
+            Flow::Jump(target) => link(block.start, Node::Block(target), EdgeKind::Taken),
+            Flow::Call(target) => {
+                link(block.start, Node::Block(target), EdgeKind::Call);
+                link(block.start, Node::Block(addr + 2), EdgeKind::Fallthrough);
+            }
+            Flow::Skip => {
+                link(block.start, Node::Block(addr + 2), EdgeKind::Fallthrough);
+                link(block.start, Node::Block(addr + 4), EdgeKind::Taken);
+            }
+            Flow::Computed(_) => link(block.start, Node::Computed, EdgeKind::Taken),
+            Flow::Return | Flow::Halt => {}
+        }
+    }
+
+    // Returns go back to the instruction after every call whose subroutine can reach them
+    let mut returns = Vec::new();
+    for call in edges.iter().filter(|edge| edge.kind == EdgeKind::Call) {
+        let Node::Block(target) = call.to else { continue };
+        let caller = blocks.iter().find(|block| block.start == call.from).unwrap();
+        let return_site = caller.last().0 + 2;
+
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![target];
+        while let Some(start) = pending.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            let block = blocks.iter().find(|block| block.start == start).unwrap();
+            if block.last().1.flow() == Flow::Return && code.contains_key(&return_site) {
+                returns.push(Edge { from: start, to: Node::Block(return_site), kind: EdgeKind::Return });
+            }
+            for edge in edges.iter().filter(|edge| edge.from == start && matches!(edge.kind, EdgeKind::Taken | EdgeKind::Fallthrough)) {
+                if let Node::Block(next) = edge.to {
+                    pending.push(next);
+                }
+            }
+        }
+    }
+    edges.extend(returns);
+
+    Cfg { blocks, edges: edges.into_iter().collect(), data: data_ranges(rom, &addresses) }
+}
+
+// Runs of ROM bytes outside every reachable instruction
+fn data_ranges(rom: &[u8], code: &[u16]) -> Vec<(u16, u16)> {
+    let mut covered = vec![false; rom.len()];
+    for &addr in code {
+        let offset = (addr - 0x200) as usize;
+        covered[offset] = true;
+        covered[offset + 1] = true;
+    }
+
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for (offset, _) in covered.iter().enumerate().filter(|(_, &covered)| !covered) {
+        let addr = 0x200 + offset as u16;
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == addr => *end = addr,
+            _ => ranges.push((addr, addr)),
+        }
+    }
+    ranges
+}
+
+impl Cfg {
+    // Graphviz source: one box per block labelled with its disassembly, data ranges in their own cluster
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box fontname=\"monospace\"];\n");
+
+        for block in &self.blocks {
+            let mut label = String::new();
+            for (addr, instruction) in &block.instructions {
+                let _ = write!(label, "{:#05X}: {}\\l", addr, instruction);
+            }
+            let _ = writeln!(dot, "    b{:03X} [label=\"{}\"];", block.start, label);
+        }
+        if self.edges.iter().any(|edge| edge.to == Node::Computed) {
+            dot.push_str("    computed [label=\"computed jump\" shape=ellipse];\n");
+        }
+
+        for edge in &self.edges {
+            let to = match edge.to {
+                Node::Block(addr) => format!("b{:03X}", addr),
+                Node::Computed => "computed".to_string(),
+            };
+            let kind = match edge.kind {
+                EdgeKind::Taken => "taken",
+                EdgeKind::Fallthrough => "fallthrough",
+                EdgeKind::Call => "call",
+                EdgeKind::Return => "return",
+            };
+            let _ = writeln!(dot, "    b{:03X} -> {} [label=\"{}\"];", edge.from, to, kind);
+        }
+
+        if !self.data.is_empty() {
+            dot.push_str("    subgraph cluster_data {\n        label=\"probable data\";\n");
+            for &(start, end) in &self.data {
+                let _ = writeln!(dot, "        d{:03X} [label=\"{:#05X}-{:#05X} ({} bytes)\" shape=note];", start, start, end, end - start + 1);
+            }
+            dot.push_str("    }\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A counting loop calling a subroutine, with a skip leaving the loop for a halt and data in between
+    const FIXTURE: [u8; 20] = [
+        0x60, 0x00,                     // 0x200 v0 := 0
+        0x70, 0x01,                     // 0x202 loop: v0 += 1
+        0x22, 0x10,                     // 0x204 call sub
+        0x30, 0x05,                     // 0x206 skip if v0 == 5
+        0x12, 0x02,                     // 0x208 jump loop
+        0x12, 0x0A,                     // 0x20A halt: jump halt
+        0xFF, 0x81, 0x81, 0xFF,         // 0x20C data
+        0x61, 0x01,                     // 0x210 sub: v1 := 1
+        0x00, 0xEE,                     // 0x212 return
+    ];
+
+    fn edge(from: u16, to: u16, kind: EdgeKind) -> Edge {
+        Edge { from, to: Node::Block(to), kind }
+    }
+
+    #[test]
+    fn blocks_split_at_the_loop_the_call_and_the_skip() {
+        let cfg = build(&FIXTURE);
+        let starts: Vec<u16> = cfg.blocks.iter().map(|block| block.start).collect();
+        assert_eq!(starts, [0x200, 0x202, 0x206, 0x208, 0x20A, 0x210]);
+        let sizes: Vec<usize> = cfg.blocks.iter().map(|block| block.instructions.len()).collect();
+        assert_eq!(sizes, [1, 2, 1, 1, 1, 2]);
+        assert_eq!(cfg.data, [(0x20C, 0x20F)]);
+    }
+
+    #[test]
+    fn edges_are_kinded_and_returns_go_to_the_call_site() {
+        let mut edges = build(&FIXTURE).edges;
+        edges.sort();
+        let mut expected = vec![
+            edge(0x200, 0x202, EdgeKind::Fallthrough),
+            edge(0x202, 0x210, EdgeKind::Call),
+            edge(0x202, 0x206, EdgeKind::Fallthrough),
+            edge(0x206, 0x208, EdgeKind::Fallthrough),
+            edge(0x206, 0x20A, EdgeKind::Taken),
+            edge(0x208, 0x202, EdgeKind::Taken),
+            edge(0x20A, 0x20A, EdgeKind::Taken),
+            edge(0x210, 0x206, EdgeKind::Return),
+        ];
+        expected.sort();
+        assert_eq!(edges, expected);
+    }
+
+    #[test]
+    fn dot_output_labels_blocks_edges_and_data() {
+        let dot = build(&FIXTURE).to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b202 -> b210 [label=\"call\"];\n"));
+        assert!(dot.contains("    b210 -> b206 [label=\"return\"];\n"));
+        assert!(dot.contains("    b206 -> b20A [label=\"taken\"];\n"));
+        assert!(dot.contains("d20C [label=\"0x20C-0x20F (4 bytes)\" shape=note]"));
+        assert!(!dot.contains("computed"), "no computed jumps here");
+    }
+
+    #[test]
+    fn computed_jumps_lead_to_the_computed_node() {
+        let cfg = build(&[0x60, 0x02, 0xB2, 0x04, 0x12, 0x04]);
+        assert_eq!(cfg.edges, [Edge { from: 0x200, to: Node::Computed, kind: EdgeKind::Taken }]);
+        assert!(cfg.to_dot().contains("computed [label=\"computed jump\" shape=ellipse]"));
+        assert_eq!(cfg.data, [(0x204, 0x205)], "nothing static reaches past BNNN");
+    }
+
+    #[test]
+    fn overlapping_instructions_get_blocks_of_their_own() {
+        // The skip reaches 0x204, the jump lands on 0x205 inside it
+        let cfg = build(&[0x30, 0x01, 0x12, 0x05, 0x12, 0x00, 0xE0, 0x12, 0x00]);
+        let starts: Vec<u16> = cfg.blocks.iter().map(|block| block.start).collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x205, 0x207]);
+        assert!(cfg.blocks.iter().all(|block| block.instructions.len() == 1));
+        assert!(cfg.edges.contains(&edge(0x205, 0x207, EdgeKind::Fallthrough)));
+        assert!(cfg.edges.contains(&edge(0x202, 0x205, EdgeKind::Taken)));
+    }
+}
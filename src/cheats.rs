@@ -0,0 +1,230 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::Chip8;
+
+// When frozen bytes are rewritten
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyMode {
+    EveryInstruction,
+    EveryFrame,
+}
+
+// One frozen memory byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub enabled: bool,
+}
+
+pub struct CheatManager {
+    cheats: Vec<Cheat>,
+    pub mode: ApplyMode,
+}
+
+impl Default for CheatManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CheatManager {
+    pub fn new() -> Self {
+        CheatManager {
+            cheats: Vec::new(),
+            mode: ApplyMode::EveryFrame,
+        }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Add an enabled freeze, replacing any existing cheat on the same address
+    pub fn add(&mut self, chip8: &Chip8, address: u16, value: u8) -> Result<(), String> {
+        if chip8.peek(address as usize).is_none() {
+            return Err(format!("cheat address {:#05X} is outside memory", address));
+        }
+
+        let cheat = Cheat { address, value, enabled: true };
+        match self.cheats.iter_mut().find(|cheat| cheat.address == address) {
+            Some(existing) => *existing = cheat,
+            None => self.cheats.push(cheat),
+        }
+        Ok(())
+    }
+
+    // Flip a cheat on or off, returns the new state or None if there's no such cheat
+    pub fn toggle(&mut self, idx: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(idx)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    // Rewrite every enabled frozen byte
+    pub fn apply(&self, chip8: &mut Chip8) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            let _ = chip8.poke(cheat.address as usize, cheat.value);
+        }
+    }
+
+    // Cheats are kept per ROM, keyed by the ROM hash
+    pub fn path_for(dir: &Path, rom_hash: u64) -> PathBuf {
+        dir.join(format!("{:016x}.cheats", rom_hash))
+    }
+
+    // Load cheats from a file of "ADDR VALUE on|off" lines, a missing file is no cheats
+    pub fn load(&mut self, chip8: &Chip8, path: &Path) -> Result<(), String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(format!("could not read {}: {}", path.display(), err)),
+        };
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (address, value, enabled) = parse_line(line)
+                .ok_or_else(|| format!("{}:{}: expected 'ADDR VALUE on|off'", path.display(), line_no + 1))?;
+            self.add(chip8, address, value)?;
+            if !enabled {
+                self.cheats.last_mut().unwrap().enabled = false;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+        }
+
+        let text: String = self.cheats.iter()
+            .map(|cheat| format!("{:#05X} {:#04X} {}\n", cheat.address, cheat.value, if cheat.enabled { "on" } else { "off" }))
+            .collect();
+        fs::write(path, text).map_err(|err| format!("could not write {}: {}", path.display(), err))
+    }
+}
+
+// Parse "ADDR=VALUE" as given on the command line, both hex
+pub fn parse_assignment(text: &str) -> Option<(u16, u8)> {
+    let (address, value) = text.split_once('=')?;
+    Some((u16::try_from(parse_hex(address)?).ok()?, u8::try_from(parse_hex(value)?).ok()?))
+}
+
+fn parse_line(line: &str) -> Option<(u16, u8, bool)> {
+    let mut parts = line.split_whitespace();
+    let address = u16::try_from(parse_hex(parts.next()?)?).ok()?;
+    let value = u8::try_from(parse_hex(parts.next()?)?).ok()?;
+    let enabled = match parts.next() {
+        Some("on") | None => true,
+        Some("off") => false,
+        _ => return None,
+    };
+    Some((address, value, enabled))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads the byte at 0x300, adds one and stores it back, forever
+    const INCREMENTER: [u8; 10] = [0xA3, 0x00, 0xF0, 0x65, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00];
+
+    fn incrementer() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&INCREMENTER).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn a_freeze_pins_a_byte_the_program_increments() {
+        let mut chip8 = incrementer();
+        let mut cheats = CheatManager::new();
+        cheats.mode = ApplyMode::EveryInstruction;
+        cheats.add(&chip8, 0x300, 0x42).unwrap();
+        for _ in 0..100 {
+            chip8.cycle();
+            cheats.apply(&mut chip8);
+            assert_eq!(chip8.peek(0x300), Some(0x42), "pinned after every instruction");
+        }
+
+        cheats.toggle(0);
+        for _ in 0..10 {
+            chip8.cycle();
+            cheats.apply(&mut chip8);
+        }
+        assert_eq!(chip8.peek(0x300), Some(0x44), "counts again once disabled");
+    }
+
+    #[test]
+    fn frame_mode_restores_the_byte_at_the_end_of_each_frame() {
+        let mut chip8 = incrementer();
+        let mut cheats = CheatManager::new();
+        cheats.add(&chip8, 0x300, 0x10).unwrap();
+        cheats.apply(&mut chip8);
+        for _ in 0..5 {
+            for _ in 0..25 {
+                chip8.cycle();
+            }
+            assert_eq!(chip8.peek(0x300), Some(0x10 + 5), "five loops into the frame");
+            cheats.apply(&mut chip8);
+            assert_eq!(chip8.peek(0x300), Some(0x10));
+        }
+    }
+
+    #[test]
+    fn freezes_land_in_write_protected_memory() {
+        let mut chip8 = incrementer();
+        chip8.protect_region(0x300..0x301);
+        chip8.set_strict_protection(true);
+        let mut cheats = CheatManager::new();
+        cheats.add(&chip8, 0x300, 0x42).unwrap();
+        cheats.apply(&mut chip8);
+        chip8.step_frame(25);
+        assert_eq!(chip8.peek(0x300), Some(0x42), "the program's writes are refused, the cheat's aren't");
+    }
+
+    #[test]
+    fn addresses_are_checked_and_replaced() {
+        let chip8 = incrementer();
+        let mut cheats = CheatManager::new();
+        assert_eq!(cheats.add(&chip8, 0x1000, 1), Err("cheat address 0x1000 is outside memory".to_string()));
+        cheats.add(&chip8, 0x300, 1).unwrap();
+        cheats.add(&chip8, 0x300, 2).unwrap();
+        assert_eq!(cheats.cheats(), [Cheat { address: 0x300, value: 2, enabled: true }]);
+        assert_eq!(cheats.toggle(1), None);
+        assert_eq!(parse_assignment("0x2F0=0x1f"), Some((0x2F0, 0x1F)));
+        assert_eq!(parse_assignment("2F0=100"), None, "value is a byte");
+    }
+
+    #[test]
+    fn cheats_persist_per_rom_hash() {
+        let chip8 = incrementer();
+        let dir = std::env::temp_dir().join(format!("chip8-cheats-{}", std::process::id()));
+        let path = CheatManager::path_for(&dir, chip8.rom_hash());
+        let mut cheats = CheatManager::new();
+        cheats.add(&chip8, 0x300, 0x42).unwrap();
+        cheats.add(&chip8, 0x301, 0x07).unwrap();
+        cheats.toggle(1);
+        cheats.save(&path).unwrap();
+
+        let mut loaded = CheatManager::new();
+        loaded.load(&chip8, &path).unwrap();
+        assert_eq!(loaded.cheats(), cheats.cheats());
+        fs::remove_dir_all(&dir).unwrap();
+        loaded.load(&chip8, &path).unwrap();
+        assert_eq!(loaded.cheats().len(), 2, "a missing file adds nothing");
+    }
+}
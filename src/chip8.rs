@@ -1,17 +1,49 @@
-use rand::Rng;
+use core::fmt;
+use core::ops::Range;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use sha1::{Digest, Sha1};
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::Read;
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::rect::Rect;
-use std::time::Duration;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+use crate::chip8x::{ColorMap, COLOR_STATE_SIZE};
+use crate::coverage::Coverage;
+use crate::cpu::{Cpu, Fault};
+use crate::disasm::{Category, Instruction};
+use crate::display::{self, Display, PackLayout};
+use crate::keypad::Keypad;
+#[cfg(feature = "paranoid")]
+use crate::paranoid::{self, PcHistory};
+use crate::memory::{DefaultBus, FlatMemory, Heatmap, HeatmapBus, MemoryBus, WriteProtect};
+use crate::prelude::*;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// SUPER-CHIP high resolution, switched to with 00FF
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
 // Fontset stored between 0x50 and onwards
-const CHIP8_FONTSET: [u8; 80] = [
+pub const FONT_BASE: usize = 0x50;
+pub const FONTSET_SIZE: usize = 80;
+
+// Where ROMs are loaded and execution starts
+pub const PROGRAM_START: usize = 0x200;
+const FONTSET_CHECKSUM: u32 = 0x3399EDF0;  // fontset_checksum of CHIP8_FONTSET loaded at FONT_BASE
+
+// Frames a key counts as recently polled after EX9E, EXA1 or FX0A looked at it
+pub const POLL_WINDOW: u64 = 60;
+
+// Bytes in a save_state payload besides memory: ROM hash, registers, I, pc, sp, stack, timers, resolution, display
+// with room for hires, keys, quirks, seed, draws, frames, the XO-CHIP audio pattern and pitch and the CHIP-8X colors
+const STATE_FIXED_SIZE: usize = 8 + 16 + 2 + 2 + 2 + 32 + 2 + 1 + HIRES_WIDTH * HIRES_HEIGHT + 16 + 8 + 8 + 8 + 8 + 1 + PATTERN_SIZE + 1
+    + COLOR_STATE_SIZE + 1 + 1;
+pub const PATTERN_SIZE: usize = 16;     // Bytes of an XO-CHIP audio pattern, 128 one bit samples
+pub const DEFAULT_PITCH: u8 = 64;       // XO-CHIP pitch register at power on, 4000 pattern bits a second
+const CHIP8_FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0,   // 0
     0x20, 0x60, 0x20, 0x20, 0x70,   // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0,   // 2
@@ -30,610 +62,2658 @@ const CHIP8_FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80    // F
 ];
 
-// Chip8 components struct
-pub struct Chip8 {
-    v: [u8; 16],                        // General Purpose Registers v0 - vF
-    index: u16,                         // Index Register
-    pc: u16,                            // Program Counter
-    sp: u16,                            // Stack Pointer
-    stack: [u16; 16],                   // Stack
-    memory: [u8; 4096],                 // Memory
-    delay_timer: u8,                    // Delay Timer
-    sound_timer: u8,                    // Sound Timer
+// Errors from the Chip8 calls an embedder can get wrong, to match on rather than parse
+#[derive(Debug)]
+pub enum Chip8Error {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),                 // The ROM file couldn't be read
+    #[cfg(feature = "zip")]
+    Archive(String),                    // The ROM file is a ZIP pack without a single readable ROM in it
+    RomEmpty,                           // Only refused by load_rom_bytes_strict
+    RomTooLarge { len: usize, capacity: usize },    // ROM bytes and the memory there is for them from 0x200
+    BufferSize { needed: usize, got: usize },   // An output buffer of the wrong length for the resolution
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Chip8Error::Io(err) => write!(f, "{}", err),
+            #[cfg(feature = "zip")]
+            Chip8Error::Archive(err) => write!(f, "{}", err),
+            Chip8Error::RomEmpty => write!(f, "ROM is empty"),
+            Chip8Error::RomTooLarge { len, capacity } => write!(f, "ROM is {} bytes, only {} fit in memory", len, capacity),
+            Chip8Error::BufferSize { needed, got } => write!(f, "buffer needs {} bytes, got {}", needed, got),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Chip8Error {
+    fn from(err: std::io::Error) -> Self {
+        Chip8Error::Io(err)
+    }
+}
+
+// A keypad check made by the ROM: EX9E/EXA1 testing a key, or FX0A completing with one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyObservation {
+    pub pc: u16,                        // Address of the checking instruction
+    pub opcode: u16,
+    pub key: u8,
+    pub pressed: bool,                  // Whether the key was down, always true for FX0A
+}
+
+// A ROM reading a register it never wrote, found by the register lint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UninitRead {
+    pub pc: u16,                        // Address of the reading instruction
+    pub opcode: u16,
+    pub register: u8,
+}
+
+// An interpreter write into a region marked with protect_region
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtectionViolation {
+    pub pc: u16,                        // Address of the writing instruction
+    pub addr: usize,
+    pub value: u8,                      // Byte the instruction tried to write
+}
+
+// A circulating dump that is truncated or patched, which load_rom_bytes warns about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BadDump {
+    pub sha1: &'static str,             // Lowercase hex, like the ROM database keys
+    pub issue: &'static str,
+    pub good_sha1: Option<&'static str>,
+}
+
+// Known bad dumps. Entries only go in with the hash of a dump checked to misbehave, so the list starts
+// empty rather than guessed
+pub const KNOWN_BAD_DUMPS: &[BadDump] = &[];
+
+// The dump in table with this SHA-1, if it is one
+pub fn known_bad_dump<'a>(table: &'a [BadDump], sha1: &str) -> Option<&'a BadDump> {
+    table.iter().find(|dump| dump.sha1 == sha1)
+}
+
+// Warn about a ROM whose SHA-1 is in table, naming the good dump when there is one
+fn warn_if_bad_dump(table: &[BadDump], sha1: &str) {
+    if let Some(dump) = known_bad_dump(table, sha1) {
+        match dump.good_sha1 {
+            Some(good) => log::warn!("This is a known bad dump: {}. A good dump has SHA-1 {}.", dump.issue, good),
+            None => log::warn!("This is a known bad dump: {}.", dump.issue),
+        }
+    }
+}
+
+// One active subroutine call, outermost first in call_stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub call_site: u16,                 // Address of the 2NNN that made the call
+    pub return_address: u16,            // Where 00EE resumes, the instruction after the call
+    pub opcode: Option<u16>,            // Opcode at the call site, None when it lies outside memory
+}
+
+// Interpreter behaviours that differ between CHIP-8 implementations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    pub clip_sprites: bool,             // Sprites are clipped at the screen edges instead of wrapping around
+    pub load_store_increment: bool,     // FX55/FX65 leave I pointing past the last register, as on the COSMAC VIP
+    pub shift_vy: bool,                 // 8XY6/8XYE shift vY into vX, as on the COSMAC VIP, instead of shifting vX in place
+    pub index_width: u8,                // Bits of I kept after it changes, 12 for standard CHIP-8 or 16 for XO-CHIP
+    pub adi_overflow_vf: bool,          // FX1E sets vF to 1 when I overflows and 0 otherwise, as on the Amiga interpreter
+    pub adi_overflow_width: u8,         // Bits of I that FX1E overflows past, 12 (0x0FFF) or 16 (0xFFFF for XO-CHIP)
+    pub variant: Variant,               // Instruction set decoded on top of CHIP-8, SUPER-CHIP and XO-CHIP
+    pub collision_delay: bool,          // DXYN's collision flag reaches vF after the next instruction instead of at once
+}
+
+// Historical CHIP-8 offshoots whose opcodes clash with the usual ones, so they're only decoded when asked for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chip8X,                             // COSMAC VIP color board: 02A0, BXY0/BXYN colors and 5XY1 octal add
+    Chip8E,                             // Gilles Detillieux's VIP interpreter: 00ED, 0151, 0188, 5XY1-5XY3, BBNN/BFNN, FX03, FX1B, FX4F and port input
+}
+
+impl Variant {
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::Chip8X => "chip8x",
+            Variant::Chip8E => "chip8e",
+        }
+    }
+
+    // The inverse of `variant as u8`, as savestates and movies store it
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Variant::Standard),
+            1 => Some(Variant::Chip8X),
+            2 => Some(Variant::Chip8E),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            clip_sprites: false,
+            load_store_increment: false,
+            shift_vy: false,
+            index_width: 12,
+            adi_overflow_vf: false,
+            adi_overflow_width: 12,
+            variant: Variant::Standard,
+            collision_delay: false,
+        }
+    }
+}
+
+// Chip8 components struct: the CPU, display and keypad, plus the machine level state around them.
+// Memory is the DefaultBus unless the machine is built with_memory on another bus
+pub struct Chip8<M: MemoryBus = DefaultBus> {
+    cpu: Cpu<M>,                        // Registers, memory, stack and timers
     opcode: u16,                        // Program Opperation Code
-    pub display: [u8; WIDTH * HEIGHT],  // Display
-    key:[u8; 16],                       // Input keys
+    pub display: Display,               // Display
+    shown: Display,                     // Display as of the last take_changes, kept across reset
+    shown_stale: bool,                  // Resolution switched since then, take_changes reports every pixel
+    keypad: Keypad,                     // Input keys
     pub draw_flag: bool,                // Determine whether or not to update screen
+    pub quirks: Quirks,                 // Active interpreter quirks
+    configured_quirks: Option<Quirks>,  // The quirks before the ROM first changed them with 00FA, what reset goes back to
+    pub lint_registers: bool,           // Track register writes and report reads of registers never written
+    pub extensions: bool,               // Run this emulator's own opcodes, off so real ROMs never see them
+    start_hires: bool,                  // Power on in 128x64, for SUPER-CHIP ROMs that never send 00FF
+    grid_overlay: bool,                 // Pixel grid requested by the 0FFF extension
+    wait_cycles: u32,                   // Cycles spent polling or waiting on input
+    work_cycles: u32,                   // Cycles spent on everything else
+    seed: u64,                          // Seed of the CXNN random generator
+    rng: StdRng,                        // CXNN random generator, seeded so runs can be reproduced
+    rng_draws: u64,                     // Numbers drawn since seeding, lets savestates restore the generator
+    rom_hash: u64,                      // FNV-1a hash of the loaded ROM
+    rom_sha1: [u8; 20],                 // SHA-1 of the loaded ROM, computed once per load
+    rom: Vec<u8>,                       // Loaded ROM image
+    frames: u64,                        // 60hz timer ticks since power on
+    nop_count: u64,                     // 0000 instructions executed
+    last_unknown_opcode: Option<u16>,   // Most recent opcode that didn't decode
+    fault: Option<Fault>,               // What stopped the CPU, nothing runs until a reset or a state load
+    rpl: [u8; 16],                      // SUPER-CHIP RPL user flags, survive resets and are persisted by the frontend
+    rpl_dirty: bool,                    // FX75 wrote the flags since the last take_rpl_dirty
+    vf_clobber: Option<(u16, u16)>,     // Address and opcode of the last op whose vF flag overwrote a vF operand
+    instruction_pc: u16,                // Address of the instruction run last, for what the bus reports about it
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,  // XO-CHIP pattern F002 loaded, None plays the plain buzzer
+    pitch: u8,                          // XO-CHIP FX3A playback pitch of the pattern
+    colors: ColorMap,                   // CHIP-8X background and zone colors
+    pending_collision: Option<u8>,      // Collision flag of the last DXYN, written to vF after the next instruction
+    timer_wait: bool,                   // CHIP-8E FX4F loaded the delay timer and is waiting for it to run out
+    pub port_input: u8,                 // Byte CHIP-8E's FXE3/FXE7 read from the VIP input port, nothing is wired to it
+    key_polls: [Option<u64>; 16],       // Frame each key was last examined by the ROM, dropped after POLL_WINDOW
+    key_observation: Option<KeyObservation>,    // Key check made by the last instruction, for key breakpoints
+    coverage: Coverage,                 // Addresses executed this session, kept across reset
+    written: u16,                       // Registers assigned since power on, bit n = vN, tracked by the lint
+    reported: u16,                      // Registers the lint already reported an uninitialized read of
+    uninit_reads: u64,                  // Reads of unwritten registers counted by the lint
+    uninit_read: Option<UninitRead>,    // First read of each unwritten register, until taken
+    frame_registers: [u8; 16],          // v0-vF as of the last tick_timers, for registers_changed_since_frame
+    #[cfg(feature = "paranoid")]
+    pc_history: PcHistory,              // Recent instruction addresses, for the report of a broken invariant
+}
+
+// Machine state for logs and bug reports. Memory and the display are left out, state_json and the frame
+// dumps cover those
+impl<M: MemoryBus> fmt::Debug for Chip8<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Chip8");
+        debug.field("pc", &format_args!("{:#05X}", self.cpu.pc))
+            .field("i", &format_args!("{:#05X}", self.cpu.index))
+            .field("v", &self.cpu.v)
+            .field("stack", &self.stack())
+            .field("delay_timer", &self.cpu.delay_timer)
+            .field("sound_timer", &self.cpu.sound_timer)
+            .field("frames", &self.frames)
+            .field("rom_sha1", &format_args!("{}", self.rom_sha1_hex()))
+            .field("quirks", &self.quirks);
+        #[cfg(feature = "std")]
+        debug.field("preset", &crate::analysis::preset_name(&self.quirks));
+        debug.finish()
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
     // New Chip8 emulation initialization
     // Initializes values at a default of 0, except for pc which is defined to start at 0x200
     pub fn new() -> Self {
+        Chip8::with_memory(HeatmapBus::new(WriteProtect::report_only(FlatMemory::new())))
+    }
+
+    // Mark memory read-only to the ROM: FX33, FX55 and any other interpreter write into the range is
+    // reported through take_protection_violation. The write still lands unless protection is strict
+    pub fn protect_region(&mut self, range: Range<usize>) {
+        self.cpu.memory.inner_mut().protect(range);
+    }
+
+    // Strict protection refuses protected writes, which then also show up in take_bus_fault
+    pub fn set_strict_protection(&mut self, strict: bool) {
+        self.cpu.memory.inner_mut().set_strict(strict);
+    }
+
+    // Most recent write into a protected region, cleared by the call
+    pub fn take_protection_violation(&mut self) -> Option<ProtectionViolation> {
+        let access = self.cpu.memory.inner_mut().take_violation()?;
+        Some(ProtectionViolation { pc: self.instruction_pc, addr: access.addr, value: access.value })
+    }
+
+    // Start or stop counting accesses per address. Off by default; turning it on starts from zero and
+    // turning it off drops the counts
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.cpu.memory.set_enabled(enabled);
+    }
+
+    // Access counts since set_heatmap(true), None while collection is off
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.cpu.memory.heatmap()
+    }
+}
+
+impl<M: MemoryBus> Chip8<M> {
+    // A machine on the given memory bus, which should come zeroed; the fontset is loaded into it
+    pub fn with_memory(memory: M) -> Self {
+        let seed = initial_seed();
         let mut chip8 = Chip8 {
-            v: [0; 16],
-            index: 0,
-            pc: 0x200,
-            sp: 0,
-            stack: [0; 16],
-            memory: [0; 4096],
-            delay_timer: 0,
-            sound_timer: 0,
+            cpu: Cpu::with_memory(memory),
             opcode: 0,
-            display: [0; WIDTH * HEIGHT],
-            key: [0; 16],
+            display: Display::new(),
+            shown: Display::new(),
+            shown_stale: false,
+            keypad: Keypad::new(),
             draw_flag: false,
+            quirks: Quirks::default(),
+            configured_quirks: None,
+            lint_registers: false,
+            extensions: false,
+            start_hires: false,
+            grid_overlay: false,
+            wait_cycles: 0,
+            work_cycles: 0,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            rng_draws: 0,
+            rom_hash: 0,
+            rom_sha1: [0; 20],
+            rom: Vec::new(),
+            frames: 0,
+            nop_count: 0,
+            last_unknown_opcode: None,
+            fault: None,
+            rpl: [0; 16],
+            rpl_dirty: false,
+            vf_clobber: None,
+            instruction_pc: PROGRAM_START as u16,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            colors: ColorMap::new(),
+            pending_collision: None,
+            timer_wait: false,
+            port_input: 0,
+            key_polls: [None; 16],
+            key_observation: None,
+            coverage: Coverage::new(),
+            written: 0,
+            reported: 0,
+            uninit_reads: 0,
+            uninit_read: None,
+            frame_registers: [0; 16],
+            #[cfg(feature = "paranoid")]
+            pc_history: PcHistory::new(),
         };
         chip8.load_fontset();
+        debug_assert_eq!(chip8.fontset_checksum(), FONTSET_CHECKSUM, "built-in fontset is corrupt or misplaced");
         chip8
     }
 
     // Load full fontset into memory starting at 0x50 as defined
     fn load_fontset(&mut self) {
-        for(i, &byte) in CHIP8_FONTSET.iter().enumerate() {
-            self.memory[0x50 + i] = byte;
+        self.cpu.load(FONT_BASE, &CHIP8_FONTSET);
+    }
+
+    // Checksum of the font region, catches edits to CHIP8_FONTSET or FONT_BASE that break the glyphs
+    pub fn fontset_checksum(&self) -> u32 {
+        self.cpu.memory.bytes()[FONT_BASE..FONT_BASE + FONTSET_SIZE].iter()
+            .fold(0u32, |sum, &byte| sum.wrapping_mul(31).wrapping_add(byte as u32))
+    }
+
+    // Replace the built-in fontset with a custom table of 16 glyphs, 5 bytes each
+    // Call before running so FX29 points at the new glyphs
+    pub fn set_fontset(&mut self, font: &[u8]) -> Result<(), String> {
+        if font.len() != FONTSET_SIZE {
+            return Err(format!("fontset must be {} bytes, got {}", FONTSET_SIZE, font.len()));
         }
+
+        self.cpu.load(FONT_BASE, font);
+        Ok(())
     }
 
-    // Fill memory with program commands
-    pub fn load_rom(&mut self, path: &str) -> Result<(), std::io::Error> {
+    // Fill memory with program commands, returning how many bytes were loaded
+    #[cfg(feature = "std")]
+    pub fn load_rom(&mut self, path: &str) -> Result<usize, Chip8Error> {
         let mut file = File::open(path)?;     // Open File in Binary Mode
         let mut buffer: Vec<u8> = Vec::new();       // Create buffer of bytes   
         file.read_to_end(&mut buffer)?;        // Read file into buffer
 
-        for (i, &byte) in buffer.iter().enumerate() {
-            if i + 512 < self.memory.len() {
-                self.memory[i + 512] = byte;
-            } else {
-                eprintln!("ROM is too large to fit in memory.");
-                break;
-            }
+        #[cfg(feature = "zip")]
+        if crate::zip::is_zip(&buffer) {        // A ROM pack holding a single ROM
+            buffer = crate::zip::extract_rom(&buffer, None).map_err(Chip8Error::Archive)?;
         }
-        Ok(())
+        self.load_rom_bytes(&buffer)
     }
 
-    // Display and Input Setup as well as emulation loop
-    pub fn run(&mut self) -> Result<(), String>{
-        // Video Render
-        let sdl_context = sdl2::init()?;
-        let video_subsystem = sdl_context.video()?;
-
-        let window = video_subsystem.window("Chip8 Emu", (WIDTH * 10) as u32, (HEIGHT * 10) as u32)
-            .position_centered()
-            .build()
-            .expect("could not initialize video subsystem");
-
-        let mut canvas = window.into_canvas().build()
-            .expect("could not make a canvas");
-
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-        let mut event_pump = sdl_context.event_pump()?;
-
-        // Game Loop
-        'running: loop {
-
-            // Event Handler
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit {..} |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                        break 'running;
-                    },
-                    Event::KeyDown { keycode: Some(key), ..} => {
-                        match key {
-                           Keycode::Num1 => self.set_key(1, 1),
-                            _ => (),
-                        }
-                    },
-                    Event::KeyUp { keycode: Some(key), ..} => {
-                        match key {
-                           Keycode::Num1 => self.set_key(1, 0),
-                            _ => (),
-                        }
-                    },
-                    _ => {}
-                }
-            }
-
-            // Proceed to next instruction
-            self.cycle();
-
-            // Redraw screen if it has been updated
-            if self.draw_flag {
-                for y in 0..HEIGHT {
-                    for x in 0..WIDTH {
-                        let idx = x + y * WIDTH;
-                        // Set the color to draw to white
-                        if self.display[idx] == 1 {
-                            canvas.set_draw_color(Color::RGB(255, 255, 255));
-                        }
-                        // Set the color to draw to black = erase pixel
-                        else {
-                            canvas.set_draw_color(Color::RGB(0, 0, 0));
-                        }
-                        canvas.fill_rect(Rect::new((x * 10) as i32, (y * 10) as i32, 10, 10)).unwrap();
-                    }
-                }
+    // Fill memory with program commands from an in-memory ROM image. Returns how many bytes were loaded:
+    // a ROM running past the end of memory is cut off there with a warning, and the count comes up short
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<usize, Chip8Error> {
+        let loaded = rom.len().min(self.rom_capacity());
+        if rom.is_empty() {
+            log::warn!("ROM is empty, execution starts on blank memory at 0x200.");
+        }
+        self.rom_hash = fnv1a(rom);
+        self.rom_sha1 = Sha1::digest(rom).into();
+        self.rom = rom.to_vec();                // Kept so reset() can reload it
 
-                self.draw_flag = false; // Reset the draw flag
-                canvas.present();       // Copy to output display
-            }
+        let digest = self.rom_sha1_hex();
+        log::info!("Loaded ROM: {} bytes, SHA-1 {}", rom.len(), digest);
+        warn_if_bad_dump(KNOWN_BAD_DUMPS, &digest);
 
-            // Sleep for 1/60 of a second, emulate 60 hz clock
-            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-            }
-        Ok(())    
+        if loaded < rom.len() {
+            log::warn!("ROM is too large to fit in memory.");
+        }
+        self.cpu.load(PROGRAM_START, rom);
+        Ok(loaded)
     }
 
-    // 1 step emulation loop
-    pub fn cycle(&mut self) {
-        self.opcode = self.fetch_opcode();  // Fetch
-        self.decode_execute(self.opcode);   // Decode and Execute
-
-        if self.delay_timer > 0 {           // Update delay timer
-            self.delay_timer -= 1;
+    // Strict load_rom_bytes: an empty ROM or one too large to fit is an error instead of a warning, and
+    // nothing of it is loaded
+    pub fn load_rom_bytes_strict(&mut self, rom: &[u8]) -> Result<usize, Chip8Error> {
+        if rom.is_empty() {
+            return Err(Chip8Error::RomEmpty);
         }
-
-        if self.sound_timer > 0 {           // Update sound timer
-            self.sound_timer -= 1;
+        if rom.len() > self.rom_capacity() {
+            return Err(Chip8Error::RomTooLarge { len: rom.len(), capacity: self.rom_capacity() });
         }
+        self.load_rom_bytes(rom)
     }
 
-    // Fetch the opcode from memory at the program counter location
-    fn fetch_opcode(&self) -> u16 {
-        (self.memory[self.pc as usize] as u16) << 8 | (self.memory[self.pc as usize + 1] as u16)
+    // Bytes of memory there are for a ROM, from 0x200 to the end
+    fn rom_capacity(&self) -> usize {
+        self.cpu.memory.len() - PROGRAM_START
     }
 
-    // Decode the opcode and run the associated function
-    fn decode_execute (&mut self, opcode: u16) {
-        match opcode & 0xF000 {
-            0x0000 => match opcode & 0x00FF {
-                0x00E0 => self.cls(),           // Clear Display
-                0x00EE => self.ret(),           // Return from subroutine
-                _ => self.pc += 2,              // Skip unknown code
-            }
-            0x1000 => self.jmp(opcode),         // Jump to address NNN
-            0x2000 => self.jsr(opcode),         // Jump to subroutine NNN
-            0x3000 => self.skeq_c(opcode),      // Skip next instruction if v[x] == NN
-            0x4000 => self.skne_c(opcode),      // Skip next instruction if v[X] != NN
-            0x5000 => self.skeq_r(opcode),      // Skip next instruction if v[X] == v[Y]
-            0x6000 => self.mov_c(opcode),       // Move constant NN to v[X]
-            0x7000 => self.add_c(opcode),       // Add constant NN to v[X]
-            0x8000 => match opcode & 0x000F {
-                0x000 => self.mov_r(opcode),    // Move v[Y] into v[X]
-                0x001 => self.or_r(opcode),     // OR v[Y] with v[X]
-                0x002 => self.and_r(opcode),    // AND v[Y] with v[X]
-                0x003 => self.xor_r(opcode),    // XOR v[Y] with v[X]
-                0x004 => self.add_r(opcode),    // Add v[Y] with v[X]
-                0x005 => self.sub_r(opcode),    // Subtract v[Y] from v[X]
-                0x006 => self.shr_r(opcode),    // Shift v[X] right
-                0x007 => self.rsb_r(opcode),    // Subtract v[X] from v[Y]
-                0x00E => self.shl_r(opcode),    // Shift v[X] left
-                _ => self.pc += 2,              // Skip unknown code
-            }
-            0x9000 => self.skne_r(opcode),      // Skip next instruction if v[X] != v[Y]
-            0xA000 => self.mvi(opcode),         // Move constant NNN to I
-            0xB000 => self.jmi(opcode),         // Jump to address NNN + v[0]
-            0xC000 => self.rand(opcode),        // Set v[X] = rand AND NN
-            0xD000 => self.sprite(opcode),      // Draw sprite at (v[X], v[Y]), height N
-            0xE000 => match opcode & 0x000F {
-                0x000E => self.skpr(opcode),    // Skip next instruction if key rX is pressed
-                0x0001 => self.skup(opcode),    // Skip next instruction if key rX is not pressed
-                _ => self.pc += 2,              // Skip unknown code
-            }
-            0xF000 => match opcode & 0x00FF {
-                0x0007 => self.gdelay(opcode),  // Get delay timer into vX
-                0x000a => self.key(opcode),     // Wait for keypress and store in vX
-                0x0015 => self.sdelay(opcode),  // Set delay timer to vX
-                0x0018 => self.ssound(opcode),  // Set sound timer to vX
-                0x001e => self.adi(opcode),     // Add vX to I
-                0x0029 => self.font(opcode),    // Point I to the sprite for hexadecimal character vX
-                0x0033 => self.bcd(opcode),     // Store bcd of vX at I, I+1, I+2
-                0x0055 => self.str(opcode),     // Store v0 - vX at I incremented each time
-                0x0065 => self.ldr(opcode),     // Load registers v0 - vX from I incremented each time
-                _ => self.pc += 2,              // Skip unknown code
-            }
-            _ => self.pc += 2,                  // Skip unknown code
-        }
+    // Restart the loaded ROM from power on, keeping the seed, font and quirks as configured, without the ROM's
+    // 00FA toggles. The bus is kept too, with its memory zeroed
+    pub fn reset(&mut self) where M: Clone {
+        let mut memory = self.cpu.memory.clone();
+        memory.bytes_mut().fill(0);
+        let mut fresh = Chip8::with_memory(memory);
+        fresh.quirks = self.configured_quirks.unwrap_or(self.quirks);
+        fresh.lint_registers = self.lint_registers;
+        fresh.extensions = self.extensions;
+        fresh.port_input = self.port_input;
+        fresh.set_start_hires(self.start_hires);
+        fresh.shown = self.shown.clone();
+        fresh.shown_stale = self.shown_stale;
+        fresh.set_seed(self.seed);
+        fresh.cpu.load(FONT_BASE, &self.cpu.memory.bytes()[FONT_BASE..FONT_BASE + FONTSET_SIZE]);
+        fresh.cpu.load(PROGRAM_START, &self.rom);        // Already hashed and checked when it was loaded
+        fresh.rom = core::mem::take(&mut self.rom);
+        fresh.rom_hash = self.rom_hash;
+        fresh.rom_sha1 = self.rom_sha1;
+        fresh.rpl = self.rpl;
+        fresh.coverage = core::mem::take(&mut self.coverage);
+        fresh.draw_flag = true;                 // Blank the old screen
+        *self = fresh;
     }
 
-    fn set_key(&mut self, idx: usize, val:u8) {
-        self.key[idx] = val;
+    // Start in the SUPER-CHIP 128x64 resolution, as some interpreters did, instead of waiting for a 00FF.
+    // Applies to the screen right away, so call it before the first cycle, and again on every reset
+    pub fn set_start_hires(&mut self, hires: bool) {
+        self.start_hires = hires;
+        self.display.set_hires(hires);
     }
 
-    /********************************************/
-    /*          Instructions/Opcodes            */
-    /********************************************/
+    // Pixels across and down at the current resolution, 64x32 or 128x64
+    pub fn resolution(&self) -> (usize, usize) {
+        self.display.resolution()
+    }
 
-    // 0x00E0
-    // Clear the display implementation
-    fn cls(&mut self) {
-        for i in 0..self.display.len() {
-            self.display[i] = 0x0;
-        }
+    // Restart the random generator from a known seed so two runs make the same CXNN results
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self.rng_draws = 0;
+    }
 
-        self.draw_flag = true;
-        self.pc += 2;                       // Increment counter
+    pub fn seed(&self) -> u64 {
+        self.seed
     }
 
-    // 0x00EE
-    // Return from subroutine implementation
-    fn ret(&mut self) {
-        self.sp -= 1;                                   // Decrepement stack pointer to get to last call
-        self.pc = self.stack[self.sp as usize] - 2;     // Return to the memory address of the subroutine call
-        self.pc += 4;                                   // Increment counter
+    // Stack pointer, the number of active calls
+    pub fn stack_pointer(&self) -> u16 {
+        self.cpu.sp
     }
 
-    // 1NNN
-    // Jump to address implementation
-    fn jmp(&mut self, opcode: u16) {
-        self.pc = opcode & 0x0FFF;          // Set current memory position to provided address
+    // Calls the stack holds before another 2NNN overflows it
+    pub fn stack_capacity(&self) -> usize {
+        self.cpu.stack.len()
     }
 
-    // 2NNN
-    // Jump to subroutine address NNN
-    fn jsr(&mut self, opcode: u16) {
-        self.stack[self.sp as usize] = self.pc;     // Set current memory position in the stack
-        self.sp += 1;                               // Increment the stack pointer to avoid overwrite
-        self.pc = opcode & 0x0FFF;                  // Set current memory position to provided address
+    // ROM image from the last load_rom
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
     }
 
-    // 3XNN
-    // Skip next instruction if register vX == constant NN
-    fn skeq_c(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let nn = (opcode & 0x00FF) as u8;                  // Extract NN constant
+    // Hash of the ROM bytes from the last load_rom, 0 before any ROM is loaded
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
 
-        if self.v[x] == nn {
-            self.pc += 2;                                      // Increment program counter by 2 = skip next instruction
-        }
-        self.pc += 2;                                          // Increment counter
+    // SHA-1 of the ROM from the last load_rom, all zeros before any ROM is loaded
+    pub fn rom_sha1(&self) -> [u8; 20] {
+        self.rom_sha1
     }
 
-    // 4XNN
-    // Skip next instruction if register vX != constant NN
-    fn skne_c(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let nn = (opcode & 0x00FF) as u8;                  // Extract NN constant
+    // rom_sha1 as lowercase hex, the form the ROM database is keyed by
+    pub fn rom_sha1_hex(&self) -> String {
+        self.rom_sha1.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
 
-        if self.v[x] != nn {
-            self.pc += 2;                                      // Increment program counter by 2 = skip next instruction
+    // Machine state for savestates, load_state restores it into a core with the same ROM loaded
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.state_size());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&self.cpu.v);
+        out.extend_from_slice(&self.cpu.index.to_le_bytes());
+        out.extend_from_slice(&self.cpu.pc.to_le_bytes());
+        out.extend_from_slice(&self.cpu.sp.to_le_bytes());
+        for addr in &self.cpu.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
         }
-        self.pc += 2;                                          // Increment counter
+        out.extend_from_slice(self.cpu.memory.bytes());
+        out.push(self.cpu.delay_timer);
+        out.push(self.cpu.sound_timer);
+        out.push(self.display.hires() as u8);
+        out.extend_from_slice(&self.display);
+        out.resize(out.len() + HIRES_WIDTH * HIRES_HEIGHT - self.display.len(), 0);
+        out.extend_from_slice(&self.keypad);
+        out.push(self.quirks.clip_sprites as u8);
+        out.push(self.quirks.load_store_increment as u8);
+        out.push(self.quirks.shift_vy as u8);
+        out.push(self.quirks.index_width);
+        out.push(self.quirks.adi_overflow_vf as u8);
+        out.push(self.quirks.adi_overflow_width);
+        out.push(self.quirks.variant as u8);
+        out.push(self.quirks.collision_delay as u8);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rng_draws.to_le_bytes());
+        out.extend_from_slice(&self.frames.to_le_bytes());
+        out.push(self.audio_pattern.is_some() as u8);
+        out.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        out.push(self.pitch);
+        out.extend_from_slice(&self.colors.to_bytes());
+        out.push(self.pending_collision.map_or(0, |flag| flag + 1));
+        out.push(self.timer_wait as u8);
+        out
     }
 
-    // 0x5XY0
-    // Skip next instruction if register vX == register vY
-    fn skeq_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() != self.state_size() {
+            return Err(format!("savestate is {} bytes, expected {}", state.len(), self.state_size()));
+        }
+        let mut pos = 0;
+        let mut take = |n: usize| {
+            pos += n;
+            &state[pos - n..pos]
+        };
+        let u16_at = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]);
+        let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
 
-        if self.v[x] == self.v[y] {
-            self.pc += 2;                                      // Increment program counter by 2 = skip next instruction
+        if u64_at(take(8)) != self.rom_hash {
+            return Err("savestate belongs to a different ROM".to_string());
+        }
+        // Check the fields the core can't run with every value of before anything is overwritten. pc and sp
+        // follow v0-vF and I, the quirks follow the stack, memory, the timers, the resolution, the display and
+        // the keys
+        let memory_size = self.cpu.memory.len();
+        let pc = u16_at(&state[8 + 16 + 2..]);
+        if pc as usize >= memory_size {
+            return Err(format!("savestate has pc at {:#05X}, outside the {} bytes of memory", pc, memory_size));
+        }
+        let sp = u16_at(&state[8 + 16 + 4..]);
+        if sp as usize > self.cpu.stack.len() {
+            return Err(format!("savestate has a stack pointer of {}, past the {} slot stack", sp, self.cpu.stack.len()));
+        }
+        let quirks_at = 8 + 16 + 6 + 32 + memory_size + 2 + 1 + HIRES_WIDTH * HIRES_HEIGHT + 16;
+        let index_width = state[quirks_at + 3];
+        if !matches!(index_width, 12 | 16) {
+            return Err(format!("savestate has an I width of {} bits, expected 12 or 16", index_width));
         }
-        self.pc += 2;                                          // Increment counter
+        let adi_overflow_width = state[quirks_at + 5];
+        if !matches!(adi_overflow_width, 12 | 16) {
+            return Err(format!("savestate has an FX1E overflow width of {} bits, expected 12 or 16", adi_overflow_width));
+        }
+        let variant = Variant::from_byte(state[quirks_at + 6])
+            .ok_or_else(|| format!("savestate has an unknown instruction set {}", state[quirks_at + 6]))?;
+        self.fault = None;
+        self.cpu.v.copy_from_slice(take(16));
+        self.cpu.index = u16_at(take(2));
+        self.cpu.pc = u16_at(take(2));
+        self.cpu.sp = u16_at(take(2));
+        for addr in self.cpu.stack.iter_mut() {
+            *addr = u16_at(take(2));
+        }
+        self.cpu.memory.bytes_mut().copy_from_slice(take(memory_size));
+        self.cpu.delay_timer = take(1)[0];
+        self.cpu.sound_timer = take(1)[0];
+        self.display.set_hires(take(1)[0] != 0);
+        let pixels = take(HIRES_WIDTH * HIRES_HEIGHT);
+        let len = self.display.len();
+        self.display.copy_from_slice(&pixels[..len]);
+        self.keypad.copy_from_slice(take(16));
+        self.quirks.clip_sprites = take(1)[0] != 0;
+        self.quirks.load_store_increment = take(1)[0] != 0;
+        self.quirks.shift_vy = take(1)[0] != 0;
+        self.quirks.index_width = take(1)[0];
+        self.quirks.adi_overflow_vf = take(1)[0] != 0;
+        self.quirks.adi_overflow_width = take(1)[0];
+        self.quirks.variant = variant;
+        take(1);
+        self.quirks.collision_delay = take(1)[0] != 0;
+
+        // The generator can't be serialized, so replay its draws from the seed
+        self.set_seed(u64_at(take(8)));
+        for _ in 0..u64_at(take(8)) {
+            self.rng.gen::<u8>();
+            self.rng_draws += 1;
+        }
+        self.frames = u64_at(take(8));
+        let loaded = take(1)[0] != 0;
+        let pattern = take(PATTERN_SIZE).try_into().unwrap();
+        self.audio_pattern = loaded.then_some(pattern);
+        self.pitch = take(1)[0];
+        self.colors = ColorMap::from_bytes(take(COLOR_STATE_SIZE).try_into().unwrap());
+        self.pending_collision = take(1)[0].checked_sub(1).map(|flag| flag & 1);
+        self.timer_wait = take(1)[0] != 0;
+        self.written = u16::MAX;                // Which registers were written before the save is unknown
+        self.frame_registers = self.cpu.v;
+        self.draw_flag = true;
+        Ok(())
     }
 
-    // 0x6XNN
-    // Move constant NN to register vX
-    fn mov_c(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
+    fn state_size(&self) -> usize {
+        STATE_FIXED_SIZE + self.cpu.memory.len()
+    }
 
-        self.v[x] = nn;                                         // set vX = NN
-        self.pc += 2;                                           // Increment counter
+    // The processor on its own, for tools that work on CPU state without the display or keypad
+    pub fn cpu(&self) -> &Cpu<M> {
+        &self.cpu
     }
 
-    // 0x7XNN
-    // Add constant NN to register vX, no carry generated
-    fn add_c(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
+    // The memory bus, for reading back what decorators such as Watchpoints collected
+    pub fn memory(&self) -> &M {
+        &self.cpu.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.cpu.memory
+    }
+
+    // Most recent access the bus refused, cleared by the call. The interpreter reads 0 in place of a
+    // refused read and drops a refused write
+    pub fn take_bus_fault(&mut self) -> Option<String> {
+        self.cpu.bus_fault.take()
+    }
 
-        self.v[x] = self.v[x].wrapping_add(nn);                 // Add NN to vX
-        self.pc += 2;                                           // Increment counter
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
     }
 
-    // 8XY0
-    // Move register vY into register vX
-    fn mov_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+    // Register, memory and display access for tooling such as scripts and debuggers
+    pub fn register(&self, x: usize) -> u8 {
+        self.cpu.v[x & 0xF]
+    }
 
-        self.v[x] = self.v[y];                                  // Set vX = vY
-        self.pc += 2;                                           // Increment counter
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.cpu.v[x & 0xF] = value;
+        self.written |= 1 << (x & 0xF);
     }
 
-    // 8XY1
-    // OR register vY with register vX, store in vX
-    fn or_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+    pub fn index(&self) -> u16 {
+        self.cpu.index
+    }
 
-        self.v[x] = self.v[x] | self.v[y];                     // OR registers
-        self.pc += 2;                                          // Increment counter
+    // Set I, kept within the configured index width like the instructions that change it
+    pub fn set_index(&mut self, value: u16) {
+        self.cpu.index = value;
+        self.cpu.mask_index(&self.quirks);
     }
 
-    // 8XY2
-    // AND register vY with register vX, store in vX
-    fn and_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
 
-        self.v[x] = self.v[x] & self.v[y];                     // AND registers
-        self.pc += 2;                                          // Increment counter
+    // Continue execution at addr, Err when it's outside memory
+    pub fn set_pc(&mut self, addr: u16) -> Result<(), String> {
+        if addr as usize >= self.cpu.memory.len() {
+            return Err(format!("address {:#05X} is outside memory", addr));
+        }
+        self.cpu.pc = addr;
+        Ok(())
     }
 
-    // 8XY3
-    // XOR register vY with register vX, store in vX
-    fn xor_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+    // Call site addresses of the active calls, innermost last, capped to the stack size if sp ran past it
+    pub fn stack(&self) -> &[u16] {
+        &self.cpu.stack[..(self.cpu.sp as usize).min(self.cpu.stack.len())]
+    }
 
-        self.v[x] = self.v[x] ^ self.v[y];                     // XOR registers
-        self.pc += 2;                                          // Increment counter
+    // Registers, timers, ROM and quirks as one JSON object, enough for someone else to set up the same
+    // machine from a bug report
+    #[cfg(feature = "std")]
+    pub fn state_json(&self) -> serde_json::Value {
+        let quirks = &self.quirks;
+        serde_json::json!({
+            "rom_sha1": self.rom_sha1_hex(),
+            "pc": self.cpu.pc,
+            "i": self.cpu.index,
+            "v": self.cpu.v,
+            "sp": self.cpu.sp,
+            "stack": self.stack(),
+            "delay_timer": self.cpu.delay_timer,
+            "sound_timer": self.cpu.sound_timer,
+            "frames": self.frames,
+            "seed": format!("{:016x}", self.seed),
+            "preset": crate::analysis::preset_name(quirks),
+            "quirks": {
+                "clip_sprites": quirks.clip_sprites,
+                "load_store_increment": quirks.load_store_increment,
+                "shift_vy": quirks.shift_vy,
+                "index_width": quirks.index_width,
+                "adi_overflow_vf": quirks.adi_overflow_vf,
+                "adi_overflow_width": quirks.adi_overflow_width,
+                "variant": quirks.variant.name(),
+                "collision_delay": quirks.collision_delay,
+            },
+        })
     }
 
-    // 8XY4
-    // Add register vY with register vX, store in vX, carry in register vF
-    fn add_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+    // The active calls as frames, outermost first
+    pub fn call_stack(&self) -> Vec<CallFrame> {
+        self.stack().iter().map(|&call_site| CallFrame {
+            call_site,
+            return_address: call_site.wrapping_add(2),
+            opcode: self.peek(call_site as usize)
+                .zip(self.peek(call_site as usize + 1))
+                .map(|(hi, lo)| (hi as u16) << 8 | lo as u16),
+        }).collect()
+    }
 
-        let (result, carry) = self.v[x].overflowing_add(self.v[y]);
-        self.v[x] = result;
-        self.v[0xF] = carry as u8;
+    pub fn delay_timer(&self) -> u8 {
+        self.cpu.delay_timer
+    }
 
-        self.pc += 2;                                           // Increment counter
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.sound_timer
     }
 
-    // 8XY5
-    // Sub register vY from register vX, vF set to 1 if borrows
-    fn sub_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
-        let vx = self.v[x] as usize;                    // Extract X register
-        let vy = self.v[y] as usize;                    // Extract Y register
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.cpu.delay_timer = value;
+    }
 
-        self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.cpu.sound_timer = value;
+    }
 
-        if vx >= vy {
-            self.v[0xF] = 1; // No borrow needed
-        } else {
-            self.v[0xF] = 0; // Borrow occurred
+    // Push a call made from call_site, so 00EE returns to the instruction after it; Err when the stack is full
+    pub fn push_call(&mut self, call_site: u16) -> Result<(), String> {
+        match self.cpu.stack.get_mut(self.cpu.sp as usize) {
+            Some(slot) => {
+                *slot = call_site;
+                self.cpu.sp += 1;
+                Ok(())
+            }
+            None => Err("stack is full".to_string()),
         }
-    
+    }
 
-        self.pc += 2;                                           // Increment counter
+    // Drop the innermost call and return its call site, Err when no call is active
+    pub fn pop_call(&mut self) -> Result<u16, String> {
+        if self.cpu.sp == 0 {
+            return Err("stack is empty".to_string());
+        }
+        self.cpu.sp = self.cpu.sp.min(self.cpu.stack.len() as u16) - 1;
+        Ok(self.cpu.stack[self.cpu.sp as usize])
     }
 
-    // 8X06
-    // Shift register vX right, bit 0 goes into register vF
-    fn shr_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let lsb = self.v[x] & 0x1;
+    // Read a memory byte, None when the address is outside memory. Tooling reads skip the bus
+    pub fn peek(&self, addr: usize) -> Option<u8> {
+        self.cpu.peek(addr)
+    }
 
-        self.v[x] >>= 1;                                        // Right shift register vX
-        self.v[0xF] = lsb;                                      // Store LSB in Flag register
-        self.pc += 2;                                           // Increment counter
+    // The height bytes at I that DXYN would draw, cut short at the end of memory
+    pub fn sprite_at_index(&self, height: usize) -> Vec<u8> {
+        let memory = self.cpu.memory.bytes();
+        let start = (self.cpu.index as usize).min(memory.len());
+        let end = (start + height).min(memory.len());
+        memory[start..end].to_vec()
     }
 
-    // 8XY7
-    // Sub register vX from register vY, store in vX, vF set to 1 if borrows
-    fn rsb_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
-        let vx = self.v[x] as usize;                    // Extract X register
-        let vy = self.v[y] as usize;                    // Extract Y register
+    // The sprite a preview shows at addr, see display::sprite_bitmap
+    pub fn sprite_bitmap(&self, addr: u16, width: usize, height: usize) -> Vec<Vec<bool>> {
+        display::sprite_bitmap(self.cpu.memory.bytes(), addr as usize, width, height)
+    }
 
-        self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+    // Width and height of the sprite the next instruction draws, or the default preview without a draw
+    pub fn next_sprite_shape(&self) -> (usize, usize) {
+        display::sprite_shape(self.opcode_at_pc())
+    }
 
-        if vy >= vx {
-            self.v[0xF] = 1; // No borrow needed
-        } else {
-            self.v[0xF] = 0; // Borrow occurred
+    // Write a memory byte, Err when the address is outside memory. Like peek this skips the bus, so
+    // pokes land even in write protected memory
+    pub fn poke(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        match self.cpu.memory.bytes_mut().get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(format!("address {:#05X} is outside memory", addr)),
         }
-        
-        self.pc += 2;                                           // Increment counter
     }
 
-    // 8X0E
-    // Shift register vX left, bit 7 goes into register vF
-    fn shl_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let msb = (self.v[x] & 0x80) >> 7;
+    // Cheat search: every memory address currently holding value
+    pub fn find_byte(&self, value: u8) -> Vec<usize> {
+        self.cpu.memory.bytes().iter()
+            .enumerate()
+            .filter(|&(_, &byte)| byte == value)
+            .map(|(addr, _)| addr)
+            .collect()
+    }
 
-        self.v[x] <<= 1;                                        // Right shift register vX
-        self.v[0xF] = msb;                                      // Store LSB in Flag register
-        self.pc += 2;                                           // Increment counter
+    // Cheat search: keep only the candidates from an earlier search that now hold value
+    pub fn narrow(&self, candidates: &[usize], value: u8) -> Vec<usize> {
+        candidates.iter()
+            .copied()
+            .filter(|&addr| self.peek(addr) == Some(value))
+            .collect()
     }
 
-    // 9XY0
-    // Skip next instruction if register vX != register vY
-    fn skne_r(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+    // Whether the pixel at (x, y) is lit, coordinates wrap like sprite drawing
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.display.pixel(x, y)
+    }
 
-        if self.v[x] != self.v[y] {
-            self.pc += 2;                                      // Increment program counter by 2 = skip next instruction
-        }
-        self.pc += 2;                                          // Increment counter
+    // Light or clear one pixel directly, so tests can start from a known screen
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.display.set_pixel(x, y, on);
+        self.draw_flag = true;
     }
 
-    // ANNN
-    // Load index register I with constant NNN
-    fn mvi(&mut self, opcode: u16) {
-        let nnn = (opcode & 0x0FFF) as u16;    // Extract NNN constant
+    // The display packed 8 pixels per byte, MSB leftmost, width / 8 bytes per row
+    pub fn packed_rows(&self) -> Vec<u8> {
+        self.display.packed_rows()
+    }
 
-        self.index = nnn;                           // Set index register to constant
-        self.pc += 2;
+    // The display at one bit per pixel in the given layout, Err unless out is layout.buffer_len() bytes
+    pub fn pack_1bpp(&self, layout: PackLayout, out: &mut [u8]) -> Result<(), Chip8Error> {
+        self.display.pack_1bpp(layout, out)
     }
 
-    // BNNN
-    // Jump to address NNN + register v0
-    fn jmi(&mut self, opcode: u16) {
-        let nnn = (opcode & 0x0FFF) as u8;      // Extract NNN constant
+    // Coordinates of every lit pixel
+    pub fn lit_pixels(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.display.lit_pixels()
+    }
 
-        self.pc = (nnn + self.v[0]) as u16;         // Point program counter to new address
+    // Pixels that changed since the last call as (x, y, lit), for frontends that only update what moved.
+    // Changes are found by comparing against the picture last handed out, so clears, resets and loaded
+    // states all show up; any the caller doesn't iterate over are reported next time. After a switch of
+    // resolution every pixel of the new size is reported, dark ones included, until a call is iterated
+    // to the end, so the caller repaints the whole screen without clearing it first
+    pub fn take_changes(&mut self) -> impl Iterator<Item = (u8, u8, bool)> + '_ {
+        if self.shown.hires() != self.display.hires() {
+            self.shown.set_hires(self.display.hires());
+            self.shown_stale = true;
+        }
+        let width = self.display.width();
+        let pixels: &[u8] = &self.display;
+        let stale = &mut self.shown_stale;
+        let all = *stale;
+        self.shown.iter_mut()
+            .zip(pixels)
+            .enumerate()
+            .filter(move |(_, (shown, &pixel))| all || **shown != pixel)
+            .map(move |(idx, (shown, &pixel))| {
+                *shown = pixel;
+                ((idx % width) as u8, (idx / width) as u8, pixel != 0)
+            })
+            .chain(core::iter::from_fn(move || {
+                *stale = false;
+                None
+            }))
     }
 
-    // CXNN
-    // Set register vX to a random number AND NN
-    fn rand(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
-        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
-        let mut rng = rand::thread_rng();            // Create random generator
+    // CHIP-8X colors: the background and the foreground of every zone. Left at power on outside CHIP-8X
+    pub fn colors(&self) -> &ColorMap {
+        &self.colors
+    }
 
-        self.v[x] = rng.gen::<u8>() & nn;                       // Set X register to random number AND nn
+    // The display in CHIP-8X color: lit pixels in the foreground palette entry of their zone, dark ones in
+    // the background palette entry of the background register
+    pub fn render_chip8x_rgba(&self, foreground: &[[u8; 4]], background: &[[u8; 4]], scale: usize, out: &mut Vec<u8>) {
+        let resolution = self.resolution();
+        let width = resolution.0;
+        let indices: Vec<u8> = self.display.iter().enumerate()
+            .map(|(idx, &pixel)| match pixel {
+                0 => 0,
+                _ => 1 + self.colors.foreground_at(idx % width, idx / width, resolution),
+            })
+            .collect();
+        let bg = background.get(self.colors.background() as usize).or(background.first()).copied().unwrap_or(crate::render::BLACK);
+        let palette: Vec<[u8; 4]> = core::iter::once(bg).chain(foreground.iter().copied()).collect();
+        crate::render::render_indexed_rgba(&indices, width, &palette, scale, out);
     }
 
-    // DXYN
-    // Draw a sprite at screen location (vX, vY) height N
-    fn sprite(&mut self, opcode: u16) {
-        let vx = self.v[((opcode & 0x0F00) >> 8) as usize] as usize; // Extract X register
-        let vy = self.v[((opcode & 0x00F0) >> 4) as usize] as usize; // Extract Y register
-        let height: usize = (opcode & 0x000F) as usize;                     // Extract height
+    // The display as RGBA at scale x scale per pixel, into out with its allocation reused
+    pub fn render_rgba(&self, fg: [u8; 4], bg: [u8; 4], scale: usize, out: &mut Vec<u8>) {
+        crate::render::render_rgba(&self.display, self.display.width(), fg, bg, scale, out);
+    }
 
-        self.v[0xF] = 0;                                                    // Reset flag register
+    // 1 step emulation loop, nothing happens while the CPU is faulted
+    pub fn cycle(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+        self.coverage.mark(self.cpu.pc);
+        self.instruction_pc = self.cpu.pc;
+        self.opcode = match self.cpu.fetch() {  // Fetch
+            Ok(opcode) => opcode,
+            Err(fault) => return self.stop(fault),
+        };
+        let linted = self.lint_registers.then(|| self.lint_reads(self.opcode));
+        #[cfg(feature = "paranoid")]
+        self.pc_history.push(self.instruction_pc);
+        self.execute_delaying_collision(self.opcode);   // Decode and Execute
+        #[cfg(feature = "paranoid")]
+        self.check_invariants();
+        if let Some(instruction) = linted {
+            self.written |= instruction.writes(&self.quirks);
+        }
 
-        // Loop through line by line and update display map
-        for yline in 0..height {
-            let pixel = self.memory[self.index as usize + yline];
-            for xline in 0..8 {
-                if (pixel & (0x80 >> xline)) != 0 {
-                    let x_pos = (vx + xline) % 64;
-                    let y_pos = (vy + yline) % 32;
-                    let idx = x_pos + (y_pos * 64);
-                    if self.display[idx] == 1 {
-                        self.v[0xF] = 1;
-                    }
-                    self.display[idx] ^= 1;
-                }
+        if Self::is_input_wait(self.opcode) {
+            self.wait_cycles += 1;
+        } else {
+            self.work_cycles += 1;
+        }
+    }
+
+    // cycle, returning what kind of instruction ran
+    pub fn cycle_classified(&mut self) -> Category {
+        self.cycle();
+        Instruction::decode(self.opcode).category()
+    }
+
+    // Step through a reference trace of (pc, opcode) pairs, one per instruction, and return the index of
+    // the first step where this machine is about to run something else. The mismatching instruction is
+    // not run, so the state at the divergence can be inspected. Timers are not ticked
+    pub fn compare_trace(&mut self, reference: &[(u16, u16)]) -> Option<usize> {
+        for (step, &expected) in reference.iter().enumerate() {
+            if (self.cpu.pc, self.opcode_at_pc()) != expected {
+                return Some(step);
             }
+            self.cycle();
         }
+        None
+    }
 
-        self.draw_flag = true;                                  // Update screen needs redrawing
-        self.pc += 2;
+    // Run one opcode as if it had just been fetched, without reading memory. pc still advances (or jumps)
+    // as the opcode says, but coverage, the register lint and the cycle counts are left alone
+    pub fn execute_opcode(&mut self, opcode: u16) {
+        self.instruction_pc = self.cpu.pc;
+        self.opcode = opcode;
+        self.execute_delaying_collision(opcode);
+        #[cfg(feature = "paranoid")]
+        self.check_invariants();
     }
 
-    // EX9E
-    // Skip if key rX is pressed
-    fn skpr(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // Panic on the first instruction that leaves the machine in a state no instruction should, naming it and
+    // the addresses run before it, instead of letting the damage surface frames later
+    #[cfg(feature = "paranoid")]
+    fn check_invariants(&self) {
+        let broken = paranoid::violations(&paranoid::Snapshot {
+            pc: self.cpu.pc,
+            sp: self.cpu.sp,
+            stack_capacity: self.cpu.stack.len(),
+            index: self.cpu.index,
+            memory_len: self.cpu.memory.len(),
+            bus_fault: self.cpu.bus_fault.is_some(),
+            quirks: &self.quirks,
+            resolution: self.display.resolution(),
+            pixels: &self.display,
+            spare_pixels: self.display.spare_pixels(),
+        });
+        if !broken.is_empty() {
+            panic!("{:04X} at {:#05X} broke an invariant: {}. Recent PCs: {}",
+                self.opcode, self.instruction_pc, broken.join(", "), self.pc_history);
+        }
+    }
+
+    // Update timers, called once per 60hz frame independent of instruction speed
+    pub fn tick_timers(&mut self) {
+        self.frames += 1;
 
-        if (self.key[self.v[x] as usize]) != 0 {
-            self.pc += 2;                                       // Skip next instruction
+        for poll in self.key_polls.iter_mut() {     // Forget polls that fell out of the window
+            if poll.is_some_and(|frame| self.frames - frame > POLL_WINDOW) {
+                *poll = None;
+            }
         }
 
-        self.pc += 2;
+        self.cpu.tick_timers();
+        self.frame_registers = self.cpu.v;
     }
 
-    // EXA1
-    // Skip if key rX is not pressed
-    fn skup(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // Registers whose value differs from the start of the frame, bit n = vN, for a debugger to highlight.
+    // A register changed and changed back in the same frame doesn't count
+    pub fn registers_changed_since_frame(&self) -> u16 {
+        (0..16).filter(|&x| self.cpu.v[x] != self.frame_registers[x]).fold(0, |mask, x| mask | 1 << x)
+    }
 
-        if (self.key[self.v[x] as usize]) == 0 {
-            self.pc += 2;                                       // Skip next instruction
+    // Advance exactly one frame: cycles instructions (the IPS budget of one frame), then one timer tick
+    pub fn step_frame(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.cycle();
         }
+        self.tick_timers();
+    }
 
-        self.pc += 2;
+    // Number of 60hz frames emulated since power on or the last reset
+    pub fn frame_count(&self) -> u64 {
+        self.frames
     }
 
-    // FX07
-    // Get delay timer into vX
-    fn gdelay(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // The buzzer sounds while the sound timer is non-zero
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
 
-        self.v[x] = self.delay_timer;                           // Load register X with delay timer
-        self.pc += 2;
+    // Whether the ROM asked for the pixel grid overlay with the 0FFF extension
+    pub fn grid_overlay(&self) -> bool {
+        self.grid_overlay
     }
 
-    // FX0A
-    // Wait for keypress, put key in register vX
-    fn key(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // The instruction pc points at, 0 for bytes outside memory
+    pub fn next_opcode(&self) -> u16 {
+        self.opcode_at_pc()
+    }
 
-        for(idx, &key_state) in self.key.iter().enumerate() {
-            if key_state != 0 {
-                self.v[x] = idx as u8;
-                self.pc += 2;
-                return;
-            }
-        }
+    // Whether the next instruction to execute is a DXYN sprite draw
+    pub fn next_is_draw(&self) -> bool {
+        self.opcode_at_pc() & 0xF000 == 0xD000
     }
 
-    // FX15
-    // Set the delay timer to vX
-    fn sdelay(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // Instruction addresses executed so far, for coverage files and trace guided disassembly
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
 
-        self.v[x] = self.sound_timer;                           // Load register X with sound timer
-        self.pc += 2;
+    // Number of 0000 padding instructions executed, counted apart from unknown opcodes
+    pub fn nop_count(&self) -> u64 {
+        self.nop_count
     }
 
-    // FX18
-    // Set the sound timer to vX
-    fn ssound(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // Most recent opcode that didn't decode to any instruction
+    pub fn last_unknown_opcode(&self) -> Option<u16> {
+        self.last_unknown_opcode
+    }
 
-        self.sound_timer = self.v[x];                           // Load register X with sound timer
-        self.pc += 2;
+    // RPL user flags as written by FX75
+    pub fn rpl(&self) -> [u8; 16] {
+        self.rpl
     }
 
-    // FX1E
-    // Add register vX to the index register I
-    fn adi(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // Restore flags persisted from an earlier run
+    pub fn set_rpl(&mut self, flags: [u8; 16]) {
+        self.rpl = flags;
+    }
 
-        self.index += self.v[x] as u16;                         // Add vX to index
-        self.pc += 2;
+    // The XO-CHIP audio pattern the buzzer plays, None until a ROM loads one with F002
+    pub fn audio_pattern(&self) -> Option<[u8; PATTERN_SIZE]> {
+        self.audio_pattern
     }
 
-    // FX29
-    // Point I to the sprite for hexadecimal character in vX
-    fn font(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+    // XO-CHIP pitch register, 64 plays the pattern at 4000 bits a second and every 48 doubles that
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
 
-        self.index = (0x50 + (self.v[x] * 5)) as u16;
-        self.pc += 2;
+    // Whether FX75 changed the flags since the last call, so they can be written back
+    pub fn take_rpl_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.rpl_dirty)
     }
 
-    // FX33
-    // Store the bcd representation of register vX at location I, I+1, I+2
-    fn bcd(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
-        
-        self.memory[self.index as usize] = self.v[x] / 100;             // Get hundreds location
-        self.memory[self.index as usize + 1] = (self.v[x] / 10) % 10;   // Get tens location
-        self.memory[self.index as usize + 2] = (self.v[x] % 100) % 10;  // Get ones location
+    // Most recent (address, opcode) that used vF as an operand and then overwrote it with the flag,
+    // cleared by the call so each one is reported once
+    pub fn take_vf_clobber(&mut self) -> Option<(u16, u16)> {
+        self.vf_clobber.take()
+    }
 
-        self.pc += 2;
+    // Reads of never written registers seen while lint_registers was on
+    pub fn uninit_reads(&self) -> u64 {
+        self.uninit_reads
     }
 
-    // FX55
-    // Store registers v0-vX at location I onwards, incrementing I to the next location each time
-    fn str(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+    // First uninitialized read of a register not reported before, cleared by the call
+    pub fn take_uninit_read(&mut self) -> Option<UninitRead> {
+        self.uninit_read.take()
+    }
+
+    // Whether the ROM has stopped in a jump to itself, the usual way CHIP-8 programs end, on a
+    // SUPER-CHIP 00FD exit or on a CPU fault
+    pub fn halted(&self) -> bool {
+        let opcode = self.opcode_at_pc();
+        self.fault.is_some() || opcode == 0x1000 | self.cpu.pc || opcode == 0x00FD
+    }
+
+    // The fault that stopped the CPU, pc is left on the instruction that raised it
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    // Input polling opcodes: EX9E, EXA1 and FX0A
+    fn is_input_wait(opcode: u16) -> bool {
+        matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1 | 0xF00A)
+    }
+
+    // Return (wait, work) cycle counts since the last call and reset them
+    pub fn take_cycle_counts(&mut self) -> (u32, u32) {
+        let counts = (self.wait_cycles, self.work_cycles);
+        self.wait_cycles = 0;
+        self.work_cycles = 0;
+        counts
+    }
+
+    // The opcode at the program counter without going over the bus, 0 for bytes outside memory
+    fn opcode_at_pc(&self) -> u16 {
+        let byte = |addr: u16| self.peek(addr as usize).unwrap_or(0) as u16;
+        byte(self.cpu.pc) << 8 | byte(self.cpu.pc.wrapping_add(1))
+    }
+
+    // decode_execute, then the collision flag a DXYN before it held back under the collision_delay quirk. A
+    // write of vF by this instruction is overwritten, as the late flag lands after it
+    fn execute_delaying_collision(&mut self, opcode: u16) {
+        let collision = self.pending_collision.take();
+        self.decode_execute(opcode);
+        if let Some(flag) = collision {
+            self.cpu.v[0xF] = flag;
+        }
+    }
 
-        for i in 0..=x {
-            self.memory[self.index as usize + i] = self.v[i];
+    // Decode the opcode and run the associated function: display, input, random and RPL instructions
+    // here, everything else on the CPU
+    fn decode_execute (&mut self, opcode: u16) {
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x0000 => return self.nop(),    // Zero padding
+                0x00E0 => return self.cls(),    // Clear Display
+                0x00C0..=0x00CF => return self.scroll_down(opcode), // Scroll down N rows (SUPER-CHIP)
+                0x00FB => return self.scroll_right(),   // Scroll right 4 columns (SUPER-CHIP)
+                0x00FC => return self.scroll_left(),    // Scroll left 4 columns (SUPER-CHIP)
+                0x00FA => return self.compat(), // Toggle FX55/FX65 index increment (interpreter extension)
+                0x00FD => return self.exit(),   // Exit the interpreter (SUPER-CHIP)
+                0x00ED if self.quirks.variant == Variant::Chip8E => return self.exit(), // Stop (CHIP-8E)
+                0x00FE => return self.lores(),  // 64x32 low resolution (SUPER-CHIP)
+                0x00FF => return self.hires(),  // 128x64 high resolution (SUPER-CHIP)
+                0x0FFF if self.extensions => return self.toggle_grid(), // Toggle the debug grid (extension)
+                0x02A0 if self.quirks.variant == Variant::Chip8X => return self.step_background(),  // Next background color (CHIP-8X)
+                _ => {}
+            }
+            0xB000 if self.quirks.variant == Variant::Chip8X => return self.zone_color(opcode), // Color zones (CHIP-8X)
+            0x8000 => match opcode & 0x000F {
+                0x004 | 0x005 | 0x007 => self.check_vf_clobber(opcode, true),
+                0x006 | 0x00E => self.check_vf_clobber(opcode, self.quirks.shift_vy),
+                _ => {}
+            }
+            0xC000 => return self.rand(opcode), // Set v[X] = rand AND NN
+            0xD000 => return self.sprite(opcode),   // Draw sprite at (v[X], v[Y]), height N
+            0xE000 => match opcode & 0x000F {
+                0x000E => return self.skpr(opcode), // Skip next instruction if key rX is pressed
+                0x0001 => return self.skup(opcode), // Skip next instruction if key rX is not pressed
+                _ => {}
+            }
+            0xF000 => match opcode & 0x00FF {
+                0x000a => return self.key(opcode),  // Wait for keypress and store in vX
+                0x0075 => return self.srpl(opcode), // Store v0 - vX in the RPL user flags
+                0x0085 => return self.lrpl(opcode), // Load v0 - vX from the RPL user flags
+                0x0002 if opcode == 0xF002 => return self.audio(),  // Load the audio pattern from I (XO-CHIP)
+                0x003A => return self.set_pitch(opcode),    // Set the audio pitch to vX (XO-CHIP)
+                0x0003 if self.quirks.variant == Variant::Chip8E => return self.port_out(opcode),   // Output vX to port 3 (CHIP-8E)
+                0x004F if self.quirks.variant == Variant::Chip8E => return self.delay_wait(opcode), // Wait vX timer ticks (CHIP-8E)
+                0x00E3 | 0x00E7 if self.quirks.variant == Variant::Chip8E => return self.port_in(opcode),   // Read port 3 into vX (CHIP-8E)
+                _ => {}
+            }
+            _ => {}
+        }
+        match self.cpu.execute(opcode, &self.quirks) {
+            Ok(true) => {}
+            Ok(false) => self.unknown(opcode),  // Skip unknown code
+            Err(fault) => self.stop(fault),
         }
+    }
 
-        self.pc += 2;
+    fn stop(&mut self, fault: Fault) {
+        log::error!("CPU {} at {:#05X}, the machine has stopped.", fault, self.cpu.pc);
+        self.fault = Some(fault);
     }
 
-    // FX65
-    // Load registers v0 to vX from location I onwards, incrementing I to the next location each time
-    fn ldr(&mut self, opcode: u16) {
-        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+    // Keys held down, bit N = key N
+    pub fn keys_mask(&self) -> u16 {
+        self.keypad.mask()
+    }
+
+    // Keys the ROM examined within the last POLL_WINDOW frames, bit N = key N
+    pub fn polled_keys(&self) -> u16 {
+        self.key_polls.iter().enumerate().fold(0, |mask, (idx, poll)| mask | (poll.is_some() as u16) << idx)
+    }
+
+    // The key check made by the instruction that just ran, if it made one
+    pub fn take_key_observation(&mut self) -> Option<KeyObservation> {
+        self.key_observation.take()
+    }
+
+    fn observe_key(&mut self, opcode: u16, key: u8, pressed: bool) {
+        self.key_observation = Some(KeyObservation { pc: self.cpu.pc, opcode, key, pressed });
+    }
 
-        for i in 0..=x {
-            self.v[i] = self.memory[self.index as usize + i];
+    fn record_poll(&mut self, idx: usize) {
+        if let Some(poll) = self.key_polls.get_mut(idx) {
+            *poll = Some(self.frames);
         }
+    }
 
-        self.pc += 2;
+    pub fn set_key(&mut self, idx: usize, val:u8) {
+        self.keypad.set(idx, val);
     }
-}
\ No newline at end of file
+
+    // Set all 16 keys at once, bit N of the mask = key N pressed
+    pub fn set_keys_mask(&mut self, mask: u16) {
+        for idx in 0..self.keypad.len() {
+            self.set_key(idx, ((mask >> idx) & 1) as u8);
+        }
+    }
+
+    /********************************************/
+    /*          Instructions/Opcodes            */
+    /********************************************/
+
+    // 0x0000
+    // Treated as a no-op since many ROMs pad with zeros
+    fn nop(&mut self) {
+        self.nop_count += 1;
+        self.cpu.advance(2);
+    }
+
+    // Any opcode without an instruction is skipped and remembered for diagnostics
+    fn unknown(&mut self, opcode: u16) {
+        self.last_unknown_opcode = Some(opcode);
+        self.cpu.advance(2);
+    }
+
+    // Register lint: count reads of registers nothing has written since power on
+    fn lint_reads(&mut self, opcode: u16) -> Instruction {
+        let instruction = Instruction::decode(opcode);
+        let unwritten = instruction.reads(&self.quirks) & !self.written;
+        if unwritten != 0 {
+            self.uninit_reads += unwritten.count_ones() as u64;
+            let fresh = unwritten & !self.reported;
+            if fresh != 0 {
+                let register = fresh.trailing_zeros() as u8;
+                self.reported |= 1 << register;
+                self.uninit_read = Some(UninitRead { pc: self.cpu.pc, opcode, register });
+            }
+        }
+        instruction
+    }
+
+    // Diagnostic for 8XY4-8XYE: vX = vF has its result replaced by the flag, and vY = vF is read just
+    // before the flag write clobbers it
+    fn check_vf_clobber(&mut self, opcode: u16, reads_vy: bool) {
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        if x == 0xF || (reads_vy && y == 0xF) {
+            self.vf_clobber = Some((self.cpu.pc, opcode));
+        }
+    }
+
+    // 0x00E0
+    // Clear the display implementation
+    fn cls(&mut self) {
+        self.display.clear();
+
+        self.draw_flag = true;
+        self.cpu.advance(2);                    // Increment counter
+    }
+
+    // 00CN
+    // SUPER-CHIP: scroll the screen down N rows of the current resolution
+    fn scroll_down(&mut self, opcode: u16) {
+        self.display.scroll_down((opcode & 0x000F) as usize);
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FB
+    // SUPER-CHIP: scroll the screen right 4 columns of the current resolution
+    fn scroll_right(&mut self) {
+        self.display.scroll_right(4);
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FC
+    // SUPER-CHIP: scroll the screen left 4 columns of the current resolution
+    fn scroll_left(&mut self) {
+        self.display.scroll_left(4);
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FA
+    // Not part of CHIP-8 or SUPER-CHIP proper: some SCHIP interpreters use it to toggle
+    // whether FX55/FX65 increment I, and a few ROMs written for them rely on it
+    fn compat(&mut self) {
+        self.configured_quirks.get_or_insert(self.quirks);
+        self.quirks.load_store_increment = !self.quirks.load_store_increment;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FE
+    // SUPER-CHIP: back to the 64x32 resolution, on a blank screen
+    fn lores(&mut self) {
+        self.display.set_hires(false);
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FF
+    // SUPER-CHIP: switch to the 128x64 resolution, on a blank screen
+    fn hires(&mut self) {
+        self.display.set_hires(true);
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x02A0
+    // CHIP-8X: step the background to the next of its four colors
+    fn step_background(&mut self) {
+        self.colors.step_background();
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0xBXY0 and 0xBXYN
+    // CHIP-8X: set the foreground color to vY. BXY0 colors blocks of zones, vX giving the columns and v(X+1)
+    // the rows as start and extent nibbles. BXYN colors N rows of the zone at pixel (vX, v(X+1))
+    fn zone_color(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let color = self.cpu.v[((opcode & 0x00F0) >> 4) as usize];
+        let (across, down) = (self.cpu.v[x], self.cpu.v[(x + 1) & 0xF]);
+        match (opcode & 0x000F) as u8 {
+            0 => self.colors.fill_blocks(across, down, color),
+            n => self.colors.fill_rows(across, down, n, color),
+        }
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x0FFF
+    // This emulator's own extension for teaching ROMs: toggle the frontend's pixel grid overlay. Only
+    // decoded with extensions on, otherwise 0FFF is an unknown opcode like any other 0NNN
+    fn toggle_grid(&mut self) {
+        self.grid_overlay = !self.grid_overlay;
+        self.draw_flag = true;
+        self.cpu.advance(2);
+    }
+
+    // 0x00FD and 0x00ED
+    // SUPER-CHIP exit and CHIP-8E stop. There is no interpreter to return to, so pc stays put and the
+    // machine idles on the instruction the way it would on a jump to itself
+    fn exit(&mut self) {}
+
+    // FX03
+    // CHIP-8E: send vX to the VIP's output port 3. Nothing is attached, the byte is only logged
+    fn port_out(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        log::debug!("{:#05X}: output {:#04X} to port 3", self.cpu.pc, self.cpu.v[x]);
+        self.cpu.advance(2);
+    }
+
+    // FXE3 and FXE7
+    // CHIP-8E: read the VIP's input port 3 into vX, FXE3 after waiting for the strobe. Without hardware
+    // the strobe is always there and the port holds port_input
+    fn port_in(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        log::debug!("{:#05X}: input {:#04X} from port 3", self.cpu.pc, self.port_input);
+        self.cpu.v[x] = self.port_input;
+        self.cpu.advance(2);
+    }
+
+    // FX4F
+    // CHIP-8E: load the delay timer with vX and stay on the instruction until it runs out. timer_wait
+    // keeps the cycles after the first from loading the timer again
+    fn delay_wait(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        if !self.timer_wait {
+            self.cpu.delay_timer = self.cpu.v[x];
+            self.timer_wait = true;
+        }
+        if self.cpu.delay_timer == 0 {
+            self.timer_wait = false;
+            self.cpu.advance(2);
+        }
+    }
+
+    // CXNN
+    // Set register vX to a random number AND NN
+    fn rand(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
+
+        self.cpu.v[x] = self.rng.gen::<u8>() & nn;                  // Set X register to random number AND nn
+        self.rng_draws += 1;
+        self.cpu.advance(2);
+    }
+
+    // DXYN
+    // Draw a sprite at screen location (vX, vY) height N. In hires DXY0 draws the SUPER-CHIP 16x16 sprite,
+    // two bytes a row
+    fn sprite(&mut self, opcode: u16) {
+        let (width, height) = self.display.resolution();
+        let vx = self.cpu.v[((opcode & 0x0F00) >> 8) as usize] as usize % width;    // Extract X register, start position always wraps
+        let vy = self.cpu.v[((opcode & 0x00F0) >> 4) as usize] as usize % height;   // Extract Y register, start position always wraps
+        let (columns, rows) = match opcode & 0x000F {
+            0 if self.display.hires() => (16, 16),
+            n => (8, n as usize),                                           // Extract height
+        };
+        let clip = self.quirks.clip_sprites;
+        let mut collided = false;
+
+        // Loop through line by line and update display map
+        for yline in 0..rows {
+            if clip && vy + yline >= height {
+                break;                                                      // Rows past the bottom edge are clipped
+            }
+            let row_at = self.cpu.index as usize + yline * columns / 8;
+            let pixel = match columns {
+                16 => (self.cpu.read(row_at) as u16) << 8 | self.cpu.read(row_at + 1) as u16,
+                _ => (self.cpu.read(row_at) as u16) << 8,
+            };
+            for xline in 0..columns {
+                if clip && vx + xline >= width {
+                    break;                                                  // Columns past the right edge are clipped
+                }
+                if (pixel & (0x8000 >> xline)) != 0 {
+                    let x_pos = (vx + xline) % width;
+                    let y_pos = (vy + yline) % height;
+                    collided |= self.display.toggle(x_pos, y_pos);
+                }
+            }
+        }
+
+        match self.quirks.collision_delay {
+            true => self.pending_collision = Some(collided as u8),  // Lands after the next instruction
+            false => self.cpu.v[0xF] = collided as u8,
+        }
+        self.draw_flag = true;                                  // Update screen needs redrawing
+        self.cpu.advance(2);
+    }
+
+    // EX9E
+    // Skip if key rX is pressed
+    fn skpr(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        self.record_poll(self.cpu.v[x] as usize);
+        self.observe_key(opcode, self.cpu.v[x], self.keypad.is_pressed(self.cpu.v[x] as usize));
+
+        if self.keypad.is_pressed(self.cpu.v[x] as usize) {
+            self.cpu.advance(self.cpu.skip_size());                 // Skip next instruction
+        }
+
+        self.cpu.advance(2);
+    }
+
+    // EXA1
+    // Skip if key rX is not pressed
+    fn skup(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        self.record_poll(self.cpu.v[x] as usize);
+        self.observe_key(opcode, self.cpu.v[x], self.keypad.is_pressed(self.cpu.v[x] as usize));
+
+        if !self.keypad.is_pressed(self.cpu.v[x] as usize) {
+            self.cpu.advance(self.cpu.skip_size());                 // Skip next instruction
+        }
+
+        self.cpu.advance(2);
+    }
+
+    // FX0A
+    // Wait for keypress, put key in register vX
+    fn key(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        for idx in 0..self.keypad.len() {                      // Waiting examines every key
+            self.record_poll(idx);
+        }
+
+        if let Some(idx) = self.keypad.first_pressed() {
+            self.observe_key(opcode, idx, true);
+            self.cpu.v[x] = idx;
+            self.cpu.advance(2);
+        }
+    }
+
+    // FX75
+    // Store registers v0-vX in the RPL user flags, SUPER-CHIP games keep high scores here
+    fn srpl(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+
+        self.rpl[..=x].copy_from_slice(&self.cpu.v[..=x]);
+        self.rpl_dirty = true;
+        self.cpu.advance(2);
+    }
+
+    // FX85
+    // Load registers v0-vX from the RPL user flags
+    fn lrpl(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+
+        self.cpu.v[..=x].copy_from_slice(&self.rpl[..=x]);
+        self.cpu.advance(2);
+    }
+
+    // F002
+    // XO-CHIP: load the 16 byte audio pattern at I, which the buzzer plays in place of its tone
+    fn audio(&mut self) {
+        let index = self.cpu.index as usize;
+        let mut pattern = [0; PATTERN_SIZE];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.cpu.read(index + offset);
+        }
+        self.audio_pattern = Some(pattern);
+        self.cpu.advance(2);
+    }
+
+    // FX3A
+    // XO-CHIP: set the pitch the audio pattern plays at to vX
+    fn set_pitch(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+
+        self.pitch = self.cpu.v[x];
+        self.cpu.advance(2);
+    }
+}
+
+// A fresh random seed for each machine. Without std there is no entropy source to draw one from, so
+// every machine starts on seed 0 until set_seed gives it another
+#[cfg(feature = "std")]
+fn initial_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+#[cfg(not(feature = "std"))]
+fn initial_seed() -> u64 {
+    0
+}
+
+// The ROM without its trailing zero padding, up to the last non-zero byte and rounded up to an even
+// length so the last instruction keeps both bytes. All zeros trims to nothing
+pub fn trim_rom(bytes: &[u8]) -> &[u8] {
+    let used = bytes.iter().rposition(|&byte| byte != 0).map_or(0, |last| (last + 2) & !1);
+    &bytes[..used.min(bytes.len())]
+}
+
+// 64-bit FNV-1a hash, small and stable across platforms
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_00fd_exits() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x01, 0xFD, 0x00, 0xFD]).unwrap();
+        assert!(!chip8.halted());
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x202, "0x01FD is a machine call, skipped like any other");
+        assert!(chip8.halted());
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x202, "00FD idles on itself");
+    }
+
+    #[test]
+    fn only_the_exact_00fa_toggles_the_increment_quirk() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x03, 0xFA, 0x00, 0xFA]).unwrap();
+        chip8.cycle();
+        assert!(!chip8.quirks.load_store_increment);
+        chip8.cycle();
+        assert!(chip8.quirks.load_store_increment);
+    }
+
+    #[test]
+    fn execute_opcode_runs_a_handler_without_a_rom() {
+        let mut chip8 = Chip8::new();
+        chip8.execute_opcode(0x6A05);
+        assert_eq!(chip8.register(0xA), 5);
+        assert_eq!(chip8.pc(), 0x202);
+    }
+
+    #[test]
+    fn zero_is_a_counted_nop_not_an_unknown_opcode() {
+        let mut chip8 = Chip8::new();
+        chip8.execute_opcode(0x0000);
+        chip8.execute_opcode(0x0000);
+        assert_eq!(chip8.nop_count(), 2);
+        assert_eq!(chip8.last_unknown_opcode(), None);
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn a_custom_fontset_lands_at_the_font_base_and_fx29_finds_it() {
+        let mut chip8 = Chip8::new();
+        let font: Vec<u8> = (0..FONTSET_SIZE as u8).collect();
+        chip8.set_fontset(&font).unwrap();
+        assert_eq!(chip8.cpu.memory.bytes()[FONT_BASE..FONT_BASE + FONTSET_SIZE], font[..]);
+
+        chip8.execute_opcode(0x6307);
+        chip8.execute_opcode(0xF329);
+        assert_eq!(chip8.cpu.index as usize, FONT_BASE + 7 * 5);
+        assert_eq!(chip8.peek(chip8.index() as usize), Some(35), "glyph 7 starts with the custom table's byte 35");
+
+        assert!(chip8.set_fontset(&font[..79]).is_err());
+    }
+
+    // (x, y) of every lit pixel in row major order
+    fn lit(chip8: &Chip8) -> Vec<(usize, usize)> {
+        let width = chip8.display.width();
+        (0..chip8.display.len()).filter(|&idx| chip8.display[idx] != 0).map(|idx| (idx % width, idx / width)).collect()
+    }
+
+    #[test]
+    fn clipped_sprites_draw_and_collide_only_on_screen() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.clip_sprites = true;
+        chip8.display[0] = 1;                           // Where a wrapped second row would land
+        chip8.decode_execute(0xA000 | (FONT_BASE as u16 + 8 * 5));    // The 8 glyph, no blank row
+        chip8.decode_execute(0x6000);
+        chip8.decode_execute(0x611F);
+        chip8.decode_execute(0xD015);
+        assert_eq!(chip8.cpu.v[0xF], 0, "the hidden rows don't collide with the pixel at the top");
+        assert_eq!(lit(&chip8), [(0, 0), (0, 31), (1, 31), (2, 31), (3, 31)]);
+
+        chip8.decode_execute(0xD015);
+        assert_eq!(chip8.cpu.v[0xF], 1, "the visible row collides with itself");
+        assert_eq!(lit(&chip8), [(0, 0)]);
+    }
+
+    #[test]
+    fn unclipped_sprites_wrap_to_the_top() {
+        let mut chip8 = Chip8::new();
+        chip8.decode_execute(0xA000 | (FONT_BASE as u16 + 8 * 5));
+        chip8.decode_execute(0x611F);
+        chip8.decode_execute(0xD015);
+        assert!(chip8.display[31 * WIDTH] != 0 && chip8.display[0] != 0 && chip8.display[3 * WIDTH] != 0);
+    }
+
+    #[test]
+    fn memory_search_finds_and_narrows_candidates() {
+        let mut chip8 = Chip8::new();
+        let marker = 0xA7;
+        assert!(chip8.find_byte(marker).is_empty(), "nothing holds the marker yet");
+        for addr in [0x300, 0x4F0, 0xE00] {
+            chip8.poke(addr, marker).unwrap();
+        }
+        let candidates = chip8.find_byte(marker);
+        assert_eq!(candidates, [0x300, 0x4F0, 0xE00]);
+
+        chip8.poke(0x4F0, 0xA8).unwrap();
+        chip8.poke(0xE00, 0xA8).unwrap();
+        let narrowed = chip8.narrow(&candidates, 0xA8);
+        assert_eq!(narrowed, [0x4F0, 0xE00], "only the ones that changed to the new value");
+        assert_eq!(chip8.narrow(&narrowed, 0x00), Vec::<usize>::new());
+        assert_eq!(chip8.narrow(&[0x10000], 0x00), Vec::<usize>::new(), "outside memory never matches");
+    }
+
+    #[test]
+    fn fx55_follows_the_increment_quirk_00fa_sets() {
+        let mut chip8 = Chip8::new();
+        for x in 0..3 {
+            chip8.set_register(x, 0x10 + x as u8);
+        }
+        chip8.set_index(0x300);
+        chip8.decode_execute(0x00FA);
+        assert!(chip8.quirks.load_store_increment, "toggled on");
+        chip8.decode_execute(0xF255);
+        assert_eq!((chip8.peek(0x300), chip8.peek(0x302)), (Some(0x10), Some(0x12)));
+        assert_eq!(chip8.index(), 0x303, "I moves past the stored registers");
+
+        chip8.decode_execute(0x00FA);
+        assert!(!chip8.quirks.load_store_increment, "toggled back off");
+        chip8.decode_execute(0xF255);
+        assert_eq!(chip8.index(), 0x303, "I stays put");
+        assert_eq!(chip8.peek(0x305), Some(0x12));
+    }
+
+    #[test]
+    fn reset_undoes_the_rom_toggling_the_increment_quirk() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.clip_sprites = true;
+        chip8.load_rom_bytes(&[0x00, 0xFA, 0x12, 0x02]).unwrap();
+        chip8.cycle();
+        assert!(chip8.quirks.load_store_increment);
+        chip8.reset();
+        assert_eq!(chip8.quirks, Quirks { clip_sprites: true, ..Quirks::default() }, "the configured quirks are back");
+        chip8.cycle();
+        chip8.reset();
+        assert!(!chip8.quirks.load_store_increment, "and again after a second run");
+    }
+
+    #[test]
+    fn states_with_odd_widths_are_refused_untouched() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x07]).unwrap();
+        let mut state = chip8.save_state();
+        state[8 + 16 + 6 + 32 + 4096 + 2 + 1 + HIRES_WIDTH * HIRES_HEIGHT + 16 + 3] = 13;
+        chip8.cycle();
+        assert_eq!(chip8.load_state(&state).unwrap_err(), "savestate has an I width of 13 bits, expected 12 or 16");
+        state[8 + 16 + 6 + 32 + 4096 + 2 + 1 + HIRES_WIDTH * HIRES_HEIGHT + 16 + 3] = 12;
+        state[8 + 16 + 6 + 32 + 4096 + 2 + 1 + HIRES_WIDTH * HIRES_HEIGHT + 16 + 5] = 40;
+        assert_eq!(chip8.load_state(&state).unwrap_err(), "savestate has an FX1E overflow width of 40 bits, expected 12 or 16");
+        assert_eq!((chip8.cpu.v[0], chip8.pc()), (7, 0x202));
+    }
+
+    #[test]
+    fn states_with_pc_or_sp_out_of_range_are_refused_untouched() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x07]).unwrap();
+        let state = chip8.save_state();
+        chip8.cycle();
+
+        let mut outside = state.clone();
+        outside[26..28].copy_from_slice(&0x1000u16.to_le_bytes());     // pc follows the rom hash, v0-vF and I
+        assert_eq!(chip8.load_state(&outside).unwrap_err(), "savestate has pc at 0x1000, outside the 4096 bytes of memory");
+        let mut overflowed = state.clone();
+        overflowed[28..30].copy_from_slice(&17u16.to_le_bytes());
+        assert_eq!(chip8.load_state(&overflowed).unwrap_err(), "savestate has a stack pointer of 17, past the 16 slot stack");
+        assert_eq!((chip8.cpu.v[0], chip8.pc()), (7, 0x202));
+
+        let mut full = state;
+        full[28..30].copy_from_slice(&16u16.to_le_bytes());
+        chip8.load_state(&full).unwrap();
+        assert_eq!(chip8.stack_pointer(), 16, "a full stack is a state the core can run");
+    }
+
+    #[test]
+    fn vf_operands_overwritten_by_the_flag_are_detected() {
+        // 8FY4 adds into vF, 81F4 reads vF just before the carry replaces it, 8124 never touches vF
+        for (opcode, clobbers) in [(0x8F14u16, true), (0x81F4, true), (0x8124, false)] {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(&opcode.to_be_bytes()).unwrap();
+            chip8.cycle();
+            let expected = clobbers.then_some((0x200, opcode));
+            assert_eq!(chip8.take_vf_clobber(), expected, "{:04X}", opcode);
+            assert_eq!(chip8.take_vf_clobber(), None, "taken once");
+        }
+    }
+
+    #[test]
+    fn polling_key_5_is_recorded_for_a_second() {
+        // v0 = 5, then loop on EX9E and EXA1 for key 5
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x05, 0xE0, 0x9E, 0xE0, 0xA1, 0x12, 0x02]).unwrap();
+        assert_eq!(chip8.polled_keys(), 0);
+        chip8.step_frame(10);
+        assert_eq!(chip8.polled_keys(), 1 << 5, "only key 5 was examined");
+
+        // Stop polling by sitting in a self jump, the record lapses after POLL_WINDOW frames
+        chip8.set_pc(0x206).unwrap();
+        chip8.poke(0x206, 0x12).unwrap();
+        chip8.poke(0x207, 0x06).unwrap();
+        for _ in 1..POLL_WINDOW {
+            chip8.step_frame(10);
+        }
+        assert_eq!(chip8.polled_keys(), 1 << 5, "still recent at the end of the window");
+        chip8.step_frame(10);
+        assert_eq!(chip8.polled_keys(), 0, "forgotten once the window has passed");
+    }
+
+    #[test]
+    fn key_checks_past_f_see_a_released_key() {
+        // v0 = 0x20 with every key held, EX9E doesn't skip and EXA1 does
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x20, 0xE0, 0x9E, 0xE0, 0xA1]).unwrap();
+        chip8.set_keys_mask(0xFFFF);
+        chip8.cycle();
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x204, "EX9E fell through");
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x208, "EXA1 skipped");
+        assert_eq!(chip8.polled_keys(), 0, "no key was examined");
+    }
+
+    #[test]
+    fn fx0a_polls_every_key() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xF3, 0x0A]).unwrap();
+        chip8.cycle();
+        assert_eq!(chip8.polled_keys(), 0xFFFF);
+    }
+
+    #[test]
+    fn the_built_in_font_has_the_known_checksum() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.fontset_checksum(), 0x3399EDF0);
+        chip8.reset();
+        assert_eq!(chip8.fontset_checksum(), FONTSET_CHECKSUM, "reset reloads the font");
+        chip8.poke(FONT_BASE + 7, 0x00).unwrap();
+        assert_ne!(chip8.fontset_checksum(), FONTSET_CHECKSUM, "a corrupted glyph changes it");
+    }
+
+    #[test]
+    fn oversized_roms_are_cut_off_at_the_end_of_memory() {
+        let mut chip8 = Chip8::new();
+        let rom = vec![0xAB; 4096];
+        let loaded = chip8.load_rom_bytes(&rom).unwrap();
+        assert!(loaded < rom.len());
+        assert_eq!(loaded, 3584, "everything from 0x200 on");
+        assert_eq!(chip8.peek(0xFFF), Some(0xAB));
+    }
+
+    #[test]
+    fn oversized_roms_log_a_warning() {
+        let mut chip8 = Chip8::new();
+        let rom = vec![0x12; 4096];
+        let logged = crate::testlog::capture(|| {
+            chip8.load_rom_bytes(&rom).unwrap();
+        });
+        assert!(logged.contains(&(log::Level::Warn, "ROM is too large to fit in memory.".to_string())), "{:?}", logged);
+
+        let logged = crate::testlog::capture(|| {
+            chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        });
+        assert!(logged.iter().all(|(level, _)| *level != log::Level::Warn), "a ROM that fits loads quietly");
+    }
+
+    #[test]
+    fn strict_loading_refuses_oversized_roms_whole() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        let rom = vec![0xAB; 4096];
+        assert!(matches!(chip8.load_rom_bytes_strict(&rom), Err(Chip8Error::RomTooLarge { len: 4096, capacity: 3584 })));
+        assert_eq!(chip8.peek(PROGRAM_START + 2), Some(0), "nothing of a refused ROM is loaded");
+        chip8.reset();
+        assert_eq!(chip8.peek(PROGRAM_START), Some(0x12), "reset reloads the ROM that did load");
+    }
+
+    #[test]
+    fn sprite_at_index_previews_the_pointed_glyph() {
+        let mut chip8 = Chip8::new();
+        chip8.set_index((FONT_BASE + 5 * 7) as u16);
+        assert_eq!(chip8.sprite_at_index(5), [0xF0, 0x10, 0x20, 0x40, 0x40], "the 7 glyph");
+        assert_eq!(chip8.sprite_at_index(5), CHIP8_FONTSET[35..40]);
+
+        let end = chip8.cpu.memory.len() - 2;
+        chip8.set_index(end as u16);
+        assert_eq!(chip8.sprite_at_index(5).len(), 2, "clamped to the end of memory");
+    }
+
+    // 0x200 calls 0x206, which calls 0x20A, which calls 0x20E, which spins
+    const NESTED_CALLS: [u8; 16] = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x22, 0x0A, 0x00, 0xEE, 0x22, 0x0E, 0x00, 0xEE, 0x12, 0x0E];
+
+    #[test]
+    fn call_stack_lists_nested_calls_outermost_first() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&NESTED_CALLS).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert_eq!(chip8.pc(), 0x20E);
+        let frame = |call_site: u16, opcode| CallFrame { call_site, return_address: call_site + 2, opcode: Some(opcode) };
+        assert_eq!(chip8.call_stack(), [frame(0x200, 0x2206), frame(0x206, 0x220A), frame(0x20A, 0x220E)]);
+    }
+
+    #[test]
+    fn step_frame_runs_its_cycles_then_ticks_timers_once() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x70, 0x01].repeat(16)).unwrap();
+        chip8.cpu.delay_timer = 5;
+        chip8.cpu.sound_timer = 3;
+        chip8.step_frame(10);
+        assert_eq!((chip8.register(0), chip8.pc()), (10, 0x214), "ten instructions ran");
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (4, 2), "timers ticked once");
+        assert_eq!(chip8.frame_count(), 1);
+    }
+
+    #[test]
+    fn register_lint_counts_reads_of_unwritten_registers() {
+        // v0 = v5 before v5 is written, then v5 = 7 and v0 = v5 again
+        let rom = [0x80, 0x50, 0x65, 0x07, 0x80, 0x50];
+        let mut chip8 = Chip8::new();
+        chip8.lint_registers = true;
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.cycle();
+        assert_eq!(chip8.uninit_reads(), 1);
+        assert_eq!(chip8.take_uninit_read(), Some(UninitRead { pc: 0x200, opcode: 0x8050, register: 5 }));
+        assert_eq!(chip8.take_uninit_read(), None, "taken once");
+        chip8.cycle();
+        chip8.cycle();
+        assert_eq!(chip8.uninit_reads(), 1, "v5 was written before the second read");
+
+        let mut unlinted = Chip8::new();
+        unlinted.load_rom_bytes(&rom).unwrap();
+        unlinted.cycle();
+        assert_eq!((unlinted.uninit_reads(), unlinted.take_uninit_read()), (0, None), "the lint is off by default");
+    }
+
+    #[test]
+    fn a_stack_underflow_stops_the_machine_until_reset() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x00, 0xEE]).unwrap();
+        let logged = crate::testlog::capture(|| {
+            chip8.cycle();
+            chip8.cycle();
+        });
+        assert_eq!(chip8.fault(), Some(Fault::StackUnderflow));
+        assert!(logged.contains(&(log::Level::Error, "CPU stack underflow at 0x202, the machine has stopped.".to_string())), "{:?}", logged);
+        assert!(chip8.halted());
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x202, "a faulted machine doesn't run");
+
+        chip8.reset();
+        assert_eq!((chip8.fault(), chip8.pc()), (None, 0x200));
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_faults() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0x00]).unwrap();
+        for _ in 0..40_000 {
+            chip8.cycle();
+        }
+        assert_eq!((chip8.fault(), chip8.pc()), (Some(Fault::PcOutOfRange), 0x1000), "zeros run as NOPs up to the end");
+        assert!(chip8.take_bus_fault().is_some());
+
+        // With all 64K of memory pc wraps around to 0 instead of overflowing
+        let mut chip8 = Chip8::with_memory(FlatMemory::with_size(0x10000));
+        chip8.cpu.pc = 0xFFFE;
+        chip8.cycle();
+        assert_eq!((chip8.fault(), chip8.pc()), (None, 0x0000));
+    }
+
+    #[test]
+    fn resolution_switches_blank_the_screen() {
+        let mut chip8 = Chip8::new();
+        chip8.display[3 + 3 * WIDTH] = 1;
+        chip8.execute_opcode(0x00FF);
+        assert_eq!(chip8.resolution(), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip8.display.len(), 128 * 64);
+        assert!(!chip8.pixel(3, 3));
+        chip8.display[100 + 50 * HIRES_WIDTH] = 1;
+        chip8.execute_opcode(0x00FE);
+        assert_eq!(chip8.resolution(), (WIDTH, HEIGHT));
+        assert_eq!(lit(&chip8), []);
+    }
+
+    #[test]
+    fn hires_draws_wrap_at_128_columns() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF, 0xA2, 0x0A, 0x60, 0x7C, 0x61, 0x3F, 0xD0, 0x11, 0xFF]).unwrap();
+        for _ in 0..5 {
+            chip8.cycle();
+        }
+        assert_eq!(lit(&chip8), [(0, 63), (1, 63), (2, 63), (3, 63), (124, 63), (125, 63), (126, 63), (127, 63)], "not at 64");
+    }
+
+    #[test]
+    fn machines_can_power_on_in_hires() {
+        let mut chip8 = Chip8::new();
+        chip8.set_start_hires(true);
+        assert_eq!(chip8.resolution(), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip8.display.len(), 128 * 64);
+        chip8.load_rom_bytes(&[0xA2, 0x08, 0x60, 0x7C, 0x61, 0x3F, 0xD0, 0x11, 0xFF]).unwrap();
+        for _ in 0..4 {
+            chip8.cycle();
+        }
+        assert_eq!(lit(&chip8), [(0, 63), (1, 63), (2, 63), (3, 63), (124, 63), (125, 63), (126, 63), (127, 63)], "no 00FF needed");
+
+        chip8.reset();
+        assert_eq!(chip8.resolution(), (HIRES_WIDTH, HIRES_HEIGHT), "kept across reset");
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "stack pointer 17 is past the 16 slot stack. Recent PCs: 0x200 0x202")]
+    fn paranoid_builds_catch_a_runaway_stack_pointer() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x61, 0x02]).unwrap();
+        chip8.cycle();
+        chip8.cpu.sp = 17;
+        chip8.cycle();
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "I 0x1234 is wider than 12 bits")]
+    fn paranoid_builds_catch_an_unmasked_index() {
+        let mut chip8 = Chip8::new();
+        chip8.cpu.index = 0x1234;
+        chip8.execute_opcode(0x6001);
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "pc 0x1800 is past the end of the 4096 bytes of memory")]
+    fn paranoid_builds_catch_a_pc_past_memory() {
+        let mut chip8 = Chip8::new();
+        chip8.cpu.pc = 0x0FFE;
+        chip8.cycle();
+        chip8.cycle();
+        assert!(chip8.take_bus_fault().is_some(), "running off the end is the ROM's fault, and reported");
+        chip8.cpu.pc = 0x17FE;
+        chip8.execute_opcode(0x6001);
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "pixels outside the 64x32 screen are lit")]
+    fn paranoid_builds_catch_pixels_off_screen() {
+        let mut chip8 = Chip8::new();
+        chip8.display.spare_pixels_mut()[0] = 1;
+        chip8.execute_opcode(0x6001);
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    fn paranoid_builds_pass_sane_runs() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF, 0xA2, 0x0A, 0x60, 0x7C, 0x61, 0x3F, 0xD0, 0x11, 0xFF]).unwrap();
+        for _ in 0..5 {
+            chip8.cycle();
+        }
+        chip8.execute_opcode(0x00FE);
+    }
+
+    #[test]
+    fn registers_changed_since_frame_marks_the_frames_writes() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x00, 0x62, 0x05, 0x67, 0x09, 0x12, 0x06]).unwrap();
+        chip8.tick_timers();
+        for _ in 0..4 {
+            chip8.cycle();
+        }
+        assert_eq!(chip8.registers_changed_since_frame(), 1 << 2 | 1 << 7, "v0 was written with the value it had");
+        chip8.tick_timers();
+        assert_eq!(chip8.registers_changed_since_frame(), 0, "a new frame starts from the current values");
+    }
+
+    fn chip8x() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.variant = Variant::Chip8X;
+        chip8
+    }
+
+    #[test]
+    fn chip8x_02a0_steps_the_background() {
+        let mut chip8 = chip8x();
+        chip8.execute_opcode(0x02A0);
+        chip8.execute_opcode(0x02A0);
+        assert_eq!((chip8.colors().background(), chip8.pc()), (2, 0x204));
+    }
+
+    #[test]
+    fn chip8x_bxy0_colors_a_block_of_zones() {
+        let mut chip8 = chip8x();
+        chip8.set_register(0, 0x10);            // Zone columns 0-1
+        chip8.set_register(1, 0x02);            // Block row 2, zone rows 8-11
+        chip8.set_register(5, 6);
+        chip8.execute_opcode(0xB050);
+        assert_eq!(chip8.pc(), 0x202, "not a jump");
+        assert_eq!((chip8.colors().zone(0, 8), chip8.colors().zone(1, 11)), (6, 6));
+        assert_eq!((chip8.colors().zone(2, 8), chip8.colors().zone(0, 12)), (1, 1));
+    }
+
+    #[test]
+    fn chip8x_bxyn_colors_rows_of_one_zone() {
+        let mut chip8 = chip8x();
+        chip8.set_register(3, 40);
+        chip8.set_register(4, 10);
+        chip8.set_register(7, 4);
+        chip8.execute_opcode(0xB372);
+        assert_eq!((chip8.colors().zone(5, 10), chip8.colors().zone(5, 11)), (4, 4));
+        assert_eq!((chip8.colors().zone(5, 12), chip8.colors().zone(4, 10)), (1, 1));
+    }
+
+    #[test]
+    fn chip8x_5xy1_adds_octal_digits() {
+        let mut chip8 = chip8x();
+        chip8.set_register(2, 0x36);
+        chip8.set_register(3, 0x25);
+        chip8.set_register(0xF, 9);
+        chip8.execute_opcode(0x5231);
+        assert_eq!((chip8.register(2), chip8.register(0xF), chip8.pc()), (0x53, 9, 0x202));
+    }
+
+    #[test]
+    fn other_variants_keep_the_standard_meanings() {
+        let mut chip8 = Chip8::new();
+        chip8.execute_opcode(0x02A0);
+        assert_eq!(chip8.last_unknown_opcode(), Some(0x02A0));
+        chip8.execute_opcode(0x5011);
+        assert_eq!(chip8.pc(), 0x206, "5XY1 skips as 5XY0 does");
+        chip8.execute_opcode(0xB350);
+        assert_eq!(chip8.pc(), 0x350);
+        assert_eq!(*chip8.colors(), ColorMap::new());
+    }
+
+    #[test]
+    fn chip8x_zones_render_in_their_color() {
+        let mut chip8 = chip8x();
+        chip8.set_register(0, 8);
+        chip8.set_register(2, 3);
+        chip8.execute_opcode(0xB021);          // Zone column 1, row 0 violet
+        chip8.execute_opcode(0x02A0);
+        chip8.set_pixel(8, 0, true);
+        chip8.set_pixel(0, 0, true);
+        let (fg, bg) = (crate::chip8x::FOREGROUND_RGBA, crate::chip8x::BACKGROUND_RGBA);
+        let mut out = Vec::new();
+        chip8.render_chip8x_rgba(&fg, &bg, 1, &mut out);
+        assert_eq!(out.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(out[8 * 4..9 * 4], fg[3], "the colored zone");
+        assert_eq!(out[..4], fg[1], "other zones keep red");
+        assert_eq!(out[4..8], bg[1], "dark pixels show the background");
+    }
+
+    #[test]
+    fn chip8x_colors_round_trip_through_states() {
+        let mut chip8 = chip8x();
+        chip8.execute_opcode(0x02A0);
+        chip8.execute_opcode(0xB001);
+        let state = chip8.save_state();
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.quirks.variant, Variant::Chip8X);
+        assert_eq!(restored.colors(), chip8.colors());
+    }
+
+    fn chip8e() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.variant = Variant::Chip8E;
+        chip8
+    }
+
+    #[test]
+    fn chip8e_00ed_stops_on_the_instruction() {
+        let mut chip8 = chip8e();
+        chip8.execute_opcode(0x00ED);
+        chip8.execute_opcode(0x00ED);
+        assert_eq!((chip8.pc(), chip8.last_unknown_opcode()), (0x200, None));
+    }
+
+    #[test]
+    fn chip8e_00f2_does_nothing() {
+        let mut chip8 = chip8e();
+        chip8.execute_opcode(0x00F2);
+        assert_eq!((chip8.pc(), chip8.last_unknown_opcode(), chip8.nop_count()), (0x202, None, 0));
+    }
+
+    #[test]
+    fn chip8e_0151_waits_for_the_delay_timer() {
+        let mut chip8 = chip8e();
+        chip8.set_delay_timer(1);
+        chip8.execute_opcode(0x0151);
+        assert_eq!(chip8.pc(), 0x200);
+        chip8.tick_timers();
+        chip8.execute_opcode(0x0151);
+        assert_eq!(chip8.pc(), 0x202);
+    }
+
+    #[test]
+    fn chip8e_0188_skips_the_next_instruction() {
+        let mut chip8 = chip8e();
+        chip8.execute_opcode(0x0188);
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn chip8e_5xy1_skips_when_vx_is_greater() {
+        let mut chip8 = chip8e();
+        chip8.set_register(1, 5);
+        chip8.set_register(2, 3);
+        chip8.execute_opcode(0x5121);
+        assert_eq!(chip8.pc(), 0x204);
+        chip8.execute_opcode(0x5211);
+        assert_eq!(chip8.pc(), 0x206);
+        chip8.execute_opcode(0x5111);
+        assert_eq!(chip8.pc(), 0x208, "equal doesn't skip");
+    }
+
+    #[test]
+    fn chip8e_5xy2_and_5xy3_move_a_register_range() {
+        let mut chip8 = chip8e();
+        for (reg, value) in [(3, 0x11), (4, 0x22), (5, 0x33), (6, 0x44)] {
+            chip8.set_register(reg, value);
+        }
+        chip8.set_index(0x300);
+        chip8.execute_opcode(0x5352);
+        assert_eq!([chip8.peek(0x300), chip8.peek(0x301), chip8.peek(0x302), chip8.peek(0x303)], [Some(0x11), Some(0x22), Some(0x33), Some(0)]);
+        assert_eq!(chip8.index(), 0x303, "I ends past the range");
+
+        chip8.set_index(0x300);
+        chip8.execute_opcode(0x5A83);           // Counts down from vA to v8
+        assert_eq!([chip8.register(0xA), chip8.register(9), chip8.register(8)], [0x11, 0x22, 0x33]);
+        assert_eq!((chip8.index(), chip8.pc()), (0x303, 0x204));
+    }
+
+    #[test]
+    fn chip8e_bbnn_and_bfnn_branch_from_the_next_instruction() {
+        let mut chip8 = chip8e();
+        chip8.set_pc(0x240).unwrap();
+        chip8.execute_opcode(0xBB10);
+        assert_eq!(chip8.pc(), 0x232);
+        chip8.execute_opcode(0xBF10);
+        assert_eq!(chip8.pc(), 0x244);
+        chip8.set_register(0, 4);
+        chip8.execute_opcode(0xB300);
+        assert_eq!(chip8.pc(), 0x304, "other BNNN still jump to NNN + v0");
+    }
+
+    #[test]
+    fn chip8e_fx1b_skips_vx_bytes() {
+        let mut chip8 = chip8e();
+        chip8.set_register(4, 3);
+        chip8.execute_opcode(0xF41B);
+        assert_eq!(chip8.pc(), 0x205);
+    }
+
+    #[test]
+    fn chip8e_fx4f_loads_the_timer_once_and_waits() {
+        let mut chip8 = chip8e();
+        chip8.set_register(1, 2);
+        chip8.execute_opcode(0xF14F);
+        assert_eq!((chip8.delay_timer(), chip8.pc()), (2, 0x200));
+        chip8.tick_timers();
+        chip8.execute_opcode(0xF14F);
+        assert_eq!((chip8.delay_timer(), chip8.pc()), (1, 0x200), "not loaded again");
+
+        let state = chip8.save_state();
+        let mut restored = chip8e();
+        restored.load_state(&state).unwrap();
+        restored.tick_timers();
+        restored.execute_opcode(0xF14F);
+        assert_eq!((restored.delay_timer(), restored.pc()), (0, 0x202));
+
+        restored.set_register(1, 0);
+        restored.execute_opcode(0xF14F);
+        assert_eq!(restored.pc(), 0x204, "a zero wait falls through");
+    }
+
+    #[test]
+    fn chip8e_port_io_reads_port_input_and_drops_output() {
+        let mut chip8 = chip8e();
+        chip8.port_input = 0x5A;
+        chip8.execute_opcode(0xF2E3);
+        chip8.execute_opcode(0xF3E7);
+        chip8.execute_opcode(0xF203);
+        assert_eq!((chip8.register(2), chip8.register(3)), (0x5A, 0x5A));
+        assert_eq!((chip8.pc(), chip8.last_unknown_opcode()), (0x206, None));
+    }
+
+    #[test]
+    fn chip8e_opcodes_stay_out_of_other_profiles() {
+        for variant in [Variant::Standard, Variant::Chip8X] {
+            let mut chip8 = Chip8::new();
+            chip8.quirks.variant = variant;
+            for opcode in [0x00ED, 0x00F2, 0x0151, 0x0188, 0xF11B, 0xF14F, 0xF103, 0xF1E3, 0xF1E7] {
+                chip8.execute_opcode(opcode);
+                assert_eq!(chip8.last_unknown_opcode(), Some(opcode), "{:?} {:04X}", variant, opcode);
+            }
+            assert_eq!((chip8.pc(), chip8.delay_timer()), (0x212, 0));
+        }
+
+        let mut chip8 = Chip8::new();
+        chip8.set_register(1, 5);
+        chip8.execute_opcode(0x5102);
+        assert_eq!(chip8.pc(), 0x202, "5XY2 compares as 5XY0 does");
+        chip8.execute_opcode(0xBB10);
+        assert_eq!(chip8.pc(), 0xB10);
+    }
+
+    #[test]
+    fn delayed_collisions_reach_vf_after_the_next_instruction() {
+        // Draw the 0 glyph twice at (0, 0), then an instruction that leaves vF alone
+        let rom = [0xF0, 0x29, 0xD0, 0x05, 0x6F, 0x07, 0xD0, 0x05, 0x61, 0x01];
+        let mut chip8 = Chip8::new();
+        chip8.quirks.collision_delay = true;
+        chip8.load_rom_bytes(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert_eq!(chip8.register(0xF), 0, "the first draw's flag lands over 6F07's write");
+        chip8.cycle();
+        assert_eq!(chip8.register(0xF), 0, "the collision hasn't reached vF yet");
+        chip8.cycle();
+        assert_eq!(chip8.register(0xF), 1);
+
+        let mut prompt = Chip8::new();
+        prompt.load_rom_bytes(&rom).unwrap();
+        for _ in 0..4 {
+            prompt.cycle();
+        }
+        assert_eq!(prompt.register(0xF), 1, "without the quirk vF is set by the draw");
+    }
+
+    #[test]
+    fn cls_clears_every_pixel_of_a_128x64_screen() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF, 0x00, 0xE0]).unwrap();
+        chip8.cycle();
+        chip8.display.fill(1);
+        assert_eq!(lit(&chip8).len(), HIRES_WIDTH * HIRES_HEIGHT);
+        chip8.cycle();
+        assert!(lit(&chip8).is_empty());
+    }
+
+    #[test]
+    fn scroll_right_moves_a_set_pixel_4_columns() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFB]).unwrap();
+        chip8.set_pixel(10, 5, true);
+        chip8.set_pixel(62, 6, true);
+        chip8.cycle();
+        assert!(!chip8.pixel(10, 5));
+        assert!(chip8.pixel(14, 5));
+        assert_eq!(chip8.lit_pixels().count(), 1, "pixels scrolled off the right edge are gone, not wrapped");
+    }
+
+    #[test]
+    fn scroll_left_and_down_move_by_the_current_resolution() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF, 0x00, 0xFC, 0x00, 0xC3]).unwrap();
+        chip8.cycle();
+        chip8.set_pixel(100, 60, true);
+        chip8.set_pixel(2, 0, true);
+        chip8.cycle();
+        assert_eq!(chip8.lit_pixels().collect::<Vec<_>>(), [(96, 60)]);
+        chip8.cycle();
+        assert_eq!(chip8.lit_pixels().collect::<Vec<_>>(), [(96, 63)]);
+    }
+
+    #[test]
+    fn dxy0_draws_16x16_in_hires() {
+        let mut chip8 = Chip8::new();
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x08, 0xD0, 0x00, 0x12, 0x06];
+        rom.extend([0x80, 0x01].repeat(16));
+        chip8.load_rom_bytes(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert_eq!(lit(&chip8).len(), 32);
+        assert!(chip8.pixel(0, 15) && chip8.pixel(15, 15) && !chip8.pixel(16, 0));
+    }
+
+    #[test]
+    fn hires_states_round_trip() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF]).unwrap();
+        chip8.cycle();
+        chip8.display[127 + 63 * HIRES_WIDTH] = 1;
+        let state = chip8.save_state();
+        assert_eq!(state.len(), STATE_FIXED_SIZE + 4096);
+
+        chip8.reset();
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.resolution(), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(lit(&chip8), [(127, 63)]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn empty_roms_warn_or_fail_strictly() {
+        let mut chip8 = Chip8::new();
+        let logged = crate::testlog::capture(|| {
+            assert_eq!(chip8.load_rom_bytes(&[]).unwrap(), 0);
+        });
+        assert!(logged.contains(&(log::Level::Warn, "ROM is empty, execution starts on blank memory at 0x200.".to_string())), "{:?}", logged);
+
+        let mut strict = Chip8::new();
+        let logged = crate::testlog::capture(|| {
+            assert!(matches!(strict.load_rom_bytes_strict(&[]), Err(Chip8Error::RomEmpty)));
+        });
+        assert!(logged.is_empty(), "a refused ROM isn't loaded or logged");
+        assert_eq!(strict.load_rom_bytes_strict(&[0x12, 0x00]).unwrap(), 2);
+        assert_eq!(Chip8Error::RomTooLarge { len: 3585, capacity: 3584 }.to_string(), "ROM is 3585 bytes, only 3584 fit in memory");
+    }
+
+    #[test]
+    fn compare_trace_reports_the_first_divergent_step() {
+        // v0 = 5, then v0 += 1 in a loop through 0x202
+        let rom = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+        let trace = [(0x200, 0x6005), (0x202, 0x7001), (0x204, 0x1202), (0x202, 0x7001)];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert_eq!(chip8.compare_trace(&trace), None);
+        assert_eq!(chip8.register(0), 7);
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let diverging = [(0x200, 0x6005), (0x202, 0x7001), (0x204, 0x1200), (0x200, 0x6005)];
+        assert_eq!(chip8.compare_trace(&diverging), Some(2));
+        assert_eq!((chip8.pc(), chip8.register(0)), (0x204, 6), "stopped before the mismatching step ran");
+        assert_eq!(chip8.compare_trace(&[(0x206, 0x0000)]), Some(0), "a wrong pc diverges too");
+    }
+
+    #[test]
+    fn packed_rows_put_the_leftmost_pixel_in_the_top_bit() {
+        // Draw 0xA5 then 0x3C at x = 4, so each row straddles the first two bytes
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x04, 0x61, 0x00, 0xA2, 0x0C, 0xD0, 0x12, 0x12, 0x08, 0x00, 0x00, 0xA5, 0x3C]).unwrap();
+        for _ in 0..4 {
+            chip8.cycle();
+        }
+        let packed = chip8.packed_rows();
+        assert_eq!(packed.len(), WIDTH / 8 * HEIGHT);
+        assert_eq!(packed[..2], [0x0A, 0x50]);
+        assert_eq!(packed[WIDTH / 8..WIDTH / 8 + 2], [0x03, 0xC0]);
+        assert!(packed[2..WIDTH / 8].iter().chain(&packed[WIDTH / 8 * 2..]).all(|&byte| byte == 0));
+
+        chip8.display.set_hires(true);
+        chip8.execute_opcode(0xD012);
+        assert_eq!(chip8.packed_rows().len(), HIRES_WIDTH / 8 * HIRES_HEIGHT, "rows follow the active width");
+        assert_eq!(chip8.packed_rows()[HIRES_WIDTH / 8..HIRES_WIDTH / 8 + 2], [0x03, 0xC0]);
+    }
+
+    #[test]
+    fn protected_writes_are_reported_with_the_writing_instruction() {
+        let mut chip8 = Chip8::new();
+        chip8.protect_region(0x300..0x310);
+        chip8.load_rom_bytes(&[0xA3, 0x00, 0x60, 0x2A, 0xF0, 0x55]).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert_eq!(chip8.take_protection_violation(), Some(ProtectionViolation { pc: 0x204, addr: 0x300, value: 0x2A }));
+        assert_eq!(chip8.peek(0x300), Some(0x2A), "the write lands outside strict mode");
+
+        chip8.reset();
+        chip8.set_strict_protection(true);
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert!(chip8.take_protection_violation().is_some(), "regions survive reset");
+        assert_eq!(chip8.peek(0x300), Some(0));
+        assert!(chip8.take_bus_fault().is_some());
+    }
+
+    #[test]
+    fn the_change_stream_follows_draws_clears_and_scrolls() {
+        let mut chip8 = Chip8::new();
+        chip8.poke(0x300, 0x81).unwrap();
+        chip8.set_index(0x300);
+        chip8.set_register(0, 2);
+        chip8.set_register(1, 3);
+
+        chip8.execute_opcode(0xD011);
+        assert_eq!(chip8.lit_pixels().collect::<Vec<_>>(), [(2, 3), (9, 3)]);
+        assert_eq!(chip8.take_changes().collect::<Vec<_>>(), [(2, 3, true), (9, 3, true)]);
+        assert_eq!(chip8.take_changes().count(), 0, "nothing new since the last call");
+
+        chip8.execute_opcode(0x00E0);
+        assert_eq!(chip8.lit_pixels().count(), 0);
+        assert_eq!(chip8.take_changes().collect::<Vec<_>>(), [(2, 3, false), (9, 3, false)]);
+
+        chip8.execute_opcode(0xD011);
+        chip8.take_changes().for_each(drop);
+        chip8.execute_opcode(0x00FB);                   // Scroll right 4 pixels
+        let mut changes: Vec<_> = chip8.take_changes().collect();
+        changes.sort();
+        assert_eq!(changes, [(2, 3, false), (6, 3, true), (9, 3, false), (13, 3, true)]);
+
+        // Changes left unread are reported by the next call
+        chip8.execute_opcode(0x00E0);
+        assert_eq!(chip8.take_changes().next(), Some((6, 3, false)));
+        assert_eq!(chip8.take_changes().collect::<Vec<_>>(), [(13, 3, false)]);
+    }
+
+    #[test]
+    fn a_resolution_switch_reports_every_pixel() {
+        let mut chip8 = Chip8::new();
+        chip8.poke(0x300, 0x80).unwrap();
+        chip8.set_index(0x300);
+        chip8.execute_opcode(0xD011);
+        chip8.take_changes().for_each(drop);
+
+        chip8.execute_opcode(0x00FF);
+        assert_eq!(chip8.resolution(), (HIRES_WIDTH, HIRES_HEIGHT));
+        chip8.set_register(0, 100);
+        chip8.set_register(1, 50);
+        chip8.execute_opcode(0xD011);
+        assert_eq!(chip8.take_changes().next(), Some((0, 0, false)), "dark pixels too");
+        let changes: Vec<_> = chip8.take_changes().collect();
+        assert_eq!(changes.len(), HIRES_WIDTH * HIRES_HEIGHT, "a partly read switch is reported again in full");
+        assert_eq!(changes.iter().filter(|&&(_, _, lit)| lit).collect::<Vec<_>>(), [&(100, 50, true)]);
+        assert_eq!(chip8.take_changes().count(), 0, "only once read to the end");
+
+        chip8.execute_opcode(0x00FE);
+        assert_eq!(chip8.take_changes().count(), WIDTH * HEIGHT);
+    }
+
+    #[test]
+    fn loaded_roms_are_hashed_once_per_load() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(b"abc").unwrap();
+        assert_eq!(chip8.rom_sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(chip8.rom_sha1()[..4], [0xA9, 0x99, 0x3E, 0x36]);
+        chip8.step_frame(10);
+        chip8.reset();
+        assert_eq!(chip8.rom_sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        chip8.load_rom_bytes(&[]).unwrap();
+        assert_eq!(chip8.rom_sha1_hex(), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn known_bad_dumps_are_warned_about() {
+        const FIXTURE: &[BadDump] = &[
+            BadDump { sha1: "9980488ed36a7070ce771e98eb60580217d266bb", issue: "test fixture", good_sha1: Some("a9993e364706816aba3e25717850c26c9cd0d89d") },
+            BadDump { sha1: "da39a3ee5e6b4b0d3255bfef95601890afd80709", issue: "empty", good_sha1: None },
+        ];
+        let mut chip8 = Chip8::new();
+        let logged = crate::testlog::capture(|| {
+            chip8.load_rom_bytes(b"bad dump").unwrap();
+            warn_if_bad_dump(FIXTURE, &chip8.rom_sha1_hex());
+            warn_if_bad_dump(FIXTURE, "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        });
+        assert_eq!(logged, [
+            (log::Level::Info, "Loaded ROM: 8 bytes, SHA-1 9980488ed36a7070ce771e98eb60580217d266bb".to_string()),
+            (log::Level::Warn, "This is a known bad dump: test fixture. A good dump has SHA-1 a9993e364706816aba3e25717850c26c9cd0d89d.".to_string()),
+            (log::Level::Warn, "This is a known bad dump: empty.".to_string()),
+        ]);
+
+        let logged = crate::testlog::capture(|| warn_if_bad_dump(FIXTURE, "a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert!(logged.is_empty(), "{:?}", logged);
+        assert_eq!(known_bad_dump(KNOWN_BAD_DUMPS, "9980488ed36a7070ce771e98eb60580217d266bb"), None, "the fixture stays out of the real table");
+    }
+
+    #[test]
+    fn the_grid_extension_only_runs_with_extensions_enabled() {
+        let rom = [0x0F, 0xFF, 0x0F, 0xFF];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.cycle();
+        assert!(!chip8.grid_overlay());
+        assert_eq!(chip8.last_unknown_opcode(), Some(0x0FFF));
+        assert_eq!(chip8.pc(), 0x202);
+
+        let mut chip8 = Chip8::new();
+        chip8.extensions = true;
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.cycle();
+        assert!(chip8.grid_overlay());
+        assert_eq!(chip8.last_unknown_opcode(), None);
+        chip8.cycle();
+        assert!(!chip8.grid_overlay(), "a second 0FFF toggles the grid back off");
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn heatmap_counts_fetches_as_executes() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA3, 0x00, 0xF0, 0x65]).unwrap();
+        chip8.set_heatmap(true);
+        chip8.cycle();
+        chip8.cycle();
+        let heatmap = chip8.heatmap().unwrap();
+        assert_eq!(heatmap.executes()[0x200], 1);
+        assert_eq!(heatmap.reads()[0x200], 0);
+        assert_eq!(heatmap.reads()[0x300], 1);
+    }
+
+    #[test]
+    fn heatmap_counts_a_loop_of_stores_and_loads() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[
+            0x61, 0x03,                         // v1 = 3
+            0xA3, 0x00,                         // loop: I = 0x300
+            0xF0, 0x55,                         // store v0 at 0x300
+            0xF0, 0x65,                         // load v0 from 0x300
+            0x71, 0xFF,                         // v1 -= 1
+            0x31, 0x00,                         // skip the jump back once v1 is 0
+            0x12, 0x02,
+            0x12, 0x0E,                         // halt
+        ]).unwrap();
+        chip8.set_heatmap(true);
+        for _ in 0..19 {
+            chip8.cycle();
+        }
+        let heatmap = chip8.heatmap().unwrap();
+        assert_eq!(heatmap.executes()[0x200], 1);
+        assert_eq!(heatmap.executes()[0x202], 3);
+        assert_eq!(heatmap.executes()[0x203], 3, "both bytes of an opcode are fetched");
+        assert_eq!(heatmap.executes()[0x20C], 2);
+        assert_eq!(heatmap.executes()[0x20E], 1);
+        assert_eq!((heatmap.reads()[0x300], heatmap.writes()[0x300]), (3, 3));
+        assert_eq!((heatmap.reads()[0x301], heatmap.writes()[0x301]), (0, 0));
+        assert_eq!(heatmap.hottest(2), [
+            crate::memory::HotRange { range: 0x200..0x210, reads: 0, writes: 0, executes: 38 },
+            crate::memory::HotRange { range: 0x300..0x301, reads: 3, writes: 3, executes: 0 },
+        ]);
+    }
+
+    #[test]
+    fn trim_rom_drops_trailing_zeros_and_keeps_an_even_length() {
+        assert_eq!(trim_rom(&[0x12, 0x00, 0x60, 0x00, 0x00, 0x00]), [0x12, 0x00, 0x60, 0x00], "the last opcode keeps its zero low byte");
+        assert_eq!(trim_rom(&[0x12, 0x00, 0x00, 0x05, 0x00, 0x00]), [0x12, 0x00, 0x00, 0x05]);
+        assert_eq!(trim_rom(&[0x12, 0x00, 0xFF]), [0x12, 0x00, 0xFF], "an odd length ROM isn't cut");
+        assert!(trim_rom(&[0; 16]).is_empty());
+        assert!(trim_rom(&[]).is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn state_json_and_debug_carry_the_quirks_and_preset() {
+        use crate::analysis::Platform;
+        use serde_json::Value;
+        let mut chip8 = Chip8::new();
+        chip8.quirks = Platform::SuperChip.quirks();
+        let state: Value = serde_json::from_str(&chip8.state_json().to_string()).unwrap();
+        assert_eq!(state.get("preset").and_then(Value::as_str), Some(Platform::SuperChip.to_string().as_str()));
+        let quirks = state.get("quirks").unwrap();
+        assert_eq!(quirks.get("clip_sprites"), Some(&Value::Bool(true)));
+        assert_eq!(quirks.get("load_store_increment"), Some(&Value::Bool(false)));
+        assert_eq!(quirks.get("shift_vy"), Some(&Value::Bool(false)));
+        assert_eq!(quirks.get("index_width"), Some(&Value::from(12)));
+        let debug = format!("{:?}", chip8);
+        assert!(debug.contains("shift_vy: false") && debug.contains(&format!("preset: {:?}", Platform::SuperChip.to_string())), "{}", debug);
+
+        chip8.quirks.shift_vy = true;
+        let state = chip8.state_json();
+        assert_eq!(state.get("preset").and_then(Value::as_str), Some("custom"));
+        assert_eq!(state.get("quirks").and_then(|quirks| quirks.get("shift_vy")), Some(&Value::Bool(true)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn fitting_roms_report_every_byte_loaded() {
+        let rom = [0x00, 0xE0, 0x12, 0x02, 0xAB];
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.load_rom_bytes(&rom).unwrap(), rom.len());
+        assert_eq!(chip8.peek(PROGRAM_START + 4), Some(0xAB));
+
+        let full = vec![0x12; 4096 - PROGRAM_START];
+        assert_eq!(chip8.load_rom_bytes(&full).unwrap(), full.len(), "a ROM filling memory exactly still fits");
+
+        let path = std::env::temp_dir().join(format!("chip8-load-{}.ch8", std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+        let loaded = chip8.load_rom(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(loaded.unwrap(), rom.len());
+        assert!(matches!(chip8.load_rom("no/such/rom.ch8"), Err(Chip8Error::Io(_))));
+    }
+
+    #[test]
+    fn changed_pixel_counts_match_the_change_stream_frame_by_frame() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05, 0x12, 0x06]).unwrap();
+        let mut counts = Vec::new();
+        for cycles in [2, 1, 1] {
+            let before = chip8.display.to_vec();
+            chip8.step_frame(cycles);
+            let changed = display::changed_pixels(&before, &chip8.display);
+            assert_eq!(changed, chip8.take_changes().count());
+            counts.push(changed);
+        }
+        assert_eq!(counts, [14, 14, 0], "the 0 glyph drawn, erased, then nothing");
+    }
+
+    #[test]
+    fn xo_chip_audio_opcodes_load_the_pattern_and_pitch() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA3, 0x00, 0xF0, 0x02, 0x65, 0x70, 0xF5, 0x3A, 0xF1, 0x02]).unwrap();
+        let pattern: [u8; PATTERN_SIZE] = core::array::from_fn(|at| at as u8 * 0x11);
+        for (at, &byte) in pattern.iter().enumerate() {
+            chip8.poke(0x300 + at, byte).unwrap();
+        }
+        assert_eq!((chip8.audio_pattern(), chip8.pitch()), (None, DEFAULT_PITCH), "the plain buzzer until F002");
+        chip8.cycle();
+        chip8.cycle();
+        assert_eq!(chip8.audio_pattern(), Some(pattern));
+        chip8.poke(0x300, 0xAB).unwrap();
+        assert_eq!(chip8.audio_pattern(), Some(pattern), "the pattern is a copy, not a view of memory");
+        chip8.cycle();
+        chip8.cycle();
+        assert_eq!(chip8.pitch(), 0x70);
+        chip8.cycle();
+        assert_eq!(chip8.last_unknown_opcode(), Some(0xF102), "only F002 loads a pattern");
+
+        chip8.reset();
+        assert_eq!((chip8.audio_pattern(), chip8.pitch()), (None, DEFAULT_PITCH));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::chip8::{HEIGHT, WIDTH};
+
+// CHIP-8X, the interpreter for the COSMAC VIP's color board: a background color for the whole screen and
+// a foreground color for lit pixels, set per zone. The color RAM it drives is 8 pixels across and one row
+// down at 64x32, BXYN colors it row by row and BXY0 in blocks of 8x4 pixels
+
+pub const ZONE_COLUMNS: usize = WIDTH / 8;
+pub const ZONE_ROWS: usize = HEIGHT;
+pub const BLOCK_ROWS: usize = 4;        // Zone rows in a BXY0 block
+pub const DEFAULT_FOREGROUND: u8 = 1;   // Red, what a lit pixel shows before any BXY0/BXYN
+
+// The color board's foreground colors, by the number BXY0/BXYN take
+pub const FOREGROUND_RGBA: [[u8; 4]; 8] = [
+    [0, 0, 0, 255],                     // Black
+    [255, 0, 0, 255],                   // Red
+    [0, 0, 255, 255],                   // Blue
+    [255, 0, 255, 255],                 // Violet
+    [0, 255, 0, 255],                   // Green
+    [255, 255, 0, 255],                 // Yellow
+    [0, 255, 255, 255],                 // Aqua
+    [255, 255, 255, 255],               // White
+];
+
+// The background colors in the order 02A0 steps through them
+pub const BACKGROUND_RGBA: [[u8; 4]; 4] = [
+    [0, 0, 128, 255],                   // Blue
+    [0, 0, 0, 255],                     // Black
+    [0, 128, 0, 255],                   // Green
+    [128, 0, 0, 255],                   // Red
+];
+
+// Bytes of ColorMap::to_bytes: the background, then a foreground per zone
+pub const COLOR_STATE_SIZE: usize = 1 + ZONE_COLUMNS * ZONE_ROWS;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorMap {
+    background: u8,                     // Index into BACKGROUND_RGBA
+    zones: [u8; ZONE_COLUMNS * ZONE_ROWS],  // Index into FOREGROUND_RGBA, row by row
+}
+
+impl Default for ColorMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ColorMap {
+    pub fn new() -> Self {
+        ColorMap { background: 0, zones: [DEFAULT_FOREGROUND; ZONE_COLUMNS * ZONE_ROWS] }
+    }
+
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    // 02A0: the next background color, back to blue after red
+    pub fn step_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUND_RGBA.len() as u8;
+    }
+
+    // Foreground color of the zone at zone column x, row y
+    pub fn zone(&self, x: usize, y: usize) -> u8 {
+        self.zones[x % ZONE_COLUMNS + (y % ZONE_ROWS) * ZONE_COLUMNS]
+    }
+
+    // Foreground color a lit pixel at (x, y) shows on a width x height screen. A hires screen takes the
+    // zone its pixel falls in at 64x32
+    pub fn foreground_at(&self, x: usize, y: usize, (width, height): (usize, usize)) -> u8 {
+        self.zone(x * WIDTH / width.max(1) / 8, y * HEIGHT / height.max(1))
+    }
+
+    // BXY0: color a block of zones. The low nibble of columns is the first zone across and the high nibble
+    // how many more follow it, rows the same in blocks of BLOCK_ROWS rows. Zones off the map are dropped
+    pub fn fill_blocks(&mut self, columns: u8, rows: u8, color: u8) {
+        let across = (columns & 0x0F) as usize..=((columns & 0x0F) + (columns >> 4)) as usize;
+        let down = (rows & 0x0F) as usize..=((rows & 0x0F) + (rows >> 4)) as usize;
+        for block in down {
+            for row in block * BLOCK_ROWS..(block + 1) * BLOCK_ROWS {
+                self.fill_row(across.clone(), row, color);
+            }
+        }
+    }
+
+    // BXYN: color n rows of the zone holding pixel (x, y)
+    pub fn fill_rows(&mut self, x: u8, y: u8, n: u8, color: u8) {
+        let column = (x as usize % WIDTH) / 8;
+        for row in y as usize..y as usize + n as usize {
+            self.fill_row(column..=column, row, color);
+        }
+    }
+
+    fn fill_row(&mut self, columns: core::ops::RangeInclusive<usize>, row: usize, color: u8) {
+        if row >= ZONE_ROWS {
+            return;
+        }
+        for column in columns.filter(|&column| column < ZONE_COLUMNS) {
+            self.zones[column + row * ZONE_COLUMNS] = color & 0x07;
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; COLOR_STATE_SIZE] {
+        let mut bytes = [0; COLOR_STATE_SIZE];
+        bytes[0] = self.background;
+        bytes[1..].copy_from_slice(&self.zones);
+        bytes
+    }
+
+    // Colors out of range are wrapped into it, so a corrupt state can't index past the palettes
+    pub fn from_bytes(bytes: &[u8; COLOR_STATE_SIZE]) -> Self {
+        let mut map = ColorMap { background: bytes[0] % BACKGROUND_RGBA.len() as u8, zones: [0; ZONE_COLUMNS * ZONE_ROWS] };
+        for (zone, &color) in map.zones.iter_mut().zip(&bytes[1..]) {
+            *zone = color & 0x07;
+        }
+        map
+    }
+}
+
+// 5XY1: vX + vY with each octal digit of the low and high nibbles added on its own, carries dropped
+pub fn add_octal(x: u8, y: u8) -> u8 {
+    ((x & 0x77) + (y & 0x77)) & 0x77
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_steps_through_four_colors() {
+        let mut map = ColorMap::new();
+        let seen: Vec<u8> = (0..5).map(|_| {
+            let color = map.background();
+            map.step_background();
+            color
+        }).collect();
+        assert_eq!(seen, [0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn blocks_cover_start_through_start_plus_extent() {
+        let mut map = ColorMap::new();
+        map.fill_blocks(0x12, 0x01, 4);
+        for y in 0..ZONE_ROWS {
+            for x in 0..ZONE_COLUMNS {
+                let inside = (2..=3).contains(&x) && (4..8).contains(&y);
+                assert_eq!(map.zone(x, y), if inside { 4 } else { DEFAULT_FOREGROUND }, "zone ({}, {})", x, y);
+            }
+        }
+        map.fill_blocks(0xF7, 0xF7, 2);
+        assert_eq!(map.zone(7, 31), 2, "clipped to the map");
+    }
+
+    #[test]
+    fn rows_color_one_zone_column() {
+        let mut map = ColorMap::new();
+        map.fill_rows(20, 30, 5, 0x0E);
+        assert_eq!((map.zone(2, 30), map.zone(2, 31)), (6, 6), "colors are 3 bits");
+        assert_eq!((map.zone(2, 29), map.zone(1, 30), map.zone(2, 0)), (1, 1, 1), "rows past the bottom are dropped");
+    }
+
+    #[test]
+    fn hires_pixels_take_the_lores_zone_they_fall_in() {
+        let mut map = ColorMap::new();
+        map.fill_rows(8, 3, 1, 5);
+        assert_eq!(map.foreground_at(8, 3, (64, 32)), 5);
+        assert_eq!(map.foreground_at(17, 7, (128, 64)), 5);
+        assert_eq!(map.foreground_at(15, 7, (128, 64)), DEFAULT_FOREGROUND);
+    }
+
+    #[test]
+    fn octal_digits_add_without_carries() {
+        assert_eq!(add_octal(0x15, 0x14), 0x21);
+        assert_eq!(add_octal(0x77, 0x11), 0x00);
+        assert_eq!(add_octal(0xFF, 0x00), 0x77, "bit 3 of each nibble is dropped");
+    }
+
+    #[test]
+    fn color_state_round_trips() {
+        let mut map = ColorMap::new();
+        map.step_background();
+        map.fill_blocks(0x00, 0x00, 3);
+        assert_eq!(ColorMap::from_bytes(&map.to_bytes()), map);
+    }
+}
@@ -0,0 +1,82 @@
+use sdl2::VideoSubsystem;
+
+use chip8::display::Display;
+#[cfg(feature = "clipboard")]
+use chip8::render;
+
+#[cfg(feature = "clipboard")]
+const SCREENSHOT_WIDTH: usize = 512;    // Pixels across a copied screenshot at either resolution
+
+// One line per row, # for a lit pixel and . for a dark one
+pub fn text_art(display: &Display) -> String {
+    let mut text = String::with_capacity((display.width() + 1) * display.height());
+    for row in display.chunks(display.width()) {
+        text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+        text.push('\n');
+    }
+    text
+}
+
+// The screen as RGBA pixels SCREENSHOT_WIDTH across, with the width and height
+#[cfg(feature = "clipboard")]
+pub fn screenshot_rgba(display: &Display) -> (usize, usize, Vec<u8>) {
+    let scale = SCREENSHOT_WIDTH / display.width();
+    let mut frame = Vec::new();
+    render::render_rgba(display, display.width(), render::WHITE, render::BLACK, scale, &mut frame);
+    (display.width() * scale, display.height() * scale, frame)
+}
+
+// Copy the screen as an image through arboard, falling back to text art through SDL when this build has
+// no clipboard feature or the host clipboard won't take an image. Returns what was copied, for the toast
+pub fn copy_screen(display: &Display, video: &VideoSubsystem) -> Result<&'static str, String> {
+    #[cfg(feature = "clipboard")]
+    match copy_image(display) {
+        Ok(()) => return Ok("copied screenshot"),
+        Err(err) => log::warn!("{}, copying the screen as text", err),
+    }
+    video.clipboard().set_clipboard_text(&text_art(display))?;
+    Ok("copied screen as text")
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_image(display: &Display) -> Result<(), String> {
+    let (width, height, rgba) = screenshot_rgba(display);
+    let image = arboard::ImageData { width, height, bytes: rgba.into() };
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_image(image))
+        .map_err(|err| format!("could not copy the screenshot: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_art_draws_a_row_per_line() {
+        let mut display = Display::new();
+        display.set_pixel(0, 0, true);
+        display.set_pixel(63, 31, true);
+        let art = text_art(&display);
+        let lines: Vec<&str> = art.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.len() == 64));
+        assert!(lines[0].starts_with("#.") && lines[31].ends_with(".#"));
+
+        display.set_hires(true);
+        assert_eq!(text_art(&display).lines().count(), 64, "hires copies all 128x64");
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[test]
+    fn screenshots_are_the_same_width_at_both_resolutions() {
+        let mut display = Display::new();
+        display.set_pixel(0, 0, true);
+        let (width, height, lores) = screenshot_rgba(&display);
+        assert_eq!((width, height), (SCREENSHOT_WIDTH, SCREENSHOT_WIDTH / 2));
+        assert_eq!(lores.len(), width * height * 4);
+        assert_eq!(lores[..4], render::WHITE, "the lit corner pixel");
+        display.set_hires(true);
+        let (width, height, _) = screenshot_rgba(&display);
+        assert_eq!((width, height), (SCREENSHOT_WIDTH, SCREENSHOT_WIDTH / 2));
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+use crate::analysis::{self, Platform};
+use crate::chip8::{fnv1a, Chip8, Chip8Error};
+use crate::cpu;
+use crate::savestate;
+
+// Compatibility check: a ROM run headless under each built-in platform profile, to see which one it
+// behaves under. Runs are seeded and get no input, so the same ROM always gives the same results
+
+pub const PROFILES: [Platform; 3] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+const SEED: u64 = 0;
+
+// What stopped a run early
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    StackOverflow { pc: u16 },          // 2NNN with every stack entry in use
+    StackUnderflow { pc: u16 },         // 00EE with nothing to return to
+    UnknownOpcode { pc: u16, opcode: u16 },
+    OutOfBounds { pc: u16 },            // pc or a memory access past the end of memory
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:#05X}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "stack underflow at {:#05X}", pc),
+            Fault::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc),
+            Fault::OutOfBounds { pc } => write!(f, "out of bounds access at {:#05X}", pc),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileRun {
+    pub platform: Platform,
+    pub fault: Option<Fault>,
+    pub drew: bool,                     // A frame ended with at least one pixel lit
+    pub display_hash: u64,              // FNV-1a of the display when the run ended
+    pub frames: u64,                    // Frames run before the end or the fault
+}
+
+impl ProfileRun {
+    // Clean runs that drew something rank first, then clean runs, then faulted ones
+    fn rank(&self) -> u8 {
+        match (&self.fault, self.drew) {
+            (None, true) => 0,
+            (None, false) => 1,
+            (Some(_), _) => 2,
+        }
+    }
+}
+
+// Which profile a ROM seems happiest under
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Any,                                // Every profile ran cleanly to the same picture
+    Best(Platform),
+    Broken,                             // Faulted under every profile
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Any => write!(f, "any"),
+            Verdict::Best(platform) => write!(f, "{}", platform),
+            Verdict::Broken => write!(f, "none"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompatReport {
+    pub name: String,
+    pub runs: Vec<ProfileRun>,
+    pub verdict: Verdict,
+}
+
+// Run a ROM under every profile for the given number of frames, Err when it doesn't fit in memory
+pub fn check(name: &str, rom: &[u8], frames: u64, cycles_per_frame: usize) -> Result<CompatReport, Chip8Error> {
+    let runs = PROFILES.iter().map(|&platform| run_profile(rom, platform, frames, cycles_per_frame)).collect::<Result<Vec<_>, _>>()?;
+    let verdict = verdict(&runs, analysis::detect_quirks(rom).platform);
+    Ok(CompatReport { name: name.to_string(), runs, verdict })
+}
+
+// The best ranked profile. Ties between clean runs that end on different pictures go to the platform
+// static analysis guessed when it is among them, otherwise to the earliest profile
+pub fn verdict(runs: &[ProfileRun], detected: Platform) -> Verdict {
+    let Some(best) = runs.iter().map(ProfileRun::rank).min() else {
+        return Verdict::Broken;
+    };
+    if best == 2 {
+        return Verdict::Broken;
+    }
+    let tied: Vec<&ProfileRun> = runs.iter().filter(|run| run.rank() == best).collect();
+    if tied.len() == runs.len() && tied.iter().all(|run| run.display_hash == tied[0].display_hash) {
+        return Verdict::Any;
+    }
+    let pick = tied.iter().find(|run| run.platform == detected).unwrap_or(&tied[0]);
+    Verdict::Best(pick.platform)
+}
+
+pub fn run_profile(rom: &[u8], platform: Platform, frames: u64, cycles_per_frame: usize) -> Result<ProfileRun, Chip8Error> {
+    let mut chip8 = Chip8::new();
+    let loaded = chip8.load_rom_bytes(rom)?;
+    if loaded < rom.len() {                 // A cut off ROM would be judged on code it doesn't have
+        return Err(Chip8Error::RomTooLarge { len: rom.len(), capacity: loaded });
+    }
+    chip8.set_seed(SEED);
+    chip8.quirks = platform.quirks();
+
+    let mut drew = false;
+    let mut ran = 0;
+    let mut fault = None;
+    'frames: for _ in 0..frames {
+        for _ in 0..cycles_per_frame {
+            if let Some(found) = step(&mut chip8) {
+                fault = Some(found);
+                break 'frames;
+            }
+        }
+        chip8.tick_timers();
+        drew |= chip8.display.contains(&1);
+        ran += 1;
+    }
+
+    Ok(ProfileRun { platform, fault, drew, display_hash: fnv1a(&chip8.display), frames: ran })
+}
+
+// Two cores running one ROM under two profiles, for picking between them by eye. Both get the same seed
+// and the same keys every frame, so the first frame their displays hash differently is where the
+// profiles start to matter for this ROM
+pub struct Comparison {
+    pub cores: [Chip8; 2],
+    pub platforms: [Platform; 2],
+    diverged: Option<u64>,              // Frame the displays first differed on, None while they agree
+}
+
+impl Comparison {
+    pub fn new(rom: &[u8], platforms: [Platform; 2], seed: u64) -> Result<Self, Chip8Error> {
+        let mut cores = [Chip8::new(), Chip8::new()];
+        for (chip8, platform) in cores.iter_mut().zip(platforms) {
+            chip8.load_rom_bytes(rom)?;
+            chip8.set_seed(seed);
+            chip8.quirks = platform.quirks();
+        }
+        Ok(Comparison { cores, platforms, diverged: None })
+    }
+
+    // One frame on both cores with the same keys. Some(frame) only on the frame the displays first
+    // stop matching
+    pub fn frame(&mut self, keys: u16, cycles: usize) -> Option<u64> {
+        for chip8 in &mut self.cores {
+            chip8.set_keys_mask(keys);
+            chip8.step_frame(cycles);
+        }
+        if self.diverged.is_some() || fnv1a(&self.cores[0].display) == fnv1a(&self.cores[1].display) {
+            return None;
+        }
+        self.diverged = Some(self.cores[0].frame_count());
+        self.diverged
+    }
+
+    pub fn diverged(&self) -> Option<u64> {
+        self.diverged
+    }
+
+    // Both cores back to power on, which starts the search for a divergence over
+    pub fn reset(&mut self) {
+        for chip8 in &mut self.cores {
+            chip8.reset();
+        }
+        self.diverged = None;
+    }
+
+    // Savestates of both cores and the divergence seen so far, restored together by load
+    pub fn save(&self) -> ([Vec<u8>; 2], Option<u64>) {
+        (self.cores.each_ref().map(|chip8| savestate::encode(chip8, false, None)), self.diverged)
+    }
+
+    pub fn load(&mut self, (states, diverged): &([Vec<u8>; 2], Option<u64>)) -> Result<(), String> {
+        for (chip8, state) in self.cores.iter_mut().zip(states) {
+            savestate::decode(chip8, state)?;
+        }
+        self.diverged = *diverged;
+        Ok(())
+    }
+}
+
+// One instruction, with what the core reported going wrong in it: a CPU fault, an access the bus
+// refused (pc running off the end of memory included) or an opcode that didn't decode
+fn step(chip8: &mut Chip8) -> Option<Fault> {
+    let pc = chip8.pc();
+    chip8.cycle();
+    match chip8.fault() {
+        Some(cpu::Fault::StackOverflow) => return Some(Fault::StackOverflow { pc }),
+        Some(cpu::Fault::StackUnderflow) => return Some(Fault::StackUnderflow { pc }),
+        Some(cpu::Fault::PcOutOfRange) => return Some(Fault::OutOfBounds { pc }),
+        None => {}
+    }
+    if chip8.take_bus_fault().is_some() {
+        return Some(Fault::OutOfBounds { pc });
+    }
+    // Runs stop at their first fault, so any unknown opcode on record is this one
+    chip8.last_unknown_opcode().map(|opcode| Fault::UnknownOpcode { pc, opcode })
+}
+
+// Reports as a JSON array, one object per ROM with its verdict and every profile's run
+pub fn to_json(reports: &[CompatReport]) -> String {
+    let roms: Vec<String> = reports.iter().map(|report| {
+        let runs: Vec<String> = report.runs.iter().map(|run| {
+            let fault = run.fault.as_ref().map_or("null".to_string(), |fault| json_string(&fault.to_string()));
+            format!("{{\"profile\": {}, \"fault\": {}, \"drew\": {}, \"display_hash\": \"{:016x}\", \"frames\": {}}}",
+                json_string(&run.platform.to_string()), fault, run.drew, run.display_hash, run.frames)
+        }).collect();
+        format!("  {{\"rom\": {}, \"verdict\": {}, \"runs\": [\n    {}\n  ]}}",
+            json_string(&report.name), json_string(&report.verdict.to_string()), runs.join(",\n    "))
+    }).collect();
+    format!("[\n{}\n]\n", roms.join(",\n"))
+}
+
+// Text table, one row per ROM with a column per profile
+pub fn to_table(reports: &[CompatReport]) -> String {
+    let name_width = reports.iter().map(|report| report.name.len()).max().unwrap_or(0).max(3);
+    let mut out = format!("{:<width$}  {:<12}", "ROM", "VERDICT", width = name_width);
+    for platform in PROFILES {
+        out.push_str(&format!("  {:<40}", platform.to_string()));
+    }
+    out.push('\n');
+    for report in reports {
+        out.push_str(&format!("{:<width$}  {:<12}", report.name, report.verdict.to_string(), width = name_width));
+        for run in &report.runs {
+            let status = match &run.fault {
+                Some(fault) => fault.to_string(),
+                None if run.drew => format!("ok {:016x}", run.display_hash),
+                None => "ok, nothing drawn".to_string(),
+            };
+            out.push_str(&format!("  {:<40}", status));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v0 = v1 >> 1 through 8016, then return with nothing on the stack unless v0 is 1, the shift of vY
+    // CHIP-8 and XO-CHIP make, and draw the 0 glyph
+    const SHIFTS_VY: [u8; 16] = [0x60, 0x00, 0x61, 0x02, 0x80, 0x16, 0x30, 0x01, 0x00, 0xEE, 0xA0, 0x50, 0xD0, 0x05, 0x12, 0x0E];
+    // The same but surviving only when 8016 shifts v0 itself, as on SUPER-CHIP
+    const SHIFTS_VX: [u8; 16] = [0x60, 0x00, 0x61, 0x02, 0x80, 0x16, 0x30, 0x00, 0x00, 0xEE, 0xA0, 0x50, 0xD0, 0x05, 0x12, 0x0E];
+
+    #[test]
+    fn roms_get_the_profile_they_run_under() {
+        let vy = check("vy.ch8", &SHIFTS_VY, 10, 10).unwrap();
+        assert_eq!(vy.verdict, Verdict::Best(Platform::Chip8));
+        assert_eq!(vy.runs[1].fault, Some(Fault::StackUnderflow { pc: 0x208 }));
+        assert!(vy.runs[0].drew && vy.runs[2].drew);
+        assert_eq!(vy.runs[0].display_hash, vy.runs[2].display_hash);
+        assert_eq!((vy.runs[0].frames, vy.runs[1].frames), (10, 0));
+
+        let vx = check("vx.ch8", &SHIFTS_VX, 10, 10).unwrap();
+        assert_eq!(vx.verdict, Verdict::Best(Platform::SuperChip));
+        assert!(vx.runs[1].fault.is_none() && vx.runs[1].drew);
+        assert!(vx.runs[0].fault.is_some() && vx.runs[2].fault.is_some());
+
+        let json = to_json(&[vy, vx]);
+        assert!(json.contains("\"rom\": \"vy.ch8\", \"verdict\": \"CHIP-8\""), "{}", json);
+        assert!(json.contains("\"rom\": \"vx.ch8\", \"verdict\": \"SUPER-CHIP\""), "{}", json);
+        assert!(json.contains("\"fault\": \"stack underflow at 0x208\""));
+    }
+
+    #[test]
+    fn agreeing_and_failing_profiles_have_their_own_verdicts() {
+        let glyph = check("glyph.ch8", &[0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04], 10, 10).unwrap();
+        assert_eq!(glyph.verdict, Verdict::Any);
+        let broken = check("ret.ch8", &[0x00, 0xEE], 10, 10).unwrap();
+        assert_eq!(broken.verdict, Verdict::Broken);
+        let table = to_table(&[glyph, broken]);
+        let rows: Vec<&str> = table.lines().collect();
+        assert!(rows[1].starts_with("glyph.ch8  any "), "{}", table);
+        assert!(rows[2].starts_with("ret.ch8    none ") && rows[2].contains("stack underflow at 0x200"), "{}", table);
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_is_out_of_bounds() {
+        // Jump to the last word, a 0000 nop, and step past it
+        let run = run_profile(&[0x1F, 0xFE], Platform::Chip8, 1, 10).unwrap();
+        assert_eq!(run.fault, Some(Fault::OutOfBounds { pc: 0x1000 }));
+        let deep = check("deep.ch8", &[0x22, 0x00], 1, 20).unwrap();
+        assert!(deep.runs.iter().all(|run| run.fault == Some(Fault::StackOverflow { pc: 0x200 })));
+    }
+
+    // Counts v2 down from 40, then draws the glyph of v1 >> 1 or v0 >> 1 depending on the shift quirk.
+    // The draw is the 125th instruction, so at 10 a frame CHIP-8 and SUPER-CHIP screens agree until frame 13
+    const LATE_SHIFT: [u8; 20] = [
+        0x60, 0x05, 0x62, 0x28, 0x72, 0xFF, 0x32, 0x00, 0x12, 0x04,
+        0x61, 0x02, 0x80, 0x16, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x12,
+    ];
+
+    #[test]
+    fn comparisons_report_the_frame_the_screens_diverge() {
+        let mut comparison = Comparison::new(&LATE_SHIFT, [Platform::Chip8, Platform::SuperChip], SEED).unwrap();
+        let diverged: Vec<u64> = (0..20).filter_map(|_| comparison.frame(0, 10)).collect();
+        assert_eq!(diverged, [13], "only the first divergent frame is reported");
+        assert_eq!(comparison.diverged(), Some(13));
+        assert_eq!(comparison.cores.each_ref().map(|chip8| chip8.register(0)), [1, 2]);
+
+        comparison.reset();
+        assert_eq!(comparison.diverged(), None);
+        assert_eq!(comparison.cores[0].display, comparison.cores[1].display);
+        let saved = comparison.save();
+        assert_eq!((0..13).filter_map(|_| comparison.frame(0, 10)).collect::<Vec<_>>(), [13]);
+        comparison.load(&saved).unwrap();
+        assert_eq!(comparison.diverged(), None, "loading restores the search from before the divergence");
+        assert_eq!((0..13).filter_map(|_| comparison.frame(0, 10)).collect::<Vec<_>>(), [13]);
+
+        let mut same = Comparison::new(&LATE_SHIFT, [Platform::Chip8, Platform::XoChip], SEED).unwrap();
+        assert!((0..20).all(|_| same.frame(0, 10).is_none()), "both shift vY");
+    }
+}
@@ -0,0 +1,422 @@
+use std::fmt;
+
+use crate::chip8::Chip8;
+
+// Breakpoint conditions: comparisons over machine state joined with AND, and the expressions they
+// compare, which the debugger also evaluates on their own as watches
+//
+//   condition  = comparison { ("&&" | "and") comparison }
+//   comparison = expr ("==" | "!=" | ">=" | "<=" | ">" | "<") number
+//   expr       = term { ("+" | "-") term }
+//   term       = atom { "*" atom }
+//   atom       = number | operand | "mem[" expr "]" | "(" expr ")"
+//   operand    = "v0".."vf" | "i" | "dt" | "st" | "sp"
+//   number     = decimal | 0x hex
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    Index,
+    DelayTimer,
+    SoundTimer,
+    StackDepth,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arith {
+    Add,
+    Sub,
+    Mul,
+}
+
+// Arithmetic over machine state, evaluated with 16 bit wrapping
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(u16),
+    Operand(Operand),
+    Memory(Box<Expr>),                  // mem[ADDR], the byte at an address, 0 outside memory
+    Binary(Box<Expr>, Arith, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    pub expr: Expr,
+    pub compare: Compare,
+    pub value: u16,
+}
+
+// Every comparison has to hold, an empty condition always does
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Condition {
+    pub all: Vec<Comparison>,
+}
+
+impl Operand {
+    fn read(self, chip8: &Chip8) -> u16 {
+        match self {
+            Operand::Register(x) => chip8.register(x as usize) as u16,
+            Operand::Index => chip8.index(),
+            Operand::DelayTimer => chip8.delay_timer() as u16,
+            Operand::SoundTimer => chip8.sound_timer() as u16,
+            Operand::StackDepth => chip8.stack().len() as u16,
+        }
+    }
+}
+
+impl Arith {
+    fn precedence(self) -> u8 {
+        match self {
+            Arith::Add | Arith::Sub => 0,
+            Arith::Mul => 1,
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, chip8: &Chip8) -> u16 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Operand(operand) => operand.read(chip8),
+            Expr::Memory(addr) => chip8.peek(addr.eval(chip8) as usize).unwrap_or(0) as u16,
+            Expr::Binary(lhs, arith, rhs) => {
+                let (lhs, rhs) = (lhs.eval(chip8), rhs.eval(chip8));
+                match arith {
+                    Arith::Add => lhs.wrapping_add(rhs),
+                    Arith::Sub => lhs.wrapping_sub(rhs),
+                    Arith::Mul => lhs.wrapping_mul(rhs),
+                }
+            }
+        }
+    }
+}
+
+impl Comparison {
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        let actual = self.expr.eval(chip8);
+        match self.compare {
+            Compare::Eq => actual == self.value,
+            Compare::Ne => actual != self.value,
+            Compare::Ge => actual >= self.value,
+            Compare::Le => actual <= self.value,
+            Compare::Gt => actual > self.value,
+            Compare::Lt => actual < self.value,
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        self.all.iter().all(|comparison| comparison.holds(chip8))
+    }
+}
+
+pub fn parse(text: &str) -> Result<Condition, String> {
+    let mut tokens = Tokens::new(text)?;
+    let mut all = Vec::new();
+    loop {
+        let expr = tokens.expr()?;
+        let compare = tokens.next().ok_or("expected a comparison after the expression")?;
+        let value = tokens.next().ok_or_else(|| format!("expected a number after '{}'", compare))?;
+        all.push(Comparison {
+            expr,
+            compare: parse_compare(&compare)?,
+            value: parse_number(&value)?,
+        });
+
+        match tokens.next().as_deref() {
+            None => return Ok(Condition { all }),
+            Some("&&" | "and") => {}
+            Some(other) => return Err(format!("expected && between comparisons, got '{}'", other)),
+        }
+    }
+}
+
+// A lone expression, as used by watches
+pub fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut tokens = Tokens::new(text)?;
+    let expr = tokens.expr()?;
+    match tokens.next() {
+        None => Ok(expr),
+        Some(other) => Err(format!("unexpected '{}' after the expression", other)),
+    }
+}
+
+// Words, numbers and operators, spaces between them are optional
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                word.push(c.to_ascii_lowercase());
+                chars.next();
+            }
+            tokens.push(word);
+        } else if "=!<>&".contains(c) {
+            let mut op = String::new();
+            while let Some(&c) = chars.peek().filter(|c| "=!<>&".contains(**c)) {
+                op.push(c);
+                chars.next();
+            }
+            tokens.push(op);
+        } else if "+-*[]()".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}' in condition", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent over the tokens, one level per precedence
+struct Tokens {
+    tokens: std::vec::IntoIter<String>,
+    peeked: Option<String>,
+}
+
+impl Tokens {
+    fn new(text: &str) -> Result<Self, String> {
+        Ok(Tokens { tokens: tokenize(text)?.into_iter(), peeked: None })
+    }
+
+    fn next(&mut self) -> Option<String> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&str> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next();
+        }
+        self.peeked.as_deref()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.next() {
+            Some(next) if next == token => Ok(()),
+            Some(next) => Err(format!("expected '{}', got '{}'", token, next)),
+            None => Err(format!("expected '{}'", token)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(arith) = self.peek().and_then(|token| match token {
+            "+" => Some(Arith::Add),
+            "-" => Some(Arith::Sub),
+            _ => None,
+        }) {
+            self.next();
+            expr = Expr::Binary(Box::new(expr), arith, Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.atom()?;
+        while self.peek() == Some("*") {
+            self.next();
+            expr = Expr::Binary(Box::new(expr), Arith::Mul, Box::new(self.atom()?));
+        }
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.next().ok_or("expected a register, i, dt, st, sp, mem[ADDR] or a number")?;
+        match token.as_str() {
+            "mem" => {
+                self.expect("[")?;
+                let addr = self.expr()?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            "(" => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => Ok(Expr::Number(parse_number(&token)?)),
+            _ => Ok(Expr::Operand(parse_operand(&token)?)),
+        }
+    }
+}
+
+fn parse_operand(token: &str) -> Result<Operand, String> {
+    Ok(match token {
+        "i" => Operand::Index,
+        "dt" => Operand::DelayTimer,
+        "st" => Operand::SoundTimer,
+        "sp" => Operand::StackDepth,
+        _ => match token.strip_prefix('v').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+            Some(x) if token.len() == 2 => Operand::Register(x),
+            _ => return Err(format!("unknown operand '{}'", token)),
+        },
+    })
+}
+
+fn parse_compare(token: &str) -> Result<Compare, String> {
+    Ok(match token {
+        "==" => Compare::Eq,
+        "!=" => Compare::Ne,
+        ">=" => Compare::Ge,
+        "<=" => Compare::Le,
+        ">" => Compare::Gt,
+        "<" => Compare::Lt,
+        _ => return Err(format!("unknown comparison '{}'", token)),
+    })
+}
+
+pub fn parse_number(token: &str) -> Result<u16, String> {
+    let parsed = match token.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => token.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", token))
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{:#04x}", value),
+            Expr::Operand(Operand::Register(x)) => write!(f, "v{:x}", x),
+            Expr::Operand(Operand::Index) => write!(f, "i"),
+            Expr::Operand(Operand::DelayTimer) => write!(f, "dt"),
+            Expr::Operand(Operand::SoundTimer) => write!(f, "st"),
+            Expr::Operand(Operand::StackDepth) => write!(f, "sp"),
+            Expr::Memory(addr) => write!(f, "mem[{}]", addr),
+            Expr::Binary(lhs, arith, rhs) => {
+                // Parenthesize operands that would otherwise regroup when parsed back
+                let grouped = |expr: &Expr, right: bool| match expr {
+                    Expr::Binary(_, inner, _) => inner.precedence() < arith.precedence()
+                        || (right && inner.precedence() == arith.precedence() && *arith == Arith::Sub),
+                    _ => false,
+                };
+                let side = |f: &mut fmt::Formatter, expr: &Expr, right: bool| {
+                    if grouped(expr, right) { write!(f, "({})", expr) } else { write!(f, "{}", expr) }
+                };
+                side(f, lhs, false)?;
+                write!(f, " {} ", match arith { Arith::Add => "+", Arith::Sub => "-", Arith::Mul => "*" })?;
+                side(f, rhs, true)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, comparison) in self.all.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " && ")?;
+            }
+            let compare = match comparison.compare {
+                Compare::Eq => "==",
+                Compare::Ne => "!=",
+                Compare::Ge => ">=",
+                Compare::Le => "<=",
+                Compare::Gt => ">",
+                Compare::Lt => "<",
+            };
+            write!(f, "{} {} {:#04x}", comparison.expr, compare, comparison.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_register(3, 0x10);
+        chip8.set_register(4, 0x20);
+        chip8.set_index(0x300);
+        chip8.set_delay_timer(5);
+        chip8.poke(0x301, 0xAB).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn comparisons_parse_into_their_parts() {
+        let condition = parse("v3 == 0x10").unwrap();
+        assert_eq!(condition.all, [Comparison { expr: Expr::Operand(Operand::Register(3)), compare: Compare::Eq, value: 0x10 }]);
+        let condition = parse("V3!=16 and i>=0x300").unwrap();
+        assert_eq!(condition.all.len(), 2);
+        assert_eq!((condition.all[0].compare, condition.all[1].compare), (Compare::Ne, Compare::Ge));
+        assert_eq!(condition.all[1].expr, Expr::Operand(Operand::Index));
+    }
+
+    #[test]
+    fn conditions_evaluate_against_the_machine() {
+        let chip8 = machine();
+        for (text, holds) in [
+            ("v3 == 0x10", true),
+            ("v3 != 0x10", false),
+            ("v4 >= 0x20", true),
+            ("v4 > 0x20", false),
+            ("v4 < 0x21", true),
+            ("i == 0x300", true),
+            ("dt <= 5 && st == 0", true),
+            ("sp == 0", true),
+            ("v3 == 0x10 && v4 == 0x21", false),
+            ("mem[i + 1] == 0xAB", true),
+            ("v3 + v4 * 2 == 0x50", true),
+            ("(v3 + v4) * 2 == 0x60", true),
+            ("v3 - 0x11 == 0xFFFF", true),
+        ] {
+            assert_eq!(parse(text).unwrap().holds(&chip8), holds, "{}", text);
+        }
+        assert!(Condition::default().holds(&chip8), "an empty condition always holds");
+    }
+
+    #[test]
+    fn malformed_conditions_are_refused() {
+        assert_eq!(parse("v3 =="), Err("expected a number after '=='".to_string()));
+        assert_eq!(parse("v3 = 1"), Err("unknown comparison '='".to_string()));
+        assert_eq!(parse("vg == 1"), Err("unknown operand 'vg'".to_string()));
+        assert_eq!(parse("v3 == 1 || v4 == 2"), Err("unexpected '|' in condition".to_string()));
+        assert_eq!(parse("v3 == 1 v4 == 2"), Err("expected && between comparisons, got 'v4'".to_string()));
+        assert_eq!(parse("mem[i == 1"), Err("expected ']', got '=='".to_string()));
+        assert_eq!(parse_number("0x1G"), Err("invalid number '0x1G'".to_string()));
+    }
+
+    #[test]
+    fn conditions_display_as_they_parse() {
+        for text in ["v3 == 0x10 && i >= 0x300", "mem[i + 0x01] != 0x00", "(v1 + v2) * 0x02 < 0x10", "v1 - (v2 - v3) == 0x00"] {
+            let condition = parse(text).unwrap();
+            assert_eq!(condition.to_string(), text);
+            assert_eq!(parse(&condition.to_string()).unwrap(), condition);
+        }
+    }
+
+    #[test]
+    fn watch_expressions_evaluate_memory_and_arithmetic() {
+        let chip8 = machine();
+        for (text, value) in [
+            ("v3", 0x10),
+            ("i", 0x300),
+            ("mem[0x301]", 0xAB),
+            ("mem[i + 1]", 0xAB),
+            ("mem[0x2000]", 0),
+            ("v3 * 8 + v4", 0xA0),
+            ("dt", 5),
+        ] {
+            assert_eq!(parse_expr(text).unwrap().eval(&chip8), value, "{}", text);
+        }
+        assert_eq!(parse_expr("mem[0x301").unwrap_err(), "expected ']'");
+        assert_eq!(parse_expr("v3 +").unwrap_err(), "expected a register, i, dt, st, sp, mem[ADDR] or a number");
+    }
+}
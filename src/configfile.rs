@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Config files hold the same options as the command line, one per line without the leading dashes:
+//
+//   # comments and blank lines are ignored
+//   ips = 900
+//   player2 = C,D
+//   speedrun
+//
+// "name = false" leaves a switch off, any other value is passed on as the option's argument
+
+// Command line arguments equivalent to a config file, so both go through the same parser
+pub fn to_args(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.split_once(|c: char| c == '=' || c.is_whitespace()) {
+            Some((name, value)) => (name.trim(), value.trim().trim_start_matches('=').trim()),
+            None => (line, ""),
+        };
+        if name.is_empty() || name.starts_with('-') {
+            return Err(format!("line {}: expected an option name, got '{}'", line_no + 1, line));
+        }
+
+        match value {
+            "false" => {}
+            "" | "true" => args.push(format!("--{}", name)),
+            _ => {
+                args.push(format!("--{}", name));
+                args.push(value.to_string());
+            }
+        }
+    }
+    Ok(args)
+}
+
+pub fn read_args(path: &Path) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    to_args(&text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Polls a file's modification time, cheap enough to check every frame
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> Self {
+        Watcher { path: path.to_path_buf(), modified: modified(path) }
+    }
+
+    // Whether the file was written since the last check
+    pub fn changed(&mut self) -> bool {
+        let now = modified(&self.path);
+        let changed = now != self.modified;
+        self.modified = now;
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_lines_become_options() {
+        let text = "# speed\nips = 900\nplayer2 C,D\nspeedrun\nscanlines = false\nclip = true\n";
+        assert_eq!(to_args(text).unwrap(), ["--ips", "900", "--player2", "C,D", "--speedrun", "--clip"]);
+        assert_eq!(to_args("ips 1\n--ips 2").unwrap_err(), "line 2: expected an option name, got '--ips 2'");
+    }
+
+    #[test]
+    fn watchers_notice_rewrites() {
+        let path = std::env::temp_dir().join(format!("chip8-watch-{}.cfg", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut watcher = Watcher::new(&path);
+        assert!(!watcher.changed(), "a missing file stays unchanged");
+        fs::write(&path, "ips = 900\n").unwrap();
+        assert!(watcher.changed(), "creating it is a change");
+        assert!(!watcher.changed());
+        let _ = fs::remove_file(&path);
+        assert!(watcher.changed(), "so is removing it");
+    }
+}
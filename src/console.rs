@@ -0,0 +1,322 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use chip8::breakpoints::{self, Breakpoints};
+use chip8::condition::parse_number;
+use chip8::display;
+use chip8::edit;
+use chip8::pbm;
+use chip8::watch::Watches;
+use chip8::{Chip8, WIDTH, HEIGHT};
+
+// Debugger console on stdin, or for a remote debugger on a TCP port: lines are read on a thread so the
+// emulation loop never blocks on them, and run between frames so an edit never lands in the middle of an
+// instruction. Headless runs are driven by it instead, see run_until_advance
+//
+// The remote protocol is the console's: one command per line, each answered with its output (errors
+// start with "Error: ") and an empty line. Clients are served one at a time on 127.0.0.1
+pub struct Console {
+    lines: Receiver<Request>,
+    port: Option<u16>,                  // Where a remote console listens
+}
+
+// A console line and where its answer goes, stdout unless it came from a remote client
+struct Request {
+    line: String,
+    reply: Option<Sender<String>>,
+}
+
+impl Request {
+    fn answer(&self, text: &str) {
+        match &self.reply {
+            Some(reply) => {
+                let _ = reply.send(text.to_string());
+            }
+            None if text.is_empty() => {}
+            None => println!("{}", text),
+        }
+    }
+
+    fn run(&self, chip8: &mut Chip8, breakpoints: &mut Breakpoints, watches: &mut Watches) {
+        match execute(&self.line, chip8, breakpoints, watches) {
+            Ok(message) => self.answer(&message),
+            Err(err) => self.answer(&format!("Error: {}", err)),
+        }
+    }
+}
+
+impl Console {
+    pub fn spawn() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(Request { line, reply: None }).is_err() {
+                    break;
+                }
+            }
+        });
+        Console { lines, port: None }
+    }
+
+    // Listen on 127.0.0.1, port 0 picks a free one
+    pub fn listen(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+        let port = listener.local_addr().map_err(|err| err.to_string())?.port();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                if serve(stream, &sender).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Console { lines, port: Some(port) })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    // Run every line typed since the last call, answering what each did; false when there were none
+    pub fn run_pending(&self, chip8: &mut Chip8, breakpoints: &mut Breakpoints, watches: &mut Watches) -> bool {
+        let mut ran = false;
+        for request in self.lines.try_iter() {
+            if request.line.trim().is_empty() {
+                request.answer("");
+                continue;
+            }
+            request.run(chip8, breakpoints, watches);
+            ran = true;
+        }
+        ran
+    }
+
+    // Headless sessions: run commands as they arrive until "run N" asks for N frames, None once stdin
+    // closes. A remote session waits for the next client instead
+    pub fn run_until_advance(&self, chip8: &mut Chip8, breakpoints: &mut Breakpoints, watches: &mut Watches) -> Option<u64> {
+        for request in self.lines.iter() {
+            let mut words = request.line.split_whitespace();
+            if words.next() == Some("run") {
+                match words.next().map(str::parse) {
+                    Some(Ok(frames)) if frames > 0 => {
+                        if request.reply.is_some() {
+                            request.answer(&format!("running {} frames", frames));
+                        }
+                        return Some(frames);
+                    }
+                    _ => request.answer("Error: expected run N with N frames to advance"),
+                }
+                continue;
+            }
+            if request.line.trim().is_empty() {
+                request.answer("");
+            } else {
+                request.run(chip8, breakpoints, watches);
+            }
+        }
+        None
+    }
+
+    // Print the watches whose value moved at the last refresh
+    pub fn print_changes(&self, watches: &Watches) {
+        for (idx, watch) in watches.watches().iter().enumerate().filter(|(_, watch)| watch.changed()) {
+            if let Some(value) = watch.value() {
+                println!("watch {}: {} = {:#06X} ({})", idx + 1, watch.expr, value, value);
+            }
+        }
+    }
+}
+
+// One remote client: each line goes to the emulation loop and its answer back to the client. Err once
+// the loop has dropped the console
+fn serve(stream: TcpStream, sender: &Sender<Request>) -> Result<(), ()> {
+    let mut writer = stream.try_clone().map_err(|_| ())?;
+    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+        let (reply, answer) = mpsc::channel();
+        sender.send(Request { line, reply: Some(reply) }).map_err(|_| ())?;
+        let Ok(text) = answer.recv() else {
+            return Err(());
+        };
+        let framed = if text.is_empty() { "\n".to_string() } else { format!("{}\n\n", text) };
+        if writer.write_all(framed.as_bytes()).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// One console command: an edit (set, poke, push, pop), a watch (watch add EXPR, watch list, watch del N),
+// print-frame for the screen as PBM, sprite [ADDR] [HEIGHT] for the bitmap DXYN would draw, state for the
+// machine and its quirks as JSON, or a breakpoint (b, break-op)
+pub fn execute(line: &str, chip8: &mut Chip8, breakpoints: &mut Breakpoints, watches: &mut Watches) -> Result<String, String> {
+    let command = line.split_whitespace().next().unwrap_or("");
+    if command == "run" {
+        Err("run N only drives headless sessions".to_string())
+    } else if command == "print-frame" {
+        Ok(pbm::encode_pbm(WIDTH, HEIGHT, &chip8.display).trim_end().to_string())
+    } else if command == "state" {
+        Ok(chip8.state_json().to_string())
+    } else if command == "sprite" {
+        sprite(line.trim().trim_start_matches("sprite"), chip8)
+    } else if command == "watch" {
+        watch(line.trim().trim_start_matches("watch").trim(), chip8, watches)
+    } else if matches!(command, "set" | "poke" | "push" | "pop") {
+        let edit = edit::parse(line)?;
+        edit.apply(chip8)?;
+        Ok(edit.to_string())
+    } else {
+        breakpoints.apply(breakpoints::parse_command(line)?);
+        Ok(format!("added {}", line.trim()))
+    }
+}
+
+// The sprite at ADDR, I by default, HEIGHT rows tall or the shape of the draw at PC. Height 0 is the
+// SCHIP 16x16 sprite like DXY0
+fn sprite(args: &str, chip8: &Chip8) -> Result<String, String> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (addr, (width, height)) = match words.as_slice() {
+        [] => (chip8.index(), chip8.next_sprite_shape()),
+        [addr] => (parse_number(addr)?, chip8.next_sprite_shape()),
+        [addr, height] => match parse_number(height)? {
+            height @ 0..=15 => (parse_number(addr)?, display::sprite_shape(0xD000 | height)),
+            _ => return Err(format!("sprite height {} is past DXYN's 15 rows", height)),
+        },
+        _ => return Err("sprites are shown with sprite [ADDR] [HEIGHT]".to_string()),
+    };
+    let rows: Vec<String> = chip8.sprite_bitmap(addr, width, height).iter()
+        .map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect())
+        .collect();
+    Ok(format!("sprite at {:#05X}, {}x{}\n{}", addr, width, height, rows.join("\n")))
+}
+
+fn watch(args: &str, chip8: &Chip8, watches: &mut Watches) -> Result<String, String> {
+    let (command, rest) = args.split_once(' ').unwrap_or((args, ""));
+    match command {
+        "add" => {
+            let number = watches.add(rest)?;
+            let value = watches.watches()[number - 1].expr.eval(chip8);
+            Ok(format!("watch {}: {} = {:#06X} ({})", number, rest.trim(), value, value))
+        }
+        "list" if watches.is_empty() => Ok("no watches".to_string()),
+        "list" => Ok(watches.watches().iter().enumerate().map(|(idx, watch)| {
+            let value = watch.expr.eval(chip8);
+            format!("watch {}: {} = {:#06X} ({})", idx + 1, watch.expr, value, value)
+        }).collect::<Vec<_>>().join("\n")),
+        "del" => {
+            let number = rest.trim().parse().map_err(|_| format!("invalid watch number '{}'", rest.trim()))?;
+            let watch = watches.remove(number)?;
+            Ok(format!("removed watch {}: {}", number, watch.expr))
+        }
+        _ => Err("watches are written watch add EXPR, watch list or watch del N".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn conditional_breakpoints_are_set_from_the_console() {
+        let mut chip8 = Chip8::new();
+        let (mut breakpoints, mut watches) = (Breakpoints::new(), Watches::new());
+        assert_eq!(execute("b 0x2A4 if v3 == 0x10", &mut chip8, &mut breakpoints, &mut watches).unwrap(), "added b 0x2A4 if v3 == 0x10");
+        assert_eq!(breakpoints.breakpoints()[0].address, 0x2A4);
+        assert_eq!(breakpoints.breakpoints()[0].condition.to_string(), "v3 == 0x10");
+        assert!(execute("b 0x2A4 if v3 ==", &mut chip8, &mut breakpoints, &mut watches).is_err());
+        assert_eq!(breakpoints.breakpoints().len(), 1, "a bad condition adds nothing");
+    }
+
+    // Send one line to a remote console and read its answer, up to the empty line ending it
+    fn ask(client: &mut BufReader<TcpStream>, console: &Console, chip8: &mut Chip8, breakpoints: &mut Breakpoints, line: &str) -> Vec<String> {
+        writeln!(client.get_mut(), "{}", line).unwrap();
+        let mut watches = Watches::new();
+        for _ in 0..500 {
+            if console.run_pending(chip8, breakpoints, &mut watches) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        let mut answer = Vec::new();
+        loop {
+            let mut text = String::new();
+            client.read_line(&mut text).unwrap();
+            if text.trim_end().is_empty() {
+                return answer;
+            }
+            answer.push(text.trim_end().to_string());
+        }
+    }
+
+    #[test]
+    fn remote_clients_set_breakpoints_over_tcp() {
+        let console = Console::listen(0).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", console.port().unwrap())).unwrap();
+        let mut client = BufReader::new(stream);
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        assert_eq!(ask(&mut client, &console, &mut chip8, &mut breakpoints, "b 0x2A4 if v3 == 0x10"), ["added b 0x2A4 if v3 == 0x10"]);
+        assert_eq!(breakpoints.breakpoints()[0].condition.to_string(), "v3 == 0x10");
+        assert_eq!(ask(&mut client, &console, &mut chip8, &mut breakpoints, "b nowhere"), ["Error: invalid breakpoint address 'nowhere'"]);
+        assert_eq!(ask(&mut client, &console, &mut chip8, &mut breakpoints, "set v3 0x10").len(), 1);
+        assert_eq!(chip8.register(3), 0x10);
+    }
+
+    #[test]
+    fn opcode_breaks_are_set_from_both_consoles() {
+        let mut chip8 = Chip8::new();
+        let (mut breakpoints, mut watches) = (Breakpoints::new(), Watches::new());
+        assert_eq!(execute("break-op DXYN", &mut chip8, &mut breakpoints, &mut watches).unwrap(), "added break-op DXYN");
+        assert!(execute("break-op DQYN", &mut chip8, &mut breakpoints, &mut watches).is_err());
+
+        let console = Console::listen(0).unwrap();
+        let mut client = BufReader::new(TcpStream::connect(("127.0.0.1", console.port().unwrap())).unwrap());
+        assert_eq!(ask(&mut client, &console, &mut chip8, &mut breakpoints, "break-op mask=F0FF value=F00A"), ["added break-op mask=F0FF value=F00A"]);
+        assert_eq!(breakpoints.patterns(), [breakpoints::OpcodeBreak { mask: 0xF000, value: 0xD000 }, breakpoints::OpcodeBreak { mask: 0xF0FF, value: 0xF00A }]);
+    }
+
+    #[test]
+    fn scripted_patches_change_what_runs_next() {
+        // Spin at 0x200 until v3 == 0x10, then set vA and spin at 0x206
+        let rom = [0x33, 0x10, 0x12, 0x00, 0x6A, 0x01, 0x12, 0x06];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let (mut breakpoints, mut watches) = (Breakpoints::new(), Watches::new());
+        let mut session = |line: &str, chip8: &mut Chip8| execute(line, chip8, &mut breakpoints, &mut watches).unwrap();
+
+        chip8.step_frame(20);
+        assert_eq!((chip8.pc(), chip8.register(0xA)), (0x200, 0), "still spinning");
+        assert_eq!(session("set v3 0x10", &mut chip8), "v3 = 0x10");
+        chip8.step_frame(20);
+        assert_eq!((chip8.pc(), chip8.register(0xA)), (0x206, 1), "the patched register ended the loop");
+
+        // Rewrite the loop at 0x206 into a jump back to 0x204 and change the value it stores
+        assert_eq!(session("poke 0x207 0x04", &mut chip8), "[0x207] = 0x04");
+        assert_eq!(session("poke 0x205 0x02", &mut chip8), "[0x205] = 0x02");
+        chip8.step_frame(3);
+        assert_eq!(chip8.register(0xA), 2, "the poked instructions ran");
+
+        assert_eq!(session("set pc 0x204", &mut chip8), "PC = 0x204");
+        assert_eq!(session("push 0x20A", &mut chip8), "pushed call from 0x20A");
+        assert_eq!(chip8.stack(), [0x20A]);
+        assert_eq!(session("pop", &mut chip8), "popped innermost call");
+        assert!(execute("pop", &mut chip8, &mut Breakpoints::new(), &mut Watches::new()).is_err());
+    }
+
+    #[test]
+    fn watches_are_managed_from_the_console() {
+        let mut chip8 = Chip8::new();
+        let (mut breakpoints, mut watches) = (Breakpoints::new(), Watches::new());
+        let mut session = |line: &str, chip8: &mut Chip8| execute(line, chip8, &mut breakpoints, &mut watches);
+        assert_eq!(session("watch list", &mut chip8).unwrap(), "no watches");
+        chip8.set_register(3, 7);
+        assert_eq!(session("watch add v3", &mut chip8).unwrap(), "watch 1: v3 = 0x0007 (7)");
+        assert_eq!(session("watch add mem[0x300]", &mut chip8).unwrap(), "watch 2: mem[0x300] = 0x0000 (0)");
+        assert_eq!(session("watch list", &mut chip8).unwrap(), "watch 1: v3 = 0x0007 (7)\nwatch 2: mem[0x300] = 0x0000 (0)");
+        assert_eq!(session("watch del 1", &mut chip8).unwrap(), "removed watch 1: v3");
+        assert_eq!(session("watch del 5", &mut chip8).unwrap_err(), "no watch 5");
+    }
+}
@@ -0,0 +1,85 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::prelude::*;
+
+const MEMORY_SIZE: usize = 4096;
+
+// Addresses the core started an instruction at, gathered while a ROM runs
+//
+// Coverage files list one executed address per line in hex, "0x200"; blank lines and
+// lines starting with # are ignored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    executed: Vec<bool>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { executed: vec![false; MEMORY_SIZE] }
+    }
+
+    pub fn mark(&mut self, addr: u16) {
+        if let Some(executed) = self.executed.get_mut(addr as usize) {
+            *executed = true;
+        }
+    }
+
+    pub fn is_executed(&self, addr: u16) -> bool {
+        self.executed.get(addr as usize).copied().unwrap_or(false)
+    }
+
+    // Number of distinct instruction addresses executed
+    pub fn len(&self) -> usize {
+        self.executed.iter().filter(|&&executed| executed).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut coverage = Coverage::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let addr = u16::from_str_radix(line.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&addr| (addr as usize) < MEMORY_SIZE)
+                .ok_or_else(|| format!("line {}: invalid address '{}'", line_no + 1, line))?;
+            coverage.mark(addr);
+        }
+        Ok(coverage)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        Coverage::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|err| format!("could not write {}: {}", path.display(), err))
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, _) in self.executed.iter().enumerate().filter(|(_, &executed)| executed) {
+            writeln!(f, "{:#05X}", addr)?;
+        }
+        Ok(())
+    }
+}
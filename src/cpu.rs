@@ -0,0 +1,932 @@
+use crate::chip8::{Quirks, Variant, FONT_BASE};
+use crate::chip8x;
+use crate::memory::{FlatMemory, MemoryBus};
+use crate::prelude::*;
+use core::fmt;
+
+// Processor state: registers, memory, program counter, stack and timers, with the instructions that only
+// touch them. The display, keypad, random generator and RPL flags belong to Chip8, which owns a Cpu and
+// runs the rest of the instruction set itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cpu<M: MemoryBus = FlatMemory> {
+    pub(crate) v: [u8; 16],             // General Purpose Registers v0 - vF
+    pub(crate) index: u16,              // Index Register
+    pub(crate) pc: u16,                 // Program Counter
+    pub(crate) sp: u16,                 // Stack Pointer
+    pub(crate) stack: [u16; 16],        // Stack
+    pub(crate) memory: M,               // Memory
+    pub(crate) delay_timer: u8,         // Delay Timer
+    pub(crate) sound_timer: u8,         // Sound Timer
+    pub(crate) bus_fault: Option<String>,   // Last access the bus refused, until taken
+}
+
+// An instruction the CPU refused to run because it would have left the machine in no sane state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    StackOverflow,                      // 2NNN with every stack slot in use
+    StackUnderflow,                     // 00EE with nothing on the stack
+    PcOutOfRange,                       // An opcode fetched from past the end of memory
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::PcOutOfRange => write!(f, "pc past the end of memory"),
+        }
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cpu {
+    // Zeroed registers and 4K of zeroed memory with pc at 0x200, where programs are loaded
+    pub fn new() -> Self {
+        Cpu::with_memory(FlatMemory::new())
+    }
+}
+
+impl<M: MemoryBus> Cpu<M> {
+    // Zeroed registers on the given memory with pc at 0x200
+    pub fn with_memory(memory: M) -> Self {
+        Cpu {
+            v: [0; 16],
+            index: 0,
+            pc: 0x200,
+            sp: 0,
+            stack: [0; 16],
+            memory,
+            delay_timer: 0,
+            sound_timer: 0,
+            bus_fault: None,
+        }
+    }
+
+    pub fn register(&self, x: usize) -> u8 {
+        self.v[x & 0xF]
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.v[x & 0xF] = value;
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // Read a memory byte, None when the address is outside memory
+    pub fn peek(&self, addr: usize) -> Option<u8> {
+        self.memory.bytes().get(addr).copied()
+    }
+
+    // Copy bytes into memory from addr, whatever runs past the end is dropped
+    pub fn load(&mut self, addr: usize, bytes: &[u8]) {
+        let memory = self.memory.bytes_mut();
+        let start = addr.min(memory.len());
+        let end = (start + bytes.len()).min(memory.len());
+        memory[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    // Opcode at the program counter, fetched over the bus. Either byte past the end of memory faults, and
+    // the bus error is kept for take_bus_fault
+    pub fn fetch(&mut self) -> Result<u16, Fault> {
+        let pc = self.pc as usize;
+        Ok((self.bus_fetch(pc)? as u16) << 8 | (self.bus_fetch(pc + 1)? as u16))
+    }
+
+    // Interpreter side bus access: a refused read gives 0 and a refused write is dropped, either way
+    // the bus error is kept for take_bus_fault
+    #[inline]
+    pub(crate) fn read(&mut self, addr: usize) -> u8 {
+        let byte = self.memory.read(addr);
+        self.refused_as_zero(byte)
+    }
+
+    #[inline]
+    fn bus_fetch(&mut self, addr: usize) -> Result<u8, Fault> {
+        self.memory.fetch(addr).map_err(|err| {
+            self.bus_fault = Some(err);
+            Fault::PcOutOfRange
+        })
+    }
+
+    // Move pc on by bytes. It wraps at the top of the 16-bit address space instead of overflowing, the
+    // fetch there then faults or, with 64K of memory, carries on from 0
+    #[inline]
+    pub(crate) fn advance(&mut self, bytes: u16) {
+        self.pc = self.pc.wrapping_add(bytes);
+    }
+
+    #[inline]
+    fn refused_as_zero(&mut self, byte: Result<u8, String>) -> u8 {
+        byte.unwrap_or_else(|err| {
+            self.bus_fault = Some(err);
+            0
+        })
+    }
+
+    #[inline]
+    pub(crate) fn write(&mut self, addr: usize, value: u8) {
+        if let Err(err) = self.memory.write(addr, value) {
+            self.bus_fault = Some(err);
+        }
+    }
+
+    // Count both timers down by one, called at 60hz
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {           // Update delay timer
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {           // Update sound timer
+            self.sound_timer -= 1;
+        }
+    }
+
+    // Run an instruction that only needs CPU state, false when the opcode isn't one of them and
+    // has to go to the display, keypad or the rest of the machine. A fault leaves the state as it was
+    pub fn execute(&mut self, opcode: u16, quirks: &Quirks) -> Result<bool, Fault> {
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00EE => self.ret()?,          // Return from subroutine
+                0x00F2 if quirks.variant == Variant::Chip8E => self.advance(2), // No operation (CHIP-8E)
+                0x0151 if quirks.variant == Variant::Chip8E => self.wait_delay(),   // Wait for the delay timer to run out (CHIP-8E)
+                0x0188 if quirks.variant == Variant::Chip8E => self.skip(),    // Skip next instruction (CHIP-8E)
+                _ => return Ok(false),
+            }
+            0x1000 => self.jmp(opcode),         // Jump to address NNN
+            0x2000 => self.jsr(opcode)?,        // Jump to subroutine NNN
+            0x3000 => self.skeq_c(opcode),      // Skip next instruction if v[x] == NN
+            0x4000 => self.skne_c(opcode),      // Skip next instruction if v[X] != NN
+            0x5000 if opcode & 0x000F == 1 && quirks.variant == Variant::Chip8X => self.add_octal(opcode),  // Add v[Y] to v[X] by octal digit (CHIP-8X)
+            0x5000 if quirks.variant == Variant::Chip8E => match opcode & 0x000F {
+                0x001 => self.skgt_r(opcode),   // Skip next instruction if v[X] > v[Y] (CHIP-8E)
+                0x002 => self.str_range(opcode, quirks),    // Store v[X] - v[Y] at I (CHIP-8E)
+                0x003 => self.ldr_range(opcode, quirks),    // Load v[X] - v[Y] from I (CHIP-8E)
+                _ => self.skeq_r(opcode),
+            }
+            0x5000 => self.skeq_r(opcode),      // Skip next instruction if v[X] == v[Y]
+            0x6000 => self.mov_c(opcode),       // Move constant NN to v[X]
+            0x7000 => self.add_c(opcode),       // Add constant NN to v[X]
+            0x8000 => match opcode & 0x000F {
+                0x000 => self.mov_r(opcode),    // Move v[Y] into v[X]
+                0x001 => self.or_r(opcode),     // OR v[Y] with v[X]
+                0x002 => self.and_r(opcode),    // AND v[Y] with v[X]
+                0x003 => self.xor_r(opcode),    // XOR v[Y] with v[X]
+                0x004 => self.add_r(opcode),    // Add v[Y] with v[X]
+                0x005 => self.sub_r(opcode),    // Subtract v[Y] from v[X]
+                0x006 => self.shr_r(opcode, quirks),    // Shift v[X] right
+                0x007 => self.rsb_r(opcode),    // Subtract v[X] from v[Y]
+                0x00E => self.shl_r(opcode, quirks),    // Shift v[X] left
+                _ => return Ok(false),
+            }
+            0x9000 => self.skne_r(opcode),      // Skip next instruction if v[X] != v[Y]
+            0xA000 => self.mvi(opcode, quirks), // Move constant NNN to I
+            0xB000 if quirks.variant == Variant::Chip8E && matches!(opcode & 0x0F00, 0x0B00 | 0x0F00) => self.branch(opcode),  // Branch NN bytes back or forward (CHIP-8E)
+            0xB000 => self.jmi(opcode),         // Jump to address NNN + v[0]
+            0xF000 => match opcode & 0x00FF {
+                0x0000 if opcode == 0xF000 => self.long_mvi(quirks),    // Move the constant in the next word to I (XO-CHIP)
+                0x0007 => self.gdelay(opcode),  // Get delay timer into vX
+                0x0015 => self.sdelay(opcode),  // Set delay timer to vX
+                0x0018 => self.ssound(opcode),  // Set sound timer to vX
+                0x001B if quirks.variant == Variant::Chip8E => self.skip_bytes(opcode), // Skip vX bytes (CHIP-8E)
+                0x001e => self.adi(opcode, quirks), // Add vX to I
+                0x0029 => self.font(opcode),    // Point I to the sprite for hexadecimal character vX
+                0x0033 => self.bcd(opcode),     // Store bcd of vX at I, I+1, I+2
+                0x0055 => self.str(opcode, quirks), // Store v0 - vX at I incremented each time
+                0x0065 => self.ldr(opcode, quirks), // Load registers v0 - vX from I incremented each time
+                _ => return Ok(false),
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /********************************************/
+    /*          Instructions/Opcodes            */
+    /********************************************/
+
+    // Keep I within the configured width, 12 bits catches standard ROMs running off the end of memory
+    pub(crate) fn mask_index(&mut self, quirks: &Quirks) {
+        if quirks.index_width == 12 {
+            self.index &= 0x0FFF;
+        }
+    }
+
+    // Bytes a skip steps over: the next instruction, which is 4 for the two words of an XO-CHIP F000 NNNN
+    pub(crate) fn skip_size(&self) -> u16 {
+        let next = self.pc as usize + 2;
+        match (self.peek(next), self.peek(next + 1)) {
+            (Some(0xF0), Some(0x00)) => 4,
+            _ => 2,
+        }
+    }
+
+    // 0x00EE
+    // Return from subroutine implementation
+    fn ret(&mut self) -> Result<(), Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow);          // Nothing to return to
+        }
+        self.sp -= 1;                                   // Decrepement stack pointer to get to last call
+        self.pc = self.stack[self.sp as usize];
+        self.advance(2);                                // Return past the subroutine call
+        Ok(())
+    }
+
+    // 0x0151
+    // CHIP-8E: stay on this instruction until the delay timer reaches 0
+    fn wait_delay(&mut self) {
+        if self.delay_timer == 0 {
+            self.advance(2);
+        }
+    }
+
+    // 0x0188
+    // CHIP-8E: skip the next instruction unconditionally
+    fn skip(&mut self) {
+        self.advance(self.skip_size() + 2);
+    }
+
+    // 1NNN
+    // Jump to address implementation
+    fn jmp(&mut self, opcode: u16) {
+        self.pc = opcode & 0x0FFF;          // Set current memory position to provided address
+    }
+
+    // 2NNN
+    // Jump to subroutine address NNN
+    fn jsr(&mut self, opcode: u16) -> Result<(), Fault> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Fault::StackOverflow);       // Every stack slot already holds a call
+        }
+        self.stack[self.sp as usize] = self.pc;     // Set current memory position in the stack
+        self.sp += 1;                               // Increment the stack pointer to avoid overwrite
+        self.pc = opcode & 0x0FFF;                  // Set current memory position to provided address
+        Ok(())
+    }
+
+    // 3XNN
+    // Skip next instruction if register vX == constant NN
+    fn skeq_c(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let nn = (opcode & 0x00FF) as u8;                  // Extract NN constant
+
+        if self.v[x] == nn {
+            self.advance(self.skip_size());                    // Skip the next instruction
+        }
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 4XNN
+    // Skip next instruction if register vX != constant NN
+    fn skne_c(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let nn = (opcode & 0x00FF) as u8;                  // Extract NN constant
+
+        if self.v[x] != nn {
+            self.advance(self.skip_size());                    // Skip the next instruction
+        }
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 0x5XY0
+    // Skip next instruction if register vX == register vY
+    fn skeq_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        if self.v[x] == self.v[y] {
+            self.advance(self.skip_size());                    // Skip the next instruction
+        }
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 0x5XY1
+    // CHIP-8X: add vY to vX one octal digit at a time, dropping the carries, vF untouched
+    fn add_octal(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        self.v[x] = chip8x::add_octal(self.v[x], self.v[y]);
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 0x5XY1
+    // CHIP-8E: skip next instruction if register vX > register vY
+    fn skgt_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        if self.v[x] > self.v[y] {
+            self.advance(self.skip_size());                    // Skip the next instruction
+        }
+        self.advance(2);                                       // Increment counter
+    }
+
+    // vX - vY in the order they go to and from memory, counting down when X is above Y
+    fn register_range(opcode: u16) -> impl Iterator<Item = usize> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let count = x.abs_diff(y) + 1;
+        (0..count).map(move |step| if x <= y { x + step } else { x - step })
+    }
+
+    // 0x5XY2
+    // CHIP-8E: store registers vX-vY at location I onwards, I ending up past the last one
+    fn str_range(&mut self, opcode: u16, quirks: &Quirks) {
+        for reg in Self::register_range(opcode) {
+            self.write(self.index as usize, self.v[reg]);
+            self.index = self.index.wrapping_add(1);
+            self.mask_index(quirks);
+        }
+        self.advance(2);
+    }
+
+    // 0x5XY3
+    // CHIP-8E: load registers vX-vY from location I onwards, I ending up past the last one
+    fn ldr_range(&mut self, opcode: u16, quirks: &Quirks) {
+        for reg in Self::register_range(opcode) {
+            self.v[reg] = self.read(self.index as usize);
+            self.index = self.index.wrapping_add(1);
+            self.mask_index(quirks);
+        }
+        self.advance(2);
+    }
+
+    // 0x6XNN
+    // Move constant NN to register vX
+    fn mov_c(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
+
+        self.v[x] = nn;                                         // set vX = NN
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 0x7XNN
+    // Add constant NN to register vX, no carry generated
+    fn add_c(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let nn = (opcode & 0x00FF) as u8;                   // Extract NN constant
+
+        self.v[x] = self.v[x].wrapping_add(nn);                 // Add NN to vX
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XY0
+    // Move register vY into register vX
+    fn mov_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        self.v[x] = self.v[y];                                  // Set vX = vY
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XY1
+    // OR register vY with register vX, store in vX
+    fn or_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        self.v[x] |= self.v[y];                                // OR registers
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 8XY2
+    // AND register vY with register vX, store in vX
+    fn and_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        self.v[x] &= self.v[y];                                // AND registers
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 8XY3
+    // XOR register vY with register vX, store in vX
+    fn xor_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;      // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;      // Extract Y register
+
+        self.v[x] ^= self.v[y];                                // XOR registers
+        self.advance(2);                                       // Increment counter
+    }
+
+    // 8XY4
+    // Add register vY with register vX, store in vX, carry in register vF
+    fn add_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+
+        let (result, carry) = self.v[x].overflowing_add(self.v[y]);
+        self.v[x] = result;
+        self.v[0xF] = carry as u8;
+
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XY5
+    // Sub register vY from register vX, vF set to 1 if borrows
+    fn sub_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+        let vx = self.v[x] as usize;                    // Extract X register
+        let vy = self.v[y] as usize;                    // Extract Y register
+
+        self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+
+        if vx >= vy {
+            self.v[0xF] = 1; // No borrow needed
+        } else {
+            self.v[0xF] = 0; // Borrow occurred
+        }
+    
+
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XY6
+    // Shift register vX right, bit 0 goes into register vF
+    fn shr_r(&mut self, opcode: u16, quirks: &Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        if quirks.shift_vy {
+            self.v[x] = self.v[((opcode & 0x00F0) >> 4) as usize];
+        }
+        let lsb = self.v[x] & 0x1;                              // Taken before the shift, vX may be vF itself
+
+        self.v[x] >>= 1;                                        // Right shift register vX
+        self.v[0xF] = lsb;                                      // Store LSB in Flag register, last so 8FY6 ends with the flag
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XY7
+    // Sub register vX from register vY, store in vX, vF set to 1 if borrows
+    fn rsb_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+        let vx = self.v[x] as usize;                    // Extract X register
+        let vy = self.v[y] as usize;                    // Extract Y register
+
+        self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+
+        if vy >= vx {
+            self.v[0xF] = 1; // No borrow needed
+        } else {
+            self.v[0xF] = 0; // Borrow occurred
+        }
+        
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 8XYE
+    // Shift register vX left, bit 7 goes into register vF
+    fn shl_r(&mut self, opcode: u16, quirks: &Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        if quirks.shift_vy {
+            self.v[x] = self.v[((opcode & 0x00F0) >> 4) as usize];
+        }
+        let msb = (self.v[x] & 0x80) >> 7;                      // Taken before the shift, vX may be vF itself
+
+        self.v[x] <<= 1;                                        // Left shift register vX
+        self.v[0xF] = msb;                                      // Store MSB in Flag register, last so 8FYE ends with the flag
+        self.advance(2);                                        // Increment counter
+    }
+
+    // 9XY0
+    // Skip next instruction if register vX != register vY
+    fn skne_r(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+        let y = ((opcode & 0x00F0) >> 4) as usize;       // Extract Y register
+
+        if self.v[x] != self.v[y] {
+            self.advance(self.skip_size());                    // Skip the next instruction
+        }
+        self.advance(2);                                       // Increment counter
+    }
+
+    // ANNN
+    // Load index register I with constant NNN
+    fn mvi(&mut self, opcode: u16, quirks: &Quirks) {
+        let nnn = opcode & 0x0FFF;              // Extract NNN constant
+
+        self.index = nnn;                           // Set index register to constant
+        self.mask_index(quirks);
+        self.advance(2);
+    }
+
+    // F000 NNNN, XO-CHIP
+    // Move the 16 bit constant NNNN in the word after the instruction to I
+    fn long_mvi(&mut self, quirks: &Quirks) {
+        let pc = self.pc as usize;
+        self.index = (self.peek(pc + 2).unwrap_or(0) as u16) << 8 | self.peek(pc + 3).unwrap_or(0) as u16;
+        self.mask_index(quirks);
+        self.advance(4);
+    }
+
+    // BNNN
+    // Jump to address NNN + register v0
+    fn jmi(&mut self, opcode: u16) {
+        let nnn = opcode & 0x0FFF;              // Extract NNN constant
+
+        self.pc = nnn + self.v[0] as u16;           // Point program counter to new address
+    }
+
+    // BBNN and BFNN
+    // CHIP-8E: branch NN bytes back (BB) or forward (BF) from the next instruction
+    fn branch(&mut self, opcode: u16) {
+        let nn = opcode & 0x00FF;               // Extract NN constant
+        let next = self.pc.wrapping_add(2);
+
+        self.pc = match opcode & 0x0F00 {
+            0x0B00 => next.wrapping_sub(nn),
+            _ => next.wrapping_add(nn),
+        };
+    }
+
+    // FX07
+    // Get delay timer into vX
+    fn gdelay(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        self.v[x] = self.delay_timer;                           // Load register X with delay timer
+        self.advance(2);
+    }
+
+    // FX15
+    // Set the delay timer to vX
+    fn sdelay(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        self.delay_timer = self.v[x];                           // Load delay timer with register X
+        self.advance(2);
+    }
+
+    // FX1B
+    // CHIP-8E: skip vX bytes past the next instruction, for data embedded in the code
+    fn skip_bytes(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        self.pc = self.pc.wrapping_add(2 + self.v[x] as u16);
+    }
+
+    // FX18
+    // Set the sound timer to vX
+    fn ssound(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        self.sound_timer = self.v[x];                           // Load register X with sound timer
+        self.advance(2);
+    }
+
+    // FX1E
+    // Add register vX to the index register I
+    fn adi(&mut self, opcode: u16, quirks: &Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        let sum = self.index as u32 + self.v[x] as u32;
+        self.index = sum as u16;                                // Add vX to index
+        if quirks.adi_overflow_vf {
+            let carried = sum.checked_shr(quirks.adi_overflow_width as u32).unwrap_or(0);      // No overflow past 32 bits
+            self.v[0xF] = (carried != 0) as u8;
+        }
+        self.mask_index(quirks);
+        self.advance(2);
+    }
+
+    // FX29
+    // Point I to the sprite for hexadecimal character in vX
+    fn font(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;       // Extract X register
+
+        self.index = (FONT_BASE + (self.v[x] as usize * 5)) as u16;
+        self.advance(2);
+    }
+
+    // FX33
+    // Store the bcd representation of register vX at location I, I+1, I+2
+    fn bcd(&mut self, opcode: u16) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+        
+        self.write(self.index as usize, self.v[x] / 100);               // Get hundreds location
+        self.write(self.index as usize + 1, (self.v[x] / 10) % 10);     // Get tens location
+        self.write(self.index as usize + 2, (self.v[x] % 100) % 10);    // Get ones location
+
+        self.advance(2);
+    }
+
+    // FX55
+    // Store registers v0-vX at location I onwards, incrementing I to the next location each time
+    fn str(&mut self, opcode: u16, quirks: &Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+
+        for i in 0..=x {
+            self.write(self.index as usize + i, self.v[i]);
+        }
+
+        if quirks.load_store_increment {
+            self.index = self.index.wrapping_add(x as u16 + 1);
+            self.mask_index(quirks);
+        }
+        self.advance(2);
+    }
+
+    // FX65
+    // Load registers v0 to vX from location I onwards, incrementing I to the next location each time
+    fn ldr(&mut self, opcode: u16, quirks: &Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;              // Extract X register
+
+        for i in 0..=x {
+            self.v[i] = self.read(self.index as usize + i);
+        }
+
+        if quirks.load_store_increment {
+            self.index = self.index.wrapping_add(x as u16 + 1);
+            self.mask_index(quirks);
+        }
+        self.advance(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quirks16() -> Quirks {
+        Quirks { index_width: 16, load_store_increment: true, ..Quirks::default() }
+    }
+
+    #[test]
+    fn load_store_increment_wraps_at_the_top_of_16_bit_i() {
+        let quirks = quirks16();
+        let mut cpu = Cpu::new();
+        cpu.index = 0xFFFE;
+        assert_eq!(cpu.execute(0xF355, &quirks), Ok(true));
+        assert_eq!(cpu.index, 0x0002);
+        cpu.index = 0xFFFF;
+        assert_eq!(cpu.execute(0xF065, &quirks), Ok(true));
+        assert_eq!(cpu.index, 0x0000);
+    }
+
+    #[test]
+    fn returning_to_a_call_at_the_top_of_memory_wraps() {
+        let mut cpu = Cpu::new();
+        cpu.stack[0] = 0xFFFE;
+        cpu.sp = 1;
+        assert_eq!(cpu.execute(0x00EE, &Quirks::default()), Ok(true));
+        assert_eq!((cpu.pc, cpu.sp), (0x0000, 0));
+    }
+
+    #[test]
+    fn load_store_increment_stays_within_12_bits() {
+        let quirks = Quirks { index_width: 12, ..quirks16() };
+        let mut cpu = Cpu::new();
+        cpu.index = 0x0FFE;
+        assert_eq!(cpu.execute(0xF155, &quirks), Ok(true));
+        assert_eq!(cpu.index, 0x0000);
+    }
+
+    // Run one register op with vX and vY preset, returning (vX, vF) afterwards
+    fn alu(opcode: u16, vx: u8, vy: u8, quirks: &Quirks) -> (u8, u8) {
+        let mut cpu = Cpu::new();
+        cpu.v[1] = vx;
+        cpu.v[2] = vy;
+        assert_eq!(cpu.execute(opcode, quirks), Ok(true));
+        assert_eq!(cpu.pc, 0x202);
+        (cpu.v[1], cpu.v[0xF])
+    }
+
+    #[test]
+    fn add_constant_wraps_and_leaves_vf_alone() {
+        let mut cpu = Cpu::new();
+        cpu.v[3] = 0xFF;
+        cpu.v[0xF] = 0xAA;
+        assert_eq!(cpu.execute(0x7302, &Quirks::default()), Ok(true));
+        assert_eq!((cpu.v[3], cpu.v[0xF]), (0x01, 0xAA));
+    }
+
+    #[test]
+    fn logic_ops_combine_the_registers() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(0x8120, 0x0F, 0x3C, &quirks).0, 0x3C);
+        assert_eq!(alu(0x8121, 0x0F, 0x3C, &quirks).0, 0x3F);
+        assert_eq!(alu(0x8122, 0x0F, 0x3C, &quirks).0, 0x0C);
+        assert_eq!(alu(0x8123, 0x0F, 0x3C, &quirks).0, 0x33);
+    }
+
+    #[test]
+    fn add_registers_sets_vf_on_carry() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(0x8124, 0xFF, 0x02, &quirks), (0x01, 1));
+        assert_eq!(alu(0x8124, 0x10, 0x20, &quirks), (0x30, 0));
+    }
+
+    #[test]
+    fn subtractions_clear_vf_on_borrow() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(0x8125, 0x05, 0x03, &quirks), (0x02, 1));
+        assert_eq!(alu(0x8125, 0x03, 0x03, &quirks), (0x00, 1), "equal operands don't borrow");
+        assert_eq!(alu(0x8125, 0x03, 0x05, &quirks), (0xFE, 0));
+        assert_eq!(alu(0x8127, 0x03, 0x05, &quirks), (0x02, 1));
+        assert_eq!(alu(0x8127, 0x05, 0x03, &quirks), (0xFE, 0));
+    }
+
+    #[test]
+    fn shifts_move_the_lost_bit_into_vf() {
+        let quirks = Quirks::default();
+        assert_eq!(alu(0x8126, 0x05, 0xF0, &quirks), (0x02, 1));
+        assert_eq!(alu(0x812E, 0x81, 0x0F, &quirks), (0x02, 1));
+        assert_eq!(alu(0x812E, 0x41, 0x0F, &quirks), (0x82, 0));
+
+        let vip = Quirks { shift_vy: true, ..Quirks::default() };
+        assert_eq!(alu(0x8126, 0x05, 0xF0, &vip), (0x78, 0), "the VIP shifts vY into vX");
+        assert_eq!(alu(0x812E, 0x01, 0x81, &vip), (0x02, 1));
+    }
+
+    #[test]
+    fn vf_as_the_destination_ends_up_holding_the_flag() {
+        let mut cpu = Cpu::new();
+        cpu.v[0xF] = 0xFF;
+        cpu.v[1] = 0x02;
+        assert_eq!(cpu.execute(0x8F14, &Quirks::default()), Ok(true));
+        assert_eq!(cpu.v[0xF], 1);
+        cpu.v[0xF] = 0x80;
+        assert_eq!(cpu.execute(0x8F0E, &Quirks::default()), Ok(true));
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn fx1e_flags_overflow_only_with_the_amiga_quirk() {
+        let sixteen = Quirks { index_width: 16, ..Quirks::default() };
+        let mut cpu = Cpu::new();
+        cpu.index = 0x0FFF;
+        cpu.v[2] = 0x01;
+        assert_eq!(cpu.execute(0xF21E, &sixteen), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0x1000, 0));
+
+        let amiga = Quirks { adi_overflow_vf: true, ..sixteen };
+        cpu.index = 0x0FFF;
+        assert_eq!(cpu.execute(0xF21E, &amiga), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0x1000, 1));
+        assert_eq!(cpu.execute(0xF21E, &amiga), Ok(true));
+        assert_eq!(cpu.v[0xF], 1, "still past 12 bits");
+        cpu.index = 0x0100;
+        assert_eq!(cpu.execute(0xF21E, &amiga), Ok(true));
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn adi_masks_i_to_12_bits_only_in_standard_mode() {
+        for (width, expected) in [(12, 0x0000), (16, 0x1000)] {
+            let quirks = Quirks { index_width: width, ..Quirks::default() };
+            let mut cpu = Cpu::new();
+            cpu.index = 0x0FFF;
+            cpu.v[2] = 1;
+            assert_eq!(cpu.execute(0xF21E, &quirks), Ok(true));
+            assert_eq!(cpu.index, expected, "{} bit I past 0x0FFF", width);
+            cpu.index = 0x0FFE;
+            assert_eq!(cpu.execute(0xF21E, &quirks), Ok(true));
+            assert_eq!(cpu.index, 0x0FFF, "{} bit I just under the boundary", width);
+        }
+    }
+
+    #[test]
+    fn mvi_loads_the_top_address_at_both_widths() {
+        for width in [12, 16] {
+            let quirks = Quirks { index_width: width, ..Quirks::default() };
+            let mut cpu = Cpu::new();
+            assert_eq!(cpu.execute(0xAFFF, &quirks), Ok(true));
+            assert_eq!(cpu.index, 0x0FFF, "{} bit I", width);
+        }
+    }
+
+    #[test]
+    fn fx1e_overflow_threshold_follows_the_configured_width() {
+        let twelve = Quirks { adi_overflow_vf: true, adi_overflow_width: 12, ..quirks16() };
+        let sixteen = Quirks { adi_overflow_width: 16, ..twelve };
+        let mut cpu = Cpu::new();
+        cpu.v[2] = 0x01;
+
+        cpu.index = 0x0FFE;
+        assert_eq!(cpu.execute(0xF21E, &twelve), Ok(true));
+        assert_eq!(cpu.v[0xF], 0, "0x0FFF is still inside 12 bits");
+        assert_eq!(cpu.execute(0xF21E, &twelve), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0x1000, 1), "crossing 0x0FFF overflows 12 bits");
+
+        cpu.index = 0x0FFF;
+        assert_eq!(cpu.execute(0xF21E, &sixteen), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0x1000, 0), "crossing 0x0FFF is fine in 16 bits");
+        cpu.index = 0xFFFE;
+        assert_eq!(cpu.execute(0xF21E, &sixteen), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0xFFFF, 0));
+        assert_eq!(cpu.execute(0xF21E, &sixteen), Ok(true));
+        assert_eq!((cpu.index, cpu.v[0xF]), (0x0000, 1), "only crossing 0xFFFF overflows 16 bits");
+
+        let wide = Quirks { adi_overflow_width: 40, ..twelve };
+        cpu.index = 0xFFFF;
+        assert_eq!(cpu.execute(0xF21E, &wide), Ok(true), "a width past the sum's bits doesn't panic the shift");
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn stack_misuse_faults_instead_of_panicking() {
+        let quirks = Quirks::default();
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.execute(0x00EE, &quirks), Err(Fault::StackUnderflow));
+        assert_eq!((cpu.pc, cpu.sp), (0x200, 0), "a fault leaves the state alone");
+
+        for _ in 0..cpu.stack.len() {
+            assert_eq!(cpu.execute(0x2200, &quirks), Ok(true));
+        }
+        assert_eq!(cpu.execute(0x2200, &quirks), Err(Fault::StackOverflow));
+        assert_eq!(cpu.sp as usize, cpu.stack.len());
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0000;
+        assert_eq!(cpu.execute(0x2300, &quirks), Ok(true));
+        assert_eq!(cpu.execute(0x00EE, &quirks), Ok(true));
+        assert_eq!(cpu.pc, 0x0002, "a call from address 0 returns to 2");
+    }
+
+    #[test]
+    fn bnnn_adds_v0_to_the_full_address() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 0x20;
+        assert_eq!(cpu.execute(0xB300, &Quirks::default()), Ok(true));
+        assert_eq!(cpu.pc, 0x320, "NNN isn't cut to its low byte");
+        cpu.v[0] = 0xFF;
+        assert_eq!(cpu.execute(0xBFFF, &Quirks::default()), Ok(true));
+        assert_eq!(cpu.pc, 0x10FE);
+    }
+
+    #[test]
+    fn fx15_sets_the_delay_timer() {
+        let mut cpu = Cpu::new();
+        cpu.v[3] = 42;
+        cpu.sound_timer = 7;
+        assert_eq!(cpu.execute(0xF315, &Quirks::default()), Ok(true));
+        assert_eq!((cpu.delay_timer, cpu.sound_timer, cpu.v[3]), (42, 7, 42));
+    }
+
+    #[test]
+    fn f000_loads_the_next_word_masked_to_the_width() {
+        for (width, expected) in [(12, 0x0FFF), (16, 0xFFFF)] {
+            let quirks = Quirks { index_width: width, ..Quirks::default() };
+            let mut cpu = Cpu::new();
+            cpu.load(0x202, &[0xFF, 0xFF]);
+            assert_eq!(cpu.execute(0xF000, &quirks), Ok(true));
+            assert_eq!((cpu.index, cpu.pc), (expected, 0x204), "{} bit I", width);
+        }
+        assert_eq!(Quirks::default().index_width, 12, "only the XO-CHIP profile widens I");
+    }
+
+    #[test]
+    fn skips_step_over_both_words_of_f000() {
+        let mut cpu = Cpu::new();
+        cpu.load(0x202, &[0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(cpu.execute(0x3000, &Quirks::default()), Ok(true));
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn shifts_of_vf_leave_the_shifted_out_bit_in_vf() {
+        let shift = |opcode, vf, vy, quirks: &Quirks| {
+            let mut cpu = Cpu::new();
+            cpu.v[0xF] = vf;
+            cpu.v[1] = vy;
+            assert_eq!(cpu.execute(opcode, quirks), Ok(true));
+            cpu.v[0xF]
+        };
+        let quirks = Quirks::default();
+        assert_eq!(shift(0x8F06, 0x02, 0, &quirks), 0, "not the shifted value 0x01");
+        assert_eq!(shift(0x8F06, 0x81, 0, &quirks), 1, "not the shifted value 0x40");
+        assert_eq!(shift(0x8F0E, 0x40, 0, &quirks), 0, "not the shifted value 0x80");
+        assert_eq!(shift(0x8F0E, 0xC0, 0, &quirks), 1, "not the shifted value 0x80");
+
+        let vip = Quirks { shift_vy: true, ..Quirks::default() };
+        assert_eq!(shift(0x8F16, 0x00, 0x03, &vip), 1);
+        assert_eq!(shift(0x8F1E, 0xFF, 0x01, &vip), 0);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+
+use crate::analysis::Platform;
+
+// Metadata for a known ROM, from the community CHIP-8 program database
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub platforms: Vec<String>,         // Database platform ids, most recommended first
+}
+
+impl RomInfo {
+    // First listed platform this emulator has a quirk preset for
+    pub fn platform(&self) -> Option<Platform> {
+        self.platforms.iter().find_map(|id| platform_for_id(id))
+    }
+}
+
+// The database built into the emulator, in the same layout. It only covers the test suite ROMs under
+// testfiles; --rom-db adds the full community database or a local one on top
+const EMBEDDED: &str = include_str!("programs.json");
+
+// Known ROMs by lowercase SHA-1 of the ROM image
+#[derive(Default)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    // Parse the programs.json layout: a list of programs, each with a "roms" object keyed by SHA-1
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document: Value = serde_json::from_str(text).map_err(|err| format!("JSON error: {}", err))?;
+        let programs = document.as_array().ok_or("ROM database must be a list of programs")?;
+
+        let mut roms = HashMap::new();
+        for program in programs {
+            let title = program.get("title").and_then(Value::as_str).unwrap_or("Unknown").to_string();
+            let authors = strings(program.get("authors"));
+            let release = program.get("release").and_then(Value::as_str).map(str::to_string);
+
+            for (hash, rom) in program.get("roms").and_then(Value::as_object).into_iter().flatten() {
+                let platforms = strings(rom.get("platforms"));
+                roms.insert(hash.to_ascii_lowercase(), RomInfo {
+                    title: title.clone(),
+                    authors: authors.clone(),
+                    release: release.clone(),
+                    platforms,
+                });
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED).expect("the embedded ROM database parses")
+    }
+
+    // Add another database's ROMs, its entries win where both know a ROM
+    pub fn merge(&mut self, other: RomDatabase) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("could not read ROM database {}: {}", path, err))?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.lookup_hash(&sha1_hex(rom))
+    }
+
+    pub fn lookup_hash(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+}
+
+// The strings in a JSON list, skipping anything else. Nothing for a missing key or a value that isn't a list
+fn strings(list: Option<&Value>) -> Vec<String> {
+    list.and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str).map(str::to_string).collect()
+}
+
+// Lowercase hex SHA-1, the form the database is keyed by
+fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Map database platform ids onto the closest quirk preset
+fn platform_for_id(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::Chip8),
+        "chip48" | "superchip1" | "superchip" => Some(Platform::SuperChip),
+        "xochip" => Some(Platform::XoChip),
+        "chip8x" => Some(Platform::Chip8X),
+        "chip8e" => Some(Platform::Chip8E),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{detect_quirks, resolve_quirks};
+    use crate::chip8::Quirks;
+
+    const KNOWN: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+    const SCHIP: [u8; 4] = [0x00, 0xFF, 0x12, 0x02];
+
+    fn fixture() -> RomDatabase {
+        let text = format!(r#"[
+            {{ "title": "Known", "authors": ["A. Author", "B. Author"], "release": "1991",
+               "roms": {{ "{}": {{ "platforms": ["megachip8", "superchip", "originalChip8"] }} }} }},
+            {{ "title": "Untitled release",
+               "roms": {{ "{}": {{ "platforms": ["xochip"] }} }} }}
+        ]"#, sha1_hex(&KNOWN).to_ascii_uppercase(), sha1_hex(&SCHIP));
+        RomDatabase::parse(&text).unwrap()
+    }
+
+    #[test]
+    fn lookup_finds_roms_by_hash() {
+        let database = fixture();
+        assert_eq!(database.len(), 2);
+        let info = database.lookup(&KNOWN).unwrap();
+        assert_eq!(info.title, "Known");
+        assert_eq!(info.authors, ["A. Author", "B. Author"]);
+        assert_eq!(info.release.as_deref(), Some("1991"));
+        assert_eq!(info.platform(), Some(Platform::SuperChip), "the first platform with a preset");
+        assert_eq!(database.lookup_hash(&sha1_hex(&KNOWN).to_ascii_uppercase()), Some(info), "hashes are case blind");
+
+        let untitled = database.lookup(&SCHIP).unwrap();
+        assert!(untitled.authors.is_empty());
+        assert_eq!(untitled.release, None);
+    }
+
+    #[test]
+    fn lookup_misses_unknown_roms() {
+        let database = fixture();
+        let mut changed = KNOWN;
+        changed[3] = 0x04;
+        assert_ne!(sha1_hex(&changed), sha1_hex(&KNOWN));
+        assert_eq!(database.lookup(&changed), None, "one byte off is another ROM");
+        assert_eq!(database.lookup(&[]), None);
+        assert_eq!(RomDatabase::default().lookup(&KNOWN), None);
+        assert!(RomDatabase::parse("{}").is_err(), "not a list");
+    }
+
+    #[test]
+    fn the_database_platform_is_the_lowest_priority() {
+        let database = fixture();
+        let platform = database.lookup(&KNOWN).and_then(RomInfo::platform);
+        assert_eq!(resolve_quirks(platform, None, Quirks::default()), Platform::SuperChip.quirks());
+
+        // A confident detection overrides it and flags go on top of both
+        let detected = detect_quirks(&[0x81, 0x26, 0x12, 0x02]);
+        let quirks = resolve_quirks(platform, Some(&detected), Quirks { clip_sprites: false, load_store_increment: true, ..Quirks::default() });
+        assert!(quirks.shift_vy, "detected");
+        assert!(quirks.clip_sprites, "from the database, the flag can't turn it off");
+        assert!(quirks.load_store_increment, "from the flags");
+
+        let unknown = database.lookup(&[0x12, 0x00]).and_then(RomInfo::platform);
+        assert_eq!(resolve_quirks(unknown, None, Quirks::default()), Quirks::default(), "unknown ROMs run as without a database");
+    }
+
+    #[test]
+    fn the_embedded_database_knows_the_test_suite() {
+        let mut database = RomDatabase::embedded();
+        assert_eq!(database.len(), 7);
+        let ibm = include_bytes!("../testfiles/2-ibm-logo.ch8");
+        assert_eq!(database.lookup(ibm).unwrap().title, "IBM logo");
+        assert_eq!(database.lookup(ibm).unwrap().platform(), Some(Platform::Chip8));
+
+        let mut overlay = fixture();
+        overlay.roms.insert(sha1_hex(ibm), RomInfo { title: "Renamed".to_string(), authors: Vec::new(), release: None, platforms: Vec::new() });
+        database.merge(overlay);
+        assert_eq!(database.len(), 9);
+        assert_eq!(database.lookup(ibm).unwrap().title, "Renamed", "the added database wins");
+    }
+}
@@ -0,0 +1,789 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+use chip8::Chip8;
+use chip8::chip8::{FONT_BASE, FONTSET_SIZE, PROGRAM_START};
+use chip8::breakpoints::Breakpoints;
+use chip8::disasm::Instruction;
+use chip8::memory::{Heatmap, MemoryBus};
+use chip8::watch::Watches;
+
+use crate::audio;
+use crate::overlay;
+
+const TEXT_SCALE: u32 = 2;
+const LINE_HEIGHT: usize = (overlay::GLYPH_HEIGHT + 2) * TEXT_SCALE as usize;
+const COLUMNS: usize = 56;              // Characters per line the window is sized for
+const ROWS: usize = 42;
+const DISASM_BEFORE: u16 = 4;           // Instructions listed ahead of PC
+const DISASM_AFTER: u16 = 11;
+const HEXDUMP_ROWS: usize = 8;          // Rows of 8 bytes from I
+const CALL_ROWS: usize = 6;             // Innermost calls listed, deeper stacks are summarized
+const KEY_CELL: u32 = 28;               // Keypad viewer square size in pixels
+const HEAT_COLUMNS: usize = 64;         // Heatmap addresses per row, 64 rows cover 4K
+const HEAT_CELL: u32 = 8;               // Heatmap square size in pixels
+const BITMAP_PAGE: usize = 4096;        // Bytes the memory bitmap shows at once, larger memories are paged
+const BITMAP_WIDTHS: [usize; 3] = [64, 128, 256];  // Bits per row W cycles the memory bitmap through
+const SPRITE_CELL: u32 = 6;             // Sprite preview pixel size, 16 of them and a label fit beside the keypad
+const SCOPE_HEIGHT: u32 = 4 * (KEY_CELL + 4) - 4;  // Audio scope beside the sprite preview, as tall as the keypad
+const PATTERN_ROWS: usize = 2;          // XO-CHIP pattern strip under the scope trace, 64 bits a row
+const PATTERN_CELL_HEIGHT: u32 = 6;
+const PATTERN_STRIP: u32 = PATTERN_ROWS as u32 * PATTERN_CELL_HEIGHT + 4;
+
+// Keypad keys in the order they sit on the COSMAC VIP keypad
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// How the keypad viewer shows a key, latched wins over pressed and pressed over polled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyView {
+    Latched,                            // Toggled on in sticky keys mode
+    Pressed,                            // Held down right now
+    Polled,                             // Examined by the ROM within the last second
+    Idle,
+}
+
+pub fn key_view(pressed: u16, polled: u16, latched: u16, key: u8) -> KeyView {
+    if latched & (1 << key) != 0 {
+        KeyView::Latched
+    } else if pressed & (1 << key) != 0 {
+        KeyView::Pressed
+    } else if polled & (1 << key) != 0 {
+        KeyView::Polled
+    } else {
+        KeyView::Idle
+    }
+}
+
+// Where an event from the SDL queue belongs when the debugger may have its own window
+//
+// Keyboard events go to the window SDL reports them for, which is the one with keyboard focus:
+// keypad input and the emulator hotkeys only reach the game from the game window, while the
+// debug window takes F10 and Escape, which both close it, 0-9/A-F, which toggle a key break, I,
+// which toggles the pixel inspector, H, which swaps the text for the memory heatmap, and M, which
+// swaps it for the memory bitmap. Routed as Debug, the keys and mouse events the window handles itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    Game,                               // Keypad, hotkeys and anything not tied to a window
+    Debug,                              // Events for the debug window that need no action
+    CloseDebug,                         // Debug window closed by its close button or a key
+    ToggleKeyBreak(u8),                 // Hex key typed into the debug window
+    ToggleInspector,                    // I typed into the debug window
+    ToggleHeatmap,                      // H typed into the debug window
+    ToggleBitmap,                       // M typed into the debug window
+    Quit,                               // Game window closed or quit requested, closes both
+}
+
+pub fn route(event: &Event, game_id: u32, debug_id: Option<u32>) -> Route {
+    let is_debug = |window_id: u32| debug_id == Some(window_id);
+    match *event {
+        Event::Quit { .. } => Route::Quit,
+        Event::Window { window_id, win_event: WindowEvent::Close, .. } if window_id == game_id => Route::Quit,
+        Event::Window { window_id, win_event: WindowEvent::Close, .. } if is_debug(window_id) => Route::CloseDebug,
+        Event::Window { window_id, .. } if is_debug(window_id) => Route::Debug,
+        Event::KeyDown { window_id, keycode: Some(Keycode::F10 | Keycode::Escape), .. } if is_debug(window_id) => Route::CloseDebug,
+        Event::KeyDown { window_id, keycode: Some(Keycode::I), repeat: false, .. } if is_debug(window_id) => Route::ToggleInspector,
+        Event::KeyDown { window_id, keycode: Some(Keycode::H), repeat: false, .. } if is_debug(window_id) => Route::ToggleHeatmap,
+        Event::KeyDown { window_id, keycode: Some(Keycode::M), repeat: false, .. } if is_debug(window_id) => Route::ToggleBitmap,
+        Event::KeyDown { window_id, keycode: Some(key), repeat: false, .. } if is_debug(window_id) => {
+            match hex_digit(key) {
+                Some(digit) => Route::ToggleKeyBreak(digit),
+                None => Route::Debug,
+            }
+        }
+        Event::KeyDown { window_id, .. } | Event::KeyUp { window_id, .. } if is_debug(window_id) => Route::Debug,
+        Event::MouseMotion { window_id, .. } | Event::MouseButtonDown { window_id, .. } if is_debug(window_id) => Route::Debug,
+        _ => Route::Game,
+    }
+}
+
+fn hex_digit(key: Keycode) -> Option<u8> {
+    // SDL keycodes for the digit and letter keys are their lowercase ASCII characters
+    char::from_u32(key.into_i32() as u32)
+        .filter(|c| c.is_ascii_digit() || c.is_ascii_lowercase())
+        .and_then(|c| c.to_digit(16))
+        .map(|digit| digit as u8)
+}
+
+// Debugger text: registers, timers, stack, disassembly around PC, memory at I, key breaks and watches.
+// Registers changed since the frame started read V2*05 instead of V2=05. A focus address moves the
+// disassembly and the memory dump there instead
+pub fn lines(chip8: &Chip8, breakpoints: &Breakpoints, watches: &Watches, focus: Option<u16>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let changed = chip8.registers_changed_since_frame();
+    for row in 0..2 {
+        let regs: Vec<String> = (row * 8..row * 8 + 8).map(|x| {
+            let mark = if changed & 1 << x != 0 { '*' } else { '=' };
+            format!("V{:X}{}{:02X}", x, mark, chip8.register(x))
+        }).collect();
+        lines.push(regs.join(" "));
+    }
+    lines.push(format!("PC={:03X} I={:03X} DT={:02X} ST={:02X}", chip8.pc(), chip8.index(), chip8.delay_timer(), chip8.sound_timer()));
+
+    lines.extend(call_stack_lines(chip8));
+    lines.push(String::new());
+    if let Some(addr) = focus {
+        lines.push(format!("VIEWING {:03X}, HOME FOLLOWS PC AND I", addr));
+    }
+
+    // The listing follows PC in instruction steps, which can be off by a byte in odd aligned code
+    let center = focus.unwrap_or(chip8.pc());
+    let start = center.saturating_sub(DISASM_BEFORE * 2);
+    for addr in (start..=center.saturating_add(DISASM_AFTER * 2)).step_by(2) {
+        let Some(opcode) = opcode_in_memory(chip8, addr) else { break };
+        let marker = if addr == chip8.pc() { ">" } else { " " };
+        lines.push(format!("{}{:03X}: {:04X}  {}", marker, addr, opcode, Instruction::decode(opcode)));
+    }
+    lines.push(String::new());
+
+    for row in 0..HEXDUMP_ROWS {
+        let addr = focus.unwrap_or(chip8.index()) as usize + row * 8;
+        let bytes: Vec<String> = (addr..addr + 8).filter_map(|a| chip8.peek(a)).map(|b| format!("{:02X}", b)).collect();
+        if bytes.is_empty() {
+            break;
+        }
+        lines.push(format!("{:03X}: {}", addr, bytes.join(" ")));
+    }
+
+    let keys: Vec<String> = breakpoints.keys().iter().map(|key_break| {
+        let key = key_break.key.map_or("ANY".to_string(), |key| format!("{:X}", key));
+        if key_break.press_only { format!("{}+", key) } else { key }
+    }).collect();
+    lines.push(format!("KEY BREAKS: {}", if keys.is_empty() { "-".to_string() } else { keys.join(" ") }));
+    lines.extend(watch_lines(watches));
+    lines
+}
+
+// Watch panel, values that changed at the last refresh are marked with * for highlighting
+pub fn watch_lines(watches: &Watches) -> Vec<String> {
+    if watches.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec![String::new(), "WATCHES".to_string()];
+    for (idx, watch) in watches.watches().iter().enumerate() {
+        let marker = if watch.changed() { "*" } else { " " };
+        let value = watch.value().map_or("-".to_string(), |value| format!("{:04X}", value));
+        lines.push(format!("{}{} {} = {}", marker, idx + 1, watch.expr, value));
+    }
+    lines
+}
+
+// Pixel inspector panel: the pixel under the mouse in the game window and where it lives in the display
+// buffer, both as the byte per pixel buffer index and as the byte and bit a sprite row would use
+pub fn inspector_lines(chip8: &Chip8, hovered: Option<(usize, usize)>) -> Vec<String> {
+    let mut lines = vec![String::new(), "INSPECTOR".to_string()];
+    lines.push(match hovered {
+        Some((x, y)) => describe_pixel(chip8, x, y),
+        None => "POINT AT THE GAME WINDOW".to_string(),
+    });
+    lines
+}
+
+pub fn describe_pixel(chip8: &Chip8, x: usize, y: usize) -> String {
+    let idx = x + y * chip8.resolution().0;
+    format!("x={},y={} {} BUF {} BYTE {} BIT {}", x, y, if chip8.pixel(x, y) { "ON" } else { "OFF" },
+        idx, idx / 8, 7 - idx % 8)
+}
+
+// Call stack panel, innermost call first: return address, then the call site disassembled
+pub fn call_stack_lines(chip8: &Chip8) -> Vec<String> {
+    let frames = chip8.call_stack();
+    let mut lines = vec![format!("CALLS: {}", frames.len())];
+    for (depth, frame) in frames.iter().enumerate().rev().take(CALL_ROWS) {
+        let call = match frame.opcode {
+            Some(opcode) => format!("{:04X}  {}", opcode, Instruction::decode(opcode)),
+            None => "outside memory".to_string(),
+        };
+        lines.push(format!(" {:2} RET {:03X}  {:03X}: {}", depth, frame.return_address, frame.call_site, call));
+    }
+    if frames.len() > CALL_ROWS {
+        lines.push(format!(" ... {} OUTER CALLS", frames.len() - CALL_ROWS));
+    }
+    lines
+}
+
+fn opcode_in_memory(chip8: &Chip8, addr: u16) -> Option<u16> {
+    let hi = chip8.peek(addr as usize)?;
+    let lo = chip8.peek(addr as usize + 1)?;
+    Some((hi as u16) << 8 | lo as u16)
+}
+
+// Heatmap color of one address: writes in red, fetches in green and reads in blue, each channel
+// brighter by powers of two so a few accesses and thousands both show
+pub fn heat_color(reads: u16, writes: u16, executes: u16) -> (u8, u8, u8) {
+    let level = |count: u16| if count == 0 { 0 } else { (40 + (16 - count.leading_zeros()) * 13) as u8 };
+    (level(writes), level(executes), level(reads))
+}
+
+// Where memory sits in the memory bitmap: byte after byte along rows of bits_per_row pixels, leftmost
+// pixel the top bit, one page of BITMAP_PAGE bytes at a time, every pixel a scale x scale square from origin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitmapLayout {
+    pub bits_per_row: usize,
+    pub page: usize,
+    pub scale: u32,
+    pub origin: (i32, i32),
+}
+
+impl BitmapLayout {
+    // The largest scale that fits a page of bits_per_row wide rows into area, at least 1
+    pub fn fit(bits_per_row: usize, page: usize, origin: (i32, i32), area: (u32, u32)) -> Self {
+        let rows = (BITMAP_PAGE * 8 / bits_per_row) as u32;
+        let scale = (area.0 / bits_per_row as u32).min(area.1 / rows).max(1);
+        BitmapLayout { bits_per_row, page, scale, origin }
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        self.bits_per_row / 8
+    }
+
+    pub fn rows(&self) -> usize {
+        BITMAP_PAGE / self.bytes_per_row()
+    }
+
+    // First address on the page
+    pub fn base(&self) -> usize {
+        self.page * BITMAP_PAGE
+    }
+
+    // The byte at addr on screen: left, top, width and height. None when it is on another page
+    pub fn byte_rect(&self, addr: usize) -> Option<(i32, i32, u32, u32)> {
+        let offset = addr.checked_sub(self.base()).filter(|&offset| offset < BITMAP_PAGE)?;
+        let (col, row) = (offset % self.bytes_per_row(), offset / self.bytes_per_row());
+        let scale = self.scale as i32;
+        Some((self.origin.0 + (col * 8) as i32 * scale, self.origin.1 + row as i32 * scale, 8 * self.scale, self.scale))
+    }
+
+    // Address of the byte under a window position, None off the bitmap
+    pub fn address_at(&self, x: i32, y: i32) -> Option<usize> {
+        let scale = self.scale.max(1) as i32;
+        let (px, py) = (x - self.origin.0, y - self.origin.1);
+        if px < 0 || py < 0 {
+            return None;
+        }
+        let (col, row) = ((px / scale) as usize, (py / scale) as usize);
+        if col >= self.bits_per_row || row >= self.rows() {
+            return None;
+        }
+        Some(self.base() + row * self.bytes_per_row() + col / 8)
+    }
+}
+
+// Where a pattern bit goes in a strip width pixels wide: left, top, width and height within the strip
+pub fn pattern_cell(bit: usize, width: u32) -> (i32, i32, u32, u32) {
+    let per_row = audio::PATTERN_BITS / PATTERN_ROWS;
+    let cell = (width / per_row as u32).max(1);
+    ((bit % per_row) as i32 * cell as i32, (bit / per_row) as i32 * PATTERN_CELL_HEIGHT as i32, cell, PATTERN_CELL_HEIGHT)
+}
+
+// One page of memory as RGBA, a pixel per bit: set bits white, or blue in the font area, clear bits
+// black, and addresses past the end of memory grey
+pub fn bitmap_rgba(memory: &[u8], layout: &BitmapLayout) -> Vec<u8> {
+    let fonts = FONT_BASE..FONT_BASE + FONTSET_SIZE;
+    let mut out = Vec::with_capacity(BITMAP_PAGE * 8 * 4);
+    for addr in layout.base()..layout.base() + BITMAP_PAGE {
+        for bit in 0..8 {
+            let rgba = match memory.get(addr) {
+                None => [40, 40, 40, 255],
+                Some(byte) if byte & (0x80 >> bit) == 0 => [0, 0, 0, 255],
+                Some(_) if fonts.contains(&addr) => [80, 160, 255, 255],
+                Some(_) => [255, 255, 255, 255],
+            };
+            out.extend_from_slice(&rgba);
+        }
+    }
+    out
+}
+
+// What the debug window shows above the keypad
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Text,
+    Heatmap,                            // Memory heatmap in place of the text
+    Bitmap,                             // Memory bitmap in place of the text
+}
+
+// Second window hosting the debugger so the game view stays clean
+pub struct DebugWindow {
+    canvas: Canvas<Window>,
+    inspecting: bool,                   // Pixel inspector shown and following the mouse
+    hovered: Option<(usize, usize)>,    // Game pixel under the mouse while inspecting
+    view: View,
+    bitmap_width: usize,                // Index into BITMAP_WIDTHS
+    bitmap_page: usize,
+    bitmap_hover: Option<usize>,        // Address under the mouse in the memory bitmap
+    focus: Option<u16>,                 // Address clicked in the bitmap, the text views show it until Home
+    latched: u16,                       // Keys latched in sticky keys mode
+    scope: Vec<f32>,                    // Recent audio samples, oldest first
+    pattern_position: Option<usize>,    // XO-CHIP pattern bit the audio device is playing
+}
+
+impl DebugWindow {
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        let width = (COLUMNS * (overlay::GLYPH_WIDTH + 1) * TEXT_SCALE as usize) as u32 + 8;
+        let height = (ROWS * LINE_HEIGHT) as u32 + 8;
+        let window = video.window("Chip8 Debugger", width, height)
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(DebugWindow {
+            canvas,
+            inspecting: false,
+            hovered: None,
+            view: View::Text,
+            bitmap_width: 1,
+            bitmap_page: 0,
+            bitmap_hover: None,
+            focus: None,
+            latched: 0,
+            scope: Vec::new(),
+            pattern_position: None,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn toggle_inspector(&mut self) -> bool {
+        self.inspecting = !self.inspecting;
+        self.hovered = None;
+        self.inspecting
+    }
+
+    pub fn is_inspecting(&self) -> bool {
+        self.inspecting
+    }
+
+    pub fn hover(&mut self, pixel: Option<(usize, usize)>) {
+        self.hovered = pixel;
+    }
+
+    pub fn set_latched(&mut self, latched: u16) {
+        self.latched = latched;
+    }
+
+    pub fn set_scope(&mut self, samples: Vec<f32>) {
+        self.scope = samples;
+    }
+
+    pub fn set_pattern_position(&mut self, position: Option<usize>) {
+        self.pattern_position = position;
+    }
+
+    pub fn toggle_heatmap(&mut self) -> bool {
+        self.view = if self.view == View::Heatmap { View::Text } else { View::Heatmap };
+        self.view == View::Heatmap
+    }
+
+    pub fn toggle_bitmap(&mut self) -> bool {
+        self.view = if self.view == View::Bitmap { View::Text } else { View::Bitmap };
+        self.bitmap_hover = None;
+        self.view == View::Bitmap
+    }
+
+    // Keys and mouse events the window handles on its own: Home drops the focus address, and in the
+    // memory bitmap W cycles the row width, Page Up and Page Down turn pages, the mouse reports the
+    // address under it and a click focuses the text views there
+    pub fn handle(&mut self, event: &Event, chip8: &Chip8) {
+        let pages = chip8.memory().bytes().len().div_ceil(BITMAP_PAGE).max(1);
+        match *event {
+            Event::KeyDown { keycode: Some(Keycode::Home), .. } => self.focus = None,
+            Event::KeyDown { keycode: Some(key), .. } if self.view == View::Bitmap => match key {
+                Keycode::W => self.bitmap_width = (self.bitmap_width + 1) % BITMAP_WIDTHS.len(),
+                Keycode::PageUp => self.bitmap_page = self.bitmap_page.saturating_sub(1),
+                Keycode::PageDown => self.bitmap_page = (self.bitmap_page + 1).min(pages - 1),
+                _ => {}
+            },
+            Event::MouseMotion { x, y, .. } if self.view == View::Bitmap => {
+                let (x, y) = self.to_drawable(x, y);
+                self.bitmap_hover = self.bitmap_layout().address_at(x, y);
+            }
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if self.view == View::Bitmap => {
+                let (x, y) = self.to_drawable(x, y);
+                if let Some(addr) = self.bitmap_layout().address_at(x, y) {
+                    self.focus = Some(addr as u16);
+                    self.view = View::Text;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Mouse position in points to drawable pixels, which differ on high-DPI displays
+    fn to_drawable(&self, x: i32, y: i32) -> (i32, i32) {
+        let (window_w, window_h) = self.canvas.window().size();
+        let (drawable_w, drawable_h) = self.canvas.output_size().unwrap_or((window_w, window_h));
+        ((x as i64 * drawable_w as i64 / window_w.max(1) as i64) as i32, (y as i64 * drawable_h as i64 / window_h.max(1) as i64) as i32)
+    }
+
+    // The bitmap below its title line, as large as the window allows
+    fn bitmap_layout(&self) -> BitmapLayout {
+        let (width, height) = self.canvas.output_size().unwrap_or((0, 0));
+        let top = 4 + LINE_HEIGHT as i32;
+        let area = (width.saturating_sub(8), height.saturating_sub(top as u32 + 2 * LINE_HEIGHT as u32 + 8));
+        BitmapLayout::fit(BITMAP_WIDTHS[self.bitmap_width], self.bitmap_page, (4, top), area)
+    }
+
+    pub fn draw(&mut self, chip8: &Chip8, breakpoints: &Breakpoints, watches: &Watches) -> Result<(), String> {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        if let (View::Heatmap, Some(heatmap)) = (self.view, chip8.heatmap()) {
+            self.draw_heatmap(heatmap)?;
+            self.canvas.present();
+            return Ok(());
+        }
+        if self.view == View::Bitmap {
+            self.draw_bitmap(chip8)?;
+            self.canvas.present();
+            return Ok(());
+        }
+        let mut lines = lines(chip8, breakpoints, watches, self.focus);
+        if self.inspecting {
+            lines.extend(inspector_lines(chip8, self.hovered));
+        }
+        for (row, line) in lines.iter().enumerate() {
+            let color = match line.chars().next() {
+                Some('>') => Color::RGB(255, 200, 0),
+                Some('*') => Color::RGB(0, 200, 255),
+                _ => Color::RGB(255, 255, 255),
+            };
+            overlay::draw_text(&mut self.canvas, line, 4, (4 + row * LINE_HEIGHT) as i32, TEXT_SCALE, color)?;
+        }
+        let top = (4 + (lines.len() + 1) * LINE_HEIGHT) as i32;
+        self.draw_keypad(chip8, top)?;
+        self.draw_sprite(chip8, top)?;
+        self.draw_scope(chip8, top)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    // Memory as a 64x64 grid, one square per address from 0x000 in the top left, with the legend under it
+    fn draw_heatmap(&mut self, heatmap: &Heatmap) -> Result<(), String> {
+        let (reads, writes, executes) = (heatmap.reads(), heatmap.writes(), heatmap.executes());
+        for addr in 0..reads.len().min(HEAT_COLUMNS * HEAT_COLUMNS) {
+            let (r, g, b) = heat_color(reads[addr], writes[addr], executes[addr]);
+            self.canvas.set_draw_color(Color::RGB(r, g, b));
+            let (x, y) = ((addr % HEAT_COLUMNS) as u32 * HEAT_CELL, (addr / HEAT_COLUMNS) as u32 * HEAT_CELL);
+            self.canvas.fill_rect(Rect::new(4 + x as i32, 4 + y as i32, HEAT_CELL, HEAT_CELL))?;
+        }
+        let top = 12 + (HEAT_COLUMNS as u32 * HEAT_CELL) as i32;
+        overlay::draw_text(&mut self.canvas, "READ", 4, top, TEXT_SCALE, Color::RGB(0, 0, 255))?;
+        overlay::draw_text(&mut self.canvas, "WRITE", 64, top, TEXT_SCALE, Color::RGB(255, 0, 0))?;
+        overlay::draw_text(&mut self.canvas, "EXECUTE", 136, top, TEXT_SCALE, Color::RGB(0, 255, 0))?;
+        Ok(())
+    }
+
+    // Memory as a bitmap with the program start underlined in red, I boxed in cyan and PC in yellow,
+    // the address under the mouse below it
+    fn draw_bitmap(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let memory = chip8.memory().bytes();
+        let pages = memory.len().div_ceil(BITMAP_PAGE).max(1);
+        self.bitmap_page = self.bitmap_page.min(pages - 1);
+        let layout = self.bitmap_layout();
+        let title = format!("MEMORY {:03X}-{:03X} PAGE {}/{} {} BITS A ROW", layout.base(), layout.base() + BITMAP_PAGE - 1,
+            layout.page + 1, pages, layout.bits_per_row);
+        overlay::draw_text(&mut self.canvas, &title, 4, 4, TEXT_SCALE, Color::RGB(255, 255, 255))?;
+
+        let frame = bitmap_rgba(memory, &layout);
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_static(PixelFormatEnum::RGBA32, layout.bits_per_row as u32, layout.rows() as u32)
+            .map_err(|err| err.to_string())?;
+        texture.update(None, &frame, layout.bits_per_row * 4).map_err(|err| err.to_string())?;
+        let (width, height) = (layout.bits_per_row as u32 * layout.scale, layout.rows() as u32 * layout.scale);
+        self.canvas.copy(&texture, None, Rect::new(layout.origin.0, layout.origin.1, width, height))?;
+
+        if let Some((_, top, _, _)) = layout.byte_rect(PROGRAM_START) {
+            self.canvas.set_draw_color(Color::RGB(255, 0, 0));
+            self.canvas.fill_rect(Rect::new(layout.origin.0, top - 1, width, 1))?;
+        }
+        let pc = chip8.pc() as usize;
+        for (addr, color) in [(chip8.index() as usize, Color::RGB(0, 255, 255)), (pc, Color::RGB(255, 200, 0)), (pc + 1, Color::RGB(255, 200, 0))] {
+            if let Some((left, top, width, height)) = layout.byte_rect(addr) {
+                self.canvas.set_draw_color(color);
+                self.canvas.draw_rect(Rect::new(left - 1, top - 1, width + 2, height + 2))?;
+            }
+        }
+
+        let top = layout.origin.1 + height as i32 + 4;
+        let hover = match self.bitmap_hover.and_then(|addr| Some((addr, *memory.get(addr)?))) {
+            Some((addr, byte)) => format!("{:03X} = {:02X}, CLICK TO VIEW", addr, byte),
+            None => "W WIDTH, PGUP/PGDN PAGE".to_string(),
+        };
+        overlay::draw_text(&mut self.canvas, &hover, 4, top, TEXT_SCALE, Color::RGB(255, 255, 255))?;
+        let top = top + LINE_HEIGHT as i32;
+        overlay::draw_text(&mut self.canvas, "FONT", 4, top, TEXT_SCALE, Color::RGB(80, 160, 255))?;
+        overlay::draw_text(&mut self.canvas, "START", 48, top, TEXT_SCALE, Color::RGB(255, 0, 0))?;
+        overlay::draw_text(&mut self.canvas, "I", 100, top, TEXT_SCALE, Color::RGB(0, 255, 255))?;
+        overlay::draw_text(&mut self.canvas, "PC", 116, top, TEXT_SCALE, Color::RGB(255, 200, 0))
+    }
+
+    // Sprite preview right of the keypad: the bitmap at I in the shape the draw at PC uses, over a dark
+    // box the size of the largest sprite so the extent of the preview shows
+    fn draw_sprite(&mut self, chip8: &Chip8, top: i32) -> Result<(), String> {
+        let left = 8 + 4 * (KEY_CELL as i32 + 4);
+        let (width, height) = chip8.next_sprite_shape();
+        overlay::draw_text(&mut self.canvas, &format!("SPRITE {}X{}", width, height), left, top, TEXT_SCALE, Color::RGB(255, 255, 255))?;
+        let top = top + LINE_HEIGHT as i32;
+        self.canvas.set_draw_color(Color::RGB(30, 30, 30));
+        self.canvas.fill_rect(Rect::new(left, top, 16 * SPRITE_CELL, 16 * SPRITE_CELL))?;
+        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+        for (y, row) in chip8.sprite_bitmap(chip8.index(), width, height).iter().enumerate() {
+            for (x, _) in row.iter().enumerate().filter(|&(_, &lit)| lit) {
+                let (x, y) = (left + x as i32 * SPRITE_CELL as i32, top + y as i32 * SPRITE_CELL as i32);
+                self.canvas.fill_rect(Rect::new(x, y, SPRITE_CELL, SPRITE_CELL))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Audio scope right of the sprite preview: the recent samples as a trace across whatever width the
+    // window leaves, a flat line while the buzzer is off. A ROM that loaded an XO-CHIP pattern gets the
+    // pattern's frequency in the label and its 128 bits in a strip under the trace
+    fn draw_scope(&mut self, chip8: &Chip8, top: i32) -> Result<(), String> {
+        let left = 8 + 4 * (KEY_CELL as i32 + 4) + 16 * SPRITE_CELL as i32 + 8;
+        let width = self.canvas.output_size()?.0.saturating_sub(left as u32 + 4);
+        if width < 2 {
+            return Ok(());
+        }
+        let pattern = chip8.audio_pattern();
+        let label = match pattern {
+            Some(_) => format!("AUDIO {}MS {:.0}HZ", audio::SCOPE_MS, audio::pattern_rate(chip8.pitch())),
+            None => format!("AUDIO {}MS", audio::SCOPE_MS),
+        };
+        overlay::draw_text(&mut self.canvas, &label, left, top, TEXT_SCALE, Color::RGB(255, 255, 255))?;
+        let top = top + LINE_HEIGHT as i32;
+        self.canvas.set_draw_color(Color::RGB(30, 30, 30));
+        self.canvas.fill_rect(Rect::new(left, top, width, SCOPE_HEIGHT))?;
+        let trace_height = if pattern.is_some() { SCOPE_HEIGHT - PATTERN_STRIP } else { SCOPE_HEIGHT };
+        if let Some(pattern) = pattern {
+            let strip_top = top + trace_height as i32 + 2;
+            for (bit, &lit) in audio::pattern_bits(&pattern).iter().enumerate() {
+                let (x, y, w, h) = pattern_cell(bit, width);
+                self.canvas.set_draw_color(match (self.pattern_position == Some(bit), lit) {
+                    (true, _) => Color::RGB(255, 60, 60),
+                    (false, true) => Color::RGB(0, 255, 120),
+                    (false, false) => Color::RGB(60, 60, 60),
+                });
+                self.canvas.fill_rect(Rect::new(left + x, strip_top + y, w, h - 1))?;
+            }
+        }
+        let middle = top + trace_height as i32 / 2;
+        self.canvas.set_draw_color(Color::RGB(80, 80, 80));
+        self.canvas.draw_line((left, middle), (left + width as i32 - 1, middle))?;
+        if self.scope.is_empty() {
+            return Ok(());
+        }
+
+        // One point per column, full volume reaching the top and bottom of the trace
+        let half = (trace_height / 2 - 1) as f32;
+        let points: Vec<Point> = (0..width).map(|x| {
+            let sample = self.scope[x as usize * self.scope.len() / width as usize] / audio::VOLUME;
+            Point::new(left + x as i32, middle - (sample.clamp(-1.0, 1.0) * half) as i32)
+        }).collect();
+        self.canvas.set_draw_color(Color::RGB(0, 255, 120));
+        self.canvas.draw_lines(points.as_slice())
+    }
+
+    // Keypad viewer: magenta while latched, green while pressed, amber while the ROM is polling the key,
+    // grey otherwise
+    fn draw_keypad(&mut self, chip8: &Chip8, top: i32) -> Result<(), String> {
+        let (pressed, polled) = (chip8.keys_mask(), chip8.polled_keys());
+        for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+            for (col, &key) in keys.iter().enumerate() {
+                let x = 4 + col as i32 * (KEY_CELL as i32 + 4);
+                let y = top + row as i32 * (KEY_CELL as i32 + 4);
+                let (fill, text) = match key_view(pressed, polled, self.latched, key) {
+                    KeyView::Latched => (Color::RGB(200, 0, 200), Color::RGB(255, 255, 255)),
+                    KeyView::Pressed => (Color::RGB(0, 200, 0), Color::RGB(0, 0, 0)),
+                    KeyView::Polled => (Color::RGB(200, 140, 0), Color::RGB(0, 0, 0)),
+                    KeyView::Idle => (Color::RGB(60, 60, 60), Color::RGB(160, 160, 160)),
+                };
+                self.canvas.set_draw_color(fill);
+                self.canvas.fill_rect(Rect::new(x, y, KEY_CELL, KEY_CELL))?;
+                let inset = (KEY_CELL as i32 - (overlay::GLYPH_HEIGHT as i32 * TEXT_SCALE as i32)) / 2;
+                overlay::draw_text(&mut self.canvas, &format!("{:X}", key), x + inset + 1, y + inset, TEXT_SCALE, text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::keyboard::Mod;
+
+    const GAME: u32 = 1;
+    const DEBUG: u32 = 2;
+
+    fn window(window_id: u32, win_event: WindowEvent) -> Event {
+        Event::Window { timestamp: 0, window_id, win_event }
+    }
+
+    fn key_down(window_id: u32, keycode: Keycode) -> Event {
+        Event::KeyDown { timestamp: 0, window_id, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false }
+    }
+
+    #[test]
+    fn closing_a_window_routes_by_its_id() {
+        assert_eq!(route(&window(DEBUG, WindowEvent::Close), GAME, Some(DEBUG)), Route::CloseDebug, "the debug window closes alone");
+        assert_eq!(route(&window(GAME, WindowEvent::Close), GAME, Some(DEBUG)), Route::Quit, "the game window closes both");
+        assert_eq!(route(&Event::Quit { timestamp: 0 }, GAME, Some(DEBUG)), Route::Quit);
+        assert_eq!(route(&window(DEBUG, WindowEvent::FocusGained), GAME, Some(DEBUG)), Route::Debug);
+        assert_eq!(route(&window(DEBUG, WindowEvent::Resized(800, 600)), GAME, Some(DEBUG)), Route::Debug);
+        assert_eq!(route(&window(GAME, WindowEvent::FocusLost), GAME, Some(DEBUG)), Route::Game);
+    }
+
+    #[test]
+    fn keys_go_to_the_focused_window() {
+        assert_eq!(route(&key_down(GAME, Keycode::W), GAME, Some(DEBUG)), Route::Game, "keypad input from the game window");
+        assert_eq!(route(&key_down(GAME, Keycode::Escape), GAME, Some(DEBUG)), Route::Game, "Escape in the game window quits through the reducer");
+        assert_eq!(route(&key_down(DEBUG, Keycode::W), GAME, Some(DEBUG)), Route::Debug, "keypad keys typed into the debugger are dropped");
+        assert_eq!(route(&key_down(DEBUG, Keycode::Escape), GAME, Some(DEBUG)), Route::CloseDebug);
+        assert_eq!(route(&key_down(DEBUG, Keycode::F10), GAME, Some(DEBUG)), Route::CloseDebug);
+        assert_eq!(route(&key_down(DEBUG, Keycode::C), GAME, Some(DEBUG)), Route::ToggleKeyBreak(0xC));
+        assert_eq!(route(&key_down(DEBUG, Keycode::Num5), GAME, Some(DEBUG)), Route::ToggleKeyBreak(0x5));
+        assert_eq!(route(&key_down(DEBUG, Keycode::G), GAME, Some(DEBUG)), Route::Debug, "G is no hex digit");
+        assert_eq!(route(&key_down(DEBUG, Keycode::I), GAME, Some(DEBUG)), Route::ToggleInspector);
+        assert_eq!(route(&key_down(DEBUG, Keycode::H), GAME, Some(DEBUG)), Route::ToggleHeatmap);
+        assert_eq!(route(&key_down(DEBUG, Keycode::M), GAME, Some(DEBUG)), Route::ToggleBitmap);
+    }
+
+    #[test]
+    fn without_a_debug_window_everything_is_the_games() {
+        assert_eq!(route(&key_down(DEBUG, Keycode::Escape), GAME, None), Route::Game);
+        assert_eq!(route(&window(DEBUG, WindowEvent::Close), GAME, None), Route::Game);
+        assert_eq!(route(&window(GAME, WindowEvent::Close), GAME, None), Route::Quit);
+    }
+
+    #[test]
+    fn key_views_rank_latched_over_pressed_over_polled() {
+        let (pressed, polled, latched) = (1 << 5 | 1 << 6, 1 << 5 | 1 << 7, 1 << 6);
+        assert_eq!(key_view(pressed, polled, latched, 5), KeyView::Pressed);
+        assert_eq!(key_view(pressed, polled, latched, 6), KeyView::Latched);
+        assert_eq!(key_view(pressed, polled, latched, 7), KeyView::Polled);
+        assert_eq!(key_view(pressed, polled, latched, 8), KeyView::Idle);
+    }
+
+    #[test]
+    fn key_breaks_toggled_in_the_window_are_listed() {
+        let chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+        let listed = |breakpoints: &Breakpoints| lines(&chip8, breakpoints, &Watches::new(), None).into_iter().find(|line| line.starts_with("KEY BREAKS")).unwrap();
+        assert_eq!(listed(&breakpoints), "KEY BREAKS: -");
+        let Route::ToggleKeyBreak(key) = route(&key_down(DEBUG, Keycode::Num5), GAME, Some(DEBUG)) else {
+            panic!("5 toggles a key break in the debug window");
+        };
+        assert!(breakpoints.toggle_key(key));
+        breakpoints.apply(chip8::breakpoints::parse_command("b key any press").unwrap());
+        assert_eq!(listed(&breakpoints), "KEY BREAKS: 5 ANY+");
+    }
+
+    #[test]
+    fn call_stack_panel_disassembles_each_call_site() {
+        // 0x200 calls 0x206, which calls 0x20A, which calls 0x20E, which spins
+        let rom = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x22, 0x0A, 0x00, 0xEE, 0x22, 0x0E, 0x00, 0xEE, 0x12, 0x0E];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert_eq!(call_stack_lines(&chip8), ["CALLS: 0"]);
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert_eq!(call_stack_lines(&chip8), [
+            "CALLS: 3",
+            "  2 RET 20C  20A: 220E  jsr 0x20E",
+            "  1 RET 208  206: 220A  jsr 0x20A",
+            "  0 RET 202  200: 2206  jsr 0x206",
+        ]);
+    }
+
+    #[test]
+    fn full_stacks_count_the_calls_past_the_panel() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        let mut state = chip8.save_state();
+        state[28..30].copy_from_slice(&16u16.to_le_bytes());        // sp follows the rom hash, V0-VF, I and PC
+        chip8.load_state(&state).unwrap();
+
+        let lines = call_stack_lines(&chip8);
+        assert_eq!(lines[0], "CALLS: 16");
+        assert_eq!(lines.len(), 1 + CALL_ROWS + 1);
+        assert_eq!(lines[CALL_ROWS + 1], format!(" ... {} OUTER CALLS", 16 - CALL_ROWS));
+    }
+
+    #[test]
+    fn bitmap_layouts_fit_a_page_in_the_area() {
+        let narrow = BitmapLayout::fit(64, 0, (0, 0), (640, 5120));
+        assert_eq!((narrow.bytes_per_row(), narrow.rows(), narrow.scale), (8, 512, 10));
+        let wide = BitmapLayout::fit(128, 0, (0, 0), (640, 5120));
+        assert_eq!((wide.bytes_per_row(), wide.rows(), wide.scale), (16, 256, 5));
+        assert_eq!(BitmapLayout::fit(256, 0, (0, 0), (100, 100)).scale, 1, "never below one pixel a bit");
+    }
+
+    #[test]
+    fn every_byte_maps_to_its_rect_and_back() {
+        for bits_per_row in [64, 128, 256] {
+            for page in [0, 3] {
+                let layout = BitmapLayout { bits_per_row, page, scale: 3, origin: (10, 20) };
+                for addr in layout.base()..layout.base() + BITMAP_PAGE {
+                    let (x, y, width, height) = layout.byte_rect(addr).unwrap();
+                    assert_eq!((width, height), (24, 3));
+                    assert_eq!(layout.address_at(x, y), Some(addr), "{} bits, page {}, top left of {:#X}", bits_per_row, page, addr);
+                    assert_eq!(layout.address_at(x + 23, y + 2), Some(addr), "bottom right of {:#X}", addr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn positions_off_the_bitmap_have_no_address() {
+        let layout = BitmapLayout { bits_per_row: 64, page: 1, scale: 2, origin: (10, 20) };
+        assert_eq!(layout.address_at(10, 20), Some(0x1000));
+        assert_eq!(layout.address_at(10 + 2 * 8, 20 + 2), Some(0x1009), "second byte of the second row");
+        assert_eq!(layout.address_at(9, 20), None);
+        assert_eq!(layout.address_at(10, 19), None);
+        assert_eq!(layout.address_at(10 + 2 * 64, 20), None);
+        assert_eq!(layout.address_at(10, 20 + 2 * 512), None);
+        assert_eq!(layout.byte_rect(0xFFF), None, "on page 0");
+        assert_eq!(layout.byte_rect(0x2000), None, "on page 2");
+    }
+
+    #[test]
+    fn bitmap_pixels_color_fonts_program_bytes_and_missing_memory() {
+        let mut memory = vec![0; 0x1800];
+        memory[FONT_BASE] = 0x80;
+        memory[0x200] = 0x01;
+        let pixel = |rgba: &[u8], addr: usize, bit: usize| rgba[(addr % BITMAP_PAGE * 8 + bit) * 4..][..4].to_vec();
+
+        let rgba = bitmap_rgba(&memory, &BitmapLayout { bits_per_row: 64, page: 0, scale: 1, origin: (0, 0) });
+        assert_eq!(rgba.len(), BITMAP_PAGE * 8 * 4);
+        assert_eq!(pixel(&rgba, FONT_BASE, 0), [80, 160, 255, 255]);
+        assert_eq!(pixel(&rgba, FONT_BASE, 1), [0, 0, 0, 255]);
+        assert_eq!(pixel(&rgba, 0x200, 7), [255, 255, 255, 255]);
+
+        let rgba = bitmap_rgba(&memory, &BitmapLayout { bits_per_row: 64, page: 1, scale: 1, origin: (0, 0) });
+        assert_eq!(pixel(&rgba, 0x17FF, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&rgba, 0x1800, 0), [40, 40, 40, 255], "past the end of memory");
+    }
+
+    #[test]
+    fn pattern_bits_fill_two_rows_of_64() {
+        assert_eq!(pattern_cell(0, 320), (0, 0, 5, PATTERN_CELL_HEIGHT));
+        assert_eq!(pattern_cell(63, 320), (315, 0, 5, PATTERN_CELL_HEIGHT));
+        assert_eq!(pattern_cell(64, 320), (0, PATTERN_CELL_HEIGHT as i32, 5, PATTERN_CELL_HEIGHT));
+        assert_eq!(pattern_cell(127, 320), (315, PATTERN_CELL_HEIGHT as i32, 5, PATTERN_CELL_HEIGHT));
+        assert_eq!(pattern_cell(10, 40), (10, 0, 1, PATTERN_CELL_HEIGHT), "a narrow window still gets a pixel a bit");
+    }
+}
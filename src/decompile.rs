@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::analysis::reachable;
+use crate::disasm::{bitmap, opcode_at, Flow, Instruction};
+
+// ROM to Octo source. Every byte of the ROM is emitted either as a statement or a byte literal,
+// so the output assembles back to the same image
+
+// Code reachable from 0x200 becomes statements; instructions overlapping another through an odd address,
+// and everything unreachable, stay byte literals
+pub fn decompile(rom: &[u8]) -> String {
+    let reached: BTreeSet<u16> = reachable(rom).into_iter().collect();
+    let code: BTreeMap<u16, Instruction> = reached.iter()
+        .filter(|&&addr| !reached.contains(&(addr - 1)) && !reached.contains(&(addr + 1)))
+        .map(|&addr| (addr, Instruction::decode(opcode_at(rom, addr).unwrap())))
+        .collect();
+    let end = 0x200 + rom.len() as u16;
+
+    // Labels can go anywhere except the middle of a statement
+    let placeable = |addr: u16| (0x200..end).contains(&addr) && !code.contains_key(&(addr - 1));
+    let mut labels = BTreeMap::from([(0x200, "main".to_string())]);
+    for instruction in code.values() {
+        let (addr, name) = match *instruction {
+            Instruction::Jsr(nnn) => (nnn, format!("sub_{:03X}", nnn)),
+            Instruction::Jmp(nnn) | Instruction::Jmi(nnn) => (nnn, format!("label_{:03X}", nnn)),
+            Instruction::Mvi(nnn) => (nnn, format!("data_{:03X}", nnn)),
+            _ => continue,
+        };
+        if placeable(addr) {
+            labels.entry(addr).or_insert(name);
+        }
+    }
+
+    let mut out = String::from("# Decompiled CHIP-8 ROM\n");
+    let mut addr = 0x200;
+    let mut wrapped = false;                // Previous statement was an if waiting for its body on this line
+    while addr < end {
+        if let Some(name) = labels.get(&addr) {
+            let _ = writeln!(out, "\n: {}", name);
+        }
+
+        match code.get(&addr) {
+            Some(&instruction) => {
+                let line = statement(instruction, &labels)
+                    .unwrap_or_else(|| bytes(opcode_at(rom, addr).unwrap(), instruction));
+                // A skip reads as an if wrapping the next statement when nothing jumps between them
+                let indent = if wrapped { "" } else { "\t" };
+                wrapped = instruction.flow() == Flow::Skip && code.contains_key(&(addr + 2)) && !labels.contains_key(&(addr + 2));
+                if wrapped {
+                    let _ = write!(out, "{}{} ", indent, line);
+                } else {
+                    let _ = writeln!(out, "{}{}", indent, line);
+                }
+                addr += 2;
+            }
+            None => {
+                let byte = rom[(addr - 0x200) as usize];
+                let _ = writeln!(out, "\t{:#04x}  # {}", byte, bitmap(byte));
+                addr += 1;
+            }
+        }
+    }
+    out
+}
+
+// Raw fallback for opcodes Octo has no statement for
+fn bytes(opcode: u16, instruction: Instruction) -> String {
+    format!("{:#04x} {:#04x}  # {}", opcode >> 8, opcode & 0xFF, instruction)
+}
+
+// Octo statement for an instruction, None when it has to stay as bytes
+fn statement(instruction: Instruction, labels: &BTreeMap<u16, String>) -> Option<String> {
+    let target = |addr: u16| labels.get(&addr).cloned().unwrap_or_else(|| format!("{:#05x}", addr));
+    Some(match instruction {
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Scd(n) => format!("scroll-down {}", n),
+        Instruction::Scr => "scroll-right".to_string(),
+        Instruction::Scl => "scroll-left".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Low => "lores".to_string(),
+        Instruction::High => "hires".to_string(),
+        Instruction::Jmp(nnn) => format!("jump {}", target(nnn)),
+        Instruction::Jsr(nnn) => labels.get(&nnn)?.clone(),        // Octo calls a subroutine by naming it
+        Instruction::SkeqC(x, nn) => format!("if v{:x} != {} then", x, nn),
+        Instruction::SkneC(x, nn) => format!("if v{:x} == {} then", x, nn),
+        Instruction::SkeqR(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SkneR(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::Skpr(x) => format!("if v{:x} -key then", x),
+        Instruction::Skup(x) => format!("if v{:x} key then", x),
+        Instruction::MovC(x, nn) => format!("v{:x} := {}", x, nn),
+        Instruction::AddC(x, nn) => format!("v{:x} += {}", x, nn),
+        Instruction::MovR(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::OrR(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::AndR(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::XorR(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddR(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::SubR(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShrR(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::RsbR(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShlR(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::Mvi(nnn) => format!("i := {}", target(nnn)),
+        Instruction::Jmi(nnn) => format!("jump0 {}", target(nnn)),
+        Instruction::Rand(x, nn) => format!("v{:x} := random {:#04x}", x, nn),
+        Instruction::Sprite(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::Gdelay(x) => format!("v{:x} := delay", x),
+        Instruction::Key(x) => format!("v{:x} := key", x),
+        Instruction::Sdelay(x) => format!("delay := v{:x}", x),
+        Instruction::Ssound(x) => format!("buzzer := v{:x}", x),
+        Instruction::Adi(x) => format!("i += v{:x}", x),
+        Instruction::Font(x) => format!("i := hex v{:x}", x),
+        Instruction::Bcd(x) => format!("bcd v{:x}", x),
+        Instruction::Str(x) => format!("save v{:x}", x),
+        Instruction::Ldr(x) => format!("load v{:x}", x),
+        Instruction::Srpl(x) => format!("saveflags v{:x}", x),
+        Instruction::Lrpl(x) => format!("loadflags v{:x}", x),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::Nop | Instruction::Compat | Instruction::Sys(_) | Instruction::Unknown(_) => return None,
+    })
+}
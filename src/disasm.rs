@@ -0,0 +1,478 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+use crate::chip8::Quirks;
+use crate::coverage::Coverage;
+use crate::prelude::*;
+
+// Instruction decoder and disassembler, mnemonics follow the names of the interpreter's opcode functions
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,                                // 0000
+    Cls,                                // 00E0
+    Scd(u8),                            // 00CN, SUPER-CHIP
+    Scr,                                // 00FB, SUPER-CHIP
+    Scl,                                // 00FC, SUPER-CHIP
+    Ret,                                // 00EE
+    Compat,                             // 00FA
+    Exit,                               // 00FD, SUPER-CHIP
+    Low,                                // 00FE, SUPER-CHIP
+    High,                               // 00FF, SUPER-CHIP
+    Sys(u16),                           // 0NNN
+    Jmp(u16),                           // 1NNN
+    Jsr(u16),                           // 2NNN
+    SkeqC(u8, u8),                      // 3XNN
+    SkneC(u8, u8),                      // 4XNN
+    SkeqR(u8, u8),                      // 5XY0
+    MovC(u8, u8),                       // 6XNN
+    AddC(u8, u8),                       // 7XNN
+    MovR(u8, u8),                       // 8XY0
+    OrR(u8, u8),                        // 8XY1
+    AndR(u8, u8),                       // 8XY2
+    XorR(u8, u8),                       // 8XY3
+    AddR(u8, u8),                       // 8XY4
+    SubR(u8, u8),                       // 8XY5
+    ShrR(u8, u8),                       // 8XY6
+    RsbR(u8, u8),                       // 8XY7
+    ShlR(u8, u8),                       // 8XYE
+    SkneR(u8, u8),                      // 9XY0
+    Mvi(u16),                           // ANNN
+    Jmi(u16),                           // BNNN
+    Rand(u8, u8),                       // CXNN
+    Sprite(u8, u8, u8),                 // DXYN
+    Skpr(u8),                           // EX9E
+    Skup(u8),                           // EXA1
+    Gdelay(u8),                         // FX07
+    Key(u8),                            // FX0A
+    Sdelay(u8),                         // FX15
+    Ssound(u8),                         // FX18
+    Adi(u8),                            // FX1E
+    Font(u8),                           // FX29
+    Bcd(u8),                            // FX33
+    Str(u8),                            // FX55
+    Ldr(u8),                            // FX65
+    Srpl(u8),                           // FX75
+    Lrpl(u8),                           // FX85
+    Audio,                              // F002, XO-CHIP
+    Pitch(u8),                          // FX3A, XO-CHIP
+    Unknown(u16),
+}
+
+// How an instruction passes control on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Next,                               // Falls through to the next instruction
+    Jump(u16),
+    Call(u16),                          // Calls NNN, then continues with the next instruction
+    Skip,                               // Either the next instruction or the one after
+    Return,
+    Computed(u16),                      // BNNN, target depends on v0
+    Halt,
+}
+
+// What kind of work an instruction does, for profiles and color coded traces
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    Jump,                               // 1NNN, BNNN
+    Call,                               // 2NNN
+    Return,                             // 00EE
+    Skip,                               // 3XNN, 4XNN, 5XY0, 9XY0
+    Load,                               // Register, index and memory moves, FX29 and FX33
+    Arithmetic,                         // 7XNN, the 8XYN ALU ops, CXNN and FX1E
+    Draw,                               // 00E0, the SUPER-CHIP scroll and resolution opcodes, DXYN
+    Input,                              // EX9E, EXA1, FX0A
+    Timer,                              // FX07, FX15, FX18 and the XO-CHIP audio F002 and FX3A
+    Misc,                               // Everything else, including unknown opcodes
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x0000 => Instruction::Nop,
+                0x00E0 => Instruction::Cls,
+                0x00C0..=0x00CF => Instruction::Scd(n),
+                0x00FB => Instruction::Scr,
+                0x00FC => Instruction::Scl,
+                0x00EE => Instruction::Ret,
+                0x00FA => Instruction::Compat,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::Low,
+                0x00FF => Instruction::High,
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jmp(nnn),
+            0x2000 => Instruction::Jsr(nnn),
+            0x3000 => Instruction::SkeqC(x, nn),
+            0x4000 => Instruction::SkneC(x, nn),
+            0x5000 if n == 0 => Instruction::SkeqR(x, y),
+            0x6000 => Instruction::MovC(x, nn),
+            0x7000 => Instruction::AddC(x, nn),
+            0x8000 => match n {
+                0x0 => Instruction::MovR(x, y),
+                0x1 => Instruction::OrR(x, y),
+                0x2 => Instruction::AndR(x, y),
+                0x3 => Instruction::XorR(x, y),
+                0x4 => Instruction::AddR(x, y),
+                0x5 => Instruction::SubR(x, y),
+                0x6 => Instruction::ShrR(x, y),
+                0x7 => Instruction::RsbR(x, y),
+                0xE => Instruction::ShlR(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9000 if n == 0 => Instruction::SkneR(x, y),
+            0xA000 => Instruction::Mvi(nnn),
+            0xB000 => Instruction::Jmi(nnn),
+            0xC000 => Instruction::Rand(x, nn),
+            0xD000 => Instruction::Sprite(x, y, n),
+            0xE000 => match nn {
+                0x9E => Instruction::Skpr(x),
+                0xA1 => Instruction::Skup(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF000 => match nn {
+                0x07 => Instruction::Gdelay(x),
+                0x0A => Instruction::Key(x),
+                0x15 => Instruction::Sdelay(x),
+                0x18 => Instruction::Ssound(x),
+                0x1E => Instruction::Adi(x),
+                0x29 => Instruction::Font(x),
+                0x33 => Instruction::Bcd(x),
+                0x55 => Instruction::Str(x),
+                0x65 => Instruction::Ldr(x),
+                0x75 => Instruction::Srpl(x),
+                0x85 => Instruction::Lrpl(x),
+                0x02 if x == 0 => Instruction::Audio,
+                0x3A => Instruction::Pitch(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    pub fn flow(&self) -> Flow {
+        match *self {
+            Instruction::Jmp(nnn) => Flow::Jump(nnn),
+            Instruction::Jsr(nnn) => Flow::Call(nnn),
+            Instruction::SkeqC(..) | Instruction::SkneC(..) | Instruction::SkeqR(..) | Instruction::SkneR(..)
+                | Instruction::Skpr(_) | Instruction::Skup(_) => Flow::Skip,
+            Instruction::Ret => Flow::Return,
+            Instruction::Jmi(nnn) => Flow::Computed(nnn),
+            Instruction::Exit => Flow::Halt,
+            _ => Flow::Next,
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match *self {
+            Instruction::Jmp(_) | Instruction::Jmi(_) => Category::Jump,
+            Instruction::Jsr(_) => Category::Call,
+            Instruction::Ret => Category::Return,
+            Instruction::SkeqC(..) | Instruction::SkneC(..) | Instruction::SkeqR(..) | Instruction::SkneR(..) => Category::Skip,
+            Instruction::MovC(..) | Instruction::MovR(..) | Instruction::Mvi(_) | Instruction::Font(_) | Instruction::Bcd(_)
+                | Instruction::Str(_) | Instruction::Ldr(_) | Instruction::Srpl(_) | Instruction::Lrpl(_) => Category::Load,
+            Instruction::AddC(..) | Instruction::OrR(..) | Instruction::AndR(..) | Instruction::XorR(..) | Instruction::AddR(..)
+                | Instruction::SubR(..) | Instruction::ShrR(..) | Instruction::RsbR(..) | Instruction::ShlR(..)
+                | Instruction::Rand(..) | Instruction::Adi(_) => Category::Arithmetic,
+            Instruction::Cls | Instruction::Scd(_) | Instruction::Scr | Instruction::Scl | Instruction::Low | Instruction::High
+                | Instruction::Sprite(..) => Category::Draw,
+            Instruction::Skpr(_) | Instruction::Skup(_) | Instruction::Key(_) => Category::Input,
+            Instruction::Gdelay(_) | Instruction::Sdelay(_) | Instruction::Ssound(_) | Instruction::Audio | Instruction::Pitch(_) => Category::Timer,
+            Instruction::Nop | Instruction::Compat | Instruction::Exit | Instruction::Sys(_) | Instruction::Unknown(_) => Category::Misc,
+        }
+    }
+
+    // Registers the instruction reads as a mask, bit n for vN
+    pub fn reads(&self, quirks: &Quirks) -> u16 {
+        let reg = |r: u8| 1u16 << r;
+        match *self {
+            Instruction::SkeqC(x, _) | Instruction::SkneC(x, _) | Instruction::AddC(x, _) => reg(x),
+            Instruction::SkeqR(x, y) | Instruction::SkneR(x, y) | Instruction::OrR(x, y) | Instruction::AndR(x, y)
+                | Instruction::XorR(x, y) | Instruction::AddR(x, y) | Instruction::SubR(x, y) | Instruction::RsbR(x, y)
+                | Instruction::Sprite(x, y, _) => reg(x) | reg(y),
+            Instruction::MovR(_, y) => reg(y),
+            Instruction::ShrR(x, y) | Instruction::ShlR(x, y) => if quirks.shift_vy { reg(y) } else { reg(x) },
+            Instruction::Jmi(_) => reg(0),
+            Instruction::Skpr(x) | Instruction::Skup(x) | Instruction::Sdelay(x) | Instruction::Ssound(x)
+                | Instruction::Adi(x) | Instruction::Font(x) | Instruction::Bcd(x) | Instruction::Pitch(x) => reg(x),
+            Instruction::Str(x) | Instruction::Srpl(x) => range(x),
+            _ => 0,
+        }
+    }
+
+    // Registers the instruction writes as a mask, including vF when it sets the flag
+    pub fn writes(&self, quirks: &Quirks) -> u16 {
+        let reg = |r: u8| 1u16 << r;
+        match *self {
+            Instruction::MovC(x, _) | Instruction::AddC(x, _) | Instruction::MovR(x, _) | Instruction::OrR(x, _)
+                | Instruction::AndR(x, _) | Instruction::XorR(x, _) | Instruction::Rand(x, _) | Instruction::Gdelay(x)
+                | Instruction::Key(x) => reg(x),
+            Instruction::AddR(x, _) | Instruction::SubR(x, _) | Instruction::ShrR(x, _) | Instruction::RsbR(x, _)
+                | Instruction::ShlR(x, _) => reg(x) | reg(0xF),
+            Instruction::Sprite(..) => reg(0xF),
+            Instruction::Adi(_) if quirks.adi_overflow_vf => reg(0xF),
+            Instruction::Ldr(x) | Instruction::Lrpl(x) => range(x),
+            _ => 0,
+        }
+    }
+}
+
+// Mask of v0 through vX
+fn range(x: u8) -> u16 {
+    (((1u32 << (x + 1)) - 1) & 0xFFFF) as u16
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "nop"),
+            Instruction::Cls => write!(f, "cls"),
+            Instruction::Scd(n) => write!(f, "scd {}", n),
+            Instruction::Scr => write!(f, "scr"),
+            Instruction::Scl => write!(f, "scl"),
+            Instruction::Ret => write!(f, "ret"),
+            Instruction::Compat => write!(f, "compat"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::Low => write!(f, "low"),
+            Instruction::High => write!(f, "high"),
+            Instruction::Sys(nnn) => write!(f, "sys {:#05X}", nnn),
+            Instruction::Jmp(nnn) => write!(f, "jmp {:#05X}", nnn),
+            Instruction::Jsr(nnn) => write!(f, "jsr {:#05X}", nnn),
+            Instruction::SkeqC(x, nn) => write!(f, "skeq v{:X}, {:#04X}", x, nn),
+            Instruction::SkneC(x, nn) => write!(f, "skne v{:X}, {:#04X}", x, nn),
+            Instruction::SkeqR(x, y) => write!(f, "skeq v{:X}, v{:X}", x, y),
+            Instruction::MovC(x, nn) => write!(f, "mov v{:X}, {:#04X}", x, nn),
+            Instruction::AddC(x, nn) => write!(f, "add v{:X}, {:#04X}", x, nn),
+            Instruction::MovR(x, y) => write!(f, "mov v{:X}, v{:X}", x, y),
+            Instruction::OrR(x, y) => write!(f, "or v{:X}, v{:X}", x, y),
+            Instruction::AndR(x, y) => write!(f, "and v{:X}, v{:X}", x, y),
+            Instruction::XorR(x, y) => write!(f, "xor v{:X}, v{:X}", x, y),
+            Instruction::AddR(x, y) => write!(f, "add v{:X}, v{:X}", x, y),
+            Instruction::SubR(x, y) => write!(f, "sub v{:X}, v{:X}", x, y),
+            Instruction::ShrR(x, y) => write!(f, "shr v{:X}, v{:X}", x, y),
+            Instruction::RsbR(x, y) => write!(f, "rsb v{:X}, v{:X}", x, y),
+            Instruction::ShlR(x, y) => write!(f, "shl v{:X}, v{:X}", x, y),
+            Instruction::SkneR(x, y) => write!(f, "skne v{:X}, v{:X}", x, y),
+            Instruction::Mvi(nnn) => write!(f, "mvi {:#05X}", nnn),
+            Instruction::Jmi(nnn) => write!(f, "jmi {:#05X}", nnn),
+            Instruction::Rand(x, nn) => write!(f, "rand v{:X}, {:#04X}", x, nn),
+            Instruction::Sprite(x, y, n) => write!(f, "sprite v{:X}, v{:X}, {}", x, y, n),
+            Instruction::Skpr(x) => write!(f, "skpr v{:X}", x),
+            Instruction::Skup(x) => write!(f, "skup v{:X}", x),
+            Instruction::Gdelay(x) => write!(f, "gdelay v{:X}", x),
+            Instruction::Key(x) => write!(f, "key v{:X}", x),
+            Instruction::Sdelay(x) => write!(f, "sdelay v{:X}", x),
+            Instruction::Ssound(x) => write!(f, "ssound v{:X}", x),
+            Instruction::Adi(x) => write!(f, "adi v{:X}", x),
+            Instruction::Font(x) => write!(f, "font v{:X}", x),
+            Instruction::Bcd(x) => write!(f, "bcd v{:X}", x),
+            Instruction::Str(x) => write!(f, "str v0-v{:X}", x),
+            Instruction::Ldr(x) => write!(f, "ldr v0-v{:X}", x),
+            Instruction::Srpl(x) => write!(f, "srpl v0-v{:X}", x),
+            Instruction::Lrpl(x) => write!(f, "lrpl v0-v{:X}", x),
+            Instruction::Audio => write!(f, "audio"),
+            Instruction::Pitch(x) => write!(f, "pitch v{:X}", x),
+            Instruction::Unknown(opcode) => write!(f, "db {:#06X}", opcode),
+        }
+    }
+}
+
+// Opcode at a memory address of a ROM loaded at 0x200, None when it falls outside the ROM
+pub fn opcode_at(rom: &[u8], addr: u16) -> Option<u16> {
+    let offset = (addr as usize).checked_sub(0x200)?;
+    let hi = *rom.get(offset)?;
+    let lo = *rom.get(offset + 1)?;
+    Some((hi as u16) << 8 | lo as u16)
+}
+
+// Linear listing of the whole ROM, one line per 2 bytes: address, opcode, mnemonic. Code and data are
+// interleaved, so data decodes as whatever instruction it happens to spell; an odd last byte is
+// listed as data
+pub fn disassemble(rom: &[u8]) -> Vec<String> {
+    disassemble_with_data(rom, &BTreeSet::new())
+}
+
+// Linear listing that shows the given addresses as data bytes rather than instructions, for jump tables
+// and sprites found by analysis or marked by hand. An instruction is only decoded when neither of its
+// bytes is data, the listing picks up in 2 byte steps again after each data run
+pub fn disassemble_with_data(rom: &[u8], data: &BTreeSet<u16>) -> Vec<String> {
+    let end = 0x200 + rom.len() as u16;
+    let mut lines = Vec::new();
+    let mut addr = 0x200;
+    while addr < end {
+        match opcode_at(rom, addr) {
+            Some(opcode) if !data.contains(&addr) && !data.contains(&(addr + 1)) => {
+                lines.push(format!("{:#05X}: {:04X}  {}", addr, opcode, Instruction::decode(opcode)));
+                addr += 2;
+            }
+            _ => {
+                let byte = rom[(addr - 0x200) as usize];
+                lines.push(format!("{:#05X}: {:02X}    db {:#04X}", addr, byte, byte));
+                addr += 1;
+            }
+        }
+    }
+    lines
+}
+
+// Listing guided by a run's coverage: executed addresses are instructions, everything else is data
+// bytes with a bitmap comment, and a marker line starts each code and data region
+pub fn disassemble_with_coverage(rom: &[u8], coverage: &Coverage) -> Vec<String> {
+    let end = 0x200 + rom.len() as u16;
+    let mut lines = Vec::new();
+    let mut in_code = None;
+    let mut addr = 0x200;
+    while addr < end {
+        let code = coverage.is_executed(addr) && addr + 1 < end;
+        if in_code != Some(code) {
+            lines.push(if code { "; code".to_string() } else { "; data".to_string() });
+            in_code = Some(code);
+        }
+
+        if code {
+            let opcode = opcode_at(rom, addr).unwrap();
+            lines.push(format!("{:#05X}: {:04X}  {}", addr, opcode, Instruction::decode(opcode)));
+            addr += 2;
+        } else {
+            let byte = rom[(addr - 0x200) as usize];
+            lines.push(format!("{:#05X}: {:02X}    db {:#04X}  ; {}", addr, byte, byte, bitmap(byte)));
+            addr += 1;
+        }
+    }
+    lines
+}
+
+// Sprite row as # for set and . for clear bits
+pub(crate) fn bitmap(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    // I = 0x206, draw the 0 glyph stored after the loop, then spin
+    const CODE_AND_SPRITE: [u8; 11] = [0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04, 0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+    #[test]
+    fn coverage_splits_code_from_sprite_data() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&CODE_AND_SPRITE).unwrap();
+        chip8.step_frame(10);
+        // The listing reads the coverage back the way the disasm subcommand loads a file
+        let coverage = Coverage::parse(&chip8.coverage().to_string()).unwrap();
+
+        assert_eq!(disassemble_with_coverage(&CODE_AND_SPRITE, &coverage), [
+            "; code",
+            "0x200: A206  mvi 0x206",
+            "0x202: D015  sprite v0, v1, 5",
+            "0x204: 1204  jmp 0x204",
+            "; data",
+            "0x206: F0    db 0xF0  ; ####....",
+            "0x207: 90    db 0x90  ; #..#....",
+            "0x208: 90    db 0x90  ; #..#....",
+            "0x209: 90    db 0x90  ; #..#....",
+            "0x20A: F0    db 0xF0  ; ####....",
+        ]);
+    }
+
+    #[test]
+    fn unexecuted_code_between_runs_is_data() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0x200);
+        coverage.mark(0x204);
+        let lines = disassemble_with_coverage(&CODE_AND_SPRITE[..6], &coverage);
+        assert_eq!(lines.iter().filter(|line| line.starts_with(';')).collect::<Vec<_>>(), ["; code", "; data", "; code"]);
+        assert_eq!(lines[2], "; data");
+        assert_eq!(lines[3], "0x202: D0    db 0xD0  ; ##.#....");
+    }
+
+    #[test]
+    fn linear_listing_decodes_every_word() {
+        assert_eq!(disassemble(&[0x00, 0xE0, 0xA2, 0x0A, 0x12, 0x04, 0x7A]), [
+            "0x200: 00E0  cls",
+            "0x202: A20A  mvi 0x20A",
+            "0x204: 1204  jmp 0x204",
+            "0x206: 7A    db 0x7A",
+        ]);
+        assert!(disassemble(&[]).is_empty());
+    }
+
+    #[test]
+    fn marked_data_is_listed_as_bytes() {
+        let data = BTreeSet::from([0x203]);
+        assert_eq!(disassemble_with_data(&[0x00, 0xE0, 0xF0, 0x90, 0x12, 0x00], &data), [
+            "0x200: 00E0  cls",
+            "0x202: F0    db 0xF0",
+            "0x203: 90    db 0x90",
+            "0x204: 1200  jmp 0x200",
+        ]);
+    }
+
+    #[test]
+    fn opcodes_fall_into_their_categories() {
+        for (opcode, category) in [
+            (0xD125, Category::Draw),
+            (0x2300, Category::Call),
+            (0x00EE, Category::Return),
+            (0x1200, Category::Jump),
+            (0xB200, Category::Jump),
+            (0x3A01, Category::Skip),
+            (0xA300, Category::Load),
+            (0xF333, Category::Load),
+            (0x8124, Category::Arithmetic),
+            (0xC0FF, Category::Arithmetic),
+            (0x00E0, Category::Draw),
+            (0xE19E, Category::Input),
+            (0xF00A, Category::Input),
+            (0xF015, Category::Timer),
+            (0x5121, Category::Misc),
+        ] {
+            assert_eq!(Instruction::decode(opcode).category(), category, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn cycles_report_the_category_they_ran() {
+        // I = 0x206, draw, call 0x20A, which returns
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA2, 0x06, 0xD0, 0x11, 0x22, 0x0A, 0x80, 0x00, 0x00, 0x00, 0x00, 0xEE]).unwrap();
+        let ran: Vec<Category> = (0..4).map(|_| chip8.cycle_classified()).collect();
+        assert_eq!(ran, [Category::Load, Category::Draw, Category::Call, Category::Return]);
+    }
+
+    #[test]
+    fn jump_tables_marked_as_data_are_not_decoded() {
+        let rom = [0xB2, 0x06, 0x00, 0xE0, 0x12, 0x00, 0x12, 0x02, 0x12, 0x04, 0xAA];
+        let data = BTreeSet::from([0x206, 0x207, 0x208, 0x209]);
+        let listing = disassemble_with_data(&rom, &data);
+        assert_eq!(listing[3..], [
+            "0x206: 12    db 0x12",
+            "0x207: 02    db 0x02",
+            "0x208: 12    db 0x12",
+            "0x209: 04    db 0x04",
+            "0x20A: AA    db 0xAA",
+        ]);
+        assert_eq!(listing[..3], disassemble(&rom)[..3]);
+        assert_eq!(disassemble_with_data(&rom, &BTreeSet::new()), disassemble(&rom));
+    }
+
+    #[test]
+    fn xo_chip_audio_opcodes_decode() {
+        assert_eq!(Instruction::decode(0xF002), Instruction::Audio);
+        assert_eq!(Instruction::decode(0xF53A), Instruction::Pitch(5));
+        assert_eq!(Instruction::decode(0xF102), Instruction::Unknown(0xF102));
+        assert_eq!(Instruction::Audio.to_string(), "audio");
+        assert_eq!(Instruction::Pitch(5).to_string(), "pitch v5");
+        assert_eq!(Instruction::Pitch(5).category(), Category::Timer);
+        assert_eq!(Instruction::Pitch(5).reads(&Quirks::default()), 1 << 5);
+    }
+}
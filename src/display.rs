@@ -0,0 +1,322 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::chip8::{Chip8Error, WIDTH, HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
+use crate::prelude::*;
+
+// Bit layouts for pack_1bpp, one bit per pixel with 1 lit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackLayout {
+    // Rows top to bottom, 8 horizontal pixels per byte with the leftmost in bit 7, width / 8 bytes a row
+    HorizontalMsbFirst,
+    // SSD1306 pages: the screen in bands of 8 rows, each band a byte per column left to right with the
+    // band's top row in bit 0, bands top to bottom
+    VerticalPages,
+}
+
+impl PackLayout {
+    // Bytes pack_1bpp fills at a resolution of width x height pixels, both layouts take one bit a pixel
+    pub fn buffer_len(self, (width, height): (usize, usize)) -> usize {
+        width * height / 8
+    }
+}
+
+// Rows a sprite preview shows when the next instruction isn't a draw, the tallest DXYN sprite
+pub const PREVIEW_HEIGHT: usize = 15;
+
+// Width and height of the sprite an opcode draws, for previews: DXYN is 8 wide and N tall, DXY0 the
+// SCHIP 16x16 sprite, anything else PREVIEW_HEIGHT rows of 8
+pub fn sprite_shape(opcode: u16) -> (usize, usize) {
+    match (opcode & 0xF000, opcode & 0x000F) {
+        (0xD000, 0) => (16, 16),
+        (0xD000, height) => (8, height as usize),
+        _ => (8, PREVIEW_HEIGHT),
+    }
+}
+
+// The sprite at addr as rows of pixels, width / 8 bytes a row with the leftmost pixel in the top bit.
+// Bytes past the end of memory come out dark, as they do when DXYN reads them
+pub fn sprite_bitmap(memory: &[u8], addr: usize, width: usize, height: usize) -> Vec<Vec<bool>> {
+    let row_bytes = width.div_ceil(8);
+    (0..height).map(|row| {
+        (0..width).map(|x| {
+            let byte = addr.checked_add(row * row_bytes + x / 8).and_then(|at| memory.get(at)).copied().unwrap_or(0);
+            byte & (0x80 >> (x % 8)) != 0
+        }).collect()
+    }).collect()
+}
+
+// How many pixels differ between two frames of the same size, what a renderer redrawing only the
+// changes would have to touch
+pub fn changed_pixels(before: &[u8], after: &[u8]) -> usize {
+    before.iter().zip(after).filter(|(old, new)| old != new).count()
+}
+
+// Monochrome framebuffer, one byte per pixel (1 lit, 0 dark) in row major order at either the 64x32
+// CHIP-8 resolution or the 128x64 SUPER-CHIP one. Derefs to the pixel bytes of the current resolution, a
+// row of width() bytes at a time, so callers can keep indexing and slicing it like the array it replaced
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Display {
+    pixels: [u8; HIRES_WIDTH * HIRES_HEIGHT],        // Room for hires, low resolution uses the start of it
+    hires: bool,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display { pixels: [0; HIRES_WIDTH * HIRES_HEIGHT], hires: false }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    // 00FF and 00FE: switch to 128x64 or back to 64x32. The screen comes up blank either way
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels.fill(0);
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
+    }
+
+    // Pixels across and down at the current resolution
+    pub fn resolution(&self) -> (usize, usize) {
+        (self.width(), self.height())
+    }
+
+    // Whether the pixel at (x, y) is lit, coordinates wrap like sprite drawing
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[(x % self.width()) + (y % self.height()) * self.width()] == 1
+    }
+
+    // Light or clear one pixel directly, for setting up a screen without drawing sprites. Unlike pixel
+    // the coordinates don't wrap, one off the screen panics
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let (width, height) = self.resolution();
+        assert!(x < width && y < height, "pixel ({}, {}) is outside the {}x{} display", x, y, width, height);
+        self.pixels[x + y * width] = on as u8;
+    }
+
+    // A single fill of the current resolution's pixels, which compiles down to a memset however large the
+    // buffer gets
+    pub fn clear(&mut self) {
+        let len = self.width() * self.height();
+        self.pixels[..len].fill(0);
+    }
+
+    // The buffer past the current resolution, which stays blank, for the paranoid build's checks
+    #[cfg(feature = "paranoid")]
+    pub(crate) fn spare_pixels(&self) -> &[u8] {
+        &self.pixels[self.width() * self.height()..]
+    }
+
+    // Lets the paranoid tests light a pixel nothing can draw to
+    #[cfg(all(test, feature = "paranoid"))]
+    pub(crate) fn spare_pixels_mut(&mut self) -> &mut [u8] {
+        let len = self.width() * self.height();
+        &mut self.pixels[len..]
+    }
+
+    // Coordinates of every lit pixel, row by row
+    pub fn lit_pixels(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let width = self.width();
+        self.iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel != 0)
+            .map(move |(idx, _)| ((idx % width) as u8, (idx / width) as u8))
+    }
+
+    // Rows packed 8 pixels to a byte, leftmost pixel in the top bit, the layout monochrome OLED panels
+    // and most other emulators take
+    pub fn packed_rows(&self) -> Vec<u8> {
+        let mut out = vec![0; PackLayout::HorizontalMsbFirst.buffer_len(self.resolution())];
+        self.pack_1bpp(PackLayout::HorizontalMsbFirst, &mut out).expect("buffer sized for the layout");
+        out
+    }
+
+    // Pack the screen at one bit per pixel into out, which must be exactly layout.buffer_len() bytes for
+    // the current resolution
+    pub fn pack_1bpp(&self, layout: PackLayout, out: &mut [u8]) -> Result<(), Chip8Error> {
+        let needed = layout.buffer_len(self.resolution());
+        if out.len() != needed {
+            return Err(Chip8Error::BufferSize { needed, got: out.len() });
+        }
+        let width = self.width();
+        match layout {
+            PackLayout::HorizontalMsbFirst => {
+                for (byte, pixels) in out.iter_mut().zip(self.chunks(8)) {
+                    *byte = pixels.iter().enumerate().fold(0, |packed, (bit, &pixel)| packed | (pixel & 1) << (7 - bit));
+                }
+            }
+            PackLayout::VerticalPages => {
+                for (idx, byte) in out.iter_mut().enumerate() {
+                    let (page, x) = (idx / width, idx % width);
+                    *byte = (0..8).fold(0, |packed, bit| packed | (self.pixels[x + (page * 8 + bit) * width] & 1) << bit);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 00CN: move the picture down n rows, blank rows come in at the top
+    pub fn scroll_down(&mut self, n: usize) {
+        let len = self.width() * self.height();
+        let shift = (n * self.width()).min(len);
+        self.pixels.copy_within(..len - shift, shift);
+        self.pixels[..shift].fill(0);
+    }
+
+    // 00FB: move the picture right n columns, blank columns come in on the left
+    pub fn scroll_right(&mut self, n: usize) {
+        let width = self.width();
+        let n = n.min(width);
+        for row in self.chunks_mut(width) {
+            row.copy_within(..width - n, n);
+            row[..n].fill(0);
+        }
+    }
+
+    // 00FC: move the picture left n columns, blank columns come in on the right
+    pub fn scroll_left(&mut self, n: usize) {
+        let width = self.width();
+        let n = n.min(width);
+        for row in self.chunks_mut(width) {
+            row.copy_within(n.., 0);
+            row[width - n..].fill(0);
+        }
+    }
+
+    // XOR one sprite pixel onto the screen, true when it turned a lit pixel off
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let (width, height) = self.resolution();
+        let pixel = &mut self.pixels[(x % width) + (y % height) * width];
+        *pixel ^= 1;
+        *pixel == 0
+    }
+}
+
+impl Deref for Display {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+}
+
+impl DerefMut for Display {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.width() * self.height();
+        &mut self.pixels[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where pack_1bpp must put the pixel at (x, y): the byte and the bit within it
+    fn packed_position(layout: PackLayout, width: usize, x: usize, y: usize) -> (usize, u8) {
+        match layout {
+            PackLayout::HorizontalMsbFirst => ((x + y * width) / 8, 0x80 >> (x % 8)),
+            PackLayout::VerticalPages => (x + (y / 8) * width, 1 << (y % 8)),
+        }
+    }
+
+    // Light each pixel on its own and check it lands on exactly its bit and nowhere else
+    fn assert_every_pixel_packs(hires: bool, layout: PackLayout) {
+        let mut display = Display::new();
+        display.set_hires(hires);
+        let (width, height) = display.resolution();
+        let mut out = vec![0; layout.buffer_len((width, height))];
+        for y in 0..height {
+            for x in 0..width {
+                display.toggle(x, y);
+                display.pack_1bpp(layout, &mut out).unwrap();
+                let (at, bit) = packed_position(layout, width, x, y);
+                for (idx, &byte) in out.iter().enumerate() {
+                    let expected = if idx == at { bit } else { 0 };
+                    assert_eq!(byte, expected, "{:?} pixel ({}, {}) at {}x{}, byte {}", layout, x, y, width, height, idx);
+                }
+                display.toggle(x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn horizontal_packing_places_every_pixel() {
+        assert_every_pixel_packs(false, PackLayout::HorizontalMsbFirst);
+        assert_every_pixel_packs(true, PackLayout::HorizontalMsbFirst);
+    }
+
+    #[test]
+    fn vertical_pages_place_every_pixel() {
+        assert_every_pixel_packs(false, PackLayout::VerticalPages);
+        assert_every_pixel_packs(true, PackLayout::VerticalPages);
+    }
+
+    #[test]
+    fn a_full_screen_packs_to_all_ones() {
+        for hires in [false, true] {
+            for layout in [PackLayout::HorizontalMsbFirst, PackLayout::VerticalPages] {
+                let mut display = Display::new();
+                display.set_hires(hires);
+                display.iter_mut().for_each(|pixel| *pixel = 1);
+                let mut out = vec![0; layout.buffer_len(display.resolution())];
+                display.pack_1bpp(layout, &mut out).unwrap();
+                assert!(out.iter().all(|&byte| byte == 0xFF), "{:?} hires {}", layout, hires);
+            }
+        }
+    }
+
+    #[test]
+    fn buffers_are_sized_for_the_resolution() {
+        assert_eq!(PackLayout::HorizontalMsbFirst.buffer_len((WIDTH, HEIGHT)), 256);
+        assert_eq!(PackLayout::VerticalPages.buffer_len((HIRES_WIDTH, HIRES_HEIGHT)), 1024);
+
+        let mut display = Display::new();
+        assert!(matches!(display.pack_1bpp(PackLayout::VerticalPages, &mut [0; 1024]), Err(Chip8Error::BufferSize { needed: 256, got: 1024 })));
+        display.set_hires(true);
+        assert!(matches!(display.pack_1bpp(PackLayout::VerticalPages, &mut [0; 256]), Err(Chip8Error::BufferSize { needed: 1024, got: 256 })));
+        assert_eq!(display.packed_rows().len(), 1024);
+    }
+
+    // Rows of a bitmap as # and . for readable comparisons
+    fn art(bitmap: &[Vec<bool>]) -> Vec<String> {
+        bitmap.iter().map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect()).collect()
+    }
+
+    #[test]
+    fn sprite_bitmaps_read_rows_leftmost_pixel_first() {
+        let memory = [0x00, 0xF0, 0x90, 0x81];
+        assert_eq!(art(&sprite_bitmap(&memory, 1, 8, 3)), ["####....", "#..#....", "#......#"]);
+        assert!(sprite_bitmap(&memory, 0, 8, 0).is_empty());
+
+        let wide = [0x80, 0x01, 0xFF, 0x00];
+        assert_eq!(art(&sprite_bitmap(&wide, 0, 16, 2)), ["#..............#", "########........"], "16 wide rows take two bytes");
+    }
+
+    #[test]
+    fn sprite_bitmaps_past_the_end_of_memory_are_dark() {
+        let memory = [0x00, 0x00, 0xFF, 0x81];
+        assert_eq!(art(&sprite_bitmap(&memory, 2, 8, 4)), ["########", "#......#", "........", "........"]);
+        assert_eq!(art(&sprite_bitmap(&memory, 3, 16, 2)), ["#......#........", "................"]);
+        assert_eq!(sprite_bitmap(&memory, usize::MAX, 8, 2), [vec![false; 8], vec![false; 8]], "no overflow at the top of the address space");
+    }
+
+    #[test]
+    fn previews_take_the_shape_of_the_next_draw() {
+        assert_eq!(sprite_shape(0xD125), (8, 5));
+        assert_eq!(sprite_shape(0xD120), (16, 16), "DXY0 is the SCHIP 16x16 sprite");
+        assert_eq!(sprite_shape(0x00E0), (8, PREVIEW_HEIGHT));
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::condition::parse_number;
+
+// A debugger command that changes the machine, applied between instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Register(u8, u8),                   // set vX NN
+    Index(u16),                         // set i NNN
+    Pc(u16),                            // set pc NNN
+    DelayTimer(u8),                     // set dt NN
+    SoundTimer(u8),                     // set st NN
+    Poke(u16, u8),                      // poke ADDR NN
+    Push(u16),                          // push ADDR, as if a call was made from ADDR
+    Pop,                                // pop, drops the innermost call
+}
+
+// Edit command syntax, numbers are decimal or 0x hex:
+//   set v3 0xFF
+//   set i 0x300
+//   set pc 0x204
+//   set dt 60
+//   poke 0x300 0xAA
+//   push 0x20A
+//   pop
+pub fn parse(text: &str) -> Result<Edit, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let byte = |token: &str| parse_number(token)?.try_into().map_err(|_| format!("{} doesn't fit in a byte", token));
+    Ok(match words.as_slice() {
+        ["set", target, value] => match *target {
+            "i" => Edit::Index(parse_number(value)?),
+            "pc" => Edit::Pc(parse_number(value)?),
+            "dt" => Edit::DelayTimer(byte(value)?),
+            "st" => Edit::SoundTimer(byte(value)?),
+            _ => match target.strip_prefix('v').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                Some(x) if target.len() == 2 => Edit::Register(x, byte(value)?),
+                _ => return Err(format!("unknown register '{}', expected v0-vf, i, pc, dt or st", target)),
+            },
+        },
+        ["poke", addr, value] => Edit::Poke(parse_number(addr)?, byte(value)?),
+        ["push", addr] => Edit::Push(parse_number(addr)?),
+        ["pop"] => Edit::Pop,
+        _ => return Err("edits are written set REG VALUE, poke ADDR VALUE, push ADDR or pop".to_string()),
+    })
+}
+
+impl Edit {
+    // Change the machine through its checked setters, Err leaves it untouched
+    pub fn apply(self, chip8: &mut Chip8) -> Result<(), String> {
+        match self {
+            Edit::Register(x, value) => chip8.set_register(x as usize, value),
+            Edit::Index(value) => chip8.set_index(value),
+            Edit::Pc(addr) => chip8.set_pc(addr)?,
+            Edit::DelayTimer(value) => chip8.set_delay_timer(value),
+            Edit::SoundTimer(value) => chip8.set_sound_timer(value),
+            Edit::Poke(addr, value) => chip8.poke(addr as usize, value)?,
+            Edit::Push(addr) => chip8.push_call(addr)?,
+            Edit::Pop => {
+                chip8.pop_call()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Edit::Register(x, value) => write!(f, "v{:X} = {:#04X}", x, value),
+            Edit::Index(value) => write!(f, "I = {:#05X}", value),
+            Edit::Pc(addr) => write!(f, "PC = {:#05X}", addr),
+            Edit::DelayTimer(value) => write!(f, "DT = {}", value),
+            Edit::SoundTimer(value) => write!(f, "ST = {}", value),
+            Edit::Poke(addr, value) => write!(f, "[{:#05X}] = {:#04X}", addr, value),
+            Edit::Push(addr) => write!(f, "pushed call from {:#05X}", addr),
+            Edit::Pop => write!(f, "popped innermost call"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_parse_from_debugger_syntax() {
+        assert_eq!(parse("set v3 0xFF").unwrap(), Edit::Register(3, 0xFF));
+        assert_eq!(parse("set vf 1").unwrap(), Edit::Register(0xF, 1));
+        assert_eq!(parse("set i 0x300").unwrap(), Edit::Index(0x300));
+        assert_eq!(parse("set pc 0x204").unwrap(), Edit::Pc(0x204));
+        assert_eq!(parse("set dt 60").unwrap(), Edit::DelayTimer(60));
+        assert_eq!(parse("poke 0x300 0xAA").unwrap(), Edit::Poke(0x300, 0xAA));
+        assert_eq!(parse("push 0x20A").unwrap(), Edit::Push(0x20A));
+        assert_eq!(parse("pop").unwrap(), Edit::Pop);
+        assert_eq!(parse("set v3 0x100").unwrap_err(), "0x100 doesn't fit in a byte");
+        assert!(parse("set v10 1").unwrap_err().starts_with("unknown register 'v10'"));
+        assert!(parse("poke 0x300").unwrap_err().starts_with("edits are written"));
+    }
+
+    #[test]
+    fn invalid_edits_leave_the_machine_untouched() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(Edit::Pc(0x1000).apply(&mut chip8).unwrap_err(), "address 0x1000 is outside memory");
+        assert_eq!(chip8.pc(), 0x200);
+        assert_eq!(Edit::Pop.apply(&mut chip8).unwrap_err(), "stack is empty");
+        for _ in 0..16 {
+            Edit::Push(0x20A).apply(&mut chip8).unwrap();
+        }
+        assert_eq!(Edit::Push(0x20A).apply(&mut chip8).unwrap_err(), "stack is full");
+        assert_eq!(chip8.stack_pointer(), 16, "sp stays inside the stack");
+        Edit::Pop.apply(&mut chip8).unwrap();
+        assert_eq!(chip8.stack().len(), 15);
+    }
+}
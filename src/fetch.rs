@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ureq::Agent;
+
+use crate::chip8::fnv1a;
+
+// ROM downloads for URLs given in place of a path, over http or https through ureq. Redirects are
+// followed, downloads capped in size and cached by URL
+
+pub const MAX_DOWNLOAD: usize = 1 << 20;        // Room for a ZIP pack of ROMs
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: u32 = 5;
+
+pub fn is_url(arg: &str) -> bool {
+    let lower = arg.to_ascii_lowercase();
+    lower.starts_with("http://") || is_https(&lower)
+}
+
+fn is_https(url: &str) -> bool {
+    url.get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"))
+}
+
+// Where a download of url is cached in dir
+pub fn cache_path(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("{:016x}.rom", fnv1a(url.as_bytes())))
+}
+
+// Something that gets the body at a URL, at most limit bytes of it
+pub trait Fetcher {
+    fn get(&mut self, url: &str, limit: usize) -> Result<Vec<u8>, String>;
+}
+
+// The ROM at url, from the cache in cache_dir when it was downloaded before. No cache_dir always
+// downloads and stores nothing. A cache that can't be written only costs the next run a download
+pub fn download(fetcher: &mut impl Fetcher, url: &str, cache_dir: Option<&Path>) -> Result<Vec<u8>, String> {
+    let cached = cache_dir.map(|dir| cache_path(dir, url));
+    if let Some(bytes) = cached.as_ref().and_then(|path| fs::read(path).ok()) {
+        return Ok(bytes);
+    }
+    let bytes = fetcher.get(url, MAX_DOWNLOAD).map_err(|err| format!("could not download {}: {}", url, err))?;
+    if let Some(path) = cached {
+        let stored = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&path, &bytes));
+        if let Err(err) = stored {
+            log::warn!("could not cache {} in {}: {}", url, path.display(), err);
+        }
+    }
+    Ok(bytes)
+}
+
+// Fetches with ureq: http and https, redirects followed and a timeout over the whole request
+pub struct HttpFetcher {
+    agent: Agent,
+}
+
+impl HttpFetcher {
+    pub fn new(timeout: Duration) -> Self {
+        let config = Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .max_redirects(MAX_REDIRECTS)
+            .user_agent("Chip8")
+            .build();
+        HttpFetcher { agent: config.into() }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn get(&mut self, url: &str, limit: usize) -> Result<Vec<u8>, String> {
+        let mut response = self.agent.get(url).call().map_err(|err| match err {
+            ureq::Error::StatusCode(status) => format!("server answered HTTP {}", status),
+            ureq::Error::TooManyRedirects => format!("more than {} redirects", MAX_REDIRECTS),
+            err => err.to_string(),
+        })?;
+        let too_large = format!("download is larger than {} bytes", limit);
+        if response.body().content_length().is_some_and(|length| length > limit as u64) {
+            return Err(too_large);
+        }
+        response.body_mut().with_config().limit(limit as u64).read_to_vec().map_err(|err| match err {
+            ureq::Error::BodyExceedsLimit(_) => too_large,
+            err => err.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Answers each connection to a local port with the next of responses, returning the base URL
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    request.push(byte[0]);
+                }
+                let _ = stream.write_all(&response);
+            }
+        });
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    fn ok(body: &[u8]) -> Vec<u8> {
+        [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes(), body].concat()
+    }
+
+    // Counts requests and answers each with the same body
+    struct MockFetcher {
+        body: Vec<u8>,
+        requests: usize,
+    }
+
+    impl Fetcher for MockFetcher {
+        fn get(&mut self, _url: &str, limit: usize) -> Result<Vec<u8>, String> {
+            self.requests += 1;
+            assert_eq!(limit, MAX_DOWNLOAD);
+            Ok(self.body.clone())
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chip8-fetch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn urls_are_told_apart_from_paths() {
+        assert!(is_url("http://example.com/pong.ch8"));
+        assert!(is_url("https://example.com/pong.ch8"));
+        assert!(is_url("HTTPS://EXAMPLE.COM/PONG.CH8"));
+        assert!(!is_url("roms/pong.ch8"));
+        assert!(!is_url("ftp://example.com/pong.ch8"));
+        assert!(!is_url("http:/pong.ch8"));
+    }
+
+    #[test]
+    fn cache_paths_are_stable_and_distinct_per_url() {
+        let dir = Path::new("downloads");
+        let pong = cache_path(dir, "http://example.com/pong.ch8");
+        assert_eq!(pong, cache_path(dir, "http://example.com/pong.ch8"));
+        assert_ne!(pong, cache_path(dir, "http://example.com/tetris.ch8"));
+        assert_eq!(pong.parent(), Some(dir));
+        let name = pong.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with(".rom") && name.len() == 20, "{}", name);
+    }
+
+    #[test]
+    fn downloads_are_cached_by_url() {
+        let dir = temp_dir("cache");
+        let url = "http://example.com/pong.ch8";
+        let mut fetcher = MockFetcher { body: vec![0x12, 0x00], requests: 0 };
+        assert_eq!(download(&mut fetcher, url, Some(&dir)).unwrap(), [0x12, 0x00]);
+        assert_eq!(fs::read(cache_path(&dir, url)).unwrap(), [0x12, 0x00]);
+
+        fetcher.body = vec![0xFF];
+        assert_eq!(download(&mut fetcher, url, Some(&dir)).unwrap(), [0x12, 0x00]);
+        assert_eq!(fetcher.requests, 1);
+
+        assert_eq!(download(&mut fetcher, url, None).unwrap(), [0xFF]);
+        assert_eq!(fetcher.requests, 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn http_downloads_follow_redirects() {
+        let base = serve(vec![
+            b"HTTP/1.1 301 Moved\r\nLocation: /roms/pong.ch8\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n\x12\x00\r\n0\r\n\r\n".to_vec(),
+        ]);
+        let mut fetcher = HttpFetcher::new(TIMEOUT);
+        assert_eq!(fetcher.get(&format!("{}/pong", base), MAX_DOWNLOAD).unwrap(), [0x12, 0x00]);
+
+        let base = serve(vec![ok(b"\x00\xE0")]);
+        assert_eq!(fetcher.get(&format!("{}/cls.ch8", base), MAX_DOWNLOAD).unwrap(), [0x00, 0xE0]);
+
+        let base = serve(vec![b"HTTP/1.1 302 Found\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n".to_vec(); 6]);
+        assert_eq!(fetcher.get(&format!("{}/loop", base), MAX_DOWNLOAD).unwrap_err(), "more than 5 redirects");
+    }
+
+    #[test]
+    fn oversized_and_missing_downloads_are_rejected() {
+        let base = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\n\r\n0123456789".to_vec(),
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ]);
+        let mut fetcher = HttpFetcher::new(TIMEOUT);
+        assert_eq!(fetcher.get(&format!("{}/a", base), 8).unwrap_err(), "download is larger than 8 bytes");
+        assert_eq!(fetcher.get(&format!("{}/b", base), 8).unwrap_err(), "download is larger than 8 bytes");
+        assert_eq!(fetcher.get(&format!("{}/c", base), 8).unwrap_err(), "server answered HTTP 404");
+    }
+}
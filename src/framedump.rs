@@ -0,0 +1,102 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chip8::{pbm, png};
+use chip8::Chip8;
+
+use crate::speedrun::format_time;
+use crate::video::rgb_frame;
+
+// File format of frame dumps and screenshots
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    #[default]
+    Png,
+    Pbm,                                // Plain text P1, for diffing and grepping
+    Xbm,
+}
+
+impl DumpFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "png" => Some(DumpFormat::Png),
+            "pbm" => Some(DumpFormat::Pbm),
+            "xbm" => Some(DumpFormat::Xbm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Png => "png",
+            DumpFormat::Pbm => "pbm",
+            DumpFormat::Xbm => "xbm",
+        }
+    }
+}
+
+// Writes numbered native resolution images of the screen to a directory, plus index.txt listing
+// each file with its frame number and emulated time
+pub struct FrameDumper {
+    dir: PathBuf,
+    format: DumpFormat,
+    every_frame: bool,
+    max_frames: Option<usize>,
+    written: usize,
+    last: Option<Vec<u8>>,              // Display of the previous dump, to skip unchanged frames
+    index: BufWriter<File>,
+}
+
+impl FrameDumper {
+    pub fn new(dir: &Path, format: DumpFormat, every_frame: bool, max_frames: Option<usize>) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+        let index_path = dir.join("index.txt");
+        let index = File::create(&index_path).map_err(|err| format!("could not create {}: {}", index_path.display(), err))?;
+
+        Ok(FrameDumper {
+            dir: dir.to_path_buf(),
+            format,
+            every_frame,
+            max_frames,
+            written: 0,
+            last: None,
+            index: BufWriter::new(index),
+        })
+    }
+
+    // Dump the current frame if the picture changed (or always with every_frame), false once the cap is reached
+    pub fn frame(&mut self, chip8: &Chip8) -> Result<bool, String> {
+        if self.max_frames.is_some_and(|max| self.written >= max) {
+            return Ok(false);
+        }
+        if !self.every_frame && self.last.as_deref() == Some(&chip8.display[..]) {
+            return Ok(true);
+        }
+
+        let frame = chip8.frame_count();
+        let name = format!("frame_{:06}.{}", frame, self.format.extension());
+        save_frame(chip8, self.format, &self.dir.join(&name))?;
+        writeln!(self.index, "{} {} {}", name, frame, format_time(frame)).map_err(|err| err.to_string())?;
+        self.index.flush().map_err(|err| err.to_string())?;
+
+        self.last = Some(chip8.display.to_vec());
+        self.written += 1;
+        Ok(self.max_frames.is_none_or(|max| self.written < max))
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+}
+
+// The screen as a native resolution image, shared by frame dumps and screenshots
+pub fn save_frame(chip8: &Chip8, format: DumpFormat, path: &Path) -> Result<(), String> {
+    let (width, height) = chip8.resolution();
+    let image = match format {
+        DumpFormat::Png => png::encode_rgb(width as u32, height as u32, &rgb_frame(&chip8.display, 1))?,
+        DumpFormat::Pbm => pbm::encode_pbm(width, height, &chip8.display).into_bytes(),
+        DumpFormat::Xbm => pbm::encode_xbm("chip8", width, height, &chip8.display).into_bytes(),
+    };
+    fs::write(path, image).map_err(|err| format!("could not write {}: {}", path.display(), err))
+}
@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+
+// Input a frontend gathers each frame from its own event source, so the emulation loop doesn't depend on it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputState {
+    pub keys: u16,                      // Held keypad keys, bit N = key N
+    pub quit: bool,
+    pub reset: bool,                    // One shot, cleared once the reset has been handled
+    pub pause: bool,                    // Stays set until toggled off again
+}
+
+impl InputState {
+    // Hand the held keys to the core
+    pub fn apply(&self, chip8: &mut Chip8) {
+        chip8.set_keys_mask(self.keys);
+    }
+}
+
+// Most timer ticks run back to back after a stall, the rest of the backlog is dropped instead of
+// fast forwarding through it
+pub const MAX_CATCH_UP: u32 = 4;
+
+// Longest wall clock step the loop feeds the pacer by default. A host stall longer than this, such as a
+// window drag, counts as this long
+pub const DEFAULT_MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+// Time since the last loop iteration as the pacer should see it, at most max
+pub fn clamp_frame_time(elapsed: Duration, max: Duration) -> Duration {
+    elapsed.min(max)
+}
+
+// What the loop should do after some wall clock time went by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pacing {
+    pub ticks: u32,                     // Emulated frames due, each one frame of instructions and a timer tick
+    pub present: bool,                  // Whether the canvas may be presented
+}
+
+// Paces timer ticks and presents separately, each with its own accumulator of elapsed time, so the
+// screen can be refreshed less (or more) often than the timers run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pacer {
+    tick_interval: Duration,
+    present_interval: Duration,
+    tick_elapsed: Duration,             // Time not yet spent on a tick
+    present_elapsed: Duration,          // Time since the last present was due
+}
+
+impl Pacer {
+    pub fn new(timer_rate: u32, max_fps: u32) -> Self {
+        Pacer {
+            tick_interval: Duration::from_secs(1) / timer_rate.max(1),
+            present_interval: Duration::from_secs(1) / max_fps.max(1),
+            tick_elapsed: Duration::ZERO,
+            present_elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn advance(&mut self, elapsed: Duration) -> Pacing {
+        self.tick_elapsed += elapsed;
+        let due = (self.tick_elapsed.as_nanos() / self.tick_interval.as_nanos()) as u32;
+        let ticks = due.min(MAX_CATCH_UP);
+        self.tick_elapsed = if due > MAX_CATCH_UP { Duration::ZERO } else { self.tick_elapsed - self.tick_interval * ticks };
+
+        self.present_elapsed += elapsed;
+        let present = self.present_elapsed >= self.present_interval;
+        if present {
+            // Presents missed while stalled are skipped, not made up
+            let remainder = self.present_elapsed.as_nanos() % self.present_interval.as_nanos();
+            self.present_elapsed = Duration::from_nanos(remainder as u64);
+        }
+        Pacing { ticks, present }
+    }
+
+    // How long until a tick or a present is next due
+    pub fn until_next(&self) -> Duration {
+        let tick = self.tick_interval.saturating_sub(self.tick_elapsed);
+        let present = self.present_interval.saturating_sub(self.present_elapsed);
+        tick.min(present)
+    }
+}
+
+// Auto-reset for demos and kiosks: how long the ROM has sat halted, and when that has gone on long
+// enough to restart it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HaltTimer {
+    delay: Duration,
+    halted_for: Duration,
+}
+
+impl HaltTimer {
+    pub fn new(delay: Duration) -> Self {
+        HaltTimer { delay, halted_for: Duration::ZERO }
+    }
+
+    // Whether to reset now, given the time since the last call and whether the ROM is halted. Running
+    // again starts the count over, and so does a reset
+    pub fn update(&mut self, halted: bool, elapsed: Duration) -> bool {
+        if !halted {
+            self.halted_for = Duration::ZERO;
+            return false;
+        }
+        self.halted_for += elapsed;
+        if self.halted_for < self.delay {
+            return false;
+        }
+        self.halted_for = Duration::ZERO;
+        true
+    }
+}
+
+// Window focus: losing it mutes the buzzer, and with pause_on_blur pauses the emulation too. Getting it back
+// unmutes and lifts only a pause made here, one set with P before the focus went stays
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FocusPause {
+    muted: bool,
+    auto_paused: bool,                  // The pause in the input state is the one focus loss set
+}
+
+impl FocusPause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, focused: bool, pause_on_blur: bool, input: &mut InputState) {
+        self.muted = !focused;
+        if !focused && pause_on_blur && !input.pause {
+            input.pause = true;
+            self.auto_paused = true;
+        } else if focused && self.auto_paused {
+            input.pause = false;
+            self.auto_paused = false;
+        }
+    }
+
+    // Whether the buzzer stays quiet whatever the sound timer says
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+}
+
+// Filters contact bounce out of the held keys: a key only changes state once it has read the same for
+// the whole interval, so a switch chattering on press or release registers as one clean edge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Debouncer {
+    interval: Duration,
+    raw: u16,                           // Keys as last read, bounce and all
+    changed_at: [Duration; 16],         // When each key's raw state last flipped
+    stable: u16,                        // Keys as reported
+}
+
+impl Debouncer {
+    pub fn new(interval: Duration) -> Self {
+        Debouncer { interval, raw: 0, changed_at: [Duration::ZERO; 16], stable: 0 }
+    }
+
+    // The debounced keys, given the raw keys read at now. now is any clock that only goes forward, such
+    // as the time since the frontend started, and update has to be called again as it passes for a key
+    // to settle even when nothing new comes in
+    pub fn update(&mut self, raw: u16, now: Duration) -> u16 {
+        let flipped = raw ^ self.raw;
+        self.raw = raw;
+        for key in 0..16 {
+            let bit = 1 << key;
+            if flipped & bit != 0 {
+                self.changed_at[key] = now;
+            }
+            if (raw ^ self.stable) & bit != 0 && now.saturating_sub(self.changed_at[key]) >= self.interval {
+                self.stable ^= bit;
+            }
+        }
+        self.stable
+    }
+}
+
+// Sticky keys for players who can't hold a key down or press a chord: each press of a key flips it
+// between latched and released, and letting go of it does nothing. FX0A completes as soon as a key is
+// down, so a latched key satisfies it right away like a held one, and keeps doing so until it's pressed
+// again to release it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StickyKeys {
+    held: u16,                          // Keys physically down at the last update
+    latched: u16,
+}
+
+impl StickyKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The latched keys, given the keys physically down now. Only keys that went down since the last
+    // update flip, so calling it again with the same keys changes nothing
+    pub fn update(&mut self, raw: u16) -> u16 {
+        self.latched ^= raw & !self.held;
+        self.held = raw;
+        self.latched
+    }
+
+    pub fn latched(&self) -> u16 {
+        self.latched
+    }
+
+    // Panic key: release every latched key. Keys still physically down stay released until pressed again
+    pub fn clear(&mut self) {
+        self.latched = 0;
+    }
+}
+
+// Turbo fire: whether a held turbo key reads as down on an emulated frame. The key pulses hz times a
+// second, down for the first half of each period, counted from frame 0 so a replay pulses the same way.
+// Faster than every other frame can't be shown, so hz is capped there
+pub fn turbo_pulse(frame: u64, hz: u32, frame_rate: u32) -> bool {
+    let frame_rate = frame_rate.max(1) as u64;
+    let hz = (hz as u64).clamp(1, (frame_rate / 2).max(1));
+    (frame * hz * 2 / frame_rate) & 1 == 0
+}
+
+// The held keys with every turbo key among them pulsed at its own rate, the rest passed through
+pub fn apply_turbo(keys: u16, turbo: &[(u8, u32)], frame: u64, frame_rate: u32) -> u16 {
+    turbo.iter().fold(keys, |keys, &(key, hz)| {
+        if turbo_pulse(frame, hz, frame_rate) { keys } else { keys & !(1 << key) }
+    })
+}
+
+// One macro step: the keypad keys held, bit N = key N, and for how many frames. No keys is a wait
+pub type MacroStep = (u16, u32);
+
+// Macro steps, comma separated, each KEYSxFRAMES or wFRAMES: 1x5,w10,1+Ax5 holds 1 for 5 frames, waits
+// 10, then holds 1 and A together for 5
+pub fn parse_macro_steps(text: &str) -> Result<Vec<MacroStep>, String> {
+    let frames = |count: &str| match count.trim().parse() {
+        Ok(frames) if frames > 0 => Ok(frames),
+        _ => Err(format!("invalid frame count '{}' in macro", count.trim())),
+    };
+    text.split(',').map(|step| {
+        let step = step.trim();
+        if let Some(count) = step.strip_prefix('w') {
+            return Ok((0, frames(count)?));
+        }
+        let (keys, count) = step.split_once('x').ok_or_else(|| format!("invalid macro step '{}', expected KEYSxFRAMES or wFRAMES", step))?;
+        let keys = keys.split('+').try_fold(0u16, |mask, key| match u8::from_str_radix(key.trim(), 16) {
+            Ok(key) if key <= 0xF => Ok(mask | 1 << key),
+            _ => Err(format!("invalid keypad key '{}' in macro", key.trim())),
+        })?;
+        Ok((keys, frames(count)?))
+    }).collect()
+}
+
+// What triggering a macro does while another is still playing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MacroOverlap {
+    #[default]
+    Queue,                              // Plays once the running ones are done
+    Cancel,                             // Stops the running ones and plays in their place
+}
+
+impl MacroOverlap {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "queue" => Ok(MacroOverlap::Queue),
+            "cancel" => Ok(MacroOverlap::Cancel),
+            _ => Err(format!("unknown macro overlap '{}', expected queue or cancel", name)),
+        }
+    }
+}
+
+// Plays macros into the keypad one emulated frame at a time. Triggered macros are laid out as the keys
+// of each frame to come, and the loop takes one frame's keys per tick and ORs them with live input, so a
+// macro always lands on the same frames after its trigger and records into movies like typed keys
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MacroPlayer {
+    overlap: MacroOverlap,
+    frames: VecDeque<u16>,              // Keys for each upcoming frame, the next one first
+}
+
+impl MacroPlayer {
+    pub fn new(overlap: MacroOverlap) -> Self {
+        MacroPlayer { overlap, frames: VecDeque::new() }
+    }
+
+    // Start or queue a macro, whichever the overlap setting says
+    pub fn trigger(&mut self, steps: &[MacroStep]) {
+        if self.overlap == MacroOverlap::Cancel {
+            self.frames.clear();
+        }
+        for &(keys, frames) in steps {
+            self.frames.extend(std::iter::repeat_n(keys, frames as usize));
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    // Keys the macros hold on the frame about to run, then on to the next
+    pub fn next_frame(&mut self) -> u16 {
+        self.frames.pop_front().unwrap_or(0)
+    }
+}
+
+// Longest loop tight_poll recognizes, and how many times in a row it has to have run
+pub const POLL_LOOP_MAX: usize = 8;
+pub const POLL_REPEATS: usize = 3;
+
+// Instructions history needs to hold for tight_poll to see every loop it can recognize
+pub const POLL_HISTORY: usize = POLL_LOOP_MAX * POLL_REPEATS;
+
+// Whether the most recent instructions, as (pc, opcode) oldest first, are a loop spinning on EX9E or
+// EXA1, and if so its length. The loop must have gone round POLL_REPEATS times the same way and be made
+// only of key and register skips, jumps, constant loads and FX07. Within a frame the keys and timers
+// hold still, so once such a loop has gone round, every further pass leaves the machine as it was
+pub fn tight_poll(history: &[(u16, u16)]) -> Option<usize> {
+    (1..=POLL_LOOP_MAX).find(|&period| {
+        let Some(tail) = history.len().checked_sub(period * POLL_REPEATS).map(|start| &history[start..]) else {
+            return false;
+        };
+        let cycle = &tail[tail.len() - period..];
+        tail.iter().zip(&tail[period..]).all(|(a, b)| a == b)
+            && cycle.iter().any(|&(_, opcode)| matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1))
+            && cycle.iter().all(|&(_, opcode)| repeats_unchanged(opcode))
+    })
+}
+
+// Instructions that change nothing but pc when run again with the same registers, keys and timers
+fn repeats_unchanged(opcode: u16) -> bool {
+    match opcode & 0xF000 {
+        0x1000 | 0x3000 | 0x4000 | 0x6000 | 0xA000 | 0xB000 => true,
+        0x5000 | 0x9000 => opcode & 0x000F == 0,
+        0xE000 => matches!(opcode & 0x00FF, 0x9E | 0xA1),
+        0xF000 => opcode & 0x00FF == 0x07,
+        _ => false,
+    }
+}
+
+// Where the game picture sits in its window, for drawing it and for turning mouse positions into CHIP-8
+// pixels. The picture is the largest rectangle of the resolution's shape, with each pixel aspect.0 wide
+// to aspect.1 tall, that fits the drawable area, centered with letterbox bars around it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScreenLayout {
+    pub window: (u32, u32),             // Window size in points, the units mouse events come in
+    pub drawable: (u32, u32),           // Drawable size in pixels, larger than the window on high-DPI displays
+    pub resolution: (usize, usize),     // CHIP-8 pixels across and down in the current mode
+    pub aspect: (u32, u32),             // Shape of one CHIP-8 pixel, width to height, 1:1 for square
+}
+
+impl ScreenLayout {
+    // The picture in drawable pixels: left, top, width and height
+    pub fn picture(&self) -> (i32, i32, u32, u32) {
+        let (drawable_w, drawable_h) = (self.drawable.0 as u64, self.drawable.1 as u64);
+        let shape_w = self.resolution.0.max(1) as u64 * self.aspect.0.max(1) as u64;
+        let shape_h = self.resolution.1.max(1) as u64 * self.aspect.1.max(1) as u64;
+
+        // Full width unless that makes the picture taller than the drawable area, then full height
+        let (width, height) = if drawable_w * shape_h <= drawable_h * shape_w {
+            (drawable_w, drawable_w * shape_h / shape_w)
+        } else {
+            (drawable_h * shape_w / shape_h, drawable_h)
+        };
+        (((drawable_w - width) / 2) as i32, ((drawable_h - height) / 2) as i32, width as u32, height as u32)
+    }
+
+    // CHIP-8 pixel under a mouse position, None outside the picture
+    pub fn pixel_at(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let (window_w, window_h) = (self.window.0.max(1) as i64, self.window.1.max(1) as i64);
+        let (drawable_w, drawable_h) = (self.drawable.0 as i64, self.drawable.1 as i64);
+        let (columns, rows) = (self.resolution.0.max(1) as i64, self.resolution.1.max(1) as i64);
+        let (left, top, width, height) = self.picture();
+        let (width, height) = (width as i64, height as i64);
+
+        // Points to drawable pixels first, then pixels to the picture
+        let px = x as i64 * drawable_w / window_w - left as i64;
+        let py = y as i64 * drawable_h / window_h - top as i64;
+        if px < 0 || py < 0 || px >= width || py >= height {
+            return None;
+        }
+        Some(((px * columns / width) as usize, (py * rows / height) as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(drawable: (u32, u32), aspect: (u32, u32)) -> ScreenLayout {
+        ScreenLayout { window: drawable, drawable, resolution: (64, 32), aspect }
+    }
+
+    #[test]
+    fn picture_fills_a_window_of_its_shape() {
+        assert_eq!(layout((640, 320), (1, 1)).picture(), (0, 0, 640, 320));
+    }
+
+    #[test]
+    fn picture_is_letterboxed_in_a_resized_window() {
+        assert_eq!(layout((640, 480), (1, 1)).picture(), (0, 80, 640, 320), "bars above and below");
+        assert_eq!(layout((1000, 320), (1, 1)).picture(), (180, 0, 640, 320), "bars left and right");
+    }
+
+    #[test]
+    fn picture_follows_the_pixel_aspect() {
+        assert_eq!(layout((640, 480), (1, 2)).picture(), (80, 0, 480, 480), "pixels twice as tall make a square picture");
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn pacer_ticks_and_presents_on_their_own_accumulators() {
+        let mut pacer = Pacer::new(100, 50);
+        let steps: Vec<Pacing> = [ms(5), ms(5), ms(5), ms(5), ms(25)].into_iter().map(|elapsed| pacer.advance(elapsed)).collect();
+        assert_eq!(steps, [
+            Pacing { ticks: 0, present: false },
+            Pacing { ticks: 1, present: false },
+            Pacing { ticks: 0, present: false },
+            Pacing { ticks: 1, present: true },
+            Pacing { ticks: 2, present: true },
+        ]);
+        assert_eq!(pacer.until_next(), ms(5), "5ms left over toward the next tick");
+    }
+
+    #[test]
+    fn pacer_presents_faster_than_it_ticks_when_asked() {
+        let mut pacer = Pacer::new(50, 100);
+        assert_eq!(pacer.advance(ms(10)), Pacing { ticks: 0, present: true });
+        assert_eq!(pacer.advance(ms(10)), Pacing { ticks: 1, present: true });
+    }
+
+    #[test]
+    fn pacer_drops_the_backlog_past_max_catch_up() {
+        let mut pacer = Pacer::new(100, 60);
+        assert_eq!(pacer.advance(ms(1000)).ticks, MAX_CATCH_UP);
+        assert_eq!(pacer.advance(ms(5)).ticks, 0, "the stall's other 96 ticks are gone");
+        let mut pacer = Pacer::new(100, 60);
+        assert_eq!(pacer.advance(ms(45)).ticks, 4);
+        assert_eq!(pacer.advance(ms(5)).ticks, 1, "within the limit the remainder carries over");
+    }
+
+    #[test]
+    fn pixel_at_maps_corners_across_windows_and_modes() {
+        for resolution in [(64, 32), (128, 64)] {
+            for drawable in [(640, 320), (640, 480), (1000, 320), (1280, 640)] {
+                let screen = ScreenLayout { window: drawable, drawable, resolution, aspect: (1, 1) };
+                let (left, top, width, height) = screen.picture();
+                let (right, bottom) = (left + width as i32 - 1, top + height as i32 - 1);
+                let last = (resolution.0 - 1, resolution.1 - 1);
+                assert_eq!(screen.pixel_at(left, top), Some((0, 0)), "{:?} in {:?}", resolution, drawable);
+                assert_eq!(screen.pixel_at(right, bottom), Some(last), "{:?} in {:?}", resolution, drawable);
+                assert_eq!(screen.pixel_at(left - 1, top), None);
+                assert_eq!(screen.pixel_at(right + 1, bottom), None);
+                assert_eq!(screen.pixel_at(left, bottom + 1), None);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_at_scales_window_points_to_high_dpi_pixels() {
+        let screen = ScreenLayout { window: (640, 320), drawable: (1280, 640), resolution: (64, 32), aspect: (1, 1) };
+        assert_eq!(screen.pixel_at(15, 25), Some((1, 2)), "points are 10 to a CHIP-8 pixel whatever the density");
+        assert_eq!(screen.pixel_at(639, 319), Some((63, 31)));
+    }
+
+    #[test]
+    fn pixel_at_skips_the_letterbox_bars() {
+        let screen = layout((640, 480), (1, 1));
+        assert_eq!(screen.pixel_at(320, 79), None);
+        assert_eq!(screen.pixel_at(320, 80), Some((32, 0)));
+        assert_eq!(screen.pixel_at(320, 400), None);
+
+        let tall = layout((640, 480), (1, 2));
+        assert_eq!(tall.pixel_at(79, 240), None);
+        assert_eq!(tall.pixel_at(80, 0), Some((0, 0)));
+        assert_eq!(tall.pixel_at(559, 479), Some((63, 31)));
+    }
+
+    #[test]
+    fn halt_timer_resets_once_the_rom_has_sat_halted_long_enough() {
+        let mut timer = HaltTimer::new(ms(3000));
+        assert!(!timer.update(true, ms(2000)));
+        assert!(!timer.update(true, ms(999)));
+        assert!(timer.update(true, ms(1)));
+        assert!(!timer.update(true, ms(2999)), "a reset starts the count over");
+    }
+
+    #[test]
+    fn halt_timer_starts_over_when_the_rom_runs_again() {
+        let mut timer = HaltTimer::new(ms(3000));
+        assert!(!timer.update(true, ms(2500)));
+        assert!(!timer.update(false, ms(16)));
+        assert!(!timer.update(true, ms(2500)));
+        assert!(timer.update(true, ms(500)));
+    }
+
+    #[test]
+    fn focus_loss_mutes_and_optionally_pauses() {
+        let mut focus = FocusPause::new();
+        let mut input = InputState::default();
+        focus.update(false, false, &mut input);
+        assert!(focus.muted() && !input.pause, "only the audio without pause_on_blur");
+        focus.update(true, false, &mut input);
+        assert!(!focus.muted() && !input.pause);
+
+        focus.update(false, true, &mut input);
+        assert!(focus.muted() && input.pause);
+        focus.update(true, true, &mut input);
+        assert!(!focus.muted() && !input.pause, "resumed on focus");
+    }
+
+    #[test]
+    fn focus_keeps_a_pause_it_didnt_make() {
+        let mut focus = FocusPause::new();
+        let mut input = InputState { pause: true, ..InputState::default() };
+        focus.update(false, true, &mut input);
+        focus.update(true, true, &mut input);
+        assert!(input.pause && !focus.muted());
+    }
+
+    #[test]
+    fn debouncer_filters_a_bounce_within_the_interval() {
+        let mut keys = Debouncer::new(ms(10));
+        assert_eq!(keys.update(0b10, ms(0)), 0);
+        assert_eq!(keys.update(0b00, ms(2)), 0, "chatter on press");
+        assert_eq!(keys.update(0b10, ms(4)), 0);
+        assert_eq!(keys.update(0b10, ms(13)), 0, "9ms since the last flip");
+        assert_eq!(keys.update(0b10, ms(14)), 0b10);
+
+        assert_eq!(keys.update(0b00, ms(20)), 0b10, "release chatter");
+        assert_eq!(keys.update(0b10, ms(21)), 0b10);
+        assert_eq!(keys.update(0b00, ms(22)), 0b10);
+        assert_eq!(keys.update(0b00, ms(32)), 0);
+    }
+
+    #[test]
+    fn debouncer_settles_each_key_on_its_own() {
+        let mut keys = Debouncer::new(ms(10));
+        keys.update(0b01, ms(0));
+        keys.update(0b11, ms(5));
+        assert_eq!(keys.update(0b11, ms(10)), 0b01);
+        assert_eq!(keys.update(0b11, ms(15)), 0b11);
+        assert_eq!(Debouncer::new(Duration::ZERO).update(0b100, ms(0)), 0b100, "a zero interval passes keys straight through");
+    }
+
+    #[test]
+    fn clamp_frame_time_caps_a_stall_at_the_maximum() {
+        assert_eq!(clamp_frame_time(Duration::from_secs(5), DEFAULT_MAX_FRAME_TIME), DEFAULT_MAX_FRAME_TIME);
+        assert_eq!(clamp_frame_time(ms(16), DEFAULT_MAX_FRAME_TIME), ms(16));
+
+        let mut pacer = Pacer::new(60, 60);
+        let ticks = pacer.advance(clamp_frame_time(Duration::from_secs(5), ms(50))).ticks;
+        assert_eq!(ticks, 3, "a 5 second drag counts as 50ms, not 300 frames");
+    }
+
+    #[test]
+    fn sticky_keys_latch_on_one_press_and_release_on_the_next() {
+        let mut sticky = StickyKeys::new();
+        assert_eq!(sticky.update(0b1), 0b1);
+        assert_eq!(sticky.update(0b0), 0b1, "letting go keeps it latched");
+        assert_eq!(sticky.update(0b0), 0b1);
+        assert_eq!(sticky.update(0b1), 0b0);
+        assert_eq!(sticky.update(0b0), 0b0);
+    }
+
+    #[test]
+    fn sticky_keys_only_flip_on_new_presses() {
+        let mut sticky = StickyKeys::new();
+        assert_eq!(sticky.update(0b01), 0b01);
+        assert_eq!(sticky.update(0b01), 0b01, "holding the key is still one press");
+        assert_eq!(sticky.update(0b11), 0b11, "a second key latches alongside");
+        assert_eq!(sticky.update(0b10), 0b11);
+        assert_eq!(sticky.update(0b11), 0b10);
+        assert_eq!(sticky.latched(), 0b10);
+    }
+
+    #[test]
+    fn clearing_sticky_keys_releases_everything_until_pressed_again() {
+        let mut sticky = StickyKeys::new();
+        sticky.update(0b101);
+        sticky.clear();
+        assert_eq!(sticky.latched(), 0);
+        assert_eq!(sticky.update(0b101), 0, "still held from before the clear");
+        assert_eq!(sticky.update(0b000), 0);
+        assert_eq!(sticky.update(0b100), 0b100);
+    }
+
+    #[test]
+    fn sticky_keys_satisfy_fx0a_while_latched() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xF3, 0x0A, 0xF4, 0x0A, 0xF5, 0x0A]).unwrap();
+        let mut sticky = StickyKeys::new();
+        chip8.set_keys_mask(sticky.update(1 << 7));
+        chip8.cycle();
+        assert_eq!((chip8.pc(), chip8.register(3)), (0x202, 7));
+
+        chip8.set_keys_mask(sticky.update(0));
+        chip8.cycle();
+        assert_eq!((chip8.pc(), chip8.register(4)), (0x204, 7), "the key is up but still latched");
+
+        chip8.set_keys_mask(sticky.update(1 << 7));
+        chip8.set_keys_mask(sticky.update(0));
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x204, "pressed again it's released and FX0A waits");
+    }
+
+    #[test]
+    fn turbo_pulses_at_its_rate_from_frame_zero() {
+        let pulses: Vec<bool> = (0..12).map(|frame| turbo_pulse(frame, 10, 60)).collect();
+        assert_eq!(pulses, [true, true, true, false, false, false, true, true, true, false, false, false]);
+        assert_eq!((0..60).filter(|&frame| turbo_pulse(frame, 10, 60)).count(), 30, "down half of every period");
+    }
+
+    #[test]
+    fn turbo_is_capped_at_every_other_frame() {
+        let pulses: Vec<bool> = (0..4).map(|frame| turbo_pulse(frame, 1000, 60)).collect();
+        assert_eq!(pulses, [true, false, true, false]);
+        assert!(turbo_pulse(0, 0, 60), "zero hz pulses as slowly as allowed rather than never");
+        assert!(!turbo_pulse(30, 0, 60));
+    }
+
+    #[test]
+    fn apply_turbo_only_pulses_the_turbo_keys() {
+        let keys = 1 << 5 | 1 << 6;
+        assert_eq!(apply_turbo(keys, &[(5, 10)], 0, 60), keys);
+        assert_eq!(apply_turbo(keys, &[(5, 10)], 3, 60), 1 << 6);
+        assert_eq!(apply_turbo(0, &[(5, 10)], 0, 60), 0, "a pulse never presses a key that isn't held");
+    }
+
+    // A poll loop as (pc, opcode) history: skip unless key 5, jump back, repeated
+    fn poll_history(passes: usize) -> Vec<(u16, u16)> {
+        (0..passes).flat_map(|_| [(0x300, 0x6005), (0x302, 0xE09E), (0x304, 0x1300)]).collect()
+    }
+
+    #[test]
+    fn tight_poll_finds_a_repeating_key_skip_loop() {
+        assert_eq!(tight_poll(&poll_history(POLL_REPEATS)), Some(3));
+        assert_eq!(tight_poll(&poll_history(POLL_REPEATS - 1)), None, "hasn't gone round often enough");
+    }
+
+    #[test]
+    fn tight_poll_ignores_loops_that_change_state_or_never_poll() {
+        let counting: Vec<(u16, u16)> = (0..POLL_REPEATS).flat_map(|_| [(0x300, 0x7001), (0x302, 0xE09E), (0x304, 0x1300)]).collect();
+        assert_eq!(tight_poll(&counting), None, "7XNN changes a register each pass");
+        let spinning: Vec<(u16, u16)> = (0..POLL_REPEATS * 2).flat_map(|_| [(0x300, 0x3000), (0x302, 0x1300)]).collect();
+        assert_eq!(tight_poll(&spinning), None, "no key read in the loop");
+
+        let mut broken = poll_history(POLL_REPEATS);
+        broken[1] = (0x302, 0xE0A1);
+        assert_eq!(tight_poll(&broken), None, "the passes must match");
+    }
+
+    #[test]
+    fn macro_steps_parse_keys_waits_and_chords() {
+        assert_eq!(parse_macro_steps("1x5, w10, 1+Ax2").unwrap(), [(1 << 1, 5), (0, 10), (1 << 1 | 1 << 0xA, 2)]);
+        assert!(parse_macro_steps("1x0").unwrap_err().contains("frame count"));
+        assert!(parse_macro_steps("Gx2").unwrap_err().contains("keypad key 'G'"));
+        assert!(parse_macro_steps("15").unwrap_err().contains("expected KEYSxFRAMES"));
+    }
+
+    #[test]
+    fn a_macro_plays_its_steps_frame_by_frame() {
+        let mut player = MacroPlayer::new(MacroOverlap::Queue);
+        player.trigger(&[(0b1, 2), (0, 1), (0b10, 1)]);
+        let frames: Vec<u16> = (0..5).map(|_| player.next_frame()).collect();
+        assert_eq!(frames, [0b1, 0b1, 0, 0b10, 0]);
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn overlapping_macros_queue_or_cancel() {
+        let mut queue = MacroPlayer::new(MacroOverlap::Queue);
+        queue.trigger(&[(0b1, 2)]);
+        queue.next_frame();
+        queue.trigger(&[(0b10, 1)]);
+        assert_eq!([queue.next_frame(), queue.next_frame(), queue.next_frame()], [0b1, 0b10, 0]);
+
+        let mut cancel = MacroPlayer::new(MacroOverlap::Cancel);
+        cancel.trigger(&[(0b1, 2)]);
+        cancel.next_frame();
+        cancel.trigger(&[(0b10, 1)]);
+        assert_eq!([cancel.next_frame(), cancel.next_frame()], [0b10, 0]);
+    }
+}
@@ -0,0 +1,223 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+use chip8::frontend::{self, InputState, MacroStep};
+
+// Default single keyboard layout, host keys laid out like the hex keypad
+//  1 2 3 4      1 2 3 C
+//  Q W E R  ->  4 5 6 D
+//  A S D F      7 8 9 E
+//  Z X C V      A 0 B F
+const PLAYER1_BINDINGS: [(Keycode, u8); 16] = [
+    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4),    (Keycode::W, 0x5),    (Keycode::E, 0x6),    (Keycode::R, 0xD),
+    (Keycode::A, 0x7),    (Keycode::S, 0x8),    (Keycode::D, 0x9),    (Keycode::F, 0xE),
+    (Keycode::Z, 0xA),    (Keycode::X, 0x0),    (Keycode::C, 0xB),    (Keycode::V, 0xF),
+];
+
+// Numpad keys handed to the second player, assigned in order to its keypad keys
+const PLAYER2_HOST_KEYS: [Keycode; 10] = [
+    Keycode::Kp8, Keycode::Kp2, Keycode::Kp4, Keycode::Kp6, Keycode::Kp5,
+    Keycode::Kp0, Keycode::Kp7, Keycode::Kp9, Keycode::Kp1, Keycode::Kp3,
+];
+
+// One set of host key to keypad key bindings and the keypad keys it currently holds down
+pub struct InputProfile {
+    pub name: &'static str,
+    bindings: Vec<(Keycode, u8)>,
+    pressed: u16,
+}
+
+impl InputProfile {
+    // Full keypad on the left side of the keyboard
+    pub fn player1() -> Self {
+        InputProfile {
+            name: "Player 1",
+            bindings: PLAYER1_BINDINGS.to_vec(),
+            pressed: 0,
+        }
+    }
+
+    // Subset of the keypad on the numpad, e.g. [0xC, 0xD] binds Keypad 8 to C and Keypad 2 to D
+    pub fn player2(keypad_keys: &[u8]) -> Result<Self, String> {
+        if keypad_keys.len() > PLAYER2_HOST_KEYS.len() {
+            return Err(format!("player 2 supports at most {} keys", PLAYER2_HOST_KEYS.len()));
+        }
+        if let Some(key) = keypad_keys.iter().find(|&&key| key > 0xF) {
+            return Err(format!("invalid keypad key {:#X}", key));
+        }
+
+        Ok(InputProfile {
+            name: "Player 2",
+            bindings: PLAYER2_HOST_KEYS.iter().copied().zip(keypad_keys.iter().copied()).collect(),
+            pressed: 0,
+        })
+    }
+
+    // Update the held keys from a host key event, returns whether the key is bound in this profile
+    pub fn handle_key(&mut self, keycode: Keycode, down: bool) -> bool {
+        let mut bound = false;
+        for &(host, keypad) in &self.bindings {
+            if host == keycode {
+                if down {
+                    self.pressed |= 1 << keypad;
+                } else {
+                    self.pressed &= !(1 << keypad);
+                }
+                bound = true;
+            }
+        }
+        bound
+    }
+
+    // Bitmask of held keypad keys, bit N = key N
+    pub fn pressed(&self) -> u16 {
+        self.pressed
+    }
+
+    // Human readable binding list, e.g. "Player 2: Keypad 8=C Keypad 2=D"
+    pub fn describe(&self) -> String {
+        let bindings: Vec<String> = self.bindings.iter()
+            .map(|&(host, keypad)| format!("{}={:X}", host.name(), keypad))
+            .collect();
+        format!("{}: {}", self.name, bindings.join(" "))
+    }
+}
+
+// Combine every profile into the single 16-key state, a key is down if any player holds it
+pub fn merge(profiles: &[InputProfile]) -> u16 {
+    profiles.iter().fold(0, |mask, profile| mask | profile.pressed())
+}
+
+// Parse a comma separated list of hex keypad keys, e.g. "C,D" or "0xC,0xD"
+pub fn parse_keypad_keys(list: &str) -> Result<Vec<u8>, String> {
+    list.split(',')
+        .map(|key| {
+            let key = key.trim();
+            let digits = key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")).unwrap_or(key);
+            u8::from_str_radix(digits, 16).map_err(|_| format!("invalid keypad key '{}'", key))
+        })
+        .collect()
+}
+
+// A macro played into the keypad when its host key is pressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroBinding {
+    pub name: String,
+    pub key: Keycode,
+    pub steps: Vec<MacroStep>,
+}
+
+// NAME:KEY:STEPS, e.g. level9:F1:1x5,w10,Ax5 with the host key by its SDL name and the steps as
+// frontend::parse_macro_steps takes them
+pub fn parse_macro(value: &str) -> Result<MacroBinding, String> {
+    let mut parts = value.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(key), Some(steps)) if !name.is_empty() => Ok(MacroBinding {
+            name: name.to_string(),
+            key: Keycode::from_name(key).ok_or_else(|| format!("unknown key '{}' for macro {}", key, name))?,
+            steps: frontend::parse_macro_steps(steps)?,
+        }),
+        _ => Err(format!("invalid macro '{}', expected NAME:KEY:STEPS", value)),
+    }
+}
+
+// Fold one SDL event into the input state: Escape or closing the window quits, F5 resets, P toggles pause
+// and everything else goes to the key bindings
+pub fn reduce(state: &mut InputState, event: &Event, profiles: &mut [InputProfile]) {
+    match *event {
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => state.quit = true,
+        Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => state.reset = true,
+        Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => state.pause = !state.pause,
+        Event::KeyDown { keycode: Some(key), .. } => {
+            for profile in profiles.iter_mut() {
+                profile.handle_key(key, true);
+            }
+        }
+        Event::KeyUp { keycode: Some(key), .. } => {
+            for profile in profiles.iter_mut() {
+                profile.handle_key(key, false);
+            }
+        }
+        _ => {}
+    }
+    state.keys = merge(profiles);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player2_binds_the_numpad_in_order() {
+        let mut player2 = InputProfile::player2(&[0xC, 0xD]).unwrap();
+        assert!(player2.handle_key(Keycode::Kp2, true));
+        assert_eq!(player2.pressed(), 1 << 0xD);
+        assert!(player2.handle_key(Keycode::Kp8, true));
+        assert_eq!(player2.pressed(), 1 << 0xC | 1 << 0xD);
+        assert!(!player2.handle_key(Keycode::Kp4, true), "only as many numpad keys as keypad keys are bound");
+        assert!(!player2.handle_key(Keycode::Q, true));
+    }
+
+    #[test]
+    fn player2_profiles_are_validated() {
+        assert!(InputProfile::player2(&[0x10]).err().unwrap().contains("invalid keypad key 0x10"));
+        assert!(InputProfile::player2(&[0; 11]).err().unwrap().contains("at most 10 keys"));
+        assert_eq!(parse_keypad_keys("C, 0xd").unwrap(), [0xC, 0xD]);
+        assert!(parse_keypad_keys("C,G").unwrap_err().contains("'G'"));
+    }
+
+    #[test]
+    fn players_merge_as_a_logical_or() {
+        let mut profiles = [InputProfile::player1(), InputProfile::player2(&[0x5, 0x8]).unwrap()];
+        profiles[0].handle_key(Keycode::W, true);
+        profiles[1].handle_key(Keycode::Kp8, true);
+        profiles[1].handle_key(Keycode::Kp2, true);
+        assert_eq!(merge(&profiles), 1 << 0x5 | 1 << 0x8);
+
+        profiles[0].handle_key(Keycode::W, false);
+        assert_eq!(merge(&profiles), 1 << 0x5 | 1 << 0x8, "player 2 still holds key 5");
+        profiles[1].handle_key(Keycode::Kp8, false);
+        assert_eq!(merge(&profiles), 1 << 0x8);
+    }
+
+    fn key(keycode: Keycode, down: bool, repeat: bool) -> Event {
+        if down {
+            Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: sdl2::keyboard::Mod::NOMOD, repeat }
+        } else {
+            Event::KeyUp { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: sdl2::keyboard::Mod::NOMOD, repeat }
+        }
+    }
+
+    #[test]
+    fn events_reduce_to_an_input_state() {
+        let mut profiles = [InputProfile::player1()];
+        let mut state = InputState::default();
+        reduce(&mut state, &key(Keycode::W, true, false), &mut profiles);
+        reduce(&mut state, &key(Keycode::V, true, false), &mut profiles);
+        assert_eq!(state.keys, 1 << 0x5 | 1 << 0xF);
+        reduce(&mut state, &key(Keycode::W, false, false), &mut profiles);
+        assert_eq!(state.keys, 1 << 0xF);
+        assert!(!state.quit && !state.reset && !state.pause);
+
+        reduce(&mut state, &key(Keycode::P, true, false), &mut profiles);
+        assert!(state.pause);
+        reduce(&mut state, &key(Keycode::P, true, true), &mut profiles);
+        assert!(state.pause, "a held P doesn't toggle again");
+        reduce(&mut state, &key(Keycode::P, true, false), &mut profiles);
+        assert!(!state.pause);
+
+        reduce(&mut state, &key(Keycode::F5, true, true), &mut profiles);
+        assert!(!state.reset, "auto-repeat doesn't reset");
+        reduce(&mut state, &key(Keycode::F5, true, false), &mut profiles);
+        assert!(state.reset);
+        assert_eq!(state.keys, 1 << 0xF, "control keys leave the keypad alone");
+
+        reduce(&mut state, &key(Keycode::Escape, true, false), &mut profiles);
+        assert!(state.quit);
+        let mut state = InputState::default();
+        reduce(&mut state, &Event::Quit { timestamp: 0 }, &mut profiles);
+        assert!(state.quit, "closing the window quits");
+    }
+}
@@ -0,0 +1,46 @@
+use core::ops::{Deref, DerefMut};
+
+// The 16 key hex keypad, one byte per key that is non-zero while the key is held
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [u8; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keys past F don't exist, so they are never held
+    pub fn is_pressed(&self, key: usize) -> bool {
+        self.keys.get(key).is_some_and(|&state| state != 0)
+    }
+
+    pub fn set(&mut self, key: usize, state: u8) {
+        self.keys[key] = state;
+    }
+
+    // Keys held down, bit N = key N
+    pub fn mask(&self) -> u16 {
+        self.keys.iter().enumerate().fold(0, |mask, (idx, &state)| mask | ((state != 0) as u16) << idx)
+    }
+
+    // Lowest numbered key held down, the one FX0A takes
+    pub fn first_pressed(&self) -> Option<u8> {
+        self.keys.iter().position(|&state| state != 0).map(|idx| idx as u8)
+    }
+}
+
+impl Deref for Keypad {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.keys
+    }
+}
+
+impl DerefMut for Keypad {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.keys
+    }
+}
@@ -1 +1,75 @@
-#![allow(nonstandard_style)]
\ No newline at end of file
+#![allow(nonstandard_style)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// The interpreter core, built with or without std
+pub mod chip8;
+pub mod chip8x;
+pub mod coverage;
+pub mod cpu;
+pub mod disasm;
+pub mod display;
+pub mod keypad;
+pub mod memory;
+pub mod panel;
+pub mod pbm;
+pub mod render;
+
+// Tooling and frontend support, std only
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod assemble;
+#[cfg(feature = "std")]
+pub mod breakpoints;
+#[cfg(feature = "std")]
+pub mod cfg;
+#[cfg(feature = "std")]
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod condition;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod decompile;
+#[cfg(feature = "std")]
+pub mod edit;
+#[cfg(feature = "std")]
+pub mod frontend;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod png;
+#[cfg(feature = "std")]
+pub mod rpl;
+#[cfg(feature = "std")]
+pub mod savestate;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod watch;
+
+pub use crate::chip8::{Chip8, Quirks, Variant, WIDTH, HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
+
+#[cfg(feature = "net")]
+pub mod fetch;
+#[cfg(feature = "netplay")]
+pub mod netplay;
+#[cfg(feature = "paranoid")]
+pub mod paranoid;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(all(feature = "std", any(test, feature = "test-util")))]
+pub mod testlog;
+#[cfg(feature = "zip")]
+pub mod zip;
+
+// What the core takes from alloc, so the same code builds whether or not the std prelude is there
+pub(crate) mod prelude {
+    pub use alloc::{format, vec};
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+}
@@ -1,19 +1,2682 @@
+use std::collections::{BTreeSet, VecDeque};
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
 
-mod chip8;
+mod audio;
+mod clipboard;
+mod configfile;
+mod console;
+mod debugger;
+mod framedump;
+mod input;
+mod overlay;
+mod speedrun;
+mod video;
+
+use chip8::{Chip8, Quirks, Variant, WIDTH, HEIGHT};
+use chip8::analysis::{self, Platform};
+use chip8::breakpoints::{self, Breakpoint, Breakpoints, KeyBreak, OpcodeBreak};
+use chip8::cheats::{ApplyMode, CheatManager};
+use chip8::chip8x;
+use chip8::compat::{self, Comparison};
+use chip8::coverage::Coverage;
+use chip8::database::RomDatabase;
+use chip8::display;
+use chip8::frontend::{self, Debouncer, FocusPause, HaltTimer, InputState, MacroOverlap, MacroPlayer, Pacer, ScreenLayout, StickyKeys, DEFAULT_MAX_FRAME_TIME};
+use log::{error, info, warn, Level, LevelFilter, Log, Metadata, Record};
+use chip8::movie::{Movie, MovieHeader, MovieSession};
+use chip8::render;
+use chip8::rpl;
+use chip8::savestate::{self, StateHeader};
+use chip8::trace::{self, TraceEvent, Tracer};
+use chip8::watch::Watches;
+#[cfg(feature = "net")]
+use chip8::fetch::{self, HttpFetcher};
+#[cfg(feature = "netplay")]
+use chip8::netplay::{Netplay, Session};
+#[cfg(feature = "script")]
+use chip8::script::Script;
+use audio::{Beeper, Waveform};
+use console::Console;
+use debugger::{DebugWindow, Route};
+use framedump::{DumpFormat, FrameDumper};
+use input::{InputProfile, MacroBinding};
+use speedrun::Splits;
+use video::{RawVideo, Recorder};
+
+const DEFAULT_IPS: usize = 700;         // Instructions per second when not specified
+const FRAME_RATE: usize = 60;           // Frames per second, also the timer rate
+const TUNE_INTERVAL: usize = 60;        // Frames between auto-tuner adjustments
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;   // Frames a headless run lasts when not specified
+const CHEAT_DIR: &str = "cheats";       // Per ROM cheat files, named by ROM hash
+const STATE_DIR: &str = "states";       // Per ROM savestate slots, named by ROM hash
+#[cfg(feature = "net")]
+const DOWNLOAD_DIR: &str = "downloads"; // ROMs fetched from URLs, named by URL hash
+const STATE_SLOTS: usize = 4;
+const LOOP_MAX_PCS: usize = 4;          // A frame spent on this few addresses counts as a tight loop
+const TOAST_FRAMES: u64 = 180;         // How long a frontend message stays on screen
+const COMPARE_GAP: usize = 8;           // Pixels between the two pictures of --compare
+const COMPARE_LABEL: usize = 18;        // Band above them for the profile names
+const HEATMAP_RANGES: usize = 10;       // Ranges listed by the --heatmap report
+const DEFAULT_SCANLINE_INTENSITY: u8 = 50;    // Percent the --scanlines rows are dimmed by
+const HALTED_TEXT: &str = "HALTED";     // Label shown once the ROM sits in a jump to itself
+const HALTED_SCALE: i32 = 2;            // Screen pixels per font pixel of that label
+
+// Frontend options parsed from the command line and the config file
+struct Config {
+    rom_path: String,
+    ips: usize,
+    timer_rate: u32,
+    max_fps: u32,
+    auto_reset: Option<Duration>,
+    debounce: Option<Duration>,         // How long a key has to read the same before it changes
+    pause_on_blur: bool,                // Pause while no window of the emulator has focus, not just the audio
+    sticky_keys: bool,                  // Key presses toggle keypad keys instead of holding them
+    turbo: Vec<(u8, u32)>,              // Keypad keys pulsed while held, with their rate in Hz
+    yield_on_poll: bool,                // Skip the rest of a frame spent spinning on a key check
+    macros: Vec<MacroBinding>,          // Keypad sequences played when their host key is pressed
+    macro_overlap: MacroOverlap,
+    max_frame_time: Duration,           // Longest stall the loop catches up on in one iteration
+    tuner: Option<IpsTuner>,
+    max_draws_per_frame: Option<u32>,
+    player2_keys: Option<Vec<u8>>,
+    quirks: Quirks,
+    index_width: Option<u8>,
+    detect_quirks: bool,
+    disasm: bool,
+    auto_quirks: bool,
+    rom_db: Option<String>,
+    cheats: Vec<(u16, u8)>,
+    cheat_mode: ApplyMode,
+    speedrun: bool,
+    splits_path: Option<String>,
+    record_video: Option<String>,
+    ffmpeg: String,
+    record_scale: usize,
+    raw_video: Option<String>,
+    waveform: Waveform,
+    scanlines: Option<u8>,
+    pixel_aspect: (u32, u32),           // Width to height of one drawn CHIP-8 pixel
+    headless: bool,
+    frames: u64,
+    dump_frames: Option<String>,
+    dump_format: DumpFormat,            // Frame dumps and screenshots
+    every_frame: bool,
+    max_dumped_frames: Option<usize>,
+    config_path: Option<String>,
+    debug_window: bool,
+    log_vf_clobbers: bool,
+    log_dirty: bool,                    // Log how many pixels each frame changed
+    lint_registers: bool,
+    extensions: bool,                   // Decode this emulator's own opcodes, such as 0FFF for the grid overlay
+    hires: bool,                        // Power on in the SUPER-CHIP 128x64 resolution
+    port_input: u8,                     // Byte CHIP-8E ROMs read from the VIP input port
+    console: bool,
+    remote: Option<u16>,                // Port of the remote console, in place of the one on stdin
+    breakpoints: Vec<Breakpoint>,
+    opcode_breaks: Vec<OpcodeBreak>,
+    key_breaks: Vec<KeyBreak>,
+    log_level: LevelFilter,
+    coverage_out: Option<String>,
+    heatmap: bool,                      // Count accesses per address and print the hottest ranges on exit
+    trace: Option<String>,
+    trace_format: String,
+    compare_trace: Option<String>,
+    compare: Option<[Platform; 2]>,     // Run the ROM under both profiles side by side
+    seed: Option<u64>,
+    record_movie: Option<String>,
+    play_movie: Option<String>,
+    force: bool,
+    strict: bool,                       // Refuse ROMs that are empty or unreadable, and protected writes
+    zip_entry: Option<String>,          // Which ROM to take from a ZIP archive holding several
+    no_cache: bool,                     // Download a ROM URL again instead of using the cached copy
+    protect: Vec<Range<usize>>,
+    help: bool,
+    script: Option<String>,             // Lua script run alongside the ROM, see chip8::script
+    #[cfg(feature = "netplay")]
+    netplay: Option<NetplayMode>,
+}
+
+// Recorded, replayed or run alongside the emulation: the instruction trace, the input movie and the script
+struct Capture {
+    tracer: Option<Tracer>,
+    movie: Option<MovieSession>,
+    script: Option<Script>,
+}
+
+// Without the script feature there is never a script to run, and the hooks below compile to nothing
+#[cfg(not(feature = "script"))]
+enum Script {}
+
+#[cfg(not(feature = "script"))]
+impl Script {
+    fn keys(&self) -> u16 {
+        match *self {}
+    }
+
+    fn before_instruction(&mut self, _chip8: &mut Chip8) -> Result<(), String> {
+        match *self {}
+    }
+
+    fn after_frame(&mut self, _chip8: &mut Chip8) -> Result<(), String> {
+        match *self {}
+    }
+
+    fn take_output(&mut self) -> Vec<String> {
+        match *self {}
+    }
+}
+
+// Which side of a netplay session this instance is
+#[cfg(feature = "netplay")]
+#[derive(PartialEq, Eq)]
+enum NetplayMode {
+    Connect(String),
+    Listen(u16),
+}
+
+// Library and frontend diagnostics go to stderr with the severity in front
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Info => eprintln!("{}", record.args()),
+            Level::Debug | Level::Trace => eprintln!("Debug: {}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// --log-level names, the levels StderrLogger tells apart
+fn parse_log_level(name: &str) -> Result<LevelFilter, String> {
+    match name {
+        "error" => Ok(LevelFilter::Error),
+        "warn" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        "debug" => Ok(LevelFilter::Debug),
+        _ => Err(format!("unknown log level '{}', expected error, warn, info or debug", name)),
+    }
+}
+
+// Load state overlay: one entry per slot, read from the slot files when it opens
+struct StatePicker {
+    selected: usize,
+    headers: Vec<Option<StateHeader>>,
+}
+
+impl StatePicker {
+    fn open(rom_hash: u64, selected: usize) -> Self {
+        let headers = (0..STATE_SLOTS)
+            .map(|slot| savestate::peek(&savestate::path_for(Path::new(STATE_DIR), rom_hash, slot)))
+            .collect();
+        StatePicker { selected, headers }
+    }
+}
+
+// Adjusts instructions per second within [min, max] based on how much time the ROM spends waiting on input
+#[derive(PartialEq, Eq)]
+struct IpsTuner {
+    min: usize,
+    max: usize,
+}
+
+impl IpsTuner {
+    // Input-bound ROMs gain nothing from speed, so slow down; compute-heavy ROMs get sped up
+    fn adjust(&self, ips: usize, wait: u32, work: u32) -> usize {
+        let total = wait + work;
+        if total == 0 {
+            return ips.clamp(self.min, self.max);
+        }
+
+        let wait_ratio = wait as f32 / total as f32;
+        let step = (ips / 10).max(1);
+        let new_ips = if wait_ratio > 0.5 {
+            ips.saturating_sub(step)                // Mostly waiting on input
+        } else if wait_ratio < 0.1 {
+            ips + step                              // Mostly doing work
+        } else {
+            ips
+        };
+        new_ips.clamp(self.min, self.max)
+    }
+}
 
 fn main() -> Result<(), String> {
-    // Command Line arguments: Usage: cargo run <rom_path>
+    // Command Line arguments: Usage: cargo run <rom_path> [options]
     let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => return disasm(&args[2..]),
+        Some("decompile") => return decompile(&args[2..]),
+        Some("assemble") => return assemble(&args[2..]),
+        Some("trim") => return trim(&args[2..]),
+        Some("check-compat") => return check_compat(&args[2..]),
+        _ => {}
+    }
+
+    let mut usage = format!("{} <rom_path> [--ips N] [--timer-rate HZ] [--max-fps N] [--auto-reset SECONDS] [--debounce-ms N] [--pause-on-blur] [--sticky-keys] [--turbo KEY:HZ] [--macro NAME:KEY:STEPS] [--macro-overlap queue|cancel] [--max-frame-ms N] [--auto-ips MIN:MAX] [--max-draws N] [--yield-on-poll] [--player2 KEYS] [--clip] [--load-store-increment] [--shift-vy] [--collision-delay] [--index-width 12|16] [--adi-overflow-vf 12|16] [--detect-quirks] [--disasm] [--auto-quirks] [--rom-db FILE] [--cheat ADDR=VAL] [--cheat-mode frame|instruction] [--speedrun] [--splits FILE] [--record-video FILE] [--ffmpeg PATH] [--record-scale N] [--raw-video FILE] [--waveform square|sine|triangle|noise] [--scanlines] [--scanline-intensity PERCENT] [--pixel-aspect W:H] [--headless] [--frames N] [--dump-frames DIR] [--dump-format png|pbm|xbm] [--every-frame] [--max-dumped-frames N] [--config FILE] [--debug-window] [--log-vf-clobbers] [--log-dirty] [--lint-registers] [--enable-extensions] [--hires] [--port-input BYTE] [--console] [--remote PORT] [--break 'ADDR [if COND]'] [--break-op PATTERN] [--break-key 'KEY|any [press]'] [--log-level error|warn|info|debug] [--coverage-out FILE] [--heatmap] [--trace FILE|-] [--trace-format text|csv|octo] [--compare-trace FILE] [--compare PLATFORM PLATFORM] [--seed N] [--record-movie FILE] [--play-movie FILE] [--force] [--protect START:END] [--strict] [--zip-entry NAME] [--help]", args[0]);
+    if cfg!(feature = "net") {
+        usage.push_str(" [--no-cache]");
+    }
+    if cfg!(feature = "netplay") {
+        usage.push_str(" [--netplay HOST:PORT | --netplay-listen PORT]");
+    }
+    if cfg!(feature = "script") {
+        usage.push_str(" [--script FILE]");
+    }
+
+    let mut config = match resolve_config(&args[1..]) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            eprintln!("Error Usage: {}", usage);
+            std::process::exit(1);
+        }
+    };
+    log::set_logger(&StderrLogger).map_err(|err| err.to_string())?;
+    log::set_max_level(config.log_level);
+
+    let mut profiles = build_profiles(&config)?;
+
+    // Help lists the key bindings of every active player
+    if config.help {
+        println!("Usage: {}", usage);
+        for note in runtime_notes() {
+            println!("{}", note);
+        }
+        for profile in &profiles {
+            println!("{}", profile.describe());
+        }
+        for &(key, hz) in &config.turbo {
+            println!("Turbo: {:X} pulses at {} Hz while held", key, hz);
+        }
+        for binding in &config.macros {
+            let frames: u32 = binding.steps.iter().map(|&(_, frames)| frames).sum();
+            println!("Macro {}: {} plays {} steps over {} frames", binding.name, binding.key.name(), binding.steps.len(), frames);
+        }
+        return Ok(());
+    }
+
+    let mut chip8 = Chip8::new();
+    #[cfg(feature = "net")]
+    let data = match fetch::is_url(&config.rom_path) {
+        true => Ok(fetch::download(&mut HttpFetcher::new(fetch::TIMEOUT), &config.rom_path, (!config.no_cache).then(|| Path::new(DOWNLOAD_DIR)))?),
+        false => std::fs::read(&config.rom_path),
+    };
+    #[cfg(not(feature = "net"))]
+    let data = std::fs::read(&config.rom_path);
+    match data {
+        Ok(data) => {
+            let rom = unpack_rom(&config, data).map_err(|err| format!("{}: {}", config.rom_path, err))?;
+            let loaded = match config.strict {
+                true => chip8.load_rom_bytes_strict(&rom),
+                false => chip8.load_rom_bytes(&rom),
+            };
+            loaded.map_err(|err| format!("{}: {}", config.rom_path, err))?;
+        }
+        Err(err) if config.strict => return Err(format!("could not read {}: {}", config.rom_path, err)),
+        Err(err) => warn!("could not read {}: {}, running with empty memory", config.rom_path, err),
+    }
+
+    // Linear listing of the loaded ROM and nothing else
+    if config.disasm {
+        for line in chip8::disasm::disassemble(chip8.rom()) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    // Known ROMs get their title and recommended platform from the database, --rom-db adds to the built in one
+    let mut database = RomDatabase::embedded();
+    if let Some(path) = &config.rom_db {
+        database.merge(RomDatabase::load(path)?);
+    }
+    let info = database.lookup_hash(&chip8.rom_sha1_hex());
+    let mut title = String::from("Chip8 Emu");
+    if let Some(info) = info {
+        title = format!("Chip8 Emu - {}", info.title);
+        println!("{} by {} ({})", info.title,
+            if info.authors.is_empty() { "unknown".to_string() } else { info.authors.join(", ") },
+            info.release.as_deref().unwrap_or("unknown release"));
+    }
+
+    // Quirks guessed from the ROM override the database, flags given on the command line still apply on top
+    let platform = info.and_then(|info| info.platform());
+    let report = (config.detect_quirks || config.auto_quirks).then(|| {
+        let mut report = analysis::detect_quirks(chip8.rom());
+        analysis::simulate_profiles(chip8.rom(), &mut report);
+        report
+    });
+    if let (true, Some(report)) = (config.detect_quirks, &report) {
+        print!("{}", report);
+        return Ok(());
+    }
+    chip8.quirks = analysis::resolve_quirks(platform, report.as_ref(), config.quirks);
+    if let Some(width) = config.index_width {
+        chip8.quirks.index_width = width;
+    }
+    chip8.lint_registers = config.lint_registers;
+    chip8.extensions = config.extensions;
+    chip8.port_input = config.port_input;
+    chip8.set_start_hires(config.hires);
+    chip8.set_heatmap(config.heatmap);
+    for region in &config.protect {
+        chip8.protect_region(region.clone());
+    }
+    chip8.set_strict_protection(config.strict);
+    if let Some(seed) = config.seed {
+        chip8.set_seed(seed);
+    }
+    if let Some(path) = &config.compare_trace {
+        return compare_trace(&mut chip8, path);
+    }
+    if let Some(platforms) = config.compare {
+        let mut comparison = Comparison::new(chip8.rom(), platforms, chip8.seed()).map_err(|err| format!("{}: {}", config.rom_path, err))?;
+        return match config.headless {
+            true => compare_headless(&mut comparison, &config),
+            false => run_compare(&mut comparison, &config, &title, &mut profiles),
+        };
+    }
+    let movie = start_movie(&mut chip8, &config)?;
+
+    // Saved cheats for this ROM, then any given on the command line
+    let mut cheats = CheatManager::new();
+    cheats.mode = config.cheat_mode;
+    let cheat_path = CheatManager::path_for(Path::new(CHEAT_DIR), chip8.rom_hash());
+    cheats.load(&chip8, &cheat_path)?;
+    for &(address, value) in &config.cheats {
+        cheats.add(&chip8, address, value)?;
+    }
+    for (idx, cheat) in cheats.cheats().iter().enumerate() {
+        println!("Cheat {} (Ctrl+{}): [{:#05X}] = {:#04X} {}", idx + 1, idx + 1, cheat.address, cheat.value,
+            if cheat.enabled { "on" } else { "off" });
+    }
+
+    // High scores and other RPL flags the ROM saved on an earlier run
+    let rpl_path = rpl::path_for(Path::new(&config.rom_path));
+    chip8.set_rpl(rpl::load(&rpl_path)?);
+
+    let tracer = match &config.trace {
+        Some(path) => Some(open_tracer(path, &config.trace_format)?),
+        None => None,
+    };
+    let script = load_script(&mut chip8, &config)?;
+    let mut capture = Capture { tracer, movie, script };
+
+    let result = if config.headless {
+        run_headless(&mut chip8, &config, &mut cheats, &mut capture)
+    } else {
+        run(&mut chip8, &mut config, &args[1..], &title, &mut profiles, &mut cheats, &mut capture)
+    };
+    // A movie played back and re-recorded is saved over itself unless --record-movie names another file
+    if let (Some(path), Some(MovieSession::Recording(movie))) = (config.record_movie.as_ref().or(config.play_movie.as_ref()), &capture.movie) {
+        movie.save(Path::new(path))?;
+        println!("Recorded {} frames to {}", movie.frames.len(), path);
+    }
+    if let Some(tracer) = &mut capture.tracer {
+        tracer.flush()?;
+    }
+    if !cheats.cheats().is_empty() {
+        cheats.save(&cheat_path)?;
+    }
+    if chip8.rpl() != [0; rpl::RPL_SIZE] {
+        rpl::save(&rpl_path, &chip8.rpl())?;
+    }
+    if chip8.uninit_reads() > 0 {
+        warn!("{} reads of registers before they were written", chip8.uninit_reads());
+    }
+    if let Some(path) = &config.coverage_out {
+        chip8.coverage().save(Path::new(path))?;
+    }
+    if let (true, Some(heatmap)) = (config.heatmap, chip8.heatmap()) {
+        println!("Hottest memory ranges:");
+        for line in heatmap.report(HEATMAP_RANGES) {
+            println!("  {}", line);
+        }
+    }
+    result
+}
+
+// What the optional features built into this binary need or leave out, for --help
+fn runtime_notes() -> Vec<&'static str> {
+    let mut notes = Vec::new();
+    if cfg!(feature = "zip") {
+        notes.push("ZIP: stored and deflate entries only, encrypted and otherwise compressed archives are refused");
+    }
+    if cfg!(feature = "net") {
+        notes.push("URLs: downloads over 1 MiB are refused, the rest are cached in downloads/ unless --no-cache is given");
+    }
+    if cfg!(feature = "clipboard") {
+        notes.push("Ctrl+C: the screen is copied as an image, or as text when the clipboard won't take one");
+    }
+    if cfg!(feature = "script") {
+        notes.push("--script: Lua 5.4, its emu functions are listed at the top of src/script.rs");
+    }
+    notes
+}
+
+// Disassembly tool: disasm [--cfg] [--coverage FILE] [--run N] [--data START:END] <rom_path>, prints a listing or
+// with --cfg a Graphviz DOT control flow graph. A coverage file from --coverage-out, or running the ROM headless for
+// N frames, splits the listing into executed code and data; without one, --data ranges are listed as bytes
+fn disasm(args: &[String]) -> Result<(), String> {
+    const USAGE: &str = "Usage: disasm [--cfg] [--coverage FILE] [--run N] [--data START:END] <rom_path>";
+    let mut cfg = false;
+    let mut data = BTreeSet::new();             // Addresses listed as bytes, from --data
+    let mut coverage_path = None;
+    let mut run_frames = None;
+    let mut rom_path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--cfg" => cfg = true,
+            "--coverage" => coverage_path = Some(iter.next().ok_or(USAGE)?),
+            "--data" => {
+                let value = iter.next().ok_or(USAGE)?;
+                let region = parse_region(value).ok_or_else(|| format!("invalid region '{}', expected START:END in hex", value))?;
+                data.extend(region.map(|addr| addr as u16));
+            }
+            "--run" => {
+                let value = iter.next().ok_or(USAGE)?;
+                run_frames = Some(value.parse::<u64>().map_err(|_| format!("invalid frame count '{}'", value))?);
+            }
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let rom_path = rom_path.ok_or(USAGE)?;
+    let rom = std::fs::read(rom_path).map_err(|err| format!("could not read {}: {}", rom_path, err))?;
+
+    let coverage = match (coverage_path, run_frames) {
+        (Some(path), _) => Some(Coverage::load(Path::new(path))?),
+        (None, Some(frames)) => {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(&rom).map_err(|err| format!("{}: {}", rom_path, err))?;
+            chip8.quirks = analysis::resolve_quirks(None, Some(&analysis::detect_quirks(&rom)), Quirks::default());
+            for _ in 0..frames {
+                chip8.step_frame(DEFAULT_IPS / FRAME_RATE);
+            }
+            Some(chip8.coverage().clone())
+        }
+        (None, None) => None,
+    };
+
+    if cfg {
+        print!("{}", chip8::cfg::build(&rom).to_dot());
+    } else {
+        let lines = match &coverage {
+            Some(coverage) => chip8::disasm::disassemble_with_coverage(&rom, coverage),
+            None => chip8::disasm::disassemble_with_data(&rom, &data),
+        };
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// Decompilation tool: decompile <rom_path>, prints Octo source
+fn decompile(args: &[String]) -> Result<(), String> {
+    let [rom_path] = args else {
+        return Err("Usage: decompile <rom_path>".to_string());
+    };
+    let rom = std::fs::read(rom_path).map_err(|err| format!("could not read {}: {}", rom_path, err))?;
+    print!("{}", chip8::decompile::decompile(&rom));
+    Ok(())
+}
+
+// Assembler: assemble <source_path> <rom_path>, Octo source such as decompile prints back to a ROM.
+// Trailing zero padding is left out of the file, memory past the ROM is zero when it loads anyway
+fn assemble(args: &[String]) -> Result<(), String> {
+    let [source_path, rom_path] = args else {
+        return Err("Usage: assemble <source_path> <rom_path>".to_string());
+    };
+    let source = std::fs::read_to_string(source_path).map_err(|err| format!("could not read {}: {}", source_path, err))?;
+    let rom = chip8::assemble::assemble(&source).map_err(|err| format!("{}:{}", source_path, err.trim_start_matches("line ")))?;
+    std::fs::write(rom_path, chip8::chip8::trim_rom(&rom)).map_err(|err| format!("could not write {}: {}", rom_path, err))
+}
+
+// Padding trimmer: trim <rom_path> [out_path], writes the ROM without its trailing zeros, over the
+// original when no output is given
+fn trim(args: &[String]) -> Result<(), String> {
+    let (rom_path, out_path) = match args {
+        [rom_path] => (rom_path, rom_path),
+        [rom_path, out_path] => (rom_path, out_path),
+        _ => return Err("Usage: trim <rom_path> [out_path]".to_string()),
+    };
+    let rom = std::fs::read(rom_path).map_err(|err| format!("could not read {}: {}", rom_path, err))?;
+    let trimmed = chip8::chip8::trim_rom(&rom);
+    std::fs::write(out_path, trimmed).map_err(|err| format!("could not write {}: {}", out_path, err))?;
+    println!("{}: {} bytes, {} of padding removed", out_path, trimmed.len(), rom.len() - trimmed.len());
+    Ok(())
+}
+
+// Compatibility report: check-compat [--frames N] [--json] <dir>, runs every ROM in dir headless under
+// each platform profile and prints which one each ROM seems happiest under
+fn check_compat(args: &[String]) -> Result<(), String> {
+    const USAGE: &str = "Usage: check-compat [--frames N] [--json] <dir>";
+    const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+    let mut frames = DEFAULT_HEADLESS_FRAMES;
+    let mut json = false;
+    let mut dir = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let value = iter.next().ok_or(USAGE)?;
+                frames = value.parse().map_err(|_| format!("invalid frame count '{}'", value))?;
+            }
+            "--json" => json = true,
+            _ if dir.is_none() => dir = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let dir = dir.ok_or(USAGE)?;
+
+    let mut paths: Vec<_> = std::fs::read_dir(dir).map_err(|err| format!("could not read {}: {}", dir, err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_string_lossy().to_ascii_lowercase().as_str())))
+        .collect();
+    paths.sort();
+
+    let mut reports = Vec::new();
+    for path in &paths {
+        let rom = match std::fs::read(path) {
+            Ok(rom) => rom,
+            Err(err) => {
+                error!("could not read {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        match compat::check(&name, &rom, frames, DEFAULT_IPS / FRAME_RATE) {
+            Ok(report) => reports.push(report),
+            Err(err) => error!("could not check {}: {}", path.display(), err),
+        }
+    }
+
+    print!("{}", if json { compat::to_json(&reports) } else { compat::to_table(&reports) });
+    Ok(())
+}
+
+// Parse the command line arguments following the program name
+fn parse_args(args: &[String]) -> Result<Config, String> {
+    let mut rom_path = None;
+    let mut ips = DEFAULT_IPS;
+    let mut timer_rate = FRAME_RATE as u32;
+    let mut max_fps = FRAME_RATE as u32;
+    let mut auto_reset = None;
+    let mut pause_on_blur = false;
+    let mut debounce = None;
+    let mut sticky_keys = false;
+    let mut turbo = Vec::new();
+    let mut yield_on_poll = false;
+    let mut macros = Vec::new();
+    let mut macro_overlap = MacroOverlap::default();
+    let mut max_frame_time = DEFAULT_MAX_FRAME_TIME;
+    let mut tuner = None;
+    let mut max_draws_per_frame = None;
+    let mut player2_keys = None;
+    let mut quirks = Quirks::default();
+    let mut index_width = None;
+    let mut detect_quirks = false;
+    let mut disasm = false;
+    let mut auto_quirks = false;
+    let mut rom_db = None;
+    let mut cheats = Vec::new();
+    let mut cheat_mode = ApplyMode::EveryFrame;
+    let mut speedrun = false;
+    let mut splits_path = None;
+    let mut record_video = None;
+    let mut ffmpeg = String::from("ffmpeg");
+    let mut record_scale = 1;
+    let mut raw_video = None;
+    let mut waveform = Waveform::default();
+    let mut scanlines = false;
+    let mut pixel_aspect = (1, 1);
+    let mut scanline_intensity = DEFAULT_SCANLINE_INTENSITY;
+    let mut headless = false;
+    let mut frames = DEFAULT_HEADLESS_FRAMES;
+    let mut dump_frames = None;
+    let mut dump_format = DumpFormat::default();
+    let mut every_frame = false;
+    let mut max_dumped_frames = None;
+    let mut config_path = None;
+    let mut debug_window = false;
+    let mut log_vf_clobbers = false;
+    let mut log_dirty = false;
+    let mut lint_registers = false;
+    let mut extensions = false;
+    let mut hires = false;
+    let mut port_input = 0;
+    let mut console = false;
+    let mut remote = None;
+    let mut breakpoints = Vec::new();
+    let mut opcode_breaks = Vec::new();
+    let mut key_breaks = Vec::new();
+    let mut log_level = LevelFilter::Info;
+    let mut coverage_out = None;
+    let mut heatmap = false;
+    let mut trace = None;
+    let mut trace_format = String::from("text");
+    let mut compare_trace = None;
+    let mut compare = None;
+    let mut seed = None;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut force = false;
+    let mut strict = false;
+    let mut zip_entry = None;
+    let mut no_cache = false;
+    let mut protect = Vec::new();
+    let mut help = false;
+    let mut script = None;
+    #[cfg(feature = "netplay")]
+    let mut netplay = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--ips" => {
+                let value = iter.next().ok_or("--ips requires a value")?;
+                ips = value.parse().map_err(|_| format!("invalid IPS '{}'", value))?;
+            }
+            "--timer-rate" => {
+                let value = iter.next().ok_or("--timer-rate requires a value")?;
+                timer_rate = value.parse().ok().filter(|&rate| rate > 0).ok_or_else(|| format!("invalid timer rate '{}'", value))?;
+            }
+            "--max-fps" => {
+                let value = iter.next().ok_or("--max-fps requires a value")?;
+                max_fps = value.parse().ok().filter(|&fps| fps > 0).ok_or_else(|| format!("invalid frame rate cap '{}'", value))?;
+            }
+            "--pause-on-blur" => pause_on_blur = true,
+            "--auto-reset" => {
+                let value = iter.next().ok_or("--auto-reset requires a delay in seconds")?;
+                let seconds = value.parse::<f64>().ok().filter(|&seconds| seconds.is_finite() && seconds >= 0.0);
+                auto_reset = Some(Duration::from_secs_f64(seconds.ok_or_else(|| format!("invalid auto reset delay '{}'", value))?));
+            }
+            "--debounce-ms" => {
+                let value = iter.next().ok_or("--debounce-ms requires a value")?;
+                debounce = Some(Duration::from_millis(value.parse().map_err(|_| format!("invalid debounce interval '{}'", value))?));
+            }
+            "--max-frame-ms" => {
+                let value = iter.next().ok_or("--max-frame-ms requires a value")?;
+                let millis = value.parse().ok().filter(|&millis| millis > 0).ok_or_else(|| format!("invalid frame time '{}'", value))?;
+                max_frame_time = Duration::from_millis(millis);
+            }
+            "--auto-ips" => {
+                let value = iter.next().ok_or("--auto-ips requires MIN:MAX")?;
+                let (min, max) = value.split_once(':').ok_or("--auto-ips requires MIN:MAX")?;
+                let min: usize = min.parse().map_err(|_| format!("invalid IPS '{}'", min))?;
+                let max: usize = max.parse().map_err(|_| format!("invalid IPS '{}'", max))?;
+                if min == 0 || min > max {
+                    return Err(format!("invalid IPS range {}:{}", min, max));
+                }
+                tuner = Some(IpsTuner { min, max });
+            }
+            "--max-draws" => {
+                let value = iter.next().ok_or("--max-draws requires a value")?;
+                match value.parse() {
+                    Ok(0) | Err(_) => return Err(format!("invalid draw cap '{}'", value)),
+                    Ok(max) => max_draws_per_frame = Some(max),
+                }
+            }
+            "--sticky-keys" => sticky_keys = true,
+            "--yield-on-poll" => yield_on_poll = true,
+            "--macro" => {
+                let value = iter.next().ok_or("--macro requires NAME:KEY:STEPS")?;
+                macros.push(input::parse_macro(value)?);
+            }
+            "--macro-overlap" => macro_overlap = MacroOverlap::parse(iter.next().ok_or("--macro-overlap requires queue or cancel")?)?,
+            "--turbo" => {
+                let value = iter.next().ok_or("--turbo requires KEY:HZ")?;
+                turbo.push(parse_turbo(value)?);
+            }
+            "--player2" => {
+                let value = iter.next().ok_or("--player2 requires a list of keypad keys, e.g. C,D")?;
+                player2_keys = Some(input::parse_keypad_keys(value)?);
+            }
+            #[cfg(feature = "netplay")]
+            "--netplay" => {
+                let value = iter.next().ok_or("--netplay requires HOST:PORT")?;
+                netplay = Some(NetplayMode::Connect(value.clone()));
+            }
+            #[cfg(feature = "netplay")]
+            "--netplay-listen" => {
+                let value = iter.next().ok_or("--netplay-listen requires a port")?;
+                let port = value.parse().map_err(|_| format!("invalid port '{}'", value))?;
+                netplay = Some(NetplayMode::Listen(port));
+            }
+            "--clip" => quirks.clip_sprites = true,
+            "--script" => script = Some(iter.next().ok_or("--script requires a file")?.clone()),
+            "--load-store-increment" => quirks.load_store_increment = true,
+            "--shift-vy" => quirks.shift_vy = true,
+            "--collision-delay" => quirks.collision_delay = true,
+            "--index-width" => {
+                index_width = match iter.next().map(String::as_str) {
+                    Some("12") => Some(12),
+                    Some("16") => Some(16),
+                    _ => return Err("--index-width requires 12 or 16".to_string()),
+                };
+            }
+            "--adi-overflow-vf" => {
+                quirks.adi_overflow_vf = true;
+                quirks.adi_overflow_width = match iter.next().map(String::as_str) {
+                    Some("12") => 12,
+                    Some("16") => 16,
+                    _ => return Err("--adi-overflow-vf requires the overflow width, 12 or 16".to_string()),
+                };
+            }
+            "--detect-quirks" => detect_quirks = true,
+            "--disasm" => disasm = true,
+            "--auto-quirks" => auto_quirks = true,
+            "--rom-db" => rom_db = Some(iter.next().ok_or("--rom-db requires a file")?.clone()),
+            "--cheat" => {
+                let value = iter.next().ok_or("--cheat requires ADDR=VAL")?;
+                cheats.push(chip8::cheats::parse_assignment(value).ok_or_else(|| format!("invalid cheat '{}'", value))?);
+            }
+            "--cheat-mode" => {
+                cheat_mode = match iter.next().map(String::as_str) {
+                    Some("frame") => ApplyMode::EveryFrame,
+                    Some("instruction") => ApplyMode::EveryInstruction,
+                    _ => return Err("--cheat-mode requires frame or instruction".to_string()),
+                };
+            }
+            "--speedrun" => speedrun = true,
+            "--splits" => {
+                splits_path = Some(iter.next().ok_or("--splits requires a file")?.clone());
+                speedrun = true;
+            }
+            "--record-video" => record_video = Some(iter.next().ok_or("--record-video requires a file")?.clone()),
+            "--ffmpeg" => ffmpeg = iter.next().ok_or("--ffmpeg requires a path")?.clone(),
+            "--record-scale" => {
+                let value = iter.next().ok_or("--record-scale requires a value")?;
+                record_scale = value.parse().ok().filter(|&scale| scale > 0).ok_or_else(|| format!("invalid scale '{}'", value))?;
+            }
+            "--waveform" => {
+                let value = iter.next().ok_or("--waveform requires square, sine, triangle or noise")?;
+                waveform = Waveform::parse(value).ok_or_else(|| format!("unknown waveform '{}'", value))?;
+            }
+            "--scanlines" => scanlines = true,
+            "--pixel-aspect" => {
+                let value = iter.next().ok_or("--pixel-aspect requires W:H")?;
+                let aspect = value.split_once(':').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                pixel_aspect = aspect.filter(|&(w, h)| w > 0 && h > 0).ok_or_else(|| format!("invalid pixel aspect '{}', expected W:H", value))?;
+            }
+            "--scanline-intensity" => {
+                let value = iter.next().ok_or("--scanline-intensity requires a percentage")?;
+                scanline_intensity = value.parse().ok().filter(|&percent| percent <= 100)
+                    .ok_or_else(|| format!("invalid scanline intensity '{}', expected 0-100", value))?;
+            }
+            "--raw-video" => raw_video = Some(iter.next().ok_or("--raw-video requires a file")?.clone()),
+            "--headless" => headless = true,
+            "--frames" => {
+                let value = iter.next().ok_or("--frames requires a value")?;
+                frames = value.parse().map_err(|_| format!("invalid frame count '{}'", value))?;
+            }
+            "--dump-format" => {
+                let value = iter.next().ok_or("--dump-format requires png, pbm or xbm")?;
+                dump_format = DumpFormat::parse(value).ok_or_else(|| format!("unknown dump format '{}'", value))?;
+            }
+            "--dump-frames" => dump_frames = Some(iter.next().ok_or("--dump-frames requires a directory")?.clone()),
+            "--every-frame" => every_frame = true,
+            "--max-dumped-frames" => {
+                let value = iter.next().ok_or("--max-dumped-frames requires a value")?;
+                max_dumped_frames = Some(value.parse().map_err(|_| format!("invalid frame count '{}'", value))?);
+            }
+            "--config" => config_path = Some(iter.next().ok_or("--config requires a file")?.clone()),
+            "--debug-window" => debug_window = true,
+            "--log-vf-clobbers" => log_vf_clobbers = true,
+            "--log-dirty" => log_dirty = true,
+            "--lint-registers" => lint_registers = true,
+            "--enable-extensions" => extensions = true,
+            "--hires" => hires = true,
+            "--port-input" => {
+                let value = iter.next().ok_or("--port-input requires a value")?;
+                port_input = value.parse().map_err(|_| format!("invalid port input '{}', expected 0-255", value))?;
+            }
+            "--console" => console = true,
+            "--remote" => {
+                let value = iter.next().ok_or("--remote requires a port")?;
+                remote = Some(value.parse().map_err(|_| format!("invalid port '{}'", value))?);
+            }
+            "--log-level" => log_level = parse_log_level(iter.next().ok_or("--log-level requires error, warn, info or debug")?)?,
+            "--heatmap" => heatmap = true,
+            "--coverage-out" => coverage_out = Some(iter.next().ok_or("--coverage-out requires a file")?.clone()),
+            "--trace" => trace = Some(iter.next().ok_or("--trace requires a file, or - for stdout")?.clone()),
+            "--compare-trace" => compare_trace = Some(iter.next().ok_or("--compare-trace requires a file")?.clone()),
+            "--compare" => {
+                let (Some(first), Some(second)) = (iter.next(), iter.next()) else {
+                    return Err("--compare requires two platforms".to_string());
+                };
+                compare = Some([Platform::parse(first)?, Platform::parse(second)?]);
+            }
+            "--trace-format" => {
+                let value = iter.next().ok_or("--trace-format requires text, csv or octo")?;
+                trace::format_for(value)?;
+                trace_format = value.clone();
+            }
+            "--seed" => {
+                let value = iter.next().ok_or("--seed requires a value")?;
+                seed = Some(value.parse().map_err(|_| format!("invalid seed '{}'", value))?);
+            }
+            "--record-movie" => record_movie = Some(iter.next().ok_or("--record-movie requires a file")?.clone()),
+            "--play-movie" => play_movie = Some(iter.next().ok_or("--play-movie requires a file")?.clone()),
+            "--force" => force = true,
+            "--strict" => strict = true,
+            "--no-cache" => no_cache = true,
+            "--zip-entry" => zip_entry = Some(iter.next().ok_or("--zip-entry requires an entry name")?.clone()),
+            "--protect" => {
+                let value = iter.next().ok_or("--protect requires START:END")?;
+                protect.push(parse_region(value).ok_or_else(|| format!("invalid region '{}', expected START:END in hex", value))?);
+            }
+            "--break" => {
+                let value = iter.next().ok_or("--break requires ADDR [if CONDITION]")?;
+                breakpoints.push(breakpoints::parse(value)?);
+            }
+            "--break-op" => {
+                let value = iter.next().ok_or("--break-op requires a pattern like DXYN or 'mask=F000 value=D000'")?;
+                opcode_breaks.push(breakpoints::parse_opcode(value)?);
+            }
+            "--break-key" => {
+                let value = iter.next().ok_or("--break-key requires KEY|any [press]")?;
+                key_breaks.push(breakpoints::parse_key(value)?);
+            }
+            "--help" | "-h" => help = true,
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if help {
+        rom_path.get_or_insert_with(String::new);
+    }
+    if console && remote.is_some() {
+        return Err("--console and --remote can't be combined".to_string());
+    }
+
+    // Speed changes on one side only would desync the peers
+    #[cfg(feature = "netplay")]
+    if netplay.is_some() && tuner.is_some() {
+        return Err("--auto-ips can't be combined with netplay".to_string());
+    }
+    #[cfg(feature = "netplay")]
+    if netplay.is_some() && timer_rate != FRAME_RATE as u32 {
+        return Err("--timer-rate can't be changed during netplay".to_string());
+    }
+    // So would editing one side's machine
+    #[cfg(feature = "netplay")]
+    if netplay.is_some() && (console || remote.is_some()) {
+        return Err("--console and --remote can't be combined with netplay".to_string());
+    }
+    // The peer's keys aren't part of a movie
+    #[cfg(feature = "netplay")]
+    if netplay.is_some() && (record_movie.is_some() || play_movie.is_some()) {
+        return Err("movies can't be recorded or played during netplay".to_string());
+    }
+
+    Ok(Config {
+        rom_path: rom_path.ok_or("missing ROM path")?,
+        ips,
+        timer_rate,
+        max_fps,
+        auto_reset,
+        debounce,
+        pause_on_blur,
+        sticky_keys,
+        turbo,
+        yield_on_poll,
+        macros,
+        macro_overlap,
+        max_frame_time,
+        tuner,
+        max_draws_per_frame,
+        player2_keys,
+        quirks,
+        index_width,
+        detect_quirks,
+        disasm,
+        auto_quirks,
+        rom_db,
+        cheats,
+        cheat_mode,
+        speedrun,
+        splits_path,
+        record_video,
+        ffmpeg,
+        record_scale,
+        raw_video,
+        waveform,
+        scanlines: scanlines.then_some(scanline_intensity),
+        pixel_aspect,
+        headless,
+        frames,
+        dump_frames,
+        dump_format,
+        every_frame,
+        max_dumped_frames,
+        config_path,
+        debug_window,
+        log_vf_clobbers,
+        log_dirty,
+        lint_registers,
+        extensions,
+        hires,
+        port_input,
+        console,
+        remote,
+        breakpoints,
+        opcode_breaks,
+        key_breaks,
+        log_level,
+        coverage_out,
+        heatmap,
+        trace,
+        trace_format,
+        compare_trace,
+        compare,
+        seed,
+        record_movie,
+        play_movie,
+        force,
+        strict,
+        zip_entry,
+        no_cache,
+        protect,
+        help,
+        script,
+        #[cfg(feature = "netplay")]
+        netplay,
+    })
+}
+
+// Options from the config file named by --config, with the command line on top
+fn resolve_config(args: &[String]) -> Result<Config, String> {
+    let config = parse_args(args)?;
+    let Some(path) = &config.config_path else {
+        return Ok(config);
+    };
+    let mut merged = configfile::read_args(Path::new(path))?;
+    merged.extend_from_slice(args);
+    parse_args(&merged)
+}
+
+// Turbo binding KEY:HZ, a hex keypad key and how many times a second it pulses
+fn parse_turbo(value: &str) -> Result<(u8, u32), String> {
+    let invalid = || format!("invalid turbo binding '{}', expected KEY:HZ", value);
+    let (key, hz) = value.split_once(':').ok_or_else(invalid)?;
+    let key = match input::parse_keypad_keys(key)?[..] {
+        [key] if key <= 0xF => key,
+        [key] => return Err(format!("invalid keypad key {:#X}", key)),
+        _ => return Err(invalid()),
+    };
+    match hz.trim().parse() {
+        Ok(hz) if hz > 0 => Ok((key, hz)),
+        _ => Err(invalid()),
+    }
+}
+
+// Player 1 always, player 2 when it has keys bound
+fn build_profiles(config: &Config) -> Result<Vec<InputProfile>, String> {
+    let mut profiles = vec![InputProfile::player1()];
+    if let Some(keys) = &config.player2_keys {
+        profiles.push(InputProfile::player2(keys)?);
+    }
+    Ok(profiles)
+}
+
+fn build_breakpoints(config: &Config) -> Breakpoints {
+    let mut breakpoints = Breakpoints::new();
+    for breakpoint in &config.breakpoints {
+        breakpoints.add(breakpoint.clone());
+    }
+    for &pattern in &config.opcode_breaks {
+        breakpoints.add_pattern(pattern);
+    }
+    for &key_break in &config.key_breaks {
+        breakpoints.add_key(key_break);
+    }
+    breakpoints
+}
+
+// Parts of the frontend a config reload touched
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ConfigChanges {
+    speed: bool,                        // IPS, auto tuning, draw cap, timer rate or present cap, applied at the next frame
+    keys: bool,                         // Player 2 bindings, applied from the next event
+    audio: bool,                        // Buzzer waveform
+    overlay: bool,                      // Speedrun timer and splits file, redrawn now
+    restart_required: bool,             // Changes that only take effect on the next start, left as they were
+}
+
+// A protected memory region, START:END in hex with END exclusive, "50:A0" for the font
+fn parse_region(value: &str) -> Option<Range<usize>> {
+    let hex = |text: &str| usize::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok();
+    let (start, end) = value.split_once(':')?;
+    let (start, end) = (hex(start)?, hex(end)?);
+    (start < end).then_some(start..end)
+}
+
+// Take the options that can change while running from a freshly resolved config and report what changed
+fn apply_config(config: &mut Config, new: Config) -> ConfigChanges {
+    let changes = ConfigChanges {
+        speed: config.ips != new.ips || config.tuner != new.tuner || config.max_draws_per_frame != new.max_draws_per_frame
+            || config.timer_rate != new.timer_rate || config.max_fps != new.max_fps || config.auto_reset != new.auto_reset
+            || config.debounce != new.debounce,
+        keys: config.player2_keys != new.player2_keys || config.sticky_keys != new.sticky_keys
+            || config.macros != new.macros || config.macro_overlap != new.macro_overlap,
+        audio: config.waveform != new.waveform,
+        overlay: config.speedrun != new.speedrun || config.splits_path != new.splits_path,
+        restart_required: config.rom_path != new.rom_path
+            || config.quirks != new.quirks
+            || config.index_width != new.index_width
+            || config.auto_quirks != new.auto_quirks
+            || config.rom_db != new.rom_db
+            || config.cheats != new.cheats
+            || config.breakpoints != new.breakpoints
+            || config.opcode_breaks != new.opcode_breaks
+            || config.key_breaks != new.key_breaks
+            || config.dump_frames != new.dump_frames
+            || config.dump_format != new.dump_format
+            || config.every_frame != new.every_frame
+            || config.max_dumped_frames != new.max_dumped_frames
+            || config.lint_registers != new.lint_registers
+            || config.extensions != new.extensions
+            || config.hires != new.hires
+            || config.port_input != new.port_input
+            || config.heatmap != new.heatmap
+            || config.console != new.console
+            || config.raw_video != new.raw_video
+            || config.trace != new.trace
+            || config.trace_format != new.trace_format
+            || config.remote != new.remote
+            || config.compare_trace != new.compare_trace
+            || config.compare != new.compare
+            || config.seed != new.seed
+            || config.record_movie != new.record_movie
+            || config.play_movie != new.play_movie
+            || config.force != new.force
+            || config.strict != new.strict
+            || config.zip_entry != new.zip_entry
+            || config.no_cache != new.no_cache
+            || config.protect != new.protect
+            || restart_required_netplay(config, &new),
+    };
+
+    config.ips = new.ips;
+    config.timer_rate = new.timer_rate;
+    config.max_fps = new.max_fps;
+    config.auto_reset = new.auto_reset;
+    config.debounce = new.debounce;
+    config.pause_on_blur = new.pause_on_blur;
+    config.max_frame_time = new.max_frame_time;
+    config.tuner = new.tuner;
+    config.max_draws_per_frame = new.max_draws_per_frame;
+    config.player2_keys = new.player2_keys;
+    config.sticky_keys = new.sticky_keys;
+    config.turbo = new.turbo;
+    config.yield_on_poll = new.yield_on_poll;
+    config.macros = new.macros;
+    config.macro_overlap = new.macro_overlap;
+    config.waveform = new.waveform;
+    config.scanlines = new.scanlines;
+    config.pixel_aspect = new.pixel_aspect;
+    config.speedrun = new.speedrun;
+    config.splits_path = new.splits_path;
+    config.cheat_mode = new.cheat_mode;
+    config.record_video = new.record_video;
+    config.ffmpeg = new.ffmpeg;
+    config.record_scale = new.record_scale;
+    config.log_vf_clobbers = new.log_vf_clobbers;
+    config.log_dirty = new.log_dirty;
+    config.log_level = new.log_level;
+    config.coverage_out = new.coverage_out;
+    log::set_max_level(config.log_level);
+    changes
+}
+
+// The ROM in a file, or the one inside it when it is a ZIP archive, --zip-entry picking between several
+#[cfg(feature = "zip")]
+fn unpack_rom(config: &Config, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !chip8::zip::is_zip(&data) {
+        return Ok(data);
+    }
+    chip8::zip::extract_rom(&data, config.zip_entry.as_deref())
+}
+
+#[cfg(not(feature = "zip"))]
+fn unpack_rom(_config: &Config, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    Ok(data)
+}
+
+#[cfg(feature = "netplay")]
+fn restart_required_netplay(config: &Config, new: &Config) -> bool {
+    config.netplay != new.netplay
+}
+
+#[cfg(not(feature = "netplay"))]
+fn restart_required_netplay(_config: &Config, _new: &Config) -> bool {
+    false
+}
+
+// Display and Input Setup as well as emulation loop
+fn run(chip8: &mut Chip8, config: &mut Config, args: &[String], title: &str, profiles: &mut Vec<InputProfile>, cheats: &mut CheatManager, capture: &mut Capture) -> Result<(), String> {
+    let Capture { tracer, movie, script } = capture;
+    // Video Render
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let window = video_subsystem.window(title, (WIDTH * 10) as u32, (HEIGHT * 10) as u32)
+        .position_centered()
+        .build()
+        .expect("could not initialize video subsystem");
+
+    let mut canvas = window.into_canvas().build()
+        .expect("could not make a canvas");
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+    let mut event_pump = sdl_context.event_pump()?;
+    let game_id = canvas.window().id();
+    let mut debug_window = if config.debug_window { Some(DebugWindow::open(&video_subsystem)?) } else { None };
+    let mut beeper = Beeper::new(&sdl_context, config.waveform);
+
+    #[cfg(feature = "netplay")]
+    let mut netplay = connect_netplay(chip8, config, canvas.window_mut())?;
+
+    // Resetting one side of a netplay session would desync it
+    #[cfg(feature = "netplay")]
+    let allow_reset = netplay.is_none();
+    #[cfg(not(feature = "netplay"))]
+    let allow_reset = true;
+
+    let mut ips = config.ips;
+    let mut dumper = match &config.dump_frames {
+        Some(dir) => Some(FrameDumper::new(Path::new(dir), config.dump_format, config.every_frame, config.max_dumped_frames)?),
+        None => None,
+    };
+    let mut splits = Splits::default();
+    let mut was_halted = false;
+    let mut was_looping = false;
+    let mut breakpoints = build_breakpoints(config);
+    let console = open_console(config)?;
+    let mut watches = Watches::new();
+    let mut was_paused = false;
+    let mut frame_steps = 0;                            // F8 presses waiting to advance a paused frame
+    let mut slot = 0;
+    let mut picker: Option<StatePicker> = None;
+    let mut input = InputState::default();
+    let mut toast: Option<(String, u64)> = None;    // Message and the frame it disappears on
+    let mut watcher = config.config_path.as_ref().map(|path| configfile::Watcher::new(Path::new(path)));
+
+    // Recording starts with the emulator, F9 stops it and starts a new numbered file
+    let mut recordings = 0;
+    let mut recorder = match &config.record_video {
+        Some(path) => Some(start_recording(config, path, &mut recordings)?),
+        None => None,
+    };
+    let mut raw_video = start_raw_video(config)?;
+    let mut pacer = Pacer::new(config.timer_rate, config.max_fps);
+    let mut halt_timer = config.auto_reset.map(HaltTimer::new);
+    let mut debouncer = config.debounce.map(Debouncer::new);
+    let mut sticky = config.sticky_keys.then(StickyKeys::new);
+    let mut macros = MacroPlayer::new(config.macro_overlap);
+    let mut focus = FocusPause::new();
+    let started = Instant::now();
+    let mut last_pace = Instant::now();
+
+    // Game Loop
+    'running: loop {
+
+        // Event Handler, frontend hotkeys first and the rest reduced into the input state
+        for event in event_pump.poll_iter() {
+            // Focus moving between the game and debug windows loses and regains it within one poll
+            if let Event::Window { win_event: win_event @ (WindowEvent::FocusGained | WindowEvent::FocusLost), .. } = event {
+                focus.update(win_event == WindowEvent::FocusGained, config.pause_on_blur, &mut input);
+                chip8.draw_flag = true;
+            }
+            match debugger::route(&event, game_id, debug_window.as_ref().map(DebugWindow::id)) {
+                Route::Game => {}
+                Route::Debug => {
+                    if let Some(window) = &mut debug_window {
+                        window.handle(&event, chip8);
+                    }
+                    continue;
+                }
+                Route::ToggleKeyBreak(key) => {
+                    let watched = breakpoints.toggle_key(key);
+                    println!("Key break on {:X} {}", key, if watched { "on" } else { "off" });
+                    continue;
+                }
+                Route::ToggleInspector => {
+                    if let Some(window) = &mut debug_window {
+                        println!("Pixel inspector {}", if window.toggle_inspector() { "on" } else { "off" });
+                    }
+                    continue;
+                }
+                Route::ToggleHeatmap => {
+                    if let Some(window) = &mut debug_window {
+                        let shown = window.toggle_heatmap();
+                        if shown && chip8.heatmap().is_none() {
+                            chip8.set_heatmap(true);    // Counting starts when the heatmap is first shown
+                        }
+                        println!("Memory heatmap {}", if shown { "on" } else { "off" });
+                    }
+                    continue;
+                }
+                Route::ToggleBitmap => {
+                    if let Some(window) = &mut debug_window {
+                        println!("Memory bitmap {}", if window.toggle_bitmap() { "on" } else { "off" });
+                    }
+                    continue;
+                }
+                Route::CloseDebug => {
+                    debug_window = None;
+                    continue;
+                }
+                Route::Quit => {
+                    input.quit = true;
+                    continue;
+                }
+            }
+
+            match event {
+                // Taken from the core's buffer, so it works while paused or in the state picker
+                Event::KeyDown { keycode: Some(Keycode::C), keymod, repeat: false, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    let message = match clipboard::copy_screen(&chip8.display, &video_subsystem) {
+                        Ok(copied) => copied.to_string(),
+                        Err(err) => {
+                            error!("{}", err);
+                            format!("copy failed: {}", err)
+                        }
+                    };
+                    toast = Some((message, chip8.frame_count() + TOAST_FRAMES));
+                    chip8.draw_flag = true;
+                },
+                Event::KeyDown { keycode: Some(key), keymod, ..} if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    toggle_cheat(cheats, key);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    let target = picker.as_ref().map_or(slot, |picker| picker.selected);
+                    let path = savestate::path_for(Path::new(STATE_DIR), chip8.rom_hash(), target);
+                    match savestate::save(chip8, &path, movie.as_ref().map(MovieSession::cursor)) {
+                        Ok(()) => println!("Saved state to slot {}", target + 1),
+                        Err(err) => error!("{}", err),
+                    }
+                    slot = target;
+                    if picker.is_some() {
+                        picker = Some(StatePicker::open(chip8.rom_hash(), slot));
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } if allow_reset => {
+                    picker = match picker {
+                        Some(_) => None,
+                        None => Some(StatePicker::open(chip8.rom_hash(), slot)),
+                    };
+                    chip8.draw_flag = true;
+                },
+                Event::KeyDown { keycode: Some(key), .. } if picker.is_some() && key != Keycode::Escape => {
+                    let open = picker.as_mut().unwrap();
+                    match key {
+                        Keycode::Left => open.selected = (open.selected + STATE_SLOTS - 1) % STATE_SLOTS,
+                        Keycode::Right => open.selected = (open.selected + 1) % STATE_SLOTS,
+                        Keycode::Return => {
+                            let path = savestate::path_for(Path::new(STATE_DIR), chip8.rom_hash(), open.selected);
+                            match load_state(chip8, &path, movie) {
+                                Ok(()) => {
+                                    println!("Loaded state from slot {}", open.selected + 1);
+                                    slot = open.selected;
+                                    picker = None;
+                                }
+                                Err(err) => error!("{}", err),
+                            }
+                        }
+                        _ => {}
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } if config.record_video.is_some() => {
+                    match recorder.take() {
+                        Some(active) => finish_recording(active),
+                        None => recorder = Some(start_recording(config, config.record_video.as_ref().unwrap(), &mut recordings)?),
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } if movie.as_ref().is_some_and(|session| !session.is_recording()) => {
+                    let session = movie.as_mut().unwrap();
+                    session.rerecord();
+                    println!("Re-recording from movie frame {}", session.position());
+                    chip8.draw_flag = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    debug_window = match debug_window.take() {
+                        Some(_) => None,
+                        None => DebugWindow::open(&video_subsystem).map_err(|err| error!("{}", err)).ok(),
+                    };
+                },
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } if input.pause && picker.is_none() && allow_reset => {
+                    frame_steps += 1;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    let path = format!("screenshot_{:06}.{}", chip8.frame_count(), config.dump_format.extension());
+                    match framedump::save_frame(chip8, config.dump_format, Path::new(&path)) {
+                        Ok(()) => println!("Saved screenshot {}", path),
+                        Err(err) => error!("{}", err),
+                    }
+                },
+                // The pixel inspector follows the mouse over the game, a left click prints the pixel
+                Event::MouseMotion { window_id, x, y, .. } if window_id == game_id && debug_window.as_ref().is_some_and(DebugWindow::is_inspecting) => {
+                    debug_window.as_mut().unwrap().hover(screen_layout(&canvas, chip8.resolution(), config.pixel_aspect).pixel_at(x, y));
+                },
+                Event::MouseButtonDown { window_id, mouse_btn: MouseButton::Left, x, y, .. }
+                    if window_id == game_id && debug_window.as_ref().is_some_and(DebugWindow::is_inspecting) => {
+                    if let Some((px, py)) = screen_layout(&canvas, chip8.resolution(), config.pixel_aspect).pixel_at(x, y) {
+                        info!("{}", debugger::describe_pixel(chip8, px, py));
+                        println!("x={},y={}", px, py);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } if sticky.is_some() => {
+                    sticky.as_mut().unwrap().clear();
+                    println!("Latched keys released");
+                },
+                Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } if config.speedrun => {
+                    splits.record(chip8.frame_count());
+                },
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } if config.macros.iter().any(|binding| binding.key == key) => {
+                    for binding in config.macros.iter().filter(|binding| binding.key == key) {
+                        let queued = macros.is_playing() && config.macro_overlap == MacroOverlap::Queue;
+                        macros.trigger(&binding.steps);
+                        println!("Macro {} {}", binding.name, if queued { "queued" } else { "started" });
+                    }
+                },
+                _ => input::reduce(&mut input, &event, profiles),
+            }
+        }
+        input.keys = frame_keys(profiles, &mut debouncer, &mut sticky, script, started.elapsed());
+
+        if input.quit {
+            break 'running;
+        }
+        if let Some(window) = &mut debug_window {
+            window.set_latched(sticky.map_or(0, |sticky| sticky.latched()));
+            window.set_scope(beeper.scope());
+            window.set_pattern_position(beeper.pattern_position());
+            window.draw(chip8, &breakpoints, &watches)?;
+        }
+
+        // Reload the config file when it changes, a broken file keeps the running options
+        if watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            let message = match resolve_config(args) {
+                Ok(new) => {
+                    let changes = apply_config(config, new);
+                    if changes.speed {
+                        ips = config.ips;
+                        pacer = Pacer::new(config.timer_rate, config.max_fps);
+                        halt_timer = config.auto_reset.map(HaltTimer::new);
+                        debouncer = config.debounce.map(Debouncer::new);
+                    }
+                    if changes.keys {
+                        match build_profiles(config) {
+                            Ok(rebuilt) => *profiles = rebuilt,
+                            Err(err) => error!("{}", err),
+                        }
+                        sticky = config.sticky_keys.then(StickyKeys::new);
+                        macros = MacroPlayer::new(config.macro_overlap);
+                    }
+                    if changes.audio {
+                        beeper = Beeper::new(&sdl_context, config.waveform);
+                    }
+                    if changes.restart_required { "config reloaded, restart required".to_string() } else { "config reloaded".to_string() }
+                }
+                Err(err) => {
+                    error!("{}", err);
+                    format!("config error: {}", err)
+                }
+            };
+            toast = Some((message, chip8.frame_count() + TOAST_FRAMES));
+            chip8.draw_flag = true;
+        }
+        if toast.as_ref().is_some_and(|(_, until)| chip8.frame_count() >= *until) {
+            toast = None;
+            chip8.draw_flag = true;
+        }
+        if input.reset {
+            input.reset = false;
+            if allow_reset {
+                chip8.reset();
+                splits.clear();
+            }
+        }
+
+        // Unpausing after a breakpoint steps over it
+        if was_paused && !input.pause {
+            breakpoints.resume();
+        }
+        was_paused = input.pause;
+        if console.as_ref().is_some_and(|console| console.run_pending(chip8, &mut breakpoints, &mut watches)) {
+            chip8.draw_flag = true;         // Show edits to the display memory or state while paused
+        }
+
+        // The emulation is paused while picking a state to load or when paused with P
+        if picker.is_some() || input.pause {
+            beeper.set_beeping(false);
+            match &picker {
+                Some(picker) => draw_state_picker(&mut canvas, picker, config.pixel_aspect)?,
+                None => {
+                    // Frame stepping: the held keys, one frame of instructions and a single timer tick per F8
+                    for _ in 0..std::mem::take(&mut frame_steps) {
+                        let keys = frontend::apply_turbo(input.keys, &config.turbo, chip8.frame_count(), config.timer_rate) | macros.next_frame();
+                        InputState { keys: movie_keys(movie, keys), ..input }.apply(chip8);
+                        for _ in 0..(ips / config.timer_rate as usize).max(1) {
+                            trace_cycle(chip8, tracer);
+                        }
+                        chip8.tick_timers();
+                        refresh_watches(&mut watches, console.as_ref(), chip8);
+                    }
+                    let picture = draw_display(&mut canvas, chip8, config.scanlines, config.pixel_aspect)?;
+                    draw_paused_overlay(&mut canvas, picture)?;
+                    if let Some(session) = movie {
+                        draw_movie_overlay(&mut canvas, session, picture)?;
+                    }
+                }
+            }
+            canvas.present();
+            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / FRAME_RATE as u32));
+            last_pace = Instant::now();     // Time spent paused isn't caught up on
+            continue 'running;
+        }
+
+        // The timers and the screen keep their own cadence: as many frames as are due, then a present if one is
+        let now = Instant::now();
+        let elapsed = frontend::clamp_frame_time(now - last_pace, config.max_frame_time);
+        let pacing = pacer.advance(elapsed);
+        last_pace = now;
+        for _ in 0..pacing.ticks {
+            // Turbo pulses and macros play before anything sees the keys, so movies and the netplay peer get
+            // the keys as the core does
+            let local = frontend::apply_turbo(input.keys, &config.turbo, chip8.frame_count(), config.timer_rate) | macros.next_frame();
+
+            // In netplay the core only advances once the peer's keys for this frame are in
+            #[cfg(feature = "netplay")]
+            let keys = match netplay.as_mut().map(|netplay| netplay.exchange(local)) {
+                Some(Ok(remote)) => {
+                    canvas.window_mut().set_title(title).map_err(|e| e.to_string())?;
+                    local | remote
+                }
+                Some(Err(err)) => {
+                    canvas.window_mut().set_title(&format!("{} - paused, {}", title, err)).map_err(|e| e.to_string())?;
+                    continue 'running;
+                }
+                None => local,
+            };
+            #[cfg(not(feature = "netplay"))]
+            let keys = local;
+            InputState { keys: movie_keys(movie, keys), ..input }.apply(chip8);
+
+            let report = run_frame(chip8, config, cheats, &mut breakpoints, &mut ips, tracer, script);
+            refresh_watches(&mut watches, console.as_ref(), chip8);
+            warn_looping(chip8, report, ips, &mut was_looping);
+            beeper.set_beeping(chip8.is_beeping() && !focus.muted());
+            beeper.set_pattern(chip8.audio_pattern(), chip8.pitch());
+
+            if let Some(active) = &mut dumper {
+                if !active.frame(chip8)? {
+                    dumper = None;
+                }
+            }
+
+            // One video frame per emulated frame, so the recording plays at emulated speed
+            raw_video_frame(&mut raw_video, chip8);
+            if let Some(active) = &mut recorder {
+                if let Err(err) = active.frame(chip8) {
+                    error!("{}", err);
+                    finish_recording(recorder.take().unwrap());
+                }
+            }
+
+            if report.breakpoint.is_some() {
+                input.pause = true;
+                chip8.draw_flag = true;
+                break;
+            }
+        }
+
+        // Redraw screen if it has been updated, the speedrun and movie overlays change every frame
+        let halted = chip8.halted();
+        if pacing.present && (chip8.draw_flag || config.speedrun || movie.is_some() || halted != was_halted) {
+            let picture = draw_display(&mut canvas, chip8, config.scanlines, config.pixel_aspect)?;
+            if chip8.grid_overlay() {
+                draw_grid_overlay(&mut canvas, chip8, picture)?;
+            }
+
+            if config.speedrun {
+                draw_speedrun_overlay(&mut canvas, chip8, &splits, picture)?;
+            }
+            if halted {
+                draw_halted_overlay(&mut canvas, picture)?;
+            }
+            if let Some(session) = movie {
+                draw_movie_overlay(&mut canvas, session, picture)?;
+            }
+            if let Some((message, _)) = &toast {
+                draw_toast(&mut canvas, message, picture)?;
+            }
+
+            chip8.draw_flag = false;    // Reset the draw flag
+            canvas.present();           // Copy to output display
+            was_halted = halted;
+        }
+
+        // A ROM left halted for the --auto-reset delay starts over, for demos and kiosks
+        if allow_reset && halt_timer.as_mut().is_some_and(|timer| timer.update(halted, elapsed)) {
+            chip8.reset();
+            splits.clear();
+        }
+
+        // Sleep until the next tick or present is due
+        ::std::thread::sleep(pacer.until_next());
+    }
+
+    if let Some(active) = recorder {
+        finish_recording(active);
+    }
+    finish_raw_video(raw_video);
+    if let Some(path) = &config.splits_path {
+        splits.dump(path)?;
+    }
+    Ok(())
+}
+
+// Raw video stream when asked for, the format is printed since the file has no header
+fn start_raw_video(config: &Config) -> Result<Option<RawVideo>, String> {
+    let Some(path) = &config.raw_video else {
+        return Ok(None);
+    };
+    let raw = RawVideo::create(Path::new(path), config.record_scale)?;
+    info!("Writing raw video to {}: rgb24, {}, {} frames per second", path, raw.video_size(), config.timer_rate);
+    Ok(Some(raw))
+}
+
+// Append the frame to the raw video, a stream that fails is reported and closed
+fn raw_video_frame(raw_video: &mut Option<RawVideo>, chip8: &Chip8) {
+    if let Some(active) = raw_video {
+        if let Err(err) = active.frame(chip8) {
+            error!("{}", err);
+            *raw_video = None;
+        }
+    }
+}
+
+fn finish_raw_video(raw_video: Option<RawVideo>) {
+    if let Some(active) = raw_video {
+        match active.finish() {
+            Ok(frames) => println!("Wrote {} raw video frames", frames),
+            Err(err) => error!("{}", err),
+        }
+    }
+}
+
+// Start recording to the given file, or for later recordings to a numbered file next to it
+fn start_recording(config: &Config, path: &str, recordings: &mut usize) -> Result<Recorder, String> {
+    *recordings += 1;
+    let path = Path::new(path);
+    let path = if *recordings == 1 {
+        path.to_path_buf()
+    } else {
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+        path.with_file_name(format!("{}-{}{}", stem, recordings, extension))
+    };
+    let recorder = Recorder::start(&config.ffmpeg, &path, config.record_scale, config.waveform)?;
+    println!("Recording video to {}", path.display());
+    Ok(recorder)
+}
+
+fn finish_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok(path) => println!("Saved video to {}", path.display()),
+        Err(err) => error!("{}", err),
+    }
+}
+
+// CHIP-8 screen scaled up to the window, pixels of the given aspect with black bars around the picture
+// With scanlines the screen is upscaled into a texture as tall as the picture and every other row dimmed
+// by the given percent. Returns the picture rect for the overlays drawn on top
+fn draw_display(canvas: &mut Canvas<Window>, chip8: &Chip8, scanlines: Option<u8>, aspect: (u32, u32)) -> Result<Rect, String> {
+    let picture = picture_rect(canvas, chip8.resolution(), aspect);
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
 
-    if args.len() != 2 {
-        eprintln!("Error Usage: {} <rom_path>", args[0]);
-        std::process::exit(1);
+    if let Some(intensity) = scanlines {
+        let (width, height) = chip8.resolution();
+        let scale = (picture.height() as usize / height).max(1);
+        let mut frame = video::rgb_frame(&chip8.display, scale);
+        video::apply_scanlines(&mut frame, width * scale, intensity);
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_static(PixelFormatEnum::RGB24, (width * scale) as u32, (height * scale) as u32)
+            .map_err(|err| err.to_string())?;
+        texture.update(None, &frame, width * scale * 3).map_err(|err| err.to_string())?;
+        canvas.copy(&texture, None, picture)?;
+        return Ok(picture);
     }
 
-    let mut chip8 = chip8::Chip8::new();
-    let _ = chip8.load_rom(&args[1]);
-    let _ = chip8.run();
+    draw_picture(canvas, chip8, picture)?;
+    Ok(picture)
+}
 
+// The display at native resolution, 64x32 or 128x64, scaled by the renderer into picture. CHIP-8X ROMs
+// are shown in the color board's palettes
+fn draw_picture(canvas: &mut Canvas<Window>, chip8: &Chip8, picture: Rect) -> Result<(), String> {
+    let (width, height) = chip8.resolution();
+    let mut frame = Vec::new();
+    match chip8.quirks.variant {
+        Variant::Chip8X => chip8.render_chip8x_rgba(&chip8x::FOREGROUND_RGBA, &chip8x::BACKGROUND_RGBA, 1, &mut frame),
+        Variant::Standard | Variant::Chip8E => chip8.render_rgba(render::WHITE, render::BLACK, 1, &mut frame),
+    }
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_static(PixelFormatEnum::RGBA32, width as u32, height as u32)
+        .map_err(|err| err.to_string())?;
+    texture.update(None, &frame, width * 4).map_err(|err| err.to_string())?;
+    canvas.copy(&texture, None, picture)
+}
+
+// Both sides of a --compare run next to each other, each under a label naming its profile. The labels turn
+// red from the frame the pictures first differ on
+fn draw_comparison(canvas: &mut Canvas<Window>, comparison: &Comparison, toast: Option<&str>) -> Result<(), String> {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    let color = match comparison.diverged() {
+        Some(_) => Color::RGB(255, 64, 64),
+        None => Color::RGB(64, 255, 64),
+    };
+    for (side, (chip8, platform)) in comparison.cores.iter().zip(comparison.platforms).enumerate() {
+        let left = side as i32 * (WIDTH * 10 + COMPARE_GAP) as i32;
+        draw_picture(canvas, chip8, Rect::new(left, COMPARE_LABEL as i32, (WIDTH * 10) as u32, (HEIGHT * 10) as u32))?;
+        overlay::draw_text(canvas, &format!("{} FRAME {}", platform, chip8.frame_count()), left + 4, 4, 2, color)?;
+    }
+    if let Some(message) = toast {
+        let y = (COMPARE_LABEL + HEIGHT * 10 - overlay::GLYPH_HEIGHT * 2 - 4) as i32;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.fill_rect(Rect::new(0, y - 4, (WIDTH * 20 + COMPARE_GAP) as u32, (overlay::GLYPH_HEIGHT * 2 + 8) as u32))?;
+        overlay::draw_text(canvas, message, 4, y, 2, Color::RGB(255, 200, 0))?;
+    }
+    Ok(())
+}
+
+// Lines between the CHIP-8 pixels of the picture, turned on and off by ROMs through the 0FFF extension
+fn draw_grid_overlay(canvas: &mut Canvas<Window>, chip8: &Chip8, picture: Rect) -> Result<(), String> {
+    let (width, height) = (picture.width() as i64, picture.height() as i64);
+    let (columns, rows) = (chip8.resolution().0 as i64, chip8.resolution().1 as i64);
+    canvas.set_draw_color(Color::RGB(64, 64, 64));
+    for x in 1..columns {
+        let x = picture.x() + (x * width / columns) as i32;
+        canvas.draw_line((x, picture.y()), (x, picture.bottom() - 1))?;
+    }
+    for y in 1..rows {
+        let y = picture.y() + (y * height / rows) as i32;
+        canvas.draw_line((picture.x(), y), (picture.right() - 1, y))?;
+    }
+    Ok(())
+}
+
+// PAUSED in the top right corner of the picture while paused with P
+fn draw_paused_overlay(canvas: &mut Canvas<Window>, picture: Rect) -> Result<(), String> {
+    let text = "PAUSED";
+    let scale = 2;
+    let x = picture.right() - (overlay::text_width(text) as i32 + 2) * scale;
+    overlay::draw_text(canvas, text, x, picture.y() + 2 * scale, scale as u32, Color::RGB(255, 200, 0))
+}
+
+// Where a picture of the given resolution sits in the window right now
+fn screen_layout(canvas: &Canvas<Window>, resolution: (usize, usize), aspect: (u32, u32)) -> ScreenLayout {
+    ScreenLayout {
+        window: canvas.window().size(),
+        drawable: canvas.window().drawable_size(),
+        resolution,
+        aspect,
+    }
+}
+
+// The picture of screen_layout as a rect, never empty so it can be drawn into
+fn picture_rect(canvas: &Canvas<Window>, resolution: (usize, usize), aspect: (u32, u32)) -> Rect {
+    let (left, top, width, height) = screen_layout(canvas, resolution, aspect).picture();
+    Rect::new(left, top, width.max(1), height.max(1))
+}
+
+// Movie frame out of the total along the top right of the picture, under PAUSED
+fn draw_movie_overlay(canvas: &mut Canvas<Window>, session: &MovieSession, picture: Rect) -> Result<(), String> {
+    let text = format!("{} {}/{}", if session.is_recording() { "REC" } else { "PLAY" },
+        session.position(), session.movie().frames.len());
+    let scale = 2;
+    let x = picture.right() - (overlay::text_width(&text) as i32 + 2) * scale;
+    let y = picture.y() + (overlay::GLYPH_HEIGHT as i32 + 4) * scale;
+    overlay::draw_text(canvas, &text, x, y, scale as u32, Color::RGB(255, 200, 0))
+}
+
+// What a frame of emulation did, for spotting ROMs that spin instead of waiting on the timers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct FrameReport {
+    cycles_run: usize,                  // Instructions run, counting any a poll loop let the frame skip
+    hit_budget: bool,                   // Ran every instruction the IPS allows, no draw cap cut it short
+    looping: bool,                      // Spent the whole budget cycling through at most LOOP_MAX_PCS addresses
+    breakpoint: Option<u16>,            // Stopped on a breakpoint for this address, the rest of the frame didn't run
+}
+
+// Run one frame worth of instructions, then update timers and periodically retune the speed
+// A draw over the per frame cap ends the frame early and runs at the start of the next one. With
+// yield_on_poll, whole passes of a tight poll loop are skipped: they can't change anything, and the
+// few instructions short of a whole pass still run, so the frame ends where running them all would
+fn run_frame(chip8: &mut Chip8, config: &Config, cheats: &mut CheatManager, breakpoints: &mut Breakpoints, ips: &mut usize, tracer: &mut Option<Tracer>, script: &mut Option<Script>) -> FrameReport {
+    let budget = (*ips / config.timer_rate as usize).max(1);
+    let mut report = FrameReport::default();
+    let mut draws = 0;
+    let mut pcs = Vec::with_capacity(LOOP_MAX_PCS + 1);     // Distinct PCs this frame, until there are too many for a loop
+    let mut history = VecDeque::with_capacity(frontend::POLL_HISTORY + 1);
+    let before = config.log_dirty.then(|| chip8.display.to_vec());
+    while report.cycles_run < budget {
+        // Checked ahead of the on_pc hooks, which run once with the deferred draw next frame
+        if chip8.next_is_draw() && !draw_allowed(config.max_draws_per_frame, draws) {
+            break;
+        }
+        run_script(script, |active| active.before_instruction(chip8));
+        if let Some(hit) = breakpoints.check(chip8) {
+            println!("{}", hit);
+            report.breakpoint = Some(hit.address());
+            return report;
+        }
+        if chip8.next_is_draw() {
+            draws += 1;
+        }
+        if pcs.len() <= LOOP_MAX_PCS && !pcs.contains(&chip8.pc()) {
+            pcs.push(chip8.pc());
+        }
+        let executed = (chip8.pc(), chip8.next_opcode());
+        trace_cycle(chip8, tracer);
+        report.cycles_run += 1;
+        // Traces list every instruction, so a traced run never skips any
+        if config.yield_on_poll && tracer.is_none() {
+            history.push_back(executed);
+            if history.len() > frontend::POLL_HISTORY {
+                history.pop_front();
+            }
+            if let Some(period) = frontend::tight_poll(history.make_contiguous()) {
+                let remaining = budget - report.cycles_run;
+                report.cycles_run += remaining - remaining % period;
+            }
+        }
+        if let Some(hit) = breakpoints.check_key(chip8) {
+            println!("{}", hit);
+            report.breakpoint = Some(hit.address());
+            return report;
+        }
+        if let (true, Some((addr, opcode))) = (config.log_vf_clobbers, chip8.take_vf_clobber()) {
+            info!("{:#05X}: {:04X} overwrites its vF operand with the flag", addr, opcode);
+        }
+        if let Some(violation) = chip8.take_protection_violation() {
+            let message = format!("{:#05X}: write of {:#04X} to protected address {:#05X}", violation.pc, violation.value, violation.addr);
+            if config.strict {
+                error!("{}, refused", message);
+            } else {
+                warn!("{}", message);
+            }
+        }
+        if let Some(read) = chip8.take_uninit_read() {
+            warn!("{:#05X}: {:04X} reads v{:X} before anything wrote it", read.pc, read.opcode, read.register);
+        }
+        if cheats.mode == ApplyMode::EveryInstruction {
+            cheats.apply(chip8);
+        }
+    }
+    report.hit_budget = report.cycles_run == budget;
+    report.looping = report.hit_budget && pcs.len() <= LOOP_MAX_PCS && pcs.len() < budget;
+    if let Some(before) = before {
+        info!("Frame {}: {} pixels changed", chip8.frame_count() + 1, display::changed_pixels(&before, &chip8.display));
+    }
+
+    chip8.tick_timers();
+    run_script(script, |active| active.after_frame(chip8));
+    if chip8.take_rpl_dirty() {
+        if let Err(err) = rpl::save(&rpl::path_for(Path::new(&config.rom_path)), &chip8.rpl()) {
+            error!("{}", err);
+        }
+    }
+    if cheats.mode == ApplyMode::EveryFrame {
+        cheats.apply(chip8);
+    }
+
+    // Retune from the measured wait/work cycles
+    if chip8.frame_count().is_multiple_of(TUNE_INTERVAL as u64) {
+        let (wait, work) = chip8.take_cycle_counts();
+        if let Some(tuner) = &config.tuner {
+            *ips = tuner.adjust(*ips, wait, work);
+        }
+    }
+    report
+}
+
+// Record to or play back from a movie when one was asked for. Playback runs with the seed the movie was
+// recorded with unless --seed says otherwise, and refuses anything else that differs unless forced
+fn start_movie(chip8: &mut Chip8, config: &Config) -> Result<Option<MovieSession>, String> {
+    if let Some(path) = &config.play_movie {
+        let movie = Movie::load(Path::new(path))?;
+        if config.seed.is_none() {
+            chip8.set_seed(movie.header.seed);
+        }
+        let mismatches = movie.header.mismatches(&MovieHeader::capture(chip8));
+        if !mismatches.is_empty() && !config.force {
+            return Err(format!("{} (--force plays it anyway)", mismatches.join("; ")));
+        }
+        for mismatch in &mismatches {
+            warn!("{}", mismatch);
+        }
+        info!("Playing {} frames from {}", movie.frames.len(), path);
+        return Ok(Some(MovieSession::Playing { movie, frame: 0 }));
+    }
+    Ok(config.record_movie.as_ref().map(|_| MovieSession::Recording(Movie::new(MovieHeader::capture(chip8)))))
+}
+
+// Load a savestate, keeping the movie in step with it: the state has to come from the movie's current
+// branch, and the movie then continues from the frame the state was saved on
+fn load_state(chip8: &mut Chip8, path: &Path, movie: &mut Option<MovieSession>) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let header = savestate::read_header(&bytes)?;
+    let cursor = match (movie.as_ref(), header.movie) {
+        (Some(session), Some(cursor)) => {
+            session.check(cursor)?;
+            Some(cursor)
+        }
+        (Some(_), None) => return Err("state was saved without a movie, loading it would desync the movie".to_string()),
+        (None, _) => None,
+    };
+    savestate::decode(chip8, &bytes)?;
+    if let (Some(session), Some(cursor)) = (movie, cursor) {
+        session.seek(cursor)?;
+    }
+    Ok(())
+}
+
+// Keys for the next frame, taken by the movie being recorded or replaced by the one being played
+fn movie_keys(movie: &mut Option<MovieSession>, live: u16) -> u16 {
+    let Some(session) = movie else {
+        return live;
+    };
+    let keys = session.keys(live);
+    if session.just_ended() {
+        info!("Movie ended, input is live again");
+    }
+    keys
+}
+
+// Execute one instruction, recording it first when tracing. A trace that can't be written is reported
+// and dropped rather than stopping the emulation
+fn trace_cycle(chip8: &mut Chip8, tracer: &mut Option<Tracer>) {
+    if let Some(active) = tracer {
+        if let Err(err) = active.record(chip8) {
+            error!("trace stopped: {}", err);
+            *tracer = None;
+        }
+    }
+    chip8.cycle();
+}
+
+// --compare with --headless: both profiles for --frames frames without input, then the frame their
+// displays first differed on
+fn compare_headless(comparison: &mut Comparison, config: &Config) -> Result<(), String> {
+    let cycles = (config.ips / config.timer_rate as usize).max(1);
+    for _ in 0..config.frames {
+        comparison.frame(0, cycles);
+    }
+    let [first, second] = comparison.platforms;
+    match comparison.diverged() {
+        Some(frame) => println!("{} and {} diverge at frame {}", first, second, frame),
+        None => println!("{} and {} agree for all {} frames", first, second, config.frames),
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+// --compare in a window: both cores side by side, fed the same keys. Reset (F5) restarts both, F6 keeps
+// a savestate of both and F7 goes back to it
+fn run_compare(comparison: &mut Comparison, config: &Config, title: &str, profiles: &mut [InputProfile]) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let [first, second] = comparison.platforms;
+    let window = video_subsystem.window(&format!("{} - {} vs {}", title, first, second), (WIDTH * 20 + COMPARE_GAP) as u32, (COMPARE_LABEL + HEIGHT * 10) as u32)
+        .position_centered()
+        .build()
+        .expect("could not initialize video subsystem");
+    let mut canvas = window.into_canvas().build()
+        .expect("could not make a canvas");
+    let mut event_pump = sdl_context.event_pump()?;
+
+    let cycles = (config.ips / config.timer_rate as usize).max(1);
+    let mut input = InputState::default();
+    let mut saved = None;
+    let mut toast: Option<(String, u64)> = None;    // Message and the frame it disappears on
+    let mut pacer = Pacer::new(config.timer_rate, config.max_fps);
+    let mut last_pace = Instant::now();
+
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    saved = Some(comparison.save());
+                    println!("Saved both states");
+                },
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    if let Some(state) = &saved {
+                        comparison.load(state)?;
+                        println!("Loaded both states");
+                    }
+                },
+                _ => input::reduce(&mut input, &event, profiles),
+            }
+        }
+        if input.quit {
+            break 'running;
+        }
+        input.keys = input::merge(profiles);
+        if input.reset {
+            input.reset = false;
+            comparison.reset();
+            toast = None;
+        }
+
+        let now = Instant::now();
+        let elapsed = frontend::clamp_frame_time(now - last_pace, config.max_frame_time);
+        let pacing = pacer.advance(elapsed);
+        last_pace = now;
+        if !input.pause {
+            for _ in 0..pacing.ticks {
+                if let Some(frame) = comparison.frame(input.keys, cycles) {
+                    info!("{} and {} diverge at frame {}", first, second, frame);
+                    toast = Some((format!("Diverged at frame {}", frame), frame + TOAST_FRAMES));
+                }
+            }
+        }
+        if toast.as_ref().is_some_and(|(_, until)| comparison.cores[0].frame_count() >= *until) {
+            toast = None;
+        }
+        if pacing.present {
+            draw_comparison(&mut canvas, comparison, toast.as_ref().map(|(message, _)| message.as_str()))?;
+            if input.pause {
+                draw_paused_overlay(&mut canvas, Rect::new(0, COMPARE_LABEL as i32, (WIDTH * 10) as u32, (HEIGHT * 10) as u32))?;
+            }
+            canvas.present();
+        }
+        ::std::thread::sleep(pacer.until_next());
+    }
+    Ok(())
+}
+
+// Run the ROM against a reference trace in the octo layout, Err naming the first step that differs
+fn compare_trace(chip8: &mut Chip8, path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+    let reference = trace::parse_reference(&text).map_err(|err| format!("{}: {}", path, err))?;
+    let Some(step) = chip8.compare_trace(&reference) else {
+        println!("{} steps match {}", reference.len(), path);
+        return Ok(());
+    };
+    let (pc, opcode) = reference[step];
+    let actual = TraceEvent::capture(chip8, step as u64);
+    Err(format!("trace diverges at step {}: expected {:04X}: {:04X}, ran {:04X}: {:04X}", step, pc, opcode, actual.pc, actual.opcode))
+}
+
+// Instruction trace to a file, or stdout for "-"
+fn open_tracer(path: &str, format: &str) -> Result<Tracer, String> {
+    let out: Box<dyn Write> = if path == "-" {
+        Box::new(io::stdout())
+    } else {
+        let file = File::create(path).map_err(|err| format!("could not create {}: {}", path, err))?;
+        Box::new(BufWriter::new(file))
+    };
+    Tracer::new(trace::format_for(format)?, out)
+}
+
+// Warn once when the ROM starts spinning in a loop, a ROM that has halted in a self jump is expected to
+fn warn_looping(chip8: &Chip8, report: FrameReport, ips: usize, was_looping: &mut bool) {
+    let looping = report.looping && !chip8.halted();
+    if looping && !*was_looping {
+        warn!("stuck in a loop around {:#05X} at {} IPS (frame {})", chip8.pc(), ips, chip8.frame_count());
+    }
+    *was_looping = looping;
+}
+
+// Emulation without a window for frame dumps and scripted runs, as fast as the host allows
+fn run_headless(chip8: &mut Chip8, config: &Config, cheats: &mut CheatManager, capture: &mut Capture) -> Result<(), String> {
+    let Capture { tracer, movie, script } = capture;
+    let mut dumper = match &config.dump_frames {
+        Some(dir) => Some(FrameDumper::new(Path::new(dir), config.dump_format, config.every_frame, config.max_dumped_frames)?),
+        None => None,
+    };
+    let mut ips = config.ips;
+    let mut was_looping = false;
+    let mut breakpoints = build_breakpoints(config);
+    let mut console = open_console(config)?;
+    let mut watches = Watches::new();
+    let mut advance = 0;
+    let mut raw_video = start_raw_video(config)?;
+
+    for _ in 0..config.frames {
+        // A console script runs in step with the frames, the ones left play out once it ends
+        if advance == 0 {
+            if let Some(session) = &console {
+                match session.run_until_advance(chip8, &mut breakpoints, &mut watches) {
+                    Some(frames) => advance = frames,
+                    None => console = None,
+                }
+            }
+        }
+        advance = advance.saturating_sub(1);
+        if movie.is_some() || script.is_some() {
+            chip8.set_keys_mask(movie_keys(movie, script_keys(script)));
+        }
+        let report = run_frame(chip8, config, cheats, &mut breakpoints, &mut ips, tracer, script);
+        refresh_watches(&mut watches, console.as_ref(), chip8);
+        if report.breakpoint.is_some() {
+            break;
+        }
+        warn_looping(chip8, report, ips, &mut was_looping);
+        raw_video_frame(&mut raw_video, chip8);
+        if let Some(dumper) = &mut dumper {
+            if !dumper.frame(chip8)? {
+                break;
+            }
+        }
+    }
+
+    if let Some(dumper) = &dumper {
+        println!("Dumped {} frames", dumper.written());
+    }
+    finish_raw_video(raw_video);
+    Ok(())
+}
+
+// The console on stdin or, with --remote, on a TCP port
+fn open_console(config: &Config) -> Result<Option<Console>, String> {
+    match config.remote {
+        Some(port) => {
+            let console = Console::listen(port)?;
+            info!("Remote console listening on 127.0.0.1:{}", console.port().unwrap_or(port));
+            Ok(Some(console))
+        }
+        None => Ok(config.console.then(Console::spawn)),
+    }
+}
+
+// Re-evaluate the watches after a frame ran, the console prints the ones that changed
+fn refresh_watches(watches: &mut Watches, console: Option<&Console>, chip8: &Chip8) {
+    if watches.is_empty() {
+        return;
+    }
+    watches.refresh(chip8);
+    if let Some(console) = console {
+        console.print_changes(watches);
+    }
+}
+
+// Short frontend message along the bottom edge of the picture
+fn draw_toast(canvas: &mut Canvas<Window>, message: &str, picture: Rect) -> Result<(), String> {
+    let y = picture.bottom() - (overlay::GLYPH_HEIGHT * 2 + 4) as i32;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rect(Rect::new(picture.x(), y - 4, picture.width(), (overlay::GLYPH_HEIGHT * 2 + 8) as u32))?;
+    overlay::draw_text(canvas, message, picture.x() + 4, y, 2, Color::RGB(255, 200, 0))
+}
+
+// Emulated time and frame number in the top left of the picture, the most recent splits below
+fn draw_speedrun_overlay(canvas: &mut Canvas<Window>, chip8: &Chip8, splits: &Splits, picture: Rect) -> Result<(), String> {
+    let text_color = Color::RGB(255, 64, 64);
+    let frame = chip8.frame_count();
+    let line_height = (overlay::GLYPH_HEIGHT as i32 + 1) * 2;
+    let (left, top) = (picture.x() + 4, picture.y() + 4);
+
+    overlay::draw_text(canvas, &format!("{} F{}", speedrun::format_time(frame), frame), left, top, 2, text_color)?;
+    for (i, line) in splits.lines().iter().rev().take(3).enumerate() {
+        overlay::draw_text(canvas, line, left, top + line_height * (i as i32 + 1), 2, text_color)?;
+    }
+    Ok(())
+}
+
+// Start --script against the loaded ROM. Its main chunk runs now, usually just registering hooks
+#[cfg(feature = "script")]
+fn load_script(chip8: &mut Chip8, config: &Config) -> Result<Option<Script>, String> {
+    let Some(path) = &config.script else {
+        return Ok(None);
+    };
+    let source = std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+    let mut script = Script::load(&source, path, chip8)?;
+    for line in script.take_output() {
+        println!("{}", line);
+    }
+    Ok(Some(script))
+}
+
+#[cfg(not(feature = "script"))]
+fn load_script(_chip8: &mut Chip8, config: &Config) -> Result<Option<Script>, String> {
+    match config.script {
+        Some(_) => Err("--script needs a build with --features script".to_string()),
+        None => Ok(None),
+    }
+}
+
+// Run a script hook and pass on what it printed. A failing hook is reported once, the script switches
+// itself off and the emulation carries on
+fn run_script(script: &mut Option<Script>, hook: impl FnOnce(&mut Script) -> Result<(), String>) {
+    if let Some(active) = script {
+        if let Err(err) = hook(active) {
+            eprintln!("script stopped: {}", err);
+        }
+        for line in active.take_output() {
+            println!("{}", line);
+        }
+    }
+}
+
+fn script_keys(script: &Option<Script>) -> u16 {
+    script.as_ref().map_or(0, Script::keys)
+}
+
+// The keys the core sees this frame, paused or running: the physical ones, debounced, then latched, with
+// the script's on top. The latch only sees edges, so it has to be fed the keys as read rather than what it
+// reported last
+fn frame_keys(profiles: &[InputProfile], debouncer: &mut Option<Debouncer>, sticky: &mut Option<StickyKeys>, script: &Option<Script>, now: Duration) -> u16 {
+    let held = match debouncer {
+        Some(debouncer) => debouncer.update(input::merge(profiles), now),
+        None => input::merge(profiles),
+    };
+    let latched = match sticky {
+        Some(sticky) => sticky.update(held),
+        None => held,
+    };
+    latched | script_keys(script)
+}
+
+// Thumbnail, slot number and save time of every slot side by side across the picture, the selected one
+// outlined. Thumbnails shrink to fit the slots, down to one window pixel per CHIP-8 pixel
+fn draw_state_picker(canvas: &mut Canvas<Window>, picker: &StatePicker, aspect: (u32, u32)) -> Result<(), String> {
+    let picture = picture_rect(canvas, (WIDTH, HEIGHT), aspect);        // Thumbnails are 64x32 whatever the resolution
+    let spacing = (picture.width() as usize / STATE_SLOTS) as i32;
+    let scale = ((spacing - 8) / WIDTH as i32).clamp(1, 2);
+    let top = picture.y() + picture.height() as i32 * 100 / (HEIGHT as i32 * 10);
+
+    canvas.set_draw_color(Color::RGB(24, 24, 24));
+    canvas.clear();
+    overlay::draw_text(canvas, "LOAD STATE - ENTER LOAD  F6 SAVE  F7 CLOSE", picture.x() + 16, picture.y() + 24, 2, Color::RGB(255, 255, 255))?;
+
+    for (slot, header) in picker.headers.iter().enumerate() {
+        let left = picture.x() + spacing * slot as i32 + (spacing - (WIDTH as i32 * scale + 4)) / 2;
+        let outline = if slot == picker.selected { Color::RGB(255, 200, 0) } else { Color::RGB(96, 96, 96) };
+        canvas.set_draw_color(outline);
+        canvas.fill_rect(Rect::new(left, top, (WIDTH as u32 * scale as u32) + 4, (HEIGHT as u32 * scale as u32) + 4))?;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.fill_rect(Rect::new(left + 2, top + 2, WIDTH as u32 * scale as u32, HEIGHT as u32 * scale as u32))?;
+
+        // States saved without a thumbnail just show the empty frame
+        if let Some(thumbnail) = header.as_ref().and_then(|header| header.thumbnail.as_ref()) {
+            canvas.set_draw_color(Color::RGB(255, 255, 255));
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    if thumbnail.pixel(x, y) {
+                        canvas.fill_rect(Rect::new(left + 2 + x as i32 * scale, top + 2 + y as i32 * scale, scale as u32, scale as u32))?;
+                    }
+                }
+            }
+        }
+
+        let label_top = top + HEIGHT as i32 * scale + 12;
+        overlay::draw_text(canvas, &format!("SLOT {}", slot + 1), left, label_top, 2, outline)?;
+        let time = header.as_ref().map_or("EMPTY".to_string(), |header| savestate::format_timestamp(header.saved_at));
+        overlay::draw_text(canvas, &time, left, label_top + 16, 2, Color::RGB(192, 192, 192))?;
+    }
+    Ok(())
+}
+
+// HALTED in the bottom right corner of the picture once the ROM sits in a jump to itself, events keep being handled so F5 and Escape work
+fn draw_halted_overlay(canvas: &mut Canvas<Window>, picture: Rect) -> Result<(), String> {
+    let (x, y) = halted_origin(picture);
+    overlay::draw_text(canvas, HALTED_TEXT, x, y, HALTED_SCALE as u32, Color::RGB(128, 128, 128))
+}
+
+// Top left of the HALTED label, two font pixels in from the picture's bottom right corner
+fn halted_origin(picture: Rect) -> (i32, i32) {
+    let x = picture.right() - (overlay::text_width(HALTED_TEXT) as i32 + 2) * HALTED_SCALE;
+    let y = picture.bottom() - (overlay::GLYPH_HEIGHT as i32 + 2) * HALTED_SCALE;
+    (x, y)
+}
+
+// Whether another DXYN may run this frame given how many already have
+fn draw_allowed(max_draws_per_frame: Option<u32>, draws: u32) -> bool {
+    max_draws_per_frame.is_none_or(|max| draws < max)
+}
+
+// Ctrl+1 to Ctrl+9 toggle the matching cheat
+fn toggle_cheat(cheats: &mut CheatManager, key: Keycode) {
+    let idx = match key {
+        Keycode::Num1 => 0, Keycode::Num2 => 1, Keycode::Num3 => 2,
+        Keycode::Num4 => 3, Keycode::Num5 => 4, Keycode::Num6 => 5,
+        Keycode::Num7 => 6, Keycode::Num8 => 7, Keycode::Num9 => 8,
+        _ => return,
+    };
+    if let Some(enabled) = cheats.toggle(idx) {
+        println!("Cheat {} {}", idx + 1, if enabled { "on" } else { "off" });
+    }
+}
+
+// Open the netplay session if requested and agree on a seed with the peer
+#[cfg(feature = "netplay")]
+fn connect_netplay(chip8: &mut Chip8, config: &Config, window: &mut sdl2::video::Window) -> Result<Option<Netplay>, String> {
+    let session = Session {
+        rom_hash: chip8.rom_hash(),
+        seed: chip8.seed(),
+        ips: config.ips as u32,
+    };
+
+    let _ = window.set_title("Chip8 Emu - waiting for peer");
+    let netplay = match &config.netplay {
+        Some(NetplayMode::Connect(addr)) => Netplay::connect(addr, session),
+        Some(NetplayMode::Listen(port)) => Netplay::listen(*port, session),
+        None => return Ok(None),
+    }.map_err(|err| err.to_string())?;
+
+    chip8.set_seed(netplay.session().seed);
+    Ok(Some(netplay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuner_slows_input_bound_roms() {
+        let tuner = IpsTuner { min: 200, max: 2000 };
+        assert_eq!(tuner.adjust(700, 80, 20), 630);
+        assert_eq!(tuner.adjust(210, 80, 20), 200, "never below the minimum");
+    }
+
+    #[test]
+    fn tuner_speeds_up_compute_heavy_roms() {
+        let tuner = IpsTuner { min: 200, max: 2000 };
+        assert_eq!(tuner.adjust(700, 5, 95), 770);
+        assert_eq!(tuner.adjust(1950, 0, 100), 2000, "never above the maximum");
+    }
+
+    #[test]
+    fn tuner_holds_a_mixed_load_steady() {
+        let tuner = IpsTuner { min: 200, max: 2000 };
+        assert_eq!(tuner.adjust(700, 30, 70), 700);
+        assert_eq!(tuner.adjust(700, 50, 50), 700, "exactly half waiting isn't input-bound");
+        assert_eq!(tuner.adjust(5000, 0, 0), 2000, "no measurements only clamps");
+        assert_eq!(tuner.adjust(5, 100, 0), 200);
+    }
+
+    #[test]
+    fn help_notes_what_each_built_feature_leans_on() {
+        let notes = runtime_notes().join("\n");
+        assert_eq!(notes.contains("ZIP:"), cfg!(feature = "zip"));
+        assert_eq!(notes.contains("URLs:"), cfg!(feature = "net"));
+        assert_eq!(notes.contains("Ctrl+C:"), cfg!(feature = "clipboard"));
+        assert_eq!(notes.contains("--script:"), cfg!(feature = "script"));
+    }
+
+    // Draws an 8 pixel line at v0, moves v0 along 8 and loops
+    const LINE_DRAWER: [u8; 10] = [0xA2, 0x08, 0xD0, 0x11, 0x70, 0x08, 0x12, 0x02, 0xFF, 0x00];
+
+    fn frames_of_lines(args: &[&str], frames: usize) -> Chip8 {
+        let config = config_of(args);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&LINE_DRAWER).unwrap();
+        let mut ips = 600;
+        for _ in 0..frames {
+            run_frame(&mut chip8, &config, &mut CheatManager::new(), &mut build_breakpoints(&config), &mut ips, &mut None, &mut None);
+        }
+        chip8
+    }
+
+    #[test]
+    fn capped_draws_commit_one_per_frame() {
+        let chip8 = frames_of_lines(&["--max-draws", "1"], 3);
+        assert_eq!(chip8.lit_pixels().count(), 3 * 8, "one line each frame");
+        assert_eq!(chip8.register(0), 3 * 8);
+        assert_eq!(chip8.next_opcode(), 0xD011, "the deferred draw runs first next frame");
+
+        let uncapped = frames_of_lines(&[], 1);
+        assert_eq!(uncapped.lit_pixels().count(), 3 * 8, "three lines in one frame without the cap");
+    }
+
+    #[cfg(feature = "script")]
+    #[test]
+    fn deferred_draws_run_their_pc_hooks_once() {
+        // Counts the hook's calls for the DXYN at 0x202 into 0x400
+        let config = config_of(&["--max-draws", "1"]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&LINE_DRAWER).unwrap();
+        let source = "emu.on_pc(0x202, function() emu.poke(0x400, emu.peek(0x400) + 1) end)";
+        let mut script = Some(Script::load(source, "t.lua", &mut chip8).unwrap());
+        for _ in 0..3 {
+            run_frame(&mut chip8, &config, &mut CheatManager::new(), &mut build_breakpoints(&config), &mut 600, &mut None, &mut script);
+        }
+        assert_eq!(chip8.lit_pixels().count(), 3 * 8);
+        assert_eq!(chip8.peek(0x400), Some(3), "once per draw, not again when one is put off");
+    }
+
+    // Waits for a key with FX0A, puts it in v1 and loops
+    const KEY_WAITER: [u8; 4] = [0xF1, 0x0A, 0x12, 0x02];
+
+    // One pass of the loop's input path: the keys worked out as run does at now_ms, then a frame with them
+    fn frame_with_keys(chip8: &mut Chip8, profiles: &[InputProfile], debouncer: &mut Option<Debouncer>, sticky: &mut Option<StickyKeys>, now_ms: u64) {
+        let config = config_of(&[]);
+        let keys = frame_keys(profiles, debouncer, sticky, &None, Duration::from_millis(now_ms));
+        InputState { keys, ..InputState::default() }.apply(chip8);
+        run_frame(chip8, &config, &mut CheatManager::new(), &mut build_breakpoints(&config), &mut 600, &mut None, &mut None);
+    }
+
+    #[test]
+    fn running_frames_see_the_debounced_keys() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&KEY_WAITER).unwrap();
+        let mut profiles = vec![InputProfile::player1()];
+        let mut debouncer = Some(Debouncer::new(Duration::from_millis(20)));
+        profiles[0].handle_key(Keycode::W, true);
+        frame_with_keys(&mut chip8, &profiles, &mut debouncer, &mut None, 0);
+        frame_with_keys(&mut chip8, &profiles, &mut debouncer, &mut None, 16);
+        assert_eq!(chip8.pc(), 0x200, "key 5 hasn't settled yet");
+        frame_with_keys(&mut chip8, &profiles, &mut debouncer, &mut None, 33);
+        assert_eq!((chip8.pc(), chip8.register(1)), (0x202, 5), "FX0A took key 5 once it settled");
+    }
+
+    #[test]
+    fn running_frames_see_latched_keys() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&KEY_WAITER).unwrap();
+        let mut profiles = vec![InputProfile::player1()];
+        let mut sticky = Some(StickyKeys::new());
+        profiles[0].handle_key(Keycode::W, true);
+        frame_keys(&profiles, &mut None, &mut sticky, &None, Duration::ZERO);
+        profiles[0].handle_key(Keycode::W, false);
+        frame_with_keys(&mut chip8, &profiles, &mut None, &mut sticky, 16);
+        assert_eq!((chip8.pc(), chip8.register(1)), (0x202, 5), "FX0A completed on key 5 after it was let go");
+
+        // Pressing it again releases the latch, even while it's still down
+        chip8.reset();
+        profiles[0].handle_key(Keycode::W, true);
+        frame_with_keys(&mut chip8, &profiles, &mut None, &mut sticky, 33);
+        assert_eq!(chip8.pc(), 0x200, "FX0A is still waiting");
+    }
+
+    #[test]
+    fn assembled_roms_are_written_without_their_padding() {
+        let dir = env::temp_dir();
+        let source = dir.join(format!("chip8-assemble-{}.8o", std::process::id()));
+        let rom = dir.join(format!("chip8-assemble-{}.ch8", std::process::id()));
+        std::fs::write(&source, ": main v0 := 0 jump main : data 0xFF 0 0 0").unwrap();
+        let args = [&source, &rom].map(|path| path.to_string_lossy().into_owned());
+        assemble(&args).unwrap();
+        assert_eq!(std::fs::read(&rom).unwrap(), [0x60, 0x00, 0x12, 0x00, 0xFF, 0x00]);
+        let _ = std::fs::remove_file(&source);
+        let _ = std::fs::remove_file(&rom);
+    }
+
+    #[test]
+    fn log_levels_parse_by_name() {
+        assert_eq!(parse_log_level("warn"), Ok(LevelFilter::Warn));
+        assert!(parse_log_level("loud").unwrap_err().contains("unknown log level 'loud'"));
+        let args = ["rom.ch8", "--log-level", "debug"].map(String::from);
+        assert_eq!(parse_args(&args).unwrap().log_level, LevelFilter::Debug);
+    }
+
+    #[test]
+    fn draw_cap_counts_against_the_limit() {
+        assert!(draw_allowed(None, 1000));
+        assert!(draw_allowed(Some(2), 1));
+        assert!(!draw_allowed(Some(2), 2));
+        let args = |cap: &str| ["rom.ch8", "--max-draws", cap].map(String::from);
+        assert!(parse_args(&args("0")).is_err(), "a zero cap would stall on the first DXYN");
+        assert_eq!(parse_args(&args("1")).unwrap().max_draws_per_frame, Some(1));
+    }
+
+    #[test]
+    fn halted_label_composites_into_the_pictures_corner() {
+        let picture = Rect::new(80, 40, 640, 320);
+        let (x, y) = halted_origin(picture);
+        let frame = overlay::composite(&overlay::text_rects(HALTED_TEXT, x, y, HALTED_SCALE as u32), 800, 400);
+        let lit: Vec<(i32, i32)> = (0..800 * 400).filter(|&i| frame[i]).map(|i| ((i % 800) as i32, (i / 800) as i32)).collect();
+        let (left, right) = (lit.iter().map(|p| p.0).min().unwrap(), lit.iter().map(|p| p.0).max().unwrap());
+        let (top, bottom) = (lit.iter().map(|p| p.1).min().unwrap(), lit.iter().map(|p| p.1).max().unwrap());
+        assert_eq!((left, top), (x, y), "H's top left pixel sits at the origin");
+        assert_eq!(right, picture.right() - 2 * HALTED_SCALE - 1, "two font pixels from the right edge");
+        assert_eq!(bottom, picture.bottom() - 2 * HALTED_SCALE - 1, "two font pixels from the bottom edge");
+        assert!(frame[y as usize * 800 + x as usize]);
+        assert!(!frame[(y as usize + 1) * 800 + x as usize + 2], "the gap in H's top row stays dark");
+    }
+
+    #[cfg(feature = "script")]
+    #[test]
+    fn scripts_drive_headless_runs() {
+        // Counts presses of key 5 into v1, stored at 0x301
+        let rom = [0x65, 0x05, 0xE5, 0x9E, 0x12, 0x02, 0x71, 0x01, 0xA3, 0x00, 0xF1, 0x55, 0xE5, 0xA1, 0x12, 0x0C, 0x12, 0x02];
+        let source = "emu.on_frame(function() if emu.frame() % 6 < 3 then emu.press(5) else emu.release(5) end end)";
+        let args: Vec<String> = ["rom.ch8", "--headless", "--frames", "30"].iter().map(|arg| arg.to_string()).collect();
+        let config = parse_args(&args).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let script = Script::load(source, "t.lua", &mut chip8).unwrap();
+        let mut capture = Capture { tracer: None, movie: None, script: Some(script) };
+        run_headless(&mut chip8, &config, &mut CheatManager::new(), &mut capture).unwrap();
+        assert_eq!(chip8.peek(0x301), Some(5), "a press every six frames");
+    }
+
+    // Draws a four pixel line in the top left corner once, then halts
+    const CORNER_LINE: [u8; 7] = [0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xF0];
+
+    // Headless run of CORNER_LINE dumping frames to a fresh directory, the files it wrote sorted by name
+    fn dump_corner_line(name: &str, args: &[&str]) -> (std::path::PathBuf, Vec<String>) {
+        let dir = env::temp_dir().join(format!("chip8-dump-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir_arg = dir.to_string_lossy().into_owned();
+        let args: Vec<String> = ["rom.ch8", "--headless", "--frames", "10", "--dump-frames", &dir_arg].iter().chain(args)
+            .map(|arg| arg.to_string()).collect();
+        let config = parse_args(&args).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&CORNER_LINE).unwrap();
+        let mut capture = Capture { tracer: None, movie: None, script: None };
+        run_headless(&mut chip8, &config, &mut CheatManager::new(), &mut capture).unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        (dir, files)
+    }
+
+    #[test]
+    fn headless_frame_dumps_write_changed_frames() {
+        let (dir, files) = dump_corner_line("changed", &[]);
+        assert_eq!(files, ["frame_000001.png", "index.txt"], "only the frame that drew is dumped");
+
+        let mut display = chip8::display::Display::new();
+        display[..4].fill(1);
+        let expected = chip8::png::encode_rgb(64, 32, &video::rgb_frame(&display, 1)).unwrap();
+        assert_eq!(std::fs::read(dir.join(&files[0])).unwrap(), expected);
+        assert_eq!(std::fs::read_to_string(dir.join("index.txt")).unwrap(), "frame_000001.png 1 00:00.01\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn every_frame_dumps_are_capped() {
+        let (dir, files) = dump_corner_line("every", &["--every-frame"]);
+        assert_eq!(files.len(), 10 + 1, "ten frames and the index");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (dir, files) = dump_corner_line("capped", &["--every-frame", "--max-dumped-frames", "3"]);
+        assert_eq!(files, ["frame_000001.png", "frame_000002.png", "frame_000003.png", "index.txt"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn config_of(args: &[&str]) -> Config {
+        let args: Vec<String> = ["rom.ch8"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        parse_args(&args).unwrap()
+    }
+
+    #[test]
+    fn config_reloads_apply_live_options_and_flag_the_rest() {
+        let mut config = config_of(&["--ips", "700"]);
+        assert_eq!(apply_config(&mut config, config_of(&["--ips", "700"])), ConfigChanges::default(), "nothing changed");
+
+        let changes = apply_config(&mut config, config_of(&["--ips", "900", "--waveform", "sine", "--player2", "C,D"]));
+        assert_eq!(changes, ConfigChanges { speed: true, keys: true, audio: true, ..ConfigChanges::default() });
+        assert_eq!((config.ips, config.waveform), (900, Waveform::Sine));
+        assert_eq!(config.player2_keys, Some(vec![0xC, 0xD]));
+
+        let changes = apply_config(&mut config, config_of(&["--ips", "900", "--waveform", "sine", "--player2", "C,D", "--clip", "--speedrun"]));
+        assert_eq!(changes, ConfigChanges { overlay: true, restart_required: true, ..ConfigChanges::default() });
+        assert!(config.speedrun, "the timer is shown straight away");
+        assert_eq!(config.quirks, config_of(&[]).quirks, "quirk changes wait for a restart");
+    }
+
+    #[test]
+    fn broken_config_files_are_reported_not_applied() {
+        let path = env::temp_dir().join(format!("chip8-config-{}.cfg", std::process::id()));
+        std::fs::write(&path, "ips = 900\n").unwrap();
+        let args: Vec<String> = ["rom.ch8", "--config", &path.to_string_lossy()].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(resolve_config(&args).unwrap().ips, 900);
+
+        std::fs::write(&path, "ips = fast\n").unwrap();
+        assert!(resolve_config(&args).is_err(), "the running config stays as it was");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn frame_report(rom: &[u8], args: &[&str]) -> FrameReport {
+        let config = config_of(args);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(rom).unwrap();
+        let mut ips = config.ips;
+        run_frame(&mut chip8, &config, &mut CheatManager::new(), &mut build_breakpoints(&config), &mut ips, &mut None, &mut None)
+    }
+
+    #[test]
+    fn self_jumps_are_reported_as_looping() {
+        let report = frame_report(&[0x12, 0x00], &["--ips", "600"]);
+        assert_eq!(report.cycles_run, 10);
+        assert!(report.hit_budget && report.looping);
+    }
+
+    #[test]
+    fn straight_line_code_is_not_looping() {
+        let report = frame_report(&[0x60, 0x01].repeat(20), &["--ips", "600"]);
+        assert!(report.hit_budget);
+        assert!(!report.looping, "ten different addresses ran");
+
+        let report = frame_report(&LINE_DRAWER, &["--ips", "600", "--max-draws", "1"]);
+        assert!(!report.hit_budget && !report.looping, "a frame cut short by the draw cap");
+    }
+
+    #[test]
+    fn vf_clobbers_are_logged_when_asked() {
+        // v0 = 5, then 8F04 adds v0 into vF and the carry overwrites the sum
+        let rom = [0x60, 0x05, 0x8F, 0x04, 0x12, 0x04];
+        let clobbers_logged = |args: &[&str]| {
+            let config = config_of(args);
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(&rom).unwrap();
+            chip8::testlog::capture(|| {
+                run_frame(&mut chip8, &config, &mut CheatManager::new(), &mut build_breakpoints(&config), &mut 600, &mut None, &mut None);
+            })
+        };
+        assert_eq!(clobbers_logged(&["--log-vf-clobbers"]), [(Level::Info, "0x202: 8F04 overwrites its vF operand with the flag".to_string())]);
+        let logs = clobbers_logged(&[]);
+        assert!(logs.is_empty(), "silent without the option: {:?}", logs);
+    }
+
+    #[test]
+    fn break_op_stops_a_frame_at_the_first_draw() {
+        let config = config_of(&["--break-op", "DXYN"]);
+        assert_eq!(config.opcode_breaks, [breakpoints::parse_opcode("DXYN").unwrap()]);
+        assert!(parse_args(&["rom.ch8".to_string(), "--break-op".to_string(), "DXY".to_string()]).is_err());
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&LINE_DRAWER).unwrap();
+        let mut breakpoints = build_breakpoints(&config);
+        let report = run_frame(&mut chip8, &config, &mut CheatManager::new(), &mut breakpoints, &mut 600, &mut None, &mut None);
+        assert_eq!((report.breakpoint, report.cycles_run), (Some(0x202), 1), "only the I load ran");
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(chip8.lit_pixels().count(), 0);
+    }
+
+    #[test]
+    fn adi_overflow_vf_takes_its_width() {
+        for (width, threshold) in [("12", 12), ("16", 16)] {
+            let config = config_of(&["--adi-overflow-vf", width]);
+            assert!(config.quirks.adi_overflow_vf);
+            assert_eq!(config.quirks.adi_overflow_width, threshold);
+        }
+        assert!(parse_args(&["rom.ch8".to_string(), "--adi-overflow-vf".to_string(), "8".to_string()]).is_err());
+    }
+
+    #[test]
+    fn scanlines_take_an_intensity() {
+        assert_eq!(config_of(&[]).scanlines, None);
+        assert_eq!(config_of(&["--scanlines"]).scanlines, Some(DEFAULT_SCANLINE_INTENSITY));
+        assert_eq!(config_of(&["--scanlines", "--scanline-intensity", "30"]).scanlines, Some(30));
+        assert!(parse_args(&["rom.ch8".to_string(), "--scanline-intensity".to_string(), "120".to_string()]).is_err());
+    }
+
+    #[test]
+    fn rerecorded_movies_replay_deterministically() {
+        // Count frames with key 5 held in v1 and sum random bytes into v3
+        let rom = [0x60, 0x05, 0xE0, 0xA1, 0x71, 0x01, 0xC2, 0xFF, 0x83, 0x24, 0x12, 0x02];
+        let boot = || {
+            let mut chip8 = Chip8::new();
+            chip8.set_seed(1);
+            chip8.load_rom_bytes(&rom).unwrap();
+            chip8
+        };
+        let play = |chip8: &mut Chip8, session: &mut MovieSession, live: u16, frames: usize| {
+            for _ in 0..frames {
+                let keys = session.keys(live);
+                chip8.set_keys_mask(keys);
+                chip8.step_frame(12);
+            }
+        };
+        let path = std::env::temp_dir().join(format!("chip8-rerecord-{}.state", std::process::id()));
+
+        let mut chip8 = boot();
+        let mut movie = Some(MovieSession::Recording(Movie::new(MovieHeader::capture(&chip8))));
+        play(&mut chip8, movie.as_mut().unwrap(), 1 << 5, 10);
+        savestate::save(&chip8, &path, movie.as_ref().map(MovieSession::cursor)).unwrap();
+        play(&mut chip8, movie.as_mut().unwrap(), 1 << 5, 10);
+        let abandoned = chip8.save_state();
+
+        load_state(&mut chip8, &path, &mut movie).unwrap();
+        assert_eq!(movie.as_ref().unwrap().position(), 10, "loading rewound the movie with the machine");
+        play(&mut chip8, movie.as_mut().unwrap(), 0, 10);
+        assert_ne!(chip8.save_state(), abandoned, "the new branch diverged");
+        let Some(MovieSession::Recording(recorded)) = movie else {
+            panic!("still recording");
+        };
+        assert_eq!(recorded.frames, [[1 << 5; 10], [0; 10]].concat());
+
+        let mut replay = boot();
+        let decoded = Movie::decode(&recorded.encode()).unwrap();
+        let mut session = MovieSession::Playing { movie: decoded, frame: 0 };
+        play(&mut replay, &mut session, 0xFFFF, 20);
+        assert_eq!(replay.save_state(), chip8.save_state(), "the re-recorded movie replays to the same machine");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,572 @@
+use core::ops::Range;
+
+use crate::prelude::*;
+
+// Memory as the interpreter sees it. Every fetch, sprite row, FX33, FX55 and FX65 goes through read and
+// write, so a bus can refuse accesses, log them or map them to something other than RAM. Loading ROMs,
+// fonts and savestates and tooling like the debugger work on bytes directly and skip all of that
+pub trait MemoryBus {
+    // The byte at addr, Err when the bus refuses the read
+    fn read(&mut self, addr: usize) -> Result<u8, String>;
+
+    // Store value at addr, Err when the bus refuses the write
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), String>;
+
+    // An opcode byte fetched for execution, a read unless the bus tells the two apart
+    fn fetch(&mut self, addr: usize) -> Result<u8, String> {
+        self.read(addr)
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Backing store, bypassing whatever the bus adds on top
+    fn bytes(&self) -> &[u8];
+    fn bytes_mut(&mut self) -> &mut [u8];
+}
+
+// Plain RAM, 4K for CHIP-8 and SUPER-CHIP or up to 64K for XO-CHIP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlatMemory {
+    bytes: Vec<u8>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    pub const SIZE: usize = 4096;
+
+    pub fn new() -> Self {
+        Self::with_size(Self::SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        FlatMemory { bytes: vec![0; size] }
+    }
+}
+
+impl MemoryBus for FlatMemory {
+    #[inline]
+    fn read(&mut self, addr: usize) -> Result<u8, String> {
+        match self.bytes.get(addr) {
+            Some(&byte) => Ok(byte),
+            None => Err(outside(addr)),
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        match self.bytes.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(outside(addr)),
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+#[cold]
+fn outside(addr: usize) -> String {
+    format!("address {:#05X} is outside memory", addr)
+}
+
+// Guards interpreter writes to ranges of addresses, such as the font or the ROM image. A strict guard
+// refuses them, otherwise the write lands; either way the last one is kept for take_violation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteProtect<B> {
+    inner: B,
+    protected: Vec<Range<usize>>,
+    strict: bool,
+    violation: Option<Access>,          // Last write into a protected range, until taken
+}
+
+impl<B: MemoryBus> WriteProtect<B> {
+    // A strict guard on one range
+    pub fn new(inner: B, protected: Range<usize>) -> Self {
+        WriteProtect { inner, protected: vec![protected], strict: true, violation: None }
+    }
+
+    // A guard with nothing protected yet that only reports, what Chip8::new starts with
+    pub fn report_only(inner: B) -> Self {
+        WriteProtect { inner, protected: Vec::new(), strict: false, violation: None }
+    }
+
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Most recent write into a protected range, cleared by the call
+    pub fn take_violation(&mut self) -> Option<Access> {
+        self.violation.take()
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: MemoryBus> MemoryBus for WriteProtect<B> {
+    fn read(&mut self, addr: usize) -> Result<u8, String> {
+        self.inner.read(addr)
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        if !self.protected.is_empty() && self.protected.iter().any(|range| range.contains(&addr)) {
+            self.violation = Some(Access { addr, value, write: true });
+            if self.strict {
+                return Err(format!("address {:#05X} is write protected", addr));
+            }
+        }
+        self.inner.write(addr, value)
+    }
+
+    fn fetch(&mut self, addr: usize) -> Result<u8, String> {
+        self.inner.fetch(addr)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.inner.bytes()
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self.inner.bytes_mut()
+    }
+}
+
+// One interpreter access to a watched address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub addr: usize,
+    pub value: u8,                      // Byte read, or the byte written
+    pub write: bool,
+}
+
+// Logs every successful access to the watched ranges, in order, until taken
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoints<B> {
+    inner: B,
+    watched: Vec<Range<usize>>,
+    hits: Vec<Access>,
+}
+
+impl<B: MemoryBus> Watchpoints<B> {
+    pub fn new(inner: B) -> Self {
+        Watchpoints { inner, watched: Vec::new(), hits: Vec::new() }
+    }
+
+    pub fn watch(&mut self, range: Range<usize>) {
+        self.watched.push(range);
+    }
+
+    // Accesses logged since the last call
+    pub fn take_hits(&mut self) -> Vec<Access> {
+        core::mem::take(&mut self.hits)
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn log(&mut self, addr: usize, value: u8, write: bool) {
+        if self.watched.iter().any(|range| range.contains(&addr)) {
+            self.hits.push(Access { addr, value, write });
+        }
+    }
+}
+
+impl<B: MemoryBus> MemoryBus for Watchpoints<B> {
+    fn read(&mut self, addr: usize) -> Result<u8, String> {
+        let value = self.inner.read(addr)?;
+        self.log(addr, value, false);
+        Ok(value)
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        self.inner.write(addr, value)?;
+        self.log(addr, value, true);
+        Ok(())
+    }
+
+    fn fetch(&mut self, addr: usize) -> Result<u8, String> {
+        self.inner.fetch(addr)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.inner.bytes()
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self.inner.bytes_mut()
+    }
+}
+
+// Counts every interpreter access into a Heatmap, fetches apart from reads. Counting is off until
+// set_enabled, so the bus costs one check per access while nobody looks at the counts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeatmapBus<B> {
+    inner: B,
+    heatmap: Option<Heatmap>,
+}
+
+impl<B: MemoryBus> HeatmapBus<B> {
+    pub fn new(inner: B) -> Self {
+        HeatmapBus { inner, heatmap: None }
+    }
+
+    // Start or stop counting. Turning it on starts from zero and turning it off drops the counts
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.heatmap = enabled.then(|| Heatmap::new(self.inner.len()));
+    }
+
+    // Counts since counting was turned on, None while it is off
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: MemoryBus> MemoryBus for HeatmapBus<B> {
+    fn read(&mut self, addr: usize) -> Result<u8, String> {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.read(addr);
+        }
+        self.inner.read(addr)
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.write(addr);
+        }
+        self.inner.write(addr, value)
+    }
+
+    fn fetch(&mut self, addr: usize) -> Result<u8, String> {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.execute(addr);
+        }
+        self.inner.fetch(addr)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.inner.bytes()
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self.inner.bytes_mut()
+    }
+}
+
+// The bus Chip8::new builds: RAM behind write protection and the heatmap, both idle until switched on
+pub type DefaultBus = HeatmapBus<WriteProtect<FlatMemory>>;
+
+// Interpreter accesses per address, for seeing where a ROM spends its reads, writes and fetches. Counts
+// saturate at u16::MAX, so a hot loop pins its addresses at the top rather than wrapping
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heatmap {
+    reads: Vec<u16>,
+    writes: Vec<u16>,
+    executes: Vec<u16>,                 // Instruction fetches, counted at both bytes of the opcode
+}
+
+// A run of neighbouring addresses that were all accessed, with the run's totals
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HotRange {
+    pub range: Range<usize>,
+    pub reads: u32,
+    pub writes: u32,
+    pub executes: u32,
+}
+
+impl HotRange {
+    pub fn total(&self) -> u32 {
+        self.reads + self.writes + self.executes
+    }
+}
+
+impl Heatmap {
+    pub fn new(size: usize) -> Self {
+        Heatmap { reads: vec![0; size], writes: vec![0; size], executes: vec![0; size] }
+    }
+
+    pub fn reads(&self) -> &[u16] {
+        &self.reads
+    }
+
+    pub fn writes(&self) -> &[u16] {
+        &self.writes
+    }
+
+    pub fn executes(&self) -> &[u16] {
+        &self.executes
+    }
+
+    // Every kind of access to addr together
+    pub fn total(&self, addr: usize) -> u32 {
+        [&self.reads, &self.writes, &self.executes].iter().map(|counts| counts.get(addr).copied().unwrap_or(0) as u32).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.fill(0);
+        self.writes.fill(0);
+        self.executes.fill(0);
+    }
+
+    fn read(&mut self, addr: usize) {
+        bump(&mut self.reads, addr);
+    }
+
+    fn write(&mut self, addr: usize) {
+        bump(&mut self.writes, addr);
+    }
+
+    fn execute(&mut self, addr: usize) {
+        bump(&mut self.executes, addr);
+    }
+
+    // The count most accessed runs of addresses, busiest first
+    pub fn hottest(&self, count: usize) -> Vec<HotRange> {
+        let mut runs: Vec<HotRange> = Vec::new();
+        for addr in (0..self.reads.len()).filter(|&addr| self.total(addr) > 0) {
+            let (reads, writes, executes) = (self.reads[addr] as u32, self.writes[addr] as u32, self.executes[addr] as u32);
+            match runs.last_mut() {
+                Some(run) if run.range.end == addr => {
+                    run.range.end = addr + 1;
+                    run.reads += reads;
+                    run.writes += writes;
+                    run.executes += executes;
+                }
+                _ => runs.push(HotRange { range: addr..addr + 1, reads, writes, executes }),
+            }
+        }
+        runs.sort_by(|a, b| b.total().cmp(&a.total()).then(a.range.start.cmp(&b.range.start)));
+        runs.truncate(count);
+        runs
+    }
+
+    // hottest as text, one line per run
+    pub fn report(&self, count: usize) -> Vec<String> {
+        self.hottest(count).iter()
+            .map(|run| format!("{:#05X}-{:#05X}  reads {:>7}  writes {:>7}  executes {:>7}",
+                run.range.start, run.range.end - 1, run.reads, run.writes, run.executes))
+            .collect()
+    }
+}
+
+fn bump(counts: &mut [u16], addr: usize) {
+    if let Some(count) = counts.get_mut(addr) {
+        *count = count.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn strict_write_protect_refuses_and_reports() {
+        let mut bus = WriteProtect::new(FlatMemory::new(), 0x50..0xA0);
+        assert!(bus.write(0x60, 7).is_err());
+        assert_eq!(bus.bytes()[0x60], 0);
+        assert_eq!(bus.take_violation(), Some(Access { addr: 0x60, value: 7, write: true }));
+        assert_eq!(bus.take_violation(), None);
+        assert!(bus.write(0xA0, 7).is_ok());
+        assert_eq!(bus.take_violation(), None);
+    }
+
+    #[test]
+    fn report_only_write_protect_lets_the_write_land() {
+        let mut bus = WriteProtect::report_only(FlatMemory::new());
+        bus.protect(0x200..0x300);
+        bus.protect(0x50..0x51);
+        assert!(bus.write(0x50, 9).is_ok());
+        assert_eq!(bus.bytes()[0x50], 9);
+        assert_eq!(bus.take_violation().map(|access| access.addr), Some(0x50));
+    }
+
+    #[test]
+    fn heatmap_bus_counts_fetches_apart_from_reads() {
+        let mut bus = HeatmapBus::new(FlatMemory::new());
+        bus.read(0x10).unwrap();
+        assert!(bus.heatmap().is_none(), "counting is off until enabled");
+        bus.set_enabled(true);
+        bus.read(0x10).unwrap();
+        bus.fetch(0x200).unwrap();
+        bus.fetch(0x201).unwrap();
+        bus.write(0x300, 1).unwrap();
+        let heatmap = bus.heatmap().unwrap();
+        assert_eq!(heatmap.reads()[0x10], 1);
+        assert_eq!(heatmap.reads()[0x200], 0);
+        assert_eq!(heatmap.executes()[0x200], 1);
+        assert_eq!(heatmap.writes()[0x300], 1);
+        assert_eq!(heatmap.total(0x201), 1);
+    }
+
+    #[test]
+    fn heatmap_counts_refused_accesses_too() {
+        let mut bus = HeatmapBus::new(WriteProtect::new(FlatMemory::new(), 0..0x200));
+        bus.set_enabled(true);
+        assert!(bus.write(0x100, 1).is_err());
+        assert!(bus.read(0x2000).is_err());
+        assert_eq!(bus.heatmap().unwrap().writes()[0x100], 1);
+    }
+
+    #[test]
+    fn hottest_merges_neighbouring_addresses() {
+        let mut heatmap = Heatmap::new(16);
+        heatmap.read(2);
+        heatmap.read(3);
+        heatmap.write(3);
+        heatmap.execute(9);
+        let hottest = heatmap.hottest(2);
+        assert_eq!(hottest[0], HotRange { range: 2..4, reads: 2, writes: 1, executes: 0 });
+        assert_eq!(hottest[1].range, 9..10);
+    }
+
+    #[test]
+    fn heatmap_counts_saturate() {
+        let mut heatmap = Heatmap::new(1);
+        for _ in 0..70_000 {
+            heatmap.read(0);
+        }
+        assert_eq!(heatmap.reads()[0], u16::MAX);
+    }
+
+    // I = 0x300, v0 = 123, store its digits with FX33 and read them back into v0-v2 with FX65
+    const BCD: [u8; 8] = [0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33, 0xF2, 0x65];
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Logged {
+        Fetch(usize),
+        Read(usize, u8),
+        Write(usize, u8),
+    }
+
+    // Decorator recording every access that reaches it, in order
+    struct LoggingBus<B> {
+        inner: B,
+        log: Vec<Logged>,
+    }
+
+    impl<B: MemoryBus> MemoryBus for LoggingBus<B> {
+        fn read(&mut self, addr: usize) -> Result<u8, String> {
+            let value = self.inner.read(addr)?;
+            self.log.push(Logged::Read(addr, value));
+            Ok(value)
+        }
+
+        fn write(&mut self, addr: usize, value: u8) -> Result<(), String> {
+            self.inner.write(addr, value)?;
+            self.log.push(Logged::Write(addr, value));
+            Ok(())
+        }
+
+        fn fetch(&mut self, addr: usize) -> Result<u8, String> {
+            self.log.push(Logged::Fetch(addr));
+            self.inner.fetch(addr)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn bytes(&self) -> &[u8] {
+            self.inner.bytes()
+        }
+
+        fn bytes_mut(&mut self) -> &mut [u8] {
+            self.inner.bytes_mut()
+        }
+    }
+
+    #[test]
+    fn a_decorator_sees_every_interpreter_access() {
+        let mut chip8 = Chip8::with_memory(LoggingBus { inner: FlatMemory::new(), log: Vec::new() });
+        chip8.load_rom_bytes(&BCD).unwrap();
+        assert!(chip8.memory().log.is_empty(), "loading the ROM skips the bus");
+        for _ in 0..4 {
+            chip8.cycle();
+        }
+
+        let log = &chip8.memory().log;
+        let fetches: Vec<usize> = log.iter().filter_map(|access| match access {
+            Logged::Fetch(addr) => Some(*addr),
+            _ => None,
+        }).collect();
+        assert_eq!(fetches, (0x200..0x208).collect::<Vec<_>>(), "both bytes of each opcode");
+        let data: Vec<Logged> = log.iter().copied().filter(|access| !matches!(access, Logged::Fetch(_))).collect();
+        assert_eq!(data, [
+            Logged::Write(0x300, 1), Logged::Write(0x301, 2), Logged::Write(0x302, 3),
+            Logged::Read(0x300, 1), Logged::Read(0x301, 2), Logged::Read(0x302, 3),
+        ]);
+        assert_eq!((chip8.register(0), chip8.register(1), chip8.register(2)), (1, 2, 3));
+    }
+
+    #[test]
+    fn watchpoints_log_only_their_ranges() {
+        let mut bus = Watchpoints::new(FlatMemory::new());
+        bus.watch(0x301..0x302);
+        bus.write(0x300, 1).unwrap();
+        bus.write(0x301, 2).unwrap();
+        assert_eq!(bus.read(0x301), Ok(2));
+        bus.fetch(0x301).unwrap();
+        assert_eq!(bus.take_hits(), [Access { addr: 0x301, value: 2, write: true }, Access { addr: 0x301, value: 2, write: false }]);
+        assert!(bus.take_hits().is_empty());
+    }
+}
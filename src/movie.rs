@@ -0,0 +1,367 @@
+use std::fs;
+use std::path::Path;
+
+use crate::chip8::{fnv1a, Chip8, Quirks, Variant};
+
+// Input movies: the session a movie was recorded in, then the held keys of every frame
+//
+//   0   "C8MV"
+//   4   format version
+//   5   ROM SHA-1, 20 bytes
+//   25  quirks: clip_sprites, load_store_increment, shift_vy, index_width, adi_overflow_vf, adi_overflow_width
+//   31  instruction set, 0 standard, 1 CHIP-8X, 2 CHIP-8E
+//   32  collision_delay quirk
+//   33  RNG seed, little endian u64
+//   41  emulator version length, then that many bytes of UTF-8
+//   ..  one little endian u16 key mask per frame
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u8 = 2;
+const FIXED_SIZE: usize = 42;                       // Everything before the emulator version string
+pub const EMULATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Everything a recording depends on besides the keys, replaying under anything else desyncs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieHeader {
+    pub rom_sha1: [u8; 20],
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub emulator_version: String,
+}
+
+impl MovieHeader {
+    // The resolved session of a machine that is about to start, not the flags that led to it
+    pub fn capture(chip8: &Chip8) -> Self {
+        MovieHeader {
+            rom_sha1: chip8.rom_sha1(),
+            quirks: chip8.quirks,
+            seed: chip8.seed(),
+            emulator_version: EMULATOR_VERSION.to_string(),
+        }
+    }
+
+    // One message per field that differs from the current session, empty when the movie can play
+    pub fn mismatches(&self, current: &MovieHeader) -> Vec<String> {
+        let hex = |digest: &[u8; 20]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mut fields = vec![
+            ("rom_sha1", hex(&self.rom_sha1), hex(&current.rom_sha1)),
+            ("emulator_version", self.emulator_version.clone(), current.emulator_version.clone()),
+            ("seed", self.seed.to_string(), current.seed.to_string()),
+        ];
+        let (recorded, now) = (&self.quirks, &current.quirks);
+        fields.extend([
+            ("clip_sprites", recorded.clip_sprites.to_string(), now.clip_sprites.to_string()),
+            ("load_store_increment", recorded.load_store_increment.to_string(), now.load_store_increment.to_string()),
+            ("shift_vy", recorded.shift_vy.to_string(), now.shift_vy.to_string()),
+            ("index_width", recorded.index_width.to_string(), now.index_width.to_string()),
+            ("adi_overflow_vf", recorded.adi_overflow_vf.to_string(), now.adi_overflow_vf.to_string()),
+            ("adi_overflow_width", recorded.adi_overflow_width.to_string(), now.adi_overflow_width.to_string()),
+            ("variant", recorded.variant.name().to_string(), now.variant.name().to_string()),
+            ("collision_delay", recorded.collision_delay.to_string(), now.collision_delay.to_string()),
+        ]);
+        fields.into_iter()
+            .filter(|(_, recorded, now)| recorded != now)
+            .map(|(name, recorded, now)| format!("movie was recorded with {}={}, current={}", name, recorded, now))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<u16>,               // Key mask held during each frame, bit n = key n
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Self {
+        Movie { header, frames: Vec::new() }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let header = &self.header;
+        let mut out = Vec::with_capacity(FIXED_SIZE + header.emulator_version.len() + self.frames.len() * 2);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&header.rom_sha1);
+        out.push(header.quirks.clip_sprites as u8);
+        out.push(header.quirks.load_store_increment as u8);
+        out.push(header.quirks.shift_vy as u8);
+        out.push(header.quirks.index_width);
+        out.push(header.quirks.adi_overflow_vf as u8);
+        out.push(header.quirks.adi_overflow_width);
+        out.push(header.quirks.variant as u8);
+        out.push(header.quirks.collision_delay as u8);
+        out.extend_from_slice(&header.seed.to_le_bytes());
+        out.push(header.emulator_version.len() as u8);
+        out.extend_from_slice(header.emulator_version.as_bytes());
+        for keys in &self.frames {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < FIXED_SIZE || &bytes[0..4] != MAGIC {
+            return Err("not a movie".to_string());
+        }
+        if bytes[4] != VERSION {
+            return Err(format!("movie format version {} is not supported, expected {}", bytes[4], VERSION));
+        }
+        if !matches!(bytes[30], 12 | 16) {
+            return Err(format!("movie has an FX1E overflow width of {} bits, expected 12 or 16", bytes[30]));
+        }
+        let variant = Variant::from_byte(bytes[31])
+            .ok_or_else(|| format!("movie has an unknown instruction set {}", bytes[31]))?;
+
+        let quirks = Quirks {
+            clip_sprites: bytes[25] != 0,
+            load_store_increment: bytes[26] != 0,
+            shift_vy: bytes[27] != 0,
+            index_width: bytes[28],
+            adi_overflow_vf: bytes[29] != 0,
+            adi_overflow_width: bytes[30],
+            variant,
+            collision_delay: bytes[32] != 0,
+        };
+        let version_end = FIXED_SIZE + bytes[41] as usize;
+        let emulator_version = bytes.get(FIXED_SIZE..version_end)
+            .and_then(|version| std::str::from_utf8(version).ok())
+            .ok_or("movie header is truncated or corrupt")?;
+        let keys = &bytes[version_end..];
+        if !keys.len().is_multiple_of(2) {
+            return Err("movie ends partway through a frame".to_string());
+        }
+
+        Ok(Movie {
+            header: MovieHeader {
+                rom_sha1: bytes[5..25].try_into().unwrap(),
+                quirks,
+                seed: u64::from_le_bytes(bytes[33..41].try_into().unwrap()),
+                emulator_version: emulator_version.to_string(),
+            },
+            frames: keys.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        Self::decode(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.encode()).map_err(|err| format!("could not write {}: {}", path.display(), err))
+    }
+}
+
+// Where a savestate sits in a movie: the frame it was taken on and a hash of the input up to there,
+// which tells a state on the current branch from one on a branch that was since re-recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieCursor {
+    pub frame: u64,
+    pub input_hash: u64,
+}
+
+impl MovieCursor {
+    pub const SIZE: usize = 16;
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.input_hash.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        MovieCursor {
+            frame: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            input_hash: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+}
+
+fn input_hash(frames: &[u16]) -> u64 {
+    let bytes: Vec<u8> = frames.iter().flat_map(|keys| keys.to_le_bytes()).collect();
+    fnv1a(&bytes)
+}
+
+// A movie being made or played back, one call to keys per emulated frame
+pub enum MovieSession {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+impl MovieSession {
+    // Keys for the coming frame: the live ones while recording, the recorded ones during playback,
+    // and the live ones again once a movie has played out
+    pub fn keys(&mut self, live: u16) -> u16 {
+        match self {
+            MovieSession::Recording(movie) => {
+                movie.frames.push(live);
+                live
+            }
+            MovieSession::Playing { movie, frame } => {
+                let keys = movie.frames.get(*frame).copied().unwrap_or(live);
+                *frame += 1;
+                keys
+            }
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        match self {
+            MovieSession::Recording(movie) | MovieSession::Playing { movie, .. } => movie,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self, MovieSession::Recording(_))
+    }
+
+    // Frames of the movie consumed so far
+    pub fn position(&self) -> usize {
+        match self {
+            MovieSession::Recording(movie) => movie.frames.len(),
+            MovieSession::Playing { movie, frame } => (*frame).min(movie.frames.len()),
+        }
+    }
+
+    // Position to store in a savestate taken now
+    pub fn cursor(&self) -> MovieCursor {
+        let frame = self.position();
+        MovieCursor { frame: frame as u64, input_hash: input_hash(&self.movie().frames[..frame]) }
+    }
+
+    // Re-record from here: playback drops the rest of the movie and appends live input from now on
+    pub fn rerecord(&mut self) {
+        if let MovieSession::Playing { movie, frame } = self {
+            let mut movie = std::mem::replace(movie, Movie::new(movie.header.clone()));
+            movie.frames.truncate(*frame);
+            *self = MovieSession::Recording(movie);
+        }
+    }
+
+    // Whether a savestate at this cursor belongs to the movie: states from past its end or from another
+    // branch of it are refused
+    pub fn check(&self, cursor: MovieCursor) -> Result<(), String> {
+        let frames = &self.movie().frames;
+        let Some(recorded) = frames.get(..cursor.frame as usize) else {
+            return Err(format!("state is from movie frame {}, the movie only has {}", cursor.frame, frames.len()));
+        };
+        if input_hash(recorded) != cursor.input_hash {
+            return Err(format!("state is from movie frame {} of a branch that has since been re-recorded", cursor.frame));
+        }
+        Ok(())
+    }
+
+    // Follow a savestate that was just loaded. While recording everything after the state is dropped so
+    // recording carries on from it; during playback the movie plays on from the state's frame
+    pub fn seek(&mut self, cursor: MovieCursor) -> Result<(), String> {
+        self.check(cursor)?;
+        match self {
+            MovieSession::Recording(movie) => movie.frames.truncate(cursor.frame as usize),
+            MovieSession::Playing { frame, .. } => *frame = cursor.frame as usize,
+        }
+        Ok(())
+    }
+
+    // Whether playback just ran out of recorded frames, true for exactly one frame
+    pub fn just_ended(&self) -> bool {
+        matches!(self, MovieSession::Playing { movie, frame } if *frame == movie.frames.len() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> MovieHeader {
+        MovieHeader { rom_sha1: [0xAB; 20], quirks: Quirks::default(), seed: 42, emulator_version: "1.2.3".to_string() }
+    }
+
+    #[test]
+    fn a_matching_session_has_no_mismatches() {
+        assert!(header().mismatches(&header()).is_empty());
+    }
+
+    #[test]
+    fn each_tampered_field_is_reported_by_name() {
+        type Tamper = fn(&mut MovieHeader);
+        let tampered: Vec<(&str, Tamper)> = vec![
+            ("rom_sha1", |h| h.rom_sha1[19] = 0),
+            ("emulator_version", |h| h.emulator_version = "1.2.4".to_string()),
+            ("seed", |h| h.seed = 43),
+            ("clip_sprites", |h| h.quirks.clip_sprites ^= true),
+            ("load_store_increment", |h| h.quirks.load_store_increment ^= true),
+            ("shift_vy", |h| h.quirks.shift_vy ^= true),
+            ("index_width", |h| h.quirks.index_width = 16),
+            ("adi_overflow_vf", |h| h.quirks.adi_overflow_vf ^= true),
+            ("adi_overflow_width", |h| h.quirks.adi_overflow_width = 16),
+            ("variant", |h| h.quirks.variant = Variant::Chip8X),
+            ("collision_delay", |h| h.quirks.collision_delay ^= true),
+        ];
+        for (name, tamper) in tampered {
+            let mut current = header();
+            tamper(&mut current);
+            let mismatches = header().mismatches(&current);
+            assert_eq!(mismatches.len(), 1, "{}: {:?}", name, mismatches);
+            assert!(mismatches[0].starts_with(&format!("movie was recorded with {}=", name)), "{}", mismatches[0]);
+        }
+    }
+
+    #[test]
+    fn mismatches_show_the_recorded_and_current_values() {
+        let mut current = header();
+        current.seed = 7;
+        assert_eq!(header().mismatches(&current), ["movie was recorded with seed=42, current=7"]);
+    }
+
+    #[test]
+    fn movies_round_trip_through_encode() {
+        let mut movie = Movie::new(MovieHeader { quirks: Quirks { shift_vy: true, index_width: 16, variant: Variant::Chip8E, collision_delay: true, ..Quirks::default() }, ..header() });
+        movie.frames = vec![0, 0x0020, 0x8001];
+        let bytes = movie.encode();
+        assert_eq!(bytes.len(), FIXED_SIZE + 5 + 6);
+        assert_eq!(Movie::decode(&bytes).unwrap(), movie);
+    }
+
+    #[test]
+    fn damaged_movies_are_refused() {
+        let mut movie = Movie::new(header());
+        movie.frames = vec![1];
+        let bytes = movie.encode();
+        assert_eq!(Movie::decode(b"C8SV").unwrap_err(), "not a movie");
+        let mut future = bytes.clone();
+        future[4] = VERSION + 1;
+        assert!(Movie::decode(&future).unwrap_err().contains("not supported"));
+        let mut wide = bytes.clone();
+        wide[30] = 40;
+        assert_eq!(Movie::decode(&wide).unwrap_err(), "movie has an FX1E overflow width of 40 bits, expected 12 or 16");
+        let mut unknown = bytes.clone();
+        unknown[31] = 3;
+        assert_eq!(Movie::decode(&unknown).unwrap_err(), "movie has an unknown instruction set 3");
+        assert_eq!(Movie::decode(&bytes[..FIXED_SIZE + 2]).unwrap_err(), "movie header is truncated or corrupt");
+        assert_eq!(Movie::decode(&bytes[..bytes.len() - 1]).unwrap_err(), "movie ends partway through a frame");
+    }
+
+    #[test]
+    fn rerecording_truncates_playback_and_refuses_states_from_the_old_branch() {
+        let movie = Movie { header: header(), frames: vec![1, 2, 3, 4, 5] };
+        let mut session = MovieSession::Playing { movie, frame: 0 };
+        assert_eq!((session.keys(0), session.keys(0)), (1, 2));
+        let old_branch = MovieCursor { frame: 4, input_hash: input_hash(&[1, 2, 3, 4]) };
+        let shared = session.cursor();
+        assert_eq!(shared, MovieCursor { frame: 2, input_hash: input_hash(&[1, 2]) });
+
+        session.rerecord();
+        assert!(session.is_recording());
+        assert_eq!(session.keys(9), 9, "live input from the re-record point");
+        assert_eq!(session.movie().frames, [1, 2, 9]);
+        assert_eq!(session.check(old_branch).unwrap_err(), "state is from movie frame 4, the movie only has 3");
+        session.keys(9);
+        assert_eq!(session.check(old_branch).unwrap_err(), "state is from movie frame 4 of a branch that has since been re-recorded");
+
+        session.seek(shared).unwrap();
+        assert_eq!(session.movie().frames, [1, 2], "recording carries on from the loaded state");
+        assert_eq!(MovieCursor::from_bytes(&shared.to_bytes()), shared);
+    }
+}
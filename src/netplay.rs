@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Lockstep two player netplay over UDP
+// Each frame both peers send their local key mask for frame N and neither
+// advances the core until the other's input for frame N has arrived.
+
+const PROTOCOL_VERSION: u8 = 1;
+const MSG_HELLO: u8 = 0x01;
+const MSG_INPUT: u8 = 0x02;
+const RESEND_INTERVAL: Duration = Duration::from_millis(15);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+const INPUT_TIMEOUT: Duration = Duration::from_millis(500);
+const REDUNDANT_FRAMES: usize = 8;     // Previous inputs repeated in every packet to cover packet loss
+
+// Everything both simulations must agree on before the first frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub ips: u32,
+}
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Timeout,                        // Peer input didn't arrive in time, safe to retry
+    Mismatch(String),               // Handshake found the peers would diverge
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetplayError::Io(err) => write!(f, "netplay socket error: {}", err),
+            NetplayError::Timeout => write!(f, "waiting for peer"),
+            NetplayError::Mismatch(msg) => write!(f, "netplay session mismatch: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        NetplayError::Io(err)
+    }
+}
+
+pub struct Netplay {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    frame: u32,                     // Frame whose inputs are being exchanged
+    local: Vec<(u32, u16)>,         // Recently sent local inputs, oldest first
+    remote: HashMap<u32, u16>,      // Received peer inputs not yet consumed
+    last_send: Option<Instant>,
+    session: Session,               // Agreed session, the host's seed wins
+}
+
+impl Netplay {
+    // Wait for a peer to connect on the given port
+    pub fn listen(port: u16, session: Session) -> Result<Self, NetplayError> {
+        Self::accept(UdpSocket::bind(("0.0.0.0", port))?, session)
+    }
+
+    // Wait for a peer's hello on an already bound socket
+    fn accept(socket: UdpSocket, session: Session) -> Result<Self, NetplayError> {
+        socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        let mut buf = [0u8; 64];
+        let (len, peer) = socket.recv_from(&mut buf).map_err(timeout_or_io)?;
+        check_hello(&buf[..len], &session)?;
+        socket.send_to(&hello(&session), peer)?;
+
+        Self::with_peer(socket, peer, session)
+    }
+
+    // Connect to a listening peer at host:port
+    pub fn connect(addr: &str, session: Session) -> Result<Self, NetplayError> {
+        let peer = addr.to_socket_addrs()?.next()
+            .ok_or_else(|| NetplayError::Mismatch(format!("could not resolve '{}'", addr)))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_read_timeout(Some(RESEND_INTERVAL))?;
+
+        // Keep saying hello until the host answers
+        let start = Instant::now();
+        let mut buf = [0u8; 64];
+        loop {
+            socket.send_to(&hello(&session), peer)?;
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer => {
+                    let seed = check_hello(&buf[..len], &session)?;
+                    return Self::with_peer(socket, peer, Session { seed, ..session });
+                }
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+            if start.elapsed() > HANDSHAKE_TIMEOUT {
+                return Err(NetplayError::Timeout);
+            }
+        }
+    }
+
+    fn with_peer(socket: UdpSocket, peer: SocketAddr, session: Session) -> Result<Self, NetplayError> {
+        socket.set_read_timeout(Some(RESEND_INTERVAL))?;
+        Ok(Netplay {
+            socket,
+            peer,
+            frame: 0,
+            local: Vec::new(),
+            remote: HashMap::new(),
+            last_send: None,
+            session,
+        })
+    }
+
+    // Session both peers agreed on, apply its seed to the core before the first frame
+    pub fn session(&self) -> Session {
+        self.session
+    }
+
+    // Frame number of the next exchange
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Send the local input for the current frame and block until the peer's arrives
+    // On Timeout nothing advances; call again with any input and the original one is resent
+    pub fn exchange(&mut self, local_keys: u16) -> Result<u16, NetplayError> {
+        if self.local.last().map(|&(frame, _)| frame) != Some(self.frame) {
+            self.local.push((self.frame, local_keys));
+            if self.local.len() > REDUNDANT_FRAMES {
+                self.local.remove(0);
+            }
+            self.last_send = None;
+        }
+
+        let start = Instant::now();
+        let mut buf = [0u8; 128];
+        while !self.remote.contains_key(&self.frame) {
+            if self.last_send.is_none_or(|sent| sent.elapsed() >= RESEND_INTERVAL) {
+                self.send_inputs()?;
+            }
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.peer => self.receive(&buf[..len]),
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+            if start.elapsed() > INPUT_TIMEOUT {
+                return Err(NetplayError::Timeout);
+            }
+        }
+
+        // Resend once more so the peer isn't left waiting on our final packet
+        self.send_inputs()?;
+        let remote_keys = self.remote.remove(&self.frame).unwrap_or(0);
+        self.frame += 1;
+        Ok(remote_keys)
+    }
+
+    // Packet: tag, count, then (frame u32, keys u16) little endian for each recent input
+    fn send_inputs(&mut self) -> Result<(), NetplayError> {
+        let mut packet = vec![MSG_INPUT, self.local.len() as u8];
+        for &(frame, keys) in &self.local {
+            packet.extend_from_slice(&frame.to_le_bytes());
+            packet.extend_from_slice(&keys.to_le_bytes());
+        }
+        self.socket.send_to(&packet, self.peer)?;
+        self.last_send = Some(Instant::now());
+        Ok(())
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        if packet.len() < 2 || packet[0] != MSG_INPUT {
+            return;                             // Late hello or garbage
+        }
+        for entry in packet[2..].chunks_exact(6).take(packet[1] as usize) {
+            let frame = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            let keys = u16::from_le_bytes([entry[4], entry[5]]);
+            if frame >= self.frame {
+                self.remote.insert(frame, keys);
+            }
+        }
+    }
+}
+
+// Packet: tag, version, rom hash u64, seed u64, ips u32 little endian
+fn hello(session: &Session) -> Vec<u8> {
+    let mut packet = vec![MSG_HELLO, PROTOCOL_VERSION];
+    packet.extend_from_slice(&session.rom_hash.to_le_bytes());
+    packet.extend_from_slice(&session.seed.to_le_bytes());
+    packet.extend_from_slice(&session.ips.to_le_bytes());
+    packet
+}
+
+// Verify the peer is running the same ROM and speed, returns the peer's seed
+fn check_hello(packet: &[u8], session: &Session) -> Result<u64, NetplayError> {
+    if packet.len() != 22 || packet[0] != MSG_HELLO {
+        return Err(NetplayError::Mismatch("unexpected handshake packet".to_string()));
+    }
+    if packet[1] != PROTOCOL_VERSION {
+        return Err(NetplayError::Mismatch(format!("peer protocol version {}, ours {}", packet[1], PROTOCOL_VERSION)));
+    }
+
+    let u64_at = |at: usize| u64::from_le_bytes(packet[at..at + 8].try_into().unwrap());
+    let peer = Session {
+        rom_hash: u64_at(2),
+        seed: u64_at(10),
+        ips: u32::from_le_bytes(packet[18..22].try_into().unwrap()),
+    };
+    if peer.rom_hash != session.rom_hash {
+        return Err(NetplayError::Mismatch(format!("peer ROM hash {:016x}, ours {:016x}", peer.rom_hash, session.rom_hash)));
+    }
+    if peer.ips != session.ips {
+        return Err(NetplayError::Mismatch(format!("peer runs {} IPS, ours {}", peer.ips, session.ips)));
+    }
+    Ok(peer.seed)
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+fn timeout_or_io(err: io::Error) -> NetplayError {
+    if is_timeout(&err) {
+        NetplayError::Timeout
+    } else {
+        NetplayError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::chip8::{fnv1a, Chip8};
+
+    // Random numbers, and a counter for each of keys 5 and 7 while they're held
+    const ROM: [u8; 16] = [
+        0xC0, 0xFF,                     // 200: v0 = rand
+        0x61, 0x05,                     // 202: v1 = 5
+        0xE1, 0xA1,                     // 204: skip unless key 5
+        0x72, 0x01,                     // 206: v2 += 1
+        0x61, 0x07,                     // 208: v1 = 7
+        0xE1, 0xA1,                     // 20A: skip unless key 7
+        0x73, 0x01,                     // 20C: v3 += 1
+        0x12, 0x00,                     // 20E: jmp 200
+    ];
+    const FRAMES: u32 = 120;
+
+    fn session(seed: u64) -> Session {
+        Session { rom_hash: fnv1a(&ROM), seed, ips: 600 }
+    }
+
+    // Play FRAMES frames holding key now and then, returning the machine's state hash after each
+    fn play(mut netplay: Netplay, key: u8, period: u32) -> Vec<u64> {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8.set_seed(netplay.session().seed);
+        (0..FRAMES).map(|frame| {
+            let local = if frame % period < 2 { 1 << key } else { 0 };
+            let remote = loop {
+                match netplay.exchange(local) {
+                    Ok(remote) => break remote,
+                    Err(NetplayError::Timeout) => continue,
+                    Err(err) => panic!("{}", err),
+                }
+            };
+            chip8.set_keys_mask(local | remote);
+            chip8.step_frame(10);
+            fnv1a(&chip8.save_state())
+        }).collect()
+    }
+
+    #[test]
+    fn loopback_peers_hash_identically_every_frame() {
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let host = thread::spawn(move || play(Netplay::accept(socket, session(1234)).unwrap(), 5, 5));
+        let guest = play(Netplay::connect(&addr, session(99)).unwrap(), 7, 3);
+        let host = host.join().unwrap();
+        assert_eq!(host.len(), FRAMES as usize);
+        assert_eq!(host, guest);
+
+        let mut solo = Chip8::new();
+        solo.load_rom_bytes(&ROM).unwrap();
+        solo.set_seed(1234);
+        solo.set_keys_mask(1 << 5 | 1 << 7);
+        solo.step_frame(10);
+        assert_eq!(host[0], fnv1a(&solo.save_state()), "the guest took the host's seed and both players' keys");
+    }
+
+    #[test]
+    fn handshakes_refuse_a_different_rom_or_speed() {
+        let ours = session(1);
+        assert_eq!(check_hello(&hello(&Session { seed: 77, ..ours }), &ours).unwrap(), 77);
+        let other_rom = check_hello(&hello(&Session { rom_hash: 1, ..ours }), &ours).unwrap_err();
+        assert!(other_rom.to_string().contains("peer ROM hash"));
+        let other_speed = check_hello(&hello(&Session { ips: 700, ..ours }), &ours).unwrap_err();
+        assert!(other_speed.to_string().contains("peer runs 700 IPS, ours 600"));
+    }
+}
@@ -0,0 +1,142 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;      // One pixel gap between characters
+
+// 3x5 frontend font, one entry per row with bit 2 = leftmost pixel
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        ' ' => [0b000; GLYPH_HEIGHT],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],  // ?
+    }
+}
+
+// Width in font pixels of a line of text
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * GLYPH_ADVANCE).saturating_sub(1)
+}
+
+// Call plot(x, y) for every lit font pixel of the text, relative to its top left corner
+pub fn render_text(text: &str, mut plot: impl FnMut(usize, usize)) {
+    for (i, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    plot(i * GLYPH_ADVANCE + col, row);
+                }
+            }
+        }
+    }
+}
+
+// The scale x scale squares covering every lit font pixel of the text drawn with its top left at (x, y)
+pub fn text_rects(text: &str, x: i32, y: i32, scale: u32) -> Vec<Rect> {
+    let mut rects = Vec::new();
+    render_text(text, |px, py| {
+        rects.push(Rect::new(x + (px as u32 * scale) as i32, y + (py as u32 * scale) as i32, scale, scale));
+    });
+    rects
+}
+
+// Draw text onto the window with each font pixel scaled to a scale x scale square
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: u32, color: Color) -> Result<(), String> {
+    canvas.set_draw_color(color);
+    canvas.fill_rects(&text_rects(text, x, y, scale))
+}
+
+// Paint the squares into a width x height framebuffer of lit flags, for tests of what text_rects draws
+#[cfg(test)]
+pub fn composite(rects: &[Rect], width: usize, height: usize) -> Vec<bool> {
+    let mut frame = vec![false; width * height];
+    for rect in rects {
+        for y in rect.top()..rect.bottom() {
+            for x in rect.left()..rect.right() {
+                frame[y as usize * width + x as usize] = true;
+            }
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_composites_at_its_offset() {
+        let frame = composite(&text_rects("HI", 2, 1, 1), 10, 7);
+        let rows: Vec<String> = frame.chunks(10).map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect()).collect();
+        assert_eq!(rows, [
+            "..........",
+            "..#.#.###.",
+            "..#.#..#..",
+            "..###..#..",
+            "..#.#..#..",
+            "..#.#.###.",
+            "..........",
+        ]);
+    }
+
+    #[test]
+    fn scaled_text_fills_its_width() {
+        let rects = text_rects("HALTED", 0, 0, 2);
+        let right = rects.iter().map(|rect| rect.right()).max().unwrap();
+        let bottom = rects.iter().map(|rect| rect.bottom()).max().unwrap();
+        assert_eq!(right, text_width("HALTED") as i32 * 2);
+        assert_eq!(bottom, GLYPH_HEIGHT as i32 * 2);
+        assert!(rects.iter().all(|rect| rect.width() == 2 && rect.height() == 2));
+    }
+}
@@ -0,0 +1,311 @@
+use core::ops::Range;
+
+use crate::display::Display;
+use crate::prelude::*;
+
+// Hardware display output: the framebuffer scaled by a whole factor and centered on a panel of any
+// size, redrawing only the rows that changed since the last frame so little has to go over SPI or I2C.
+// Drawing goes through PanelSink, which the embedded-graphics feature implements for draw targets
+
+// Where the picture sits on the panel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub scale: u32,                     // Panel pixels per CHIP-8 pixel, 0 when the panel is too small
+    pub left: u32,
+    pub top: u32,
+}
+
+impl Placement {
+    // The largest whole scale that fits a picture of the given resolution on a panel of width x height, centered
+    pub fn fit(width: u32, height: u32, (columns, rows): (usize, usize)) -> Self {
+        let (columns, rows) = (columns.max(1) as u32, rows.max(1) as u32);
+        let scale = (width / columns).min(height / rows);
+        Placement {
+            scale,
+            left: (width - columns * scale) / 2,
+            top: (height - rows * scale) / 2,
+        }
+    }
+}
+
+// Something that can fill rectangles of lit or dark pixels, such as a monochrome draw target or a
+// color one showing lit and dark in two palette colors
+pub trait PanelSink {
+    type Error;
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, lit: bool) -> Result<(), Self::Error>;
+}
+
+// Draws frames to a panel, remembering what it last drew to tell which rows changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Panel {
+    size: (u32, u32),                   // Panel width and height in its own pixels
+    shown: Option<Display>,             // Picture on the panel, None until the first frame is drawn
+}
+
+impl Panel {
+    pub fn new(width: u32, height: u32) -> Self {
+        Panel { size: (width, height), shown: None }
+    }
+
+    // Where a picture at the display's resolution goes, 00FE/00FF move it and change its scale
+    pub fn placement(&self, display: &Display) -> Placement {
+        Placement::fit(self.size.0, self.size.1, display.resolution())
+    }
+
+    // Forget what is on the panel so the next frame is drawn in full, after the panel was cleared
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    // Runs of rows that differ from what the panel shows, every row before the first frame and after a
+    // switch of resolution
+    pub fn dirty_rows(&self, display: &Display) -> Vec<Range<usize>> {
+        let (width, height) = display.resolution();
+        let shown = match &self.shown {
+            Some(shown) if shown.resolution() == display.resolution() => shown,
+            _ => return core::iter::once(0..height).collect(),
+        };
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for y in (0..height).filter(|&y| shown[y * width..(y + 1) * width] != display[y * width..(y + 1) * width]) {
+            match runs.last_mut() {
+                Some(run) if run.end == y => run.end = y + 1,
+                _ => runs.push(y..y + 1),
+            }
+        }
+        runs
+    }
+
+    // Draw the rows that changed, each horizontal run of same colored pixels as one filled rectangle. A
+    // switch of resolution blanks the whole panel first, the picture's bars change with its scale
+    pub fn draw<S: PanelSink>(&mut self, display: &Display, sink: &mut S) -> Result<(), S::Error> {
+        let Placement { scale, left, top } = self.placement(display);
+        if scale == 0 {
+            return Ok(());
+        }
+        if self.shown.as_ref().is_some_and(|shown| shown.resolution() != display.resolution()) {
+            sink.fill(0, 0, self.size.0, self.size.1, false)?;
+        }
+        let width = display.width();
+        for rows in self.dirty_rows(display) {
+            for y in rows {
+                let row = &display[y * width..(y + 1) * width];
+                let mut start = 0;
+                while start < width {
+                    let lit = row[start] != 0;
+                    let end = row[start..].iter().position(|&pixel| (pixel != 0) != lit).map_or(width, |run| start + run);
+                    sink.fill(left + start as u32 * scale, top + y as u32 * scale, (end - start) as u32 * scale, scale, lit)?;
+                    start = end;
+                }
+            }
+        }
+        self.shown = Some(display.clone());
+        Ok(())
+    }
+}
+
+// embedded-graphics draw targets as sinks: a monochrome one such as an SSD1306 driver shows lit pixels as
+// On, a color one such as an ST7789 driver shows lit and dark in two palette colors
+#[cfg(feature = "embedded-graphics")]
+pub mod graphics {
+    use embedded_graphics::draw_target::DrawTarget;
+    use embedded_graphics::geometry::{Point, Size};
+    use embedded_graphics::pixelcolor::{BinaryColor, Rgb888};
+    use embedded_graphics::primitives::Rectangle;
+
+    use super::PanelSink;
+
+    fn area(x: u32, y: u32, width: u32, height: u32) -> Rectangle {
+        Rectangle::new(Point::new(x as i32, y as i32), Size::new(width, height))
+    }
+
+    pub struct BinaryTarget<'a, D>(pub &'a mut D);
+
+    impl<D: DrawTarget<Color = BinaryColor>> PanelSink for BinaryTarget<'_, D> {
+        type Error = D::Error;
+
+        fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, lit: bool) -> Result<(), D::Error> {
+            self.0.fill_solid(&area(x, y, width, height), BinaryColor::from(lit))
+        }
+    }
+
+    pub struct PaletteTarget<'a, D: DrawTarget> {
+        target: &'a mut D,
+        lit: D::Color,
+        dark: D::Color,
+    }
+
+    impl<'a, D: DrawTarget> PaletteTarget<'a, D> where D::Color: From<Rgb888> {
+        // Lit pixels in fg and dark ones in bg, RGBA like the rest of the renderers with alpha ignored
+        pub fn new(target: &'a mut D, fg: [u8; 4], bg: [u8; 4]) -> Self {
+            let color = |[r, g, b, _]: [u8; 4]| D::Color::from(Rgb888::new(r, g, b));
+            PaletteTarget { target, lit: color(fg), dark: color(bg) }
+        }
+    }
+
+    impl<D: DrawTarget> PanelSink for PaletteTarget<'_, D> {
+        type Error = D::Error;
+
+        fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, lit: bool) -> Result<(), D::Error> {
+            self.target.fill_solid(&area(x, y, width, height), if lit { self.lit } else { self.dark })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::display::Display;
+        use crate::panel::Panel;
+        use crate::render::{BLACK, WHITE};
+        use embedded_graphics::mock_display::MockDisplay;
+        use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+
+        #[test]
+        fn a_known_frame_lands_on_a_binary_target() {
+            let mut display = Display::new();
+            display.toggle(0, 0);
+            display.toggle(63, 31);
+            let mut panel = Panel::new(64, 48);
+            let mut target = MockDisplay::<BinaryColor>::new();
+            panel.draw(&display, &mut BinaryTarget(&mut target)).unwrap();
+            assert_eq!(target.affected_area(), area(0, 8, 64, 32), "centered at scale 1");
+            assert_eq!(target.get_pixel(Point::new(0, 8)), Some(BinaryColor::On));
+            assert_eq!(target.get_pixel(Point::new(1, 8)), Some(BinaryColor::Off));
+            assert_eq!(target.get_pixel(Point::new(63, 39)), Some(BinaryColor::On));
+
+            // A fresh mock refuses overdraw, so this also checks nothing past the changed row is sent
+            display.toggle(5, 20);
+            let mut target = MockDisplay::<BinaryColor>::new();
+            panel.draw(&display, &mut BinaryTarget(&mut target)).unwrap();
+            assert_eq!(target.affected_area(), area(0, 28, 64, 1), "only row 20 is redrawn");
+        }
+
+        #[test]
+        fn color_targets_show_the_palette() {
+            let mut display = Display::new();
+            display.toggle(1, 0);
+            let mut panel = Panel::new(64, 64);
+            let mut target = MockDisplay::<Rgb565>::new();
+            panel.draw(&display, &mut PaletteTarget::new(&mut target, [255, 0, 0, 255], BLACK)).unwrap();
+            assert_eq!(target.get_pixel(Point::new(1, 16)), Some(Rgb565::RED));
+            assert_eq!(target.get_pixel(Point::new(0, 16)), Some(Rgb565::BLACK));
+            assert_eq!(target.get_pixel(Point::new(0, 0)), None, "the bars above and below aren't touched");
+
+            let mut target = MockDisplay::<Rgb565>::new();
+            panel.invalidate();
+            panel.draw(&display, &mut PaletteTarget::new(&mut target, WHITE, BLACK)).unwrap();
+            assert_eq!(target.get_pixel(Point::new(1, 16)), Some(Rgb565::WHITE));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stand-in for a monochrome draw target: a panel framebuffer plus every rectangle filled into it
+    struct MockTarget {
+        width: u32,
+        pixels: Vec<bool>,
+        fills: Vec<(u32, u32, u32, u32, bool)>,
+    }
+
+    impl MockTarget {
+        fn new(width: u32, height: u32) -> Self {
+            MockTarget { width, pixels: vec![false; (width * height) as usize], fills: Vec::new() }
+        }
+
+        fn lit(&self, x: u32, y: u32) -> bool {
+            self.pixels[(x + y * self.width) as usize]
+        }
+    }
+
+    impl PanelSink for MockTarget {
+        type Error = String;
+
+        fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, lit: bool) -> Result<(), String> {
+            if x + width > self.width || (y + height) * self.width > self.pixels.len() as u32 {
+                return Err(format!("{}x{} at ({}, {}) is off the panel", width, height, x, y));
+            }
+            for row in y..y + height {
+                for column in x..x + width {
+                    self.pixels[(column + row * self.width) as usize] = lit;
+                }
+            }
+            self.fills.push((x, y, width, height, lit));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pictures_take_the_largest_whole_scale_centered() {
+        assert_eq!(Placement::fit(128, 64, (64, 32)), Placement { scale: 2, left: 0, top: 0 });
+        assert_eq!(Placement::fit(132, 70, (64, 32)), Placement { scale: 2, left: 2, top: 3 });
+        assert_eq!(Placement::fit(240, 240, (128, 64)), Placement { scale: 1, left: 56, top: 88 });
+        assert_eq!(Placement::fit(32, 16, (64, 32)).scale, 0, "too small a panel");
+    }
+
+    #[test]
+    fn a_known_frame_lands_scaled_on_the_panel() {
+        let mut display = Display::new();
+        for x in [0, 1, 2, 63] {
+            display.toggle(x, 0);
+        }
+        display.toggle(5, 31);
+        let mut panel = Panel::new(132, 70);
+        let mut target = MockTarget::new(132, 70);
+        panel.draw(&display, &mut target).unwrap();
+
+        for y in 0..70 {
+            for x in 0..132 {
+                let inside = (2..130).contains(&x) && (3..67).contains(&y);
+                let expected = inside && display.pixel(((x - 2) / 2) as usize, ((y - 3) / 2) as usize);
+                assert_eq!(target.lit(x, y), expected, "panel pixel ({}, {})", x, y);
+            }
+        }
+        assert_eq!(target.fills[..3], [(2, 3, 6, 2, true), (8, 3, 120, 2, false), (128, 3, 2, 2, true)], "runs fill as one rectangle");
+        assert_eq!(target.fills.len(), 3 + 30 + 3, "one fill per run, one for each blank row");
+    }
+
+    #[test]
+    fn only_changed_rows_are_redrawn() {
+        let mut display = Display::new();
+        let mut panel = Panel::new(128, 64);
+        let mut target = MockTarget::new(128, 64);
+        panel.draw(&display, &mut target).unwrap();
+        assert_eq!(target.fills.len(), 32);
+
+        target.fills.clear();
+        panel.draw(&display, &mut target).unwrap();
+        assert!(target.fills.is_empty(), "nothing changed");
+
+        display.toggle(10, 7);
+        display.toggle(10, 8);
+        assert_eq!(panel.dirty_rows(&display), vec![7..9]);
+        panel.draw(&display, &mut target).unwrap();
+        assert!(target.fills.iter().all(|&(_, y, _, _, _)| y == 14 || y == 16), "{:?}", target.fills);
+        assert!(target.lit(20, 14) && target.lit(21, 17) && !target.lit(22, 14));
+
+        target.fills.clear();
+        panel.invalidate();
+        panel.draw(&display, &mut target).unwrap();
+        assert_eq!(target.fills.len(), 32 + 4, "a full redraw after invalidate");
+    }
+
+    #[test]
+    fn switching_resolution_blanks_the_panel_first() {
+        let mut display = Display::new();
+        display.toggle(0, 0);
+        let mut panel = Panel::new(128, 64);
+        let mut target = MockTarget::new(128, 64);
+        panel.draw(&display, &mut target).unwrap();
+        assert!(target.lit(1, 1));
+
+        display.set_hires(true);
+        target.fills.clear();
+        panel.draw(&display, &mut target).unwrap();
+        assert_eq!(target.fills[0], (0, 0, 128, 64, false));
+        assert_eq!(target.fills.len(), 1 + 64);
+        assert!(!target.lit(1, 1), "the low resolution pixel is gone");
+    }
+}
@@ -0,0 +1,109 @@
+use core::fmt;
+
+use crate::chip8::Quirks;
+use crate::prelude::*;
+
+// Instructions remembered for the report of a broken invariant
+pub const HISTORY_LEN: usize = 16;
+
+// The last HISTORY_LEN instruction addresses, oldest first once it wraps
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PcHistory {
+    pcs: [u16; HISTORY_LEN],
+    next: usize,                        // Slot the next address goes in
+    len: usize,                         // Slots filled so far, up to HISTORY_LEN
+}
+
+impl Default for PcHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PcHistory {
+    pub fn new() -> Self {
+        PcHistory { pcs: [0; HISTORY_LEN], next: 0, len: 0 }
+    }
+
+    pub fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = (self.len + 1).min(HISTORY_LEN);
+    }
+
+    // Addresses oldest first
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let start = (self.next + HISTORY_LEN - self.len) % HISTORY_LEN;
+        (0..self.len).map(move |step| self.pcs[(start + step) % HISTORY_LEN])
+    }
+}
+
+// Space separated, the most recent address last
+impl fmt::Display for PcHistory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, pc) in self.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#05X}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+// What the core is in the middle of, as far as the checks below are concerned
+pub struct Snapshot<'a> {
+    pub pc: u16,
+    pub sp: u16,
+    pub stack_capacity: usize,
+    pub index: u16,
+    pub memory_len: usize,
+    pub bus_fault: bool,                // The bus refused an access of this instruction, or one not yet taken
+    pub quirks: &'a Quirks,
+    pub resolution: (usize, usize),
+    pub pixels: &'a [u8],               // The display at its current resolution
+    pub spare_pixels: &'a [u8],         // The rest of the display buffer, blank unless hires is on
+}
+
+// Every invariant the state breaks, empty for a sane machine. pc can be odd, CHIP-8 code isn't aligned. It
+// can sit right at the end of memory after the last word ran, and go further once the bus has reported
+// fetching out there: that is a ROM running off the end, which the bus fault already tells the frontend
+pub fn violations(state: &Snapshot) -> Vec<String> {
+    let mut broken = Vec::new();
+    if state.sp as usize > state.stack_capacity {
+        broken.push(format!("stack pointer {} is past the {} slot stack", state.sp, state.stack_capacity));
+    }
+    if state.pc as usize > state.memory_len && !state.bus_fault {
+        broken.push(format!("pc {:#06X} is past the end of the {} bytes of memory", state.pc, state.memory_len));
+    }
+    if state.quirks.index_width == 12 && state.index > 0x0FFF {
+        broken.push(format!("I {:#06X} is wider than 12 bits", state.index));
+    }
+    let (width, height) = state.resolution;
+    if state.pixels.len() != width * height {
+        broken.push(format!("display holds {} pixels at {}x{}", state.pixels.len(), width, height));
+    }
+    if let Some(idx) = state.pixels.iter().position(|&pixel| pixel > 1) {
+        broken.push(format!("pixel ({}, {}) is {}, not 0 or 1", idx % width, idx / width, state.pixels[idx]));
+    }
+    if state.spare_pixels.iter().any(|&pixel| pixel != 0) {
+        broken.push(format!("pixels outside the {}x{} screen are lit", width, height));
+    }
+    broken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_last_addresses_in_order() {
+        let mut history = PcHistory::new();
+        for pc in 0..20 {
+            history.push(0x200 + pc * 2);
+        }
+        assert_eq!(history.iter().count(), HISTORY_LEN);
+        assert_eq!(history.iter().next(), Some(0x208));
+        assert!(history.to_string().ends_with("0x224 0x226"), "{}", history);
+    }
+}
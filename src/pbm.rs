@@ -0,0 +1,95 @@
+use core::fmt::Write;
+
+use crate::prelude::*;
+
+// Plain text bitmaps for shell pipelines: PBM (P1) and XBM, one byte per pixel in, lit pixels as 1 bits.
+// Both count a 1 as black, so viewers show the screen inverted; diff and grep don't care
+
+const PBM_LINE: usize = 70;             // Longest line the format allows
+
+// P1 header, then each row as 0 and 1 digits, wrapped before 70 characters
+pub fn encode_pbm(width: usize, height: usize, pixels: &[u8]) -> String {
+    assert_eq!(pixels.len(), width * height, "pixel buffer doesn't match the image size");
+
+    let mut out = format!("P1\n{} {}\n", width, height);
+    for row in pixels.chunks(width.max(1)) {
+        for line in row.chunks(PBM_LINE) {
+            out.extend(line.iter().map(|&pixel| if pixel != 0 { '1' } else { '0' }));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// C source defining name_width, name_height and name_bits. Rows are padded to whole bytes and the
+// leftmost pixel of each byte is its lowest bit, 12 bytes to a line like the X tools write them
+pub fn encode_xbm(name: &str, width: usize, height: usize, pixels: &[u8]) -> String {
+    assert_eq!(pixels.len(), width * height, "pixel buffer doesn't match the image size");
+
+    let bytes: Vec<u8> = pixels.chunks(width.max(1))
+        .flat_map(|row| row.chunks(8).map(|group| {
+            group.iter().enumerate().fold(0, |byte, (bit, &pixel)| byte | ((pixel != 0) as u8) << bit)
+        }))
+        .collect();
+
+    let mut out = format!("#define {0}_width {1}\n#define {0}_height {2}\nstatic unsigned char {0}_bits[] = {{\n", name, width, height);
+    let lines = bytes.chunks(12).count();
+    for (idx, line) in bytes.chunks(12).enumerate() {
+        let last = idx + 1 == lines;
+        out.push_str("  ");
+        for (col, byte) in line.iter().enumerate() {
+            let _ = write!(out, " {:#04x}", byte);
+            if !(last && col == line.len() - 1) {
+                out.push(',');
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("};\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: [u8; 6] = [1, 0, 1, 0, 1, 0];
+
+    #[test]
+    fn pbm_matches_the_golden_file() {
+        assert_eq!(encode_pbm(3, 2, &PIXELS), "P1\n3 2\n101\n010\n");
+    }
+
+    #[test]
+    fn pbm_rows_wrap_before_70_characters() {
+        let mut pixels = vec![0; 75];
+        pixels[70] = 1;
+        let expected = format!("P1\n75 1\n{}\n10000\n", "0".repeat(70));
+        assert_eq!(encode_pbm(75, 1, &pixels), expected);
+    }
+
+    #[test]
+    fn xbm_matches_the_golden_file() {
+        let mut pixels = vec![0; 20];
+        pixels[0] = 1;
+        pixels[9] = 1;
+        pixels[10 + 7] = 1;
+        assert_eq!(encode_xbm("screen", 10, 2, &pixels),
+            "#define screen_width 10\n\
+             #define screen_height 2\n\
+             static unsigned char screen_bits[] = {\n   \
+             0x01, 0x02, 0x80, 0x00\n\
+             };\n");
+    }
+
+    #[test]
+    fn xbm_puts_12_bytes_on_a_line() {
+        let out = encode_xbm("row", 104, 1, &[0; 104]);
+        let body: Vec<&str> = out.lines().skip(3).collect();
+        assert_eq!(body, [
+            "   0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,",
+            "   0x00",
+            "};",
+        ]);
+    }
+}
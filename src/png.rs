@@ -0,0 +1,46 @@
+// PNG images of 8 bit RGB pixels through the png crate, deflated at its default level
+
+// rgb holds width * height pixels of 3 bytes each, rows top to bottom. A buffer of any other length is refused
+pub fn encode_rgb(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>, String> {
+    let needed = width as usize * height as usize * 3;
+    if rgb.len() != needed {
+        return Err(format!("RGB buffer is {} bytes, a {}x{} image needs {}", rgb.len(), width, height, needed));
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut png, width, height);
+    encoder.set_color(::png::ColorType::Rgb);
+    encoder.set_depth(::png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(describe)?;
+    writer.write_image_data(rgb).map_err(describe)?;
+    writer.finish().map_err(describe)?;
+    Ok(png)
+}
+
+fn describe(err: ::png::EncodingError) -> String {
+    format!("could not encode the PNG: {}", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_round_trip_compressed() {
+        let mut rgb = vec![0; 64 * 32 * 3];
+        rgb[..3].copy_from_slice(&[255, 255, 255]);
+        let png = encode_rgb(64, 32, &rgb).unwrap();
+        assert!(png.len() < rgb.len() / 10, "{} bytes", png.len());
+
+        let mut reader = ::png::Decoder::new(std::io::Cursor::new(png)).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (64, 32, ::png::ColorType::Rgb));
+        assert_eq!(decoded, rgb);
+    }
+
+    #[test]
+    fn buffers_of_the_wrong_size_are_refused() {
+        assert_eq!(encode_rgb(2, 2, &[0; 11]).unwrap_err(), "RGB buffer is 11 bytes, a 2x2 image needs 12");
+    }
+}
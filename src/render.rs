@@ -0,0 +1,131 @@
+use crate::prelude::*;
+
+// Display to pixel conversion shared by every frontend, so the window, screenshots, frame dumps and
+// video recordings all show the same picture
+
+pub const WHITE: [u8; 4] = [255, 255, 255, 255];
+pub const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+// Fill out with the display as tightly packed RGBA, every pixel a scale x scale square of fg when lit
+// and bg when dark. The display is columns pixels a row, 64 or 128, and as many rows as it holds. out is
+// cleared first and its allocation reused
+pub fn render_rgba(display: &[u8], columns: usize, fg: [u8; 4], bg: [u8; 4], scale: usize, out: &mut Vec<u8>) {
+    render_planes_rgba(&[display], columns, &[bg, fg], scale, out);
+}
+
+// XO-CHIP style output: pixel color n of the palette, where bit k of n is the pixel in plane k. Two
+// planes and a four color palette give the XO-CHIP screen, missing palette entries render as bg
+pub fn render_planes_rgba(planes: &[&[u8]], columns: usize, palette: &[[u8; 4]], scale: usize, out: &mut Vec<u8>) {
+    let scale = scale.max(1);
+    let columns = columns.max(1);
+    let rows = planes.first().map_or(0, |pixels| pixels.len() / columns);
+    let width = columns * scale;
+    out.clear();
+    out.reserve(width * rows * scale * 4);
+    for y in 0..rows {
+        let row_start = out.len();
+        for x in 0..columns {
+            let color = planes.iter().enumerate()
+                .fold(0, |color, (plane, pixels)| color | ((pixels[x + y * columns] & 1) as usize) << plane);
+            let rgba = palette.get(color).or(palette.first()).copied().unwrap_or(BLACK);
+            for _ in 0..scale {
+                out.extend_from_slice(&rgba);
+            }
+        }
+        // The rest of the row's scale lines repeat the first one
+        for _ in 1..scale {
+            out.extend_from_within(row_start..row_start + width * 4);
+        }
+    }
+}
+
+// Output through a palette: pixel n is palette[indices[n]], for screens that color pixels one by one
+// like CHIP-8X. Indices past the palette render as its first entry
+pub fn render_indexed_rgba(indices: &[u8], columns: usize, palette: &[[u8; 4]], scale: usize, out: &mut Vec<u8>) {
+    let scale = scale.max(1);
+    let columns = columns.max(1);
+    out.clear();
+    out.reserve(indices.len() * scale * scale * 4);
+    for row in indices.chunks(columns) {
+        let row_start = out.len();
+        for &index in row {
+            let rgba = palette.get(index as usize).or(palette.first()).copied().unwrap_or(BLACK);
+            for _ in 0..scale {
+                out.extend_from_slice(&rgba);
+            }
+        }
+        for _ in 1..scale {
+            out.extend_from_within(row_start..row_start + row.len() * scale * 4);
+        }
+    }
+}
+
+// RGBA to RGB24 in place, for outputs without an alpha channel
+pub fn strip_alpha(rgba: &mut Vec<u8>) {
+    let pixels = rgba.len() / 4;
+    for pixel in 0..pixels {
+        rgba.copy_within(pixel * 4..pixel * 4 + 3, pixel * 3);
+    }
+    rgba.truncate(pixels * 3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FG: [u8; 4] = [10, 20, 30, 200];
+    const BG: [u8; 4] = [1, 2, 3, 0];
+    // Lit top left and bottom right
+    const CHECKER: [u8; 4] = [1, 0, 0, 1];
+
+    #[test]
+    fn a_2x2_pattern_renders_byte_for_byte() {
+        let mut out = Vec::new();
+        render_rgba(&CHECKER, 2, FG, BG, 1, &mut out);
+        assert_eq!(out, [FG, BG, BG, FG].concat());
+
+        render_rgba(&CHECKER, 2, FG, BG, 3, &mut out);
+        let top = [FG, FG, FG, BG, BG, BG].concat();
+        let bottom = [BG, BG, BG, FG, FG, FG].concat();
+        assert_eq!(out, [&top[..], &top, &top, &bottom, &bottom, &bottom].concat());
+        assert_eq!(out.len(), 6 * 6 * 4);
+    }
+
+    #[test]
+    fn the_output_allocation_is_reused() {
+        let mut out = Vec::new();
+        render_rgba(&CHECKER, 2, FG, BG, 3, &mut out);
+        let (capacity, pointer) = (out.capacity(), out.as_ptr());
+        render_rgba(&CHECKER, 2, BG, FG, 1, &mut out);
+        assert_eq!(out, [BG, FG, FG, BG].concat(), "cleared before filling");
+        assert_eq!((out.capacity(), out.as_ptr()), (capacity, pointer));
+    }
+
+    #[test]
+    fn planes_pick_palette_entries_by_bit() {
+        let palette = [BLACK, [255, 0, 0, 255], [0, 255, 0, 255], WHITE];
+        let mut out = Vec::new();
+        render_planes_rgba(&[&[1, 0, 1, 0], &[0, 1, 1, 0]], 2, &palette, 1, &mut out);
+        assert_eq!(out, [palette[1], palette[2], palette[3], palette[0]].concat());
+
+        render_planes_rgba(&[&[1, 0, 1, 0], &[0, 1, 1, 0]], 2, &palette[..2], 1, &mut out);
+        assert_eq!(out[4..8], BLACK, "missing palette entries fall back to the first");
+    }
+
+    #[test]
+    fn indexed_pixels_look_up_the_palette() {
+        let mut out = Vec::new();
+        render_indexed_rgba(&[0, 1, 2, 9], 2, &[BG, FG, WHITE], 2, &mut out);
+        let top = [BG, BG, FG, FG].concat();
+        let bottom = [WHITE, WHITE, BG, BG].concat();
+        assert_eq!(out, [&top[..], &top, &bottom, &bottom].concat());
+    }
+
+    #[test]
+    fn strip_alpha_leaves_rgb24() {
+        let mut out = Vec::new();
+        render_rgba(&CHECKER, 2, FG, BG, 1, &mut out);
+        strip_alpha(&mut out);
+        assert_eq!(out, [10, 20, 30, 1, 2, 3, 1, 2, 3, 10, 20, 30]);
+    }
+}
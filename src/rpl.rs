@@ -0,0 +1,82 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// SUPER-CHIP RPL user flags kept beside the ROM as <rom>.rpl, 16 raw bytes
+
+pub const RPL_SIZE: usize = 16;
+
+// game.ch8 -> game.rpl
+pub fn path_for(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("rpl")
+}
+
+// Flags from file bytes, shorter files (8 flag SUPER-CHIP saves) are zero filled
+pub fn decode(bytes: &[u8]) -> [u8; RPL_SIZE] {
+    let mut flags = [0; RPL_SIZE];
+    let len = bytes.len().min(RPL_SIZE);
+    flags[..len].copy_from_slice(&bytes[..len]);
+    flags
+}
+
+// Zeroed flags when the ROM has never saved any
+pub fn load(path: &Path) -> Result<[u8; RPL_SIZE], String> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(decode(&bytes)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok([0; RPL_SIZE]),
+        Err(err) => Err(format!("could not read {}: {}", path.display(), err)),
+    }
+}
+
+pub fn save(path: &Path, flags: &[u8; RPL_SIZE]) -> Result<(), String> {
+    fs::write(path, flags).map_err(|err| format!("could not write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn flags_round_trip_through_the_rpl_file() {
+        let dir = std::env::temp_dir().join(format!("chip8-rpl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = path_for(&dir.join("game.ch8"));
+        assert_eq!(path, dir.join("game.rpl"));
+
+        assert_eq!(load(&path).unwrap(), [0; RPL_SIZE], "a ROM that never saved has zeroed flags");
+        let flags: [u8; RPL_SIZE] = core::array::from_fn(|i| i as u8 * 17);
+        save(&path, &flags).unwrap();
+        assert_eq!(load(&path).unwrap(), flags);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn short_saves_are_zero_filled() {
+        let mut expected = [0; RPL_SIZE];
+        expected[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(decode(&[1, 2, 3, 4, 5, 6, 7, 8]), expected);
+        assert_eq!(decode(&[9; 20]), [9; RPL_SIZE], "extra bytes are ignored");
+    }
+
+    #[test]
+    fn fx75_scores_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("chip8-rpl-restart-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = path_for(&dir.join("game.ch8"));
+
+        // v0 = 0x42, v1 = 0x99, FX75 with X = 1
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 0x42, 0x61, 0x99, 0xF1, 0x75]).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        assert!(chip8.take_rpl_dirty());
+        save(&path, &chip8.rpl()).unwrap();
+
+        let mut restarted = Chip8::new();
+        restarted.set_rpl(load(&path).unwrap());
+        assert_eq!(restarted.rpl()[..3], [0x42, 0x99, 0]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chip8::{Chip8, WIDTH, HEIGHT};
+use crate::movie::MovieCursor;
+
+// Savestate files: a small header with the save time and an optional thumbnail, then the machine state
+//
+//   0   "C8SV"
+//   4   format version
+//   5   flags, bit 0 = thumbnail present, bit 1 = movie cursor present
+//   6   save time, unix seconds, little endian u64
+//   14  thumbnail, 1 bit per pixel row major (only when flagged)
+//   ..  movie cursor, frame and input hash as little endian u64s (only when flagged)
+//   ..  Chip8::save_state payload
+
+const MAGIC: &[u8; 4] = b"C8SV";
+const VERSION: u8 = 8;                  // 2 added the FX1E overflow quirks, 3 hires, 4 the movie cursor, 5 XO-CHIP audio, 6 CHIP-8X,
+                                        // 7 the collision delay, 8 the CHIP-8E timer wait
+const OLDEST_VERSION: u8 = 8;           // 8 grew the payload, older ones don't fit it
+const FLAG_THUMBNAIL: u8 = 0x01;
+const FLAG_MOVIE: u8 = 0x02;
+const HEADER_SIZE: usize = 14;
+pub const THUMBNAIL_SIZE: usize = WIDTH * HEIGHT / 8;
+
+// The screen at save time at 64x32, a hires screen at half size with each pixel lit when any of the four
+// it stands for is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    bits: [u8; THUMBNAIL_SIZE],
+}
+
+impl Thumbnail {
+    pub fn capture(chip8: &Chip8) -> Self {
+        let (width, height) = chip8.resolution();
+        let (across, down) = (width / WIDTH, height / HEIGHT);
+        let mut bits = [0; THUMBNAIL_SIZE];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if (0..across * down).any(|at| chip8.pixel(x * across + at % across, y * down + at / across)) {
+                    let idx = x + y * WIDTH;
+                    bits[idx / 8] |= 0x80 >> (idx % 8);
+                }
+            }
+        }
+        Thumbnail { bits }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let idx = (x % WIDTH) + (y % HEIGHT) * WIDTH;
+        self.bits[idx / 8] & (0x80 >> (idx % 8)) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateHeader {
+    pub saved_at: u64,                  // Unix seconds
+    pub thumbnail: Option<Thumbnail>,   // None for states saved without one
+    pub movie: Option<MovieCursor>,     // Movie position at save time, None when no movie was active
+}
+
+// Serialize the machine with a header stamped now
+pub fn encode(chip8: &Chip8, with_thumbnail: bool, movie: Option<MovieCursor>) -> Vec<u8> {
+    let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(if with_thumbnail { FLAG_THUMBNAIL } else { 0 } | if movie.is_some() { FLAG_MOVIE } else { 0 });
+    out.extend_from_slice(&saved_at.to_le_bytes());
+    if with_thumbnail {
+        out.extend_from_slice(&Thumbnail::capture(chip8).bits);
+    }
+    if let Some(cursor) = movie {
+        out.extend_from_slice(&cursor.to_bytes());
+    }
+    out.extend_from_slice(&chip8.save_state());
+    out
+}
+
+// Parse just the header, a thumbnail that is missing or cut short reads as None
+pub fn read_header(bytes: &[u8]) -> Result<StateHeader, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err("not a savestate".to_string());
+    }
+    if !(OLDEST_VERSION..=VERSION).contains(&bytes[4]) {
+        return Err(format!("unsupported savestate version {}", bytes[4]));
+    }
+
+    let saved_at = u64::from_le_bytes(bytes[6..14].try_into().unwrap());
+    let thumbnail = match bytes.get(HEADER_SIZE..HEADER_SIZE + THUMBNAIL_SIZE) {
+        Some(bits) if bytes[5] & FLAG_THUMBNAIL != 0 => Some(Thumbnail { bits: bits.try_into().unwrap() }),
+        _ => None,
+    };
+    let cursor_at = thumbnail_end(bytes[5]);
+    let movie = match bytes.get(cursor_at..cursor_at + MovieCursor::SIZE) {
+        Some(cursor) if bytes[5] & FLAG_MOVIE != 0 => Some(MovieCursor::from_bytes(cursor.try_into().unwrap())),
+        _ => None,
+    };
+    Ok(StateHeader { saved_at, thumbnail, movie })
+}
+
+// Offset just past the thumbnail, where the movie cursor or the payload starts
+fn thumbnail_end(flags: u8) -> usize {
+    HEADER_SIZE + if flags & FLAG_THUMBNAIL != 0 { THUMBNAIL_SIZE } else { 0 }
+}
+
+// Restore the machine from a whole savestate file's bytes
+pub fn decode(chip8: &mut Chip8, bytes: &[u8]) -> Result<StateHeader, String> {
+    let header = read_header(bytes)?;
+    let offset = thumbnail_end(bytes[5]) + if bytes[5] & FLAG_MOVIE != 0 { MovieCursor::SIZE } else { 0 };
+    chip8.load_state(bytes.get(offset..).ok_or("savestate is truncated")?)?;
+    Ok(header)
+}
+
+// Savestates live next to each other per ROM hash: <hash>.<slot>.state
+pub fn path_for(dir: &Path, rom_hash: u64, slot: usize) -> PathBuf {
+    dir.join(format!("{:016x}.{}.state", rom_hash, slot))
+}
+
+pub fn save(chip8: &Chip8, path: &Path, movie: Option<MovieCursor>) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+    }
+    fs::write(path, encode(chip8, true, movie)).map_err(|err| format!("could not write {}: {}", path.display(), err))
+}
+
+pub fn load(chip8: &mut Chip8, path: &Path) -> Result<StateHeader, String> {
+    let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    decode(chip8, &bytes)
+}
+
+// Header of a slot file without reading the machine state, None for empty or unreadable slots
+pub fn peek(path: &Path) -> Option<StateHeader> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.take((HEADER_SIZE + THUMBNAIL_SIZE + MovieCursor::SIZE) as u64).read_to_end(&mut bytes).ok()?;
+    read_header(&bytes).ok()
+}
+
+// Unix seconds as a UTC "YYYY-MM-DD HH:MM"
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let minutes = secs % 86400 / 60;
+
+    // Civil date from days since 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{DEFAULT_PITCH, HIRES_WIDTH};
+
+    #[test]
+    fn thumbnails_match_the_screen_at_save_time() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(include_bytes!("../testfiles/2-ibm-logo.ch8")).unwrap();
+        for _ in 0..30 * 15 {
+            chip8.cycle();
+        }
+        assert!(chip8.lit_pixels().count() > 0, "the logo is drawn");
+        let screen: Vec<bool> = (0..WIDTH * HEIGHT).map(|at| chip8.pixel(at % WIDTH, at / WIDTH)).collect();
+
+        let dir = std::env::temp_dir().join(format!("chip8-savestate-test-{}", std::process::id()));
+        let path = path_for(&dir, 0x1234, 3);
+        save(&chip8, &path, None).unwrap();
+        chip8.reset();
+        let thumbnail = peek(&path).unwrap().thumbnail.unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        for (at, &lit) in screen.iter().enumerate() {
+            assert_eq!(thumbnail.pixel(at % WIDTH, at / WIDTH), lit, "pixel {},{}", at % WIDTH, at / WIDTH);
+        }
+    }
+
+    #[test]
+    fn states_without_a_thumbnail_degrade_gracefully() {
+        let mut chip8 = Chip8::new();
+        chip8.display[3 + 4 * WIDTH] = 1;
+        let bytes = encode(&chip8, false, None);
+        assert_eq!(read_header(&bytes).unwrap().thumbnail, None);
+        decode(&mut Chip8::new(), &bytes).unwrap();
+
+        let cut = &encode(&chip8, true, None)[..HEADER_SIZE + THUMBNAIL_SIZE / 2];
+        assert_eq!(read_header(cut).unwrap().thumbnail, None, "a thumbnail cut short");
+        assert_eq!(peek(Path::new("/nonexistent/slot.state")), None, "an empty slot");
+    }
+
+    #[test]
+    fn timestamps_format_as_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13");
+    }
+
+    #[test]
+    fn hires_thumbnails_are_halved() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x00, 0xFF]).unwrap();
+        chip8.cycle();
+        chip8.display[127 + 63 * HIRES_WIDTH] = 1;
+        let bytes = encode(&chip8, true, None);
+        let thumbnail = read_header(&bytes).unwrap().thumbnail.unwrap();
+        assert!(thumbnail.pixel(63, 31));
+        assert_eq!((0..WIDTH * HEIGHT).filter(|&at| thumbnail.pixel(at % WIDTH, at / WIDTH)).count(), 1);
+    }
+
+    #[test]
+    fn xo_chip_audio_round_trips() {
+        let rom = [0xA3, 0x00, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        let mut saved = Chip8::new();
+        saved.load_rom_bytes(&rom).unwrap();
+        saved.poke(0x300, 0xF0).unwrap();
+        saved.poke(0x30F, 0x0F).unwrap();
+        for _ in 0..4 {
+            saved.cycle();
+        }
+        let bytes = encode(&saved, false, None);
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert_eq!((chip8.audio_pattern(), chip8.pitch()), (None, DEFAULT_PITCH));
+        decode(&mut chip8, &bytes).unwrap();
+        let pattern = chip8.audio_pattern().unwrap();
+        assert_eq!((pattern[0], pattern[1], pattern[15]), (0xF0, 0x00, 0x0F));
+        assert_eq!(chip8.pitch(), 0x70);
+
+        let mut old = bytes.clone();
+        old[4] = 4;
+        assert_eq!(decode(&mut chip8, &old).unwrap_err(), "unsupported savestate version 4", "version 4 had no XO-CHIP audio");
+    }
+}
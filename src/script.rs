@@ -0,0 +1,539 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use mlua::debug::Debug;
+use mlua::{AnyUserData, Function, HookTriggers, Lua, MultiValue, UserData, UserDataMethods, Value, VmState};
+
+use crate::chip8::Chip8;
+
+// Scripts driving the emulator from outside the ROM, for bots, scripted input and test harnesses. They
+// are Lua 5.4, run by the interpreter mlua builds in. Besides the standard library, with print going to
+// the emulator's output, the machine is behind the emu userdata:
+//
+//   emu.reg(x) emu.set_reg(x, v)       V registers
+//   emu.index() emu.set_index(v)       I
+//   emu.pc() emu.set_pc(addr)
+//   emu.peek(addr) emu.poke(addr, v)   memory bytes, peek is nil outside memory
+//   emu.pixel(x, y)                    whether a pixel is lit
+//   emu.press(key) emu.release(key)    keys the script holds, on top of the player's
+//   emu.frame()                        frames emulated so far
+//   emu.save_state()                   a slot number for emu.load_state(slot)
+//   emu.on_frame(fn)                   fn() after every frame
+//   emu.on_pc(addr, fn)                fn() before every instruction at addr
+//
+// The hooks run on the emulation thread, between frames and between instructions. Each frame they share
+// a budget of Lua instructions; a script that runs past it or raises an error is reported once and
+// switched off while the emulator carries on
+
+pub const STEP_BUDGET: u64 = 100_000;  // Lua instructions a script gets per frame, and to load
+const HOOK_INTERVAL: u32 = 100;         // Instructions between budget checks
+const LENT_CHIP8: &str = "chip8";       // Registry slot of the machine while a hook runs
+
+// What the emu functions change besides the machine, kept as the Lua state's app data
+#[derive(Default)]
+struct Machine {
+    keys: u16,
+    output: Vec<String>,
+    states: Vec<Vec<u8>>,
+    frame_hooks: Vec<Function>,
+    pc_hooks: BTreeMap<u16, Vec<Function>>,
+    steps: u64,
+    budget: u64,
+}
+
+// An error raised at the Lua code debug describes, "name:line: message" like Lua's own
+fn raised_at(debug: &Debug, message: String) -> mlua::Error {
+    let source = debug.source().short_src.map(|src| src.into_owned()).unwrap_or_default();
+    match debug.current_line() {
+        Some(line) => mlua::Error::RuntimeError(format!("{}:{}: {}", source, line, message)),
+        None => mlua::Error::RuntimeError(format!("{}: {}", source, message)),
+    }
+}
+
+// An error raised at the Lua code that called the running emu function
+fn raised(lua: &Lua, message: String) -> mlua::Error {
+    lua.inspect_stack(1, |debug| raised_at(debug, message.clone())).unwrap_or(mlua::Error::RuntimeError(message))
+}
+
+// Lua's name for a value's type, where numbers are numbers whether or not they're integers
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Integer(_) => "number",
+        other => other.type_name(),
+    }
+}
+
+// The arguments an emu function was called with, and the name its errors are reported against
+struct Args<'a> {
+    lua: &'a Lua,
+    name: &'static str,
+    values: MultiValue,
+}
+
+impl Args<'_> {
+    fn bad<T>(&self, n: usize, message: impl Into<String>) -> mlua::Result<T> {
+        Err(raised(self.lua, format!("bad argument #{} to 'emu.{}' ({})", n + 1, self.name, message.into())))
+    }
+
+    fn get(&self, n: usize) -> &Value {
+        self.values.get(n).unwrap_or(&Value::Nil)
+    }
+
+    fn number(&self, n: usize) -> mlua::Result<f64> {
+        match self.get(n) {
+            Value::Integer(value) => Ok(*value as f64),
+            Value::Number(value) => Ok(*value),
+            other => self.bad(n, format!("number expected, got {}", type_name(other))),
+        }
+    }
+
+    // An integer argument within range, like a register number or an address
+    fn int(&self, n: usize, range: RangeInclusive<i64>) -> mlua::Result<i64> {
+        let value = match self.get(n) {
+            Value::Integer(value) => *value,
+            _ => {
+                let value = self.number(n)?;
+                if value.fract() != 0.0 || value.abs() >= i64::MAX as f64 {
+                    return self.bad(n, "number has no integer representation");
+                }
+                value as i64
+            }
+        };
+        if !range.contains(&value) {
+            return self.bad(n, format!("{} is outside {}..{}", value, range.start(), range.end()));
+        }
+        Ok(value)
+    }
+
+    fn function(&self, n: usize) -> mlua::Result<Function> {
+        match self.get(n) {
+            Value::Function(function) => Ok(function.clone()),
+            other => self.bad(n, format!("function expected, got {}", type_name(other))),
+        }
+    }
+
+    // A machine error, like a poke into ROM, raised at the caller's line
+    fn fail<T>(&self, message: String) -> mlua::Result<T> {
+        Err(raised(self.lua, message))
+    }
+}
+
+fn machine(lua: &Lua) -> mlua::Result<mlua::AppDataRefMut<'_, Machine>> {
+    lua.app_data_mut::<Machine>().ok_or_else(|| mlua::Error::RuntimeError("the script has no machine".to_string()))
+}
+
+// Run f on the machine lent to the hook that's running
+fn with_chip8<R>(lua: &Lua, f: impl FnOnce(&mut Chip8) -> R) -> mlua::Result<R> {
+    let lent: AnyUserData = lua.named_registry_value(LENT_CHIP8)?;
+    lent.borrow_mut_scoped::<Chip8, _>(f)
+}
+
+// The emu global. It holds nothing itself: its functions reach the machine lent for the current call
+struct Emu;
+
+impl UserData for Emu {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        fn add<M, R>(methods: &mut M, name: &'static str, f: fn(&Args) -> mlua::Result<R>)
+        where
+            M: UserDataMethods<Emu>,
+            R: mlua::IntoLuaMulti + 'static,
+        {
+            methods.add_function(name, move |lua, values: MultiValue| f(&Args { lua, name, values }));
+        }
+
+        add(methods, "reg", |args| {
+            let x = args.int(0, 0..=15)? as usize;
+            with_chip8(args.lua, |chip8| chip8.register(x))
+        });
+        add(methods, "set_reg", |args| {
+            let (x, value) = (args.int(0, 0..=15)? as usize, args.int(1, 0..=255)? as u8);
+            with_chip8(args.lua, |chip8| chip8.set_register(x, value))
+        });
+        add(methods, "index", |args| with_chip8(args.lua, |chip8| chip8.index()));
+        add(methods, "set_index", |args| {
+            let value = args.int(0, 0..=0xFFFF)? as u16;
+            with_chip8(args.lua, |chip8| chip8.set_index(value))
+        });
+        add(methods, "pc", |args| with_chip8(args.lua, |chip8| chip8.pc()));
+        add(methods, "set_pc", |args| {
+            let addr = args.int(0, 0..=0xFFFF)? as u16;
+            with_chip8(args.lua, |chip8| chip8.set_pc(addr))?.or_else(|err| args.fail(err))
+        });
+        add(methods, "peek", |args| {
+            let addr = args.number(0)?;
+            let addr = (addr.fract() == 0.0 && addr >= 0.0).then_some(addr as usize);
+            with_chip8(args.lua, |chip8| addr.and_then(|addr| chip8.peek(addr)))
+        });
+        add(methods, "poke", |args| {
+            let (addr, value) = (args.int(0, 0..=0xFFFF)? as usize, args.int(1, 0..=255)? as u8);
+            with_chip8(args.lua, |chip8| chip8.poke(addr, value))?.or_else(|err| args.fail(err))
+        });
+        add(methods, "pixel", |args| {
+            let (width, height) = with_chip8(args.lua, |chip8| chip8.resolution())?;
+            let x = args.int(0, 0..=width as i64 - 1)? as usize;
+            let y = args.int(1, 0..=height as i64 - 1)? as usize;
+            with_chip8(args.lua, |chip8| chip8.pixel(x, y))
+        });
+        add(methods, "press", |args| {
+            let key = args.int(0, 0..=15)?;
+            machine(args.lua)?.keys |= 1 << key;
+            Ok(())
+        });
+        add(methods, "release", |args| {
+            let key = args.int(0, 0..=15)?;
+            machine(args.lua)?.keys &= !(1 << key);
+            Ok(())
+        });
+        add(methods, "frame", |args| with_chip8(args.lua, |chip8| chip8.frame_count()));
+        add(methods, "save_state", |args| {
+            let state = with_chip8(args.lua, |chip8| chip8.save_state())?;
+            let mut machine = machine(args.lua)?;
+            machine.states.push(state);
+            Ok(machine.states.len())
+        });
+        add(methods, "load_state", |args| {
+            let saved = machine(args.lua)?.states.len();
+            let slot = args.int(0, 1..=saved.max(1) as i64)? as usize;
+            let Some(state) = machine(args.lua)?.states.get(slot - 1).cloned() else {
+                return args.bad(0, "no state has been saved");
+            };
+            with_chip8(args.lua, |chip8| chip8.load_state(&state))?.or_else(|err| args.fail(err))
+        });
+        add(methods, "on_frame", |args| {
+            let hook = args.function(0)?;
+            machine(args.lua)?.frame_hooks.push(hook);
+            Ok(())
+        });
+        add(methods, "on_pc", |args| {
+            let addr = args.int(0, 0..=0xFFFF)? as u16;
+            let hook = args.function(1)?;
+            machine(args.lua)?.pc_hooks.entry(addr).or_default().push(hook);
+            Ok(())
+        });
+    }
+}
+
+// The message of a script error, without the wrapping mlua adds around errors raised in callbacks or the
+// traceback it appends
+fn message(err: &mlua::Error) -> String {
+    let text = match err {
+        mlua::Error::CallbackError { cause, .. } => return message(cause),
+        mlua::Error::RuntimeError(text) | mlua::Error::SyntaxError { message: text, .. } => text.clone(),
+        other => other.to_string(),
+    };
+    match text.split_once("\nstack traceback:") {
+        Some((text, _)) => text.to_string(),
+        None => text,
+    }
+}
+
+// A loaded script and its hooks. Once it fails it stays switched off, holding no keys
+pub struct Script {
+    lua: Lua,
+    error: Option<String>,
+}
+
+impl Script {
+    // Compile a script and run its main chunk, which usually just registers hooks. name is what errors
+    // are reported against, normally the file name
+    pub fn load(source: &str, name: &str, chip8: &mut Chip8) -> Result<Script, String> {
+        Script::with_budget(source, name, chip8, STEP_BUDGET)
+    }
+
+    pub fn with_budget(source: &str, name: &str, chip8: &mut Chip8, budget: u64) -> Result<Script, String> {
+        let script = Script { lua: Lua::new(), error: None };
+        script.setup(budget).map_err(|err| message(&err))?;
+        let chunk = script.lua.load(source).set_name(format!("@{}", name));
+        script.lend(chip8, || chunk.exec()).map_err(|err| message(&err))?;
+        script.machine().steps = 0;
+        Ok(script)
+    }
+
+    fn setup(&self, budget: u64) -> mlua::Result<()> {
+        let lua = &self.lua;
+        lua.set_app_data(Machine { budget, ..Machine::default() });
+        lua.globals().set("emu", Emu)?;
+
+        let tostring: Function = lua.globals().get("tostring")?;
+        let print = lua.create_function(move |lua, values: MultiValue| {
+            let line = values.into_iter().map(|value| tostring.call(value)).collect::<mlua::Result<Vec<String>>>()?;
+            machine(lua)?.output.push(line.join("\t"));
+            Ok(())
+        })?;
+        lua.globals().set("print", print)?;
+
+        lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), |lua, debug| {
+            let mut machine = machine(lua)?;
+            machine.steps += HOOK_INTERVAL as u64;
+            if machine.steps <= machine.budget {
+                return Ok(VmState::Continue);
+            }
+            Err(raised_at(debug, format!("ran past its budget of {} instructions in one frame", machine.budget)))
+        })
+    }
+
+    fn machine(&self) -> mlua::AppDataRefMut<'_, Machine> {
+        self.lua.app_data_mut::<Machine>().expect("scripts are set up with a machine")
+    }
+
+    // Run f with the machine lent to the emu functions. It goes back when f returns, anything the
+    // script kept of it stops working
+    fn lend<R>(&self, chip8: &mut Chip8, f: impl FnOnce() -> mlua::Result<R>) -> mlua::Result<R> {
+        self.lua.scope(|scope| {
+            let lent = scope.create_any_userdata_ref_mut(chip8)?;
+            self.lua.set_named_registry_value(LENT_CHIP8, &lent)?;
+            f()
+        })
+    }
+
+    // Keys the script is holding down, to OR into the player's
+    pub fn keys(&self) -> u16 {
+        self.machine().keys
+    }
+
+    // Why the script was switched off, None while it's still running
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    // Lines the script printed since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.machine().output)
+    }
+
+    // Run the on_pc hooks for the instruction about to execute. Err the one time the script fails
+    pub fn before_instruction(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        if self.error.is_some() {
+            return Ok(());
+        }
+        let hooks = self.machine().pc_hooks.get(&chip8.pc()).cloned();
+        match hooks {
+            Some(hooks) => self.run_hooks(&hooks, chip8),
+            None => Ok(()),
+        }
+    }
+
+    // Run the on_frame hooks once a frame is done and start the next frame's budget
+    pub fn after_frame(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let result = match self.error {
+            None => {
+                let hooks = self.machine().frame_hooks.clone();
+                self.run_hooks(&hooks, chip8)
+            }
+            Some(_) => Ok(()),
+        };
+        self.machine().steps = 0;
+        result
+    }
+
+    fn run_hooks(&mut self, hooks: &[Function], chip8: &mut Chip8) -> Result<(), String> {
+        let result = self.lend(chip8, || hooks.iter().try_for_each(|hook| hook.call::<()>(())));
+        if let Err(err) = result {
+            let message = message(&err);
+            let mut machine = self.machine();
+            machine.keys = 0;
+            machine.frame_hooks.clear();
+            machine.pc_hooks.clear();
+            drop(machine);
+            self.error = Some(message.clone());
+            return Err(message);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::WIDTH;
+
+    // Waits for key 5, counts the press in v1 and stores v0..v1 at 0x300, then waits for the release
+    const PRESS_COUNTER: [u8; 18] = [
+        0x65, 0x05, 0xE5, 0x9E, 0x12, 0x02, 0x71, 0x01, 0xA3, 0x00,
+        0xF1, 0x55, 0xE5, 0xA1, 0x12, 0x0C, 0x12, 0x02,
+    ];
+
+    fn counter() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&PRESS_COUNTER).unwrap();
+        chip8
+    }
+
+    // The frontend's order: script keys in, pc hooks before each instruction, frame hooks after the frame
+    fn run(script: &mut Script, chip8: &mut Chip8, frames: usize) {
+        for _ in 0..frames {
+            chip8.set_keys_mask(script.keys());
+            for _ in 0..10 {
+                let _ = script.before_instruction(chip8);
+                chip8.cycle();
+            }
+            chip8.tick_timers();
+            let _ = script.after_frame(chip8);
+        }
+    }
+
+    fn printed(source: &str) -> String {
+        let mut script = Script::load(source, "t.lua", &mut Chip8::new()).unwrap();
+        script.take_output().join("\n")
+    }
+
+    #[test]
+    fn scripted_presses_are_counted_by_the_rom() {
+        let source = "
+            -- press key 5 for two frames at each of these frames
+            local presses = {3, 10, 20}
+            emu.on_frame(function()
+                for _, frame in ipairs(presses) do
+                    if emu.frame() == frame then emu.press(5) end
+                    if emu.frame() == frame + 2 then emu.release(5) end
+                end
+            end)
+        ";
+        let mut chip8 = counter();
+        let mut script = Script::load(source, "presses.lua", &mut chip8).unwrap();
+        run(&mut script, &mut chip8, 40);
+        assert_eq!(script.error(), None);
+        assert_eq!(chip8.peek(0x301), Some(3), "three presses");
+        assert_eq!(script.keys(), 0, "released");
+    }
+
+    #[test]
+    fn pc_hooks_run_before_their_instruction() {
+        let source = "
+            local count, seen = 0, nil
+            emu.on_pc(0x206, function()
+                count = count + 1
+                seen = emu.reg(1)
+            end)
+            emu.on_frame(function()
+                if emu.frame() % 4 == 0 then emu.press(5) else emu.release(5) end
+                emu.poke(0x3F0, count)
+                if seen then emu.poke(0x3F1, seen) end
+            end)
+        ";
+        let mut chip8 = counter();
+        let mut script = Script::load(source, "t.lua", &mut chip8).unwrap();
+        run(&mut script, &mut chip8, 17);
+        assert_eq!(chip8.peek(0x3F0), Some(4), "one call per press");
+        assert_eq!(chip8.peek(0x3F1), Some(3), "v1 before the add");
+        assert_eq!(chip8.register(1), 4);
+    }
+
+    #[test]
+    fn operators_follow_lua_precedence() {
+        assert_eq!(printed("print(1 + 2 * 3, 2 ^ 3 ^ 2, 7 // 2, -7 % 3, 1 .. 2, -2 ^ 2)"), "7\t512.0\t3\t2\t12\t-4.0");
+        assert_eq!(printed("print(0xF0 | 0x0F, 1 << 4, ~0 & 0xFF, 6 ~ 3, 0x80 >> 7)"), "255\t16\t255\t5\t1");
+        assert_eq!(printed("print(1 < 2 and 'yes' or 'no', nil or false, not nil, 'a' .. 'b' == 'ab', 1 / 2)"), "yes\tfalse\ttrue\ttrue\t0.5");
+    }
+
+    #[test]
+    fn closures_tables_and_loops() {
+        let source = "
+            local function counter()
+                local n = 0
+                return function() n = n + 1 return n end
+            end
+            local a, b = counter(), counter()
+            a() a()
+            print(a(), b())
+
+            local t = {10, 20, 30, x = 1, ['y'] = 2}
+            local sum = 0
+            for _, v in pairs(t) do sum = sum + v end
+            print(#t, sum, t.x + t.y)
+
+            local odd = 0
+            for i = 9, 1, -2 do odd = odd + i end
+            local n = 0
+            while true do n = n + 1 if n == 5 then break end end
+            repeat n = n - 1 until n == 2
+            print(odd, n)
+
+            local fns = {}
+            for i = 1, 3 do fns[i] = function() return i end end
+            print(fns[1]() + fns[3]())
+        ";
+        assert_eq!(printed(source), "3\t1\n3\t63\t3\n25\t2\n4");
+    }
+
+    #[test]
+    fn strings_format_and_convert() {
+        assert_eq!(printed("print(string.format('%04X %-3s|%5.2f %d%%', 0x2a, 'ab', 3.14159, -3))"), "002A ab | 3.14 -3%");
+        assert_eq!(printed("print(tonumber('0x1F'), tonumber('12', 8), tonumber('nope'), tostring(1.5), type({}), #'four')"), "31\t10\tnil\t1.5\ttable\t4");
+        assert_eq!(printed("print('tab\\tend', [[long\nstring]]) -- comment\n--[[ block\ncomment ]] print('after')"), "tab\tend\tlong\nstring\nafter");
+    }
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        let err = Script::load("local x = 1\nif x then\n", "t.lua", &mut Chip8::new()).err().unwrap();
+        assert_eq!(err, "t.lua:3: 'end' expected (to close 'if' at line 2) near <eof>");
+        let err = Script::load("x = = 1", "t.lua", &mut Chip8::new()).err().unwrap();
+        assert_eq!(err, "t.lua:1: unexpected symbol near '='");
+        let err = Script::load("local s = 'open\n", "t.lua", &mut Chip8::new()).err().unwrap();
+        assert_eq!(err, "t.lua:1: unfinished string near ''open'");
+    }
+
+    #[test]
+    fn runaway_scripts_are_cut_off_and_disabled() {
+        let mut chip8 = counter();
+        let source = "emu.on_frame(function()\n  emu.press(5)\n  while true do end\nend)";
+        let mut script = Script::with_budget(source, "t.lua", &mut chip8, 1_000).unwrap();
+        let err = script.after_frame(&mut chip8).unwrap_err();
+        assert_eq!(err, "t.lua:3: ran past its budget of 1000 instructions in one frame");
+        assert_eq!(script.error(), Some(err.as_str()));
+        assert_eq!(script.keys(), 0, "keys let go");
+        assert_eq!(script.after_frame(&mut chip8), Ok(()), "reported once");
+
+        let err = Script::with_budget("while true do end", "t.lua", &mut chip8, 1_000).err().unwrap();
+        assert!(err.contains("budget"), "main chunk too");
+        let err = Script::with_budget("local function f() return 1 + f() end\nf()", "t.lua", &mut chip8, u64::MAX).err().unwrap();
+        assert_eq!(err, "t.lua:1: stack overflow");
+    }
+
+    #[test]
+    fn runtime_errors_disable_the_script() {
+        let source = "
+            emu.on_frame(function()
+                emu.press(3)
+                if emu.frame() == 2 then
+                    emu.presss(1)
+                end
+            end)
+        ";
+        let mut chip8 = counter();
+        let mut script = Script::load(source, "t.lua", &mut chip8).unwrap();
+        run(&mut script, &mut chip8, 1);
+        assert_eq!(script.keys(), 1 << 3);
+        chip8.tick_timers();
+        let err = script.after_frame(&mut chip8).unwrap_err();
+        assert_eq!(err, "t.lua:5: attempt to call a nil value (field 'presss')");
+        assert_eq!(script.keys(), 0);
+        run(&mut script, &mut chip8, 2);
+        assert_eq!(script.keys(), 0, "stays off");
+    }
+
+    #[test]
+    fn machine_access_checks_its_arguments() {
+        let mut chip8 = counter();
+        chip8.display[3 + 4 * WIDTH] = 1;
+        let source = "
+            emu.set_reg(2, 0x7F)
+            emu.set_index(0x2F0)
+            local slot = emu.save_state()
+            emu.set_reg(2, 0)
+            emu.load_state(slot)
+            print(emu.reg(2), emu.index(), emu.pc(), emu.peek(0x200), emu.peek(0x10000), emu.pixel(3, 4), emu.pixel(4, 4))
+        ";
+        let mut script = Script::load(source, "t.lua", &mut chip8).unwrap();
+        assert_eq!(script.take_output(), ["127\t752\t512\t101\tnil\ttrue\tfalse"]);
+
+        let bad = |source: &str| Script::load(source, "t.lua", &mut Chip8::new()).err().unwrap();
+        assert_eq!(bad("emu.set_reg(16, 0)"), "t.lua:1: bad argument #1 to 'emu.set_reg' (16 is outside 0..15)");
+        assert_eq!(bad("emu.poke(0x300, 256)"), "t.lua:1: bad argument #2 to 'emu.poke' (256 is outside 0..255)");
+        assert_eq!(bad("emu.press('5')"), "t.lua:1: bad argument #1 to 'emu.press' (number expected, got string)");
+        assert_eq!(bad("emu.load_state(1)"), "t.lua:1: bad argument #1 to 'emu.load_state' (no state has been saved)");
+        assert_eq!(bad("emu.on_frame(1)"), "t.lua:1: bad argument #1 to 'emu.on_frame' (function expected, got number)");
+        assert_eq!(bad("local t = nil\nprint(t.x)"), "t.lua:2: attempt to index a nil value (local 't')");
+        assert_eq!(bad("error('boom')"), "t.lua:1: boom");
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+
+const FRAME_RATE: u64 = 60;
+
+// Emulated time as mm:ss.ff from a 60hz frame count, ff is hundredths of a second
+pub fn format_time(frames: u64) -> String {
+    let seconds = frames / FRAME_RATE;
+    let hundredths = (frames % FRAME_RATE) * 100 / FRAME_RATE;
+    format!("{:02}:{:02}.{:02}", seconds / 60, seconds % 60, hundredths)
+}
+
+// Split times recorded by frame number so pauses and fast forward don't skew them
+#[derive(Default)]
+pub struct Splits {
+    frames: Vec<u64>,
+}
+
+impl Splits {
+    pub fn record(&mut self, frame: u64) {
+        self.frames.push(frame);
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // One line per split with its total time and the time since the previous split
+    pub fn lines(&self) -> Vec<String> {
+        let mut previous = 0;
+        self.frames.iter().enumerate().map(|(i, &frame)| {
+            let line = format!("{}. {} +{}", i + 1, format_time(frame), format_time(frame.saturating_sub(previous)));
+            previous = frame;
+            line
+        }).collect()
+    }
+
+    pub fn dump(&self, path: &str) -> Result<(), String> {
+        let mut text = self.lines().join("\n");
+        text.push('\n');
+        fs::write(path, text).map_err(|err| format!("could not write splits to {}: {}", path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+
+    #[test]
+    fn times_are_minutes_seconds_and_hundredths() {
+        assert_eq!(format_time(0), "00:00.00");
+        assert_eq!(format_time(59), "00:00.98");
+        assert_eq!(format_time(60), "00:01.00");
+        assert_eq!(format_time(60 * 60 + 30), "01:00.50");
+        assert_eq!(format_time(60 * 60 * 60 + 3), "60:00.05", "minutes don't wrap at the hour");
+    }
+
+    #[test]
+    fn splits_show_total_and_delta() {
+        let mut splits = Splits::default();
+        splits.record(90);
+        splits.record(150);
+        splits.record(150);
+        assert_eq!(splits.lines(), ["1. 00:01.50 +00:01.50", "2. 00:02.50 +00:01.00", "3. 00:02.50 +00:00.00"]);
+
+        let path = std::env::temp_dir().join(format!("chip8-splits-{}.txt", std::process::id()));
+        splits.dump(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), splits.lines().join("\n") + "\n");
+        fs::remove_file(&path).unwrap();
+
+        splits.clear();
+        assert!(splits.lines().is_empty());
+    }
+
+    #[test]
+    fn the_timer_comes_back_with_a_savestate() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        for _ in 0..90 {
+            chip8.step_frame(1);
+        }
+        let state = chip8.save_state();
+        for _ in 0..30 {
+            chip8.step_frame(1);
+        }
+        chip8.load_state(&state).unwrap();
+        assert_eq!(format_time(chip8.frame_count()), "00:01.50");
+        chip8.reset();
+        assert_eq!(format_time(chip8.frame_count()), "00:00.00", "a reset starts the clock over");
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Test logger for the log crate, recording every message with the thread that logged it so tests
+// running in parallel each see only their own. Built for the library's own tests and, through the
+// test-util feature, for the frontend's
+
+static CAPTURED: Mutex<Vec<(ThreadId, Level, String)>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED.lock().unwrap().push((thread::current().id(), record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+// The messages this thread logged while f ran, at every level. Installs the capturing logger as the
+// process wide one, so only for test binaries
+pub fn capture(f: impl FnOnce()) -> Vec<(Level, String)> {
+    let _ = log::set_logger(&CaptureLogger);
+    log::set_max_level(LevelFilter::Debug);
+    take_captured();
+    f();
+    take_captured()
+}
+
+fn take_captured() -> Vec<(Level, String)> {
+    let mut captured = CAPTURED.lock().unwrap();
+    let id = thread::current().id();
+    let (mine, others) = captured.drain(..).partition::<Vec<_>, _>(|(thread, _, _)| *thread == id);
+    *captured = others;
+    mine.into_iter().map(|(_, level, message)| (level, message)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_captured_per_thread() {
+        let logged = capture(|| {
+            log::error!("error {}", 1);
+            log::warn!("warn {}", 2);
+            thread::spawn(|| log::info!("elsewhere")).join().unwrap();
+            log::debug!("debug {}", 3);
+        });
+        assert_eq!(logged, [
+            (Level::Error, "error 1".to_string()),
+            (Level::Warn, "warn 2".to_string()),
+            (Level::Debug, "debug 3".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::Write;
+
+use crate::chip8::Chip8;
+use crate::disasm::Instruction;
+
+// Instruction traces: one event per executed instruction, written out by a pluggable format
+//
+// Events hold the machine state just before the instruction runs, so the first line of a
+// trace shows the power on registers
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub cycle: u64,                     // Instructions traced before this one
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub index: u16,
+    pub sp: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEvent {
+    // The instruction at pc and the registers it will run with
+    pub fn capture(chip8: &Chip8, cycle: u64) -> Self {
+        let pc = chip8.pc();
+        let byte = |addr: u16| chip8.peek(addr as usize).unwrap_or(0) as u16;
+        TraceEvent {
+            cycle,
+            pc,
+            opcode: byte(pc) << 8 | byte(pc.wrapping_add(1)),
+            v: std::array::from_fn(|x| chip8.register(x)),
+            index: chip8.index(),
+            sp: chip8.stack_pointer(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
+        Instruction::decode(self.opcode).to_string()
+    }
+}
+
+// A trace file layout, adding one is a matter of implementing this and naming it in format_for
+pub trait TraceFormat {
+    // Written once before the first event, None when the format has no header
+    fn header(&self) -> Option<String> {
+        None
+    }
+
+    // One line for an event, without the newline
+    fn line(&self, event: &TraceEvent) -> String;
+}
+
+// Readable listing, the disassembly line followed by the registers
+pub struct TextFormat;
+
+impl TraceFormat for TextFormat {
+    fn line(&self, event: &TraceEvent) -> String {
+        let registers: Vec<String> = event.v.iter().enumerate().map(|(x, v)| format!("v{:X}={:02X}", x, v)).collect();
+        format!("{:>8}  {:#05X}: {:04X}  {:<20} {} I={:03X} SP={} DT={:02X} ST={:02X}",
+            event.cycle, event.pc, event.opcode, event.mnemonic(), registers.join(" "),
+            event.index, event.sp, event.delay_timer, event.sound_timer)
+    }
+}
+
+// Spreadsheet friendly: cycle, pc, opcode, mnemonic, v0..vF, i, sp, dt, st, numbers in decimal
+pub struct CsvFormat;
+
+impl TraceFormat for CsvFormat {
+    fn header(&self) -> Option<String> {
+        let registers: Vec<String> = (0..16).map(|x| format!("v{:x}", x)).collect();
+        Some(format!("cycle,pc,opcode,mnemonic,{},i,sp,dt,st", registers.join(",")))
+    }
+
+    fn line(&self, event: &TraceEvent) -> String {
+        let registers: Vec<String> = event.v.iter().map(|v| v.to_string()).collect();
+        format!("{},{},{},{},{},{},{},{},{}",
+            event.cycle, event.pc, event.opcode, csv_field(&event.mnemonic()), registers.join(","),
+            event.index, event.sp, event.delay_timer, event.sound_timer)
+    }
+}
+
+// Just the address and opcode in fixed width hex, the log shape Octo and most C interpreters can be
+// made to print, so traces from them diff line by line against ours
+pub struct OctoFormat;
+
+impl TraceFormat for OctoFormat {
+    fn line(&self, event: &TraceEvent) -> String {
+        format!("{:04X}: {:04X}", event.pc, event.opcode)
+    }
+}
+
+pub const FORMATS: [&str; 3] = ["text", "csv", "octo"];
+
+pub fn format_for(name: &str) -> Result<Box<dyn TraceFormat>, String> {
+    match name {
+        "text" => Ok(Box::new(TextFormat)),
+        "csv" => Ok(Box::new(CsvFormat)),
+        "octo" => Ok(Box::new(OctoFormat)),
+        _ => Err(format!("unknown trace format '{}', expected one of {}", name, FORMATS.join(", "))),
+    }
+}
+
+// A reference trace in the octo layout, "PPPP: OOOO" per line, as (pc, opcode) pairs for
+// Chip8::compare_trace. Anything after the opcode is ignored, as are blank lines
+pub fn parse_reference(text: &str) -> Result<Vec<(u16, u16)>, String> {
+    let hex = |field: &str| u16::from_str_radix(field.trim_start_matches("0x"), 16).ok();
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            let mut fields = line.split_whitespace();
+            let pc = fields.next().and_then(|field| hex(field.trim_end_matches(':')));
+            let opcode = fields.next().and_then(hex);
+            pc.zip(opcode).ok_or(format!("line {}: expected 'PPPP: OOOO', got '{}'", n + 1, line.trim()))
+        })
+        .collect()
+}
+
+// Quoted when it holds a separator or a quote, quotes doubled inside
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Writes a line per traced instruction in the chosen format
+pub struct Tracer {
+    format: Box<dyn TraceFormat>,
+    out: Box<dyn Write>,
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn new(format: Box<dyn TraceFormat>, mut out: Box<dyn Write>) -> Result<Self, String> {
+        if let Some(header) = format.header() {
+            writeln!(out, "{}", header).map_err(|err| err.to_string())?;
+        }
+        Ok(Tracer { format, out, cycles: 0 })
+    }
+
+    // Record the instruction chip8 is about to execute
+    pub fn record(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let event = TraceEvent::capture(chip8, self.cycles);
+        self.cycles += 1;
+        writeln!(self.out, "{}", self.format.line(&event)).map_err(|err| err.to_string())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn event() -> TraceEvent {
+        let mut v = [0; 16];
+        v[0x0] = 0x12;
+        v[0xF] = 0x01;
+        TraceEvent { cycle: 3, pc: 0x202, opcode: 0x6A05, v, index: 0x2F0, sp: 1, delay_timer: 0x3C, sound_timer: 0 }
+    }
+
+    // A writer the test keeps a handle on after handing it to a Tracer
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn text_lines_match_the_golden_layout() {
+        assert_eq!(TextFormat.header(), None);
+        assert_eq!(TextFormat.line(&event()),
+            "       3  0x202: 6A05  mov vA, 0x05         v0=12 v1=00 v2=00 v3=00 v4=00 v5=00 v6=00 v7=00 \
+             v8=00 v9=00 vA=00 vB=00 vC=00 vD=00 vE=00 vF=01 I=2F0 SP=1 DT=3C ST=00");
+    }
+
+    #[test]
+    fn csv_lines_match_the_golden_layout() {
+        assert_eq!(CsvFormat.header().unwrap(),
+            "cycle,pc,opcode,mnemonic,v0,v1,v2,v3,v4,v5,v6,v7,v8,v9,va,vb,vc,vd,ve,vf,i,sp,dt,st");
+        assert_eq!(CsvFormat.line(&event()), "3,514,27141,\"mov vA, 0x05\",18,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,752,1,60,0");
+    }
+
+    #[test]
+    fn octo_lines_match_the_golden_layout() {
+        assert_eq!(OctoFormat.header(), None);
+        assert_eq!(OctoFormat.line(&event()), "0202: 6A05");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("cls"), "cls");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn format_names_round_trip_and_unknown_ones_are_refused() {
+        for name in FORMATS {
+            assert!(format_for(name).is_ok(), "{}", name);
+        }
+        assert!(format_for("json").err().unwrap().contains("text, csv, octo"));
+    }
+
+    #[test]
+    fn tracer_writes_the_header_then_a_line_per_instruction() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x6A, 0x05, 0x12, 0x02]).unwrap();
+        let out = Shared::default();
+        let mut tracer = Tracer::new(format_for("csv").unwrap(), Box::new(out.clone())).unwrap();
+        for _ in 0..2 {
+            tracer.record(&chip8).unwrap();
+            chip8.cycle();
+        }
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("cycle,pc,"));
+        assert!(lines[1].starts_with("0,512,27141,"));
+        assert!(lines[2].starts_with("1,514,4610,jmp 0x202,0,0,0,0,0,0,0,0,0,0,5,"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn reference_traces_parse_octo_lines_and_point_at_bad_ones() {
+        let text = "0200: 6A05\n\n0x0202: 1202 trailing notes\n";
+        assert_eq!(parse_reference(text).unwrap(), [(0x200, 0x6A05), (0x202, 0x1202)]);
+        assert_eq!(parse_reference("0200: 6A05\n0202\n").unwrap_err(), "line 2: expected 'PPPP: OOOO', got '0202'");
+    }
+}
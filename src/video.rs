@@ -0,0 +1,301 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use chip8::display::Display;
+use chip8::render;
+use chip8::{Chip8, WIDTH, HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
+
+use crate::audio::{Oscillator, Waveform, SAMPLE_RATE, TONE_HZ, VOLUME};
+
+const FRAME_RATE: u32 = 60;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / FRAME_RATE as usize;
+
+// Records emulated frames to a video file through ffmpeg. Frames go to ffmpeg's stdin as raw RGB
+// while the buzzer is spooled to a PCM file next to the output, finish() muxes the two
+pub struct Recorder {
+    ffmpeg: String,
+    path: PathBuf,
+    scale: usize,
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    audio: BufWriter<File>,
+    oscillator: Oscillator,
+}
+
+impl Recorder {
+    pub fn start(ffmpeg: &str, path: &Path, scale: usize, waveform: Waveform) -> Result<Self, String> {
+        check_ffmpeg(ffmpeg)?;
+        let (video_part, audio_part) = part_paths(path);
+
+        let mut child = Command::new(ffmpeg)
+            .args(video_args(WIDTH * scale, HEIGHT * scale, &video_part))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("could not start {}: {}", ffmpeg, err))?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+        let audio = File::create(&audio_part).map_err(|err| format!("could not create {}: {}", audio_part.display(), err))?;
+
+        Ok(Recorder {
+            ffmpeg: ffmpeg.to_string(),
+            path: path.to_path_buf(),
+            scale,
+            child,
+            stdin,
+            audio: BufWriter::new(audio),
+            oscillator: Oscillator::new(waveform, TONE_HZ, SAMPLE_RATE, VOLUME),
+        })
+    }
+
+    // Append one emulated frame of picture and sound
+    pub fn frame(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.stdin.write_all(&recording_frame(&chip8.display, self.scale))
+            .map_err(|err| format!("ffmpeg stopped accepting frames: {}", err))?;
+        self.oscillator.set_pattern(chip8.audio_pattern(), chip8.pitch());
+        for sample in buzzer_samples(chip8.is_beeping(), &mut self.oscillator) {
+            self.audio.write_all(&sample.to_le_bytes()).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    // Close the video stream, mux in the sound and remove the intermediate files
+    pub fn finish(self) -> Result<PathBuf, String> {
+        let Recorder { ffmpeg, path, mut child, stdin, audio, .. } = self;
+        let (video_part, audio_part) = part_paths(&path);
+
+        drop(stdin.into_inner().map_err(|err| err.to_string())?);
+        audio.into_inner().map_err(|err| err.to_string())?.sync_all().map_err(|err| err.to_string())?;
+        let status = child.wait().map_err(|err| err.to_string())?;
+        if !status.success() {
+            return Err(format!("ffmpeg failed to encode {}", video_part.display()));
+        }
+
+        let status = Command::new(&ffmpeg)
+            .args(mux_args(&video_part, &audio_part, &path))
+            .status()
+            .map_err(|err| format!("could not start {}: {}", ffmpeg, err))?;
+        if !status.success() {
+            return Err(format!("ffmpeg failed to write {}", path.display()));
+        }
+        let _ = fs::remove_file(video_part);
+        let _ = fs::remove_file(audio_part);
+        Ok(path)
+    }
+}
+
+// Every emulated frame as raw RGB24 written straight to a file, for external tools. The stream reads
+// back with ffmpeg -f rawvideo -pixel_format rgb24 -video_size WxH -framerate 60 -i FILE, a FIFO made
+// with mkfifo pipes it into a running process
+pub struct RawVideo {
+    out: BufWriter<File>,
+    scale: usize,
+    frames: u64,
+}
+
+impl RawVideo {
+    pub fn create(path: &Path, scale: usize) -> Result<Self, String> {
+        let file = File::create(path).map_err(|err| format!("could not create {}: {}", path.display(), err))?;
+        Ok(RawVideo { out: BufWriter::new(file), scale, frames: 0 })
+    }
+
+    // Frame size as ffmpeg's -video_size wants it
+    pub fn video_size(&self) -> String {
+        format!("{}x{}", WIDTH * self.scale, HEIGHT * self.scale)
+    }
+
+    pub fn frame(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.out.write_all(&recording_frame(&chip8.display, self.scale)).map_err(|err| format!("raw video stopped: {}", err))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Flush what is buffered, the number of frames written
+    pub fn finish(mut self) -> Result<u64, String> {
+        self.out.flush().map_err(|err| format!("raw video stopped: {}", err))?;
+        Ok(self.frames)
+    }
+}
+
+// Fail early with a clear message when ffmpeg can't be run
+pub fn check_ffmpeg(ffmpeg: &str) -> Result<(), String> {
+    match Command::new(ffmpeg).arg("-version").stdout(Stdio::null()).stderr(Stdio::null()).status() {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(format!("video recording needs ffmpeg, '{}' could not be run (set the path with --ffmpeg)", ffmpeg)),
+    }
+}
+
+// Intermediate video and sound files written next to the output
+fn part_paths(path: &Path) -> (PathBuf, PathBuf) {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    (path.with_file_name(format!("{}.part.mkv", name)), path.with_file_name(format!("{}.part.pcm", name)))
+}
+
+// ffmpeg arguments encoding 60fps raw RGB frames from stdin
+pub fn video_args(width: usize, height: usize, output: &Path) -> Vec<String> {
+    [
+        "-y", "-loglevel", "error",
+        "-f", "rawvideo", "-pixel_format", "rgb24",
+        "-video_size", &format!("{}x{}", width, height),
+        "-framerate", &FRAME_RATE.to_string(),
+        "-i", "-",
+        "-c:v", "libx264", "-pix_fmt", "yuv420p",
+        &output.to_string_lossy(),
+    ].iter().map(|arg| arg.to_string()).collect()
+}
+
+// ffmpeg arguments combining the encoded video with the raw mono 16 bit buzzer track
+pub fn mux_args(video: &Path, audio: &Path, output: &Path) -> Vec<String> {
+    [
+        "-y", "-loglevel", "error",
+        "-i", &video.to_string_lossy(),
+        "-f", "s16le", "-ar", &SAMPLE_RATE.to_string(), "-ac", "1",
+        "-i", &audio.to_string_lossy(),
+        "-c:v", "copy", "-c:a", "aac", "-shortest",
+        &output.to_string_lossy(),
+    ].iter().map(|arg| arg.to_string()).collect()
+}
+
+// Display as RGB24 with every pixel scaled to a scale x scale square, white on black like the window
+pub fn rgb_frame(display: &Display, scale: usize) -> Vec<u8> {
+    let mut frame = Vec::new();
+    render::render_rgba(display, display.width(), render::WHITE, render::BLACK, scale, &mut frame);
+    render::strip_alpha(&mut frame);
+    frame
+}
+
+// rgb_frame at WIDTH * scale by HEIGHT * scale whatever the resolution, so a recording keeps one frame
+// size across 00FE/00FF. A hires pixel is half a low resolution one, at odd scales rounded to whole pixels
+pub fn recording_frame(display: &Display, scale: usize) -> Vec<u8> {
+    if !display.hires() {
+        return rgb_frame(display, scale);
+    }
+    let native = rgb_frame(display, 1);
+    let (width, height) = (WIDTH * scale.max(1), HEIGHT * scale.max(1));
+    let mut frame = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let at = (x * HIRES_WIDTH / width + y * HIRES_HEIGHT / height * HIRES_WIDTH) * 3;
+            frame.extend_from_slice(&native[at..at + 3]);
+        }
+    }
+    frame
+}
+
+// CRT look for an RGB24 frame: every other physical row, starting with the second, dimmed by intensity percent
+pub fn apply_scanlines(frame: &mut [u8], width: usize, intensity: u8) {
+    let keep = 100 - intensity.min(100) as u16;
+    for row in frame.chunks_mut(width * 3).skip(1).step_by(2) {
+        for value in row {
+            *value = (*value as u16 * keep / 100) as u8;
+        }
+    }
+}
+
+// One frame of 16 bit buzzer samples, the same tone the audio device plays
+pub fn buzzer_samples(beeping: bool, oscillator: &mut Oscillator) -> Vec<i16> {
+    let mut samples = [0.0; SAMPLES_PER_FRAME];
+    if beeping {
+        oscillator.fill(&mut samples);
+    }
+    samples.iter().map(|&sample| (sample * i16::MAX as f32) as i16).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_args_read_60fps_rgb_from_stdin() {
+        let args = video_args(640, 320, Path::new("out.mp4.part.mkv"));
+        let after = |flag: &str| args[args.iter().position(|arg| arg == flag).unwrap() + 1].as_str();
+        assert_eq!(after("-f"), "rawvideo");
+        assert_eq!(after("-pixel_format"), "rgb24");
+        assert_eq!(after("-video_size"), "640x320");
+        assert_eq!(after("-framerate"), "60");
+        assert_eq!(after("-i"), "-");
+        assert_eq!(args.last().unwrap(), "out.mp4.part.mkv");
+    }
+
+    #[test]
+    fn mux_args_add_the_buzzer_track() {
+        let (video, audio) = part_paths(Path::new("clips/out.mp4"));
+        assert_eq!((video.as_path(), audio.as_path()), (Path::new("clips/out.mp4.part.mkv"), Path::new("clips/out.mp4.part.pcm")));
+        let args = mux_args(&video, &audio, Path::new("clips/out.mp4"));
+        let inputs: Vec<&str> = args.iter().zip(&args[1..]).filter(|(flag, _)| *flag == "-i").map(|(_, path)| path.as_str()).collect();
+        assert_eq!(inputs, ["clips/out.mp4.part.mkv", "clips/out.mp4.part.pcm"]);
+        assert!(args.windows(2).any(|pair| pair == ["-ar", "44100"]));
+        assert_eq!(args.last().unwrap(), "clips/out.mp4");
+    }
+
+    #[test]
+    fn frames_serialize_as_scaled_rgb() {
+        let mut display = Display::new();
+        display[1] = 1;
+        let frame = rgb_frame(&display, 2);
+        assert_eq!(frame.len(), WIDTH * 2 * HEIGHT * 2 * 3);
+        let at = |x: usize, y: usize| &frame[(x + y * WIDTH * 2) * 3..][..3];
+        assert_eq!(at(1, 0), [0, 0, 0]);
+        assert_eq!((at(2, 0), at(3, 1)), (&[255, 255, 255][..], &[255, 255, 255][..]), "pixel 1,0 covers a 2x2 square");
+        assert_eq!(at(4, 0), [0, 0, 0]);
+
+        display.set_hires(true);
+        assert_eq!(recording_frame(&display, 2).len(), frame.len(), "hires keeps the recording's frame size");
+    }
+
+    #[test]
+    fn each_frame_carries_a_frame_of_sound() {
+        let mut oscillator = Oscillator::new(Waveform::Square, TONE_HZ, SAMPLE_RATE, VOLUME);
+        let silent = buzzer_samples(false, &mut oscillator);
+        assert_eq!(silent.len(), 735);
+        assert!(silent.iter().all(|&sample| sample == 0));
+        assert!(buzzer_samples(true, &mut oscillator).iter().any(|&sample| sample != 0));
+    }
+
+    #[test]
+    fn a_missing_ffmpeg_is_reported_clearly() {
+        let err = check_ffmpeg("/nonexistent/ffmpeg").unwrap_err();
+        assert!(err.contains("'/nonexistent/ffmpeg' could not be run"), "{}", err);
+    }
+
+    #[test]
+    fn scanlines_dim_every_other_row() {
+        // Four rows two pixels wide, all white
+        let mut frame = vec![0xFF; 2 * 4 * 3];
+        apply_scanlines(&mut frame, 2, 40);
+        let rows: Vec<&[u8]> = frame.chunks(2 * 3).collect();
+        for (y, row) in rows.iter().enumerate() {
+            let expected = if y % 2 == 1 { 153 } else { 0xFF };         // 60% of 255 on the dimmed rows
+            assert!(row.iter().all(|&value| value == expected), "row {} is {:?}", y, row);
+        }
+
+        let mut frame = vec![200; 2 * 2 * 3];
+        apply_scanlines(&mut frame, 2, 100);
+        assert_eq!(frame, [200, 200, 200, 200, 200, 200, 0, 0, 0, 0, 0, 0], "full intensity blacks the dimmed rows out");
+        apply_scanlines(&mut frame, 2, 0);
+        assert_eq!(frame[..6], [200; 6], "zero intensity changes nothing");
+    }
+
+    #[test]
+    fn raw_video_appends_each_frame_as_rgb24() {
+        // Draw the 0 glyph at the top left
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04]).unwrap();
+        chip8.step_frame(3);
+        let path = std::env::temp_dir().join(format!("chip8-raw-video-{}.rgb", std::process::id()));
+
+        let mut raw = RawVideo::create(&path, 2).unwrap();
+        assert_eq!(raw.video_size(), "128x64");
+        raw.frame(&chip8).unwrap();
+        raw.frame(&chip8).unwrap();
+        assert_eq!(raw.finish(), Ok(2));
+
+        let bytes = fs::read(&path).unwrap();
+        let frame = rgb_frame(&chip8.display, 2);
+        assert_eq!(frame.len(), 128 * 64 * 3);
+        assert_eq!(bytes.len(), 2 * frame.len(), "frames are written back to back");
+        assert_eq!(bytes[..frame.len()], frame);
+        assert_eq!(bytes[..3], [0xFF; 3], "the glyph's top left pixel is white");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::chip8::Chip8;
+use crate::condition::{self, Expr};
+
+// Debugger watch expressions. Each refresh re-evaluates them and flags the ones whose value moved,
+// so the debugger can highlight what the last step changed
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub expr: Expr,
+    value: Option<u16>,                 // None until the first refresh
+    changed: bool,                      // Value differs from the refresh before
+}
+
+impl Watch {
+    pub fn value(&self) -> Option<u16> {
+        self.value
+    }
+
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    fn refresh(&mut self, chip8: &Chip8) {
+        let value = self.expr.eval(chip8);
+        self.changed = self.value.is_some_and(|previous| previous != value);
+        self.value = Some(value);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+}
+
+impl Watches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    // Parse and add an expression, returning its 1 based number
+    pub fn add(&mut self, text: &str) -> Result<usize, String> {
+        let expr = condition::parse_expr(text)?;
+        self.watches.push(Watch { expr, value: None, changed: false });
+        Ok(self.watches.len())
+    }
+
+    // Remove the watch with the given 1 based number
+    pub fn remove(&mut self, number: usize) -> Result<Watch, String> {
+        match number.checked_sub(1).filter(|&idx| idx < self.watches.len()) {
+            Some(idx) => Ok(self.watches.remove(idx)),
+            None => Err(format!("no watch {}", number)),
+        }
+    }
+
+    pub fn refresh(&mut self, chip8: &Chip8) {
+        for watch in &mut self.watches {
+            watch.refresh(chip8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refreshes_flag_only_values_that_moved() {
+        let mut chip8 = Chip8::new();
+        let mut watches = Watches::new();
+        assert_eq!(watches.add("v1 * 8 + v2"), Ok(1));
+        assert_eq!(watches.add("i"), Ok(2));
+        assert!(watches.add("v1 +").is_err());
+        assert_eq!(watches.watches()[0].value(), None, "unevaluated until the first refresh");
+
+        watches.refresh(&chip8);
+        assert_eq!(watches.watches()[0].value(), Some(0));
+        assert!(!watches.watches()[0].changed(), "the first value is not a change");
+
+        chip8.set_register(1, 2);
+        watches.refresh(&chip8);
+        assert_eq!((watches.watches()[0].value(), watches.watches()[0].changed()), (Some(16), true));
+        assert!(!watches.watches()[1].changed());
+        watches.refresh(&chip8);
+        assert!(!watches.watches()[0].changed(), "unchanged since the last refresh");
+
+        assert_eq!(watches.remove(1).unwrap().expr.to_string(), "v1 * 0x08 + v2");
+        assert_eq!(watches.remove(2).unwrap_err(), "no watch 2");
+        assert_eq!(watches.watches().len(), 1);
+    }
+
+    #[test]
+    fn a_watched_memory_cell_follows_the_program() {
+        // mem[0x300] += 1 forever, through v0
+        let rom = [0xA3, 0x00, 0xF0, 0x65, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let mut watches = Watches::new();
+        watches.add("mem[0x300]").unwrap();
+        watches.refresh(&chip8);
+
+        for count in 1..=3 {
+            chip8.step_frame(rom.len() / 2);
+            watches.refresh(&chip8);
+            assert_eq!(watches.watches()[0].value(), Some(count));
+            assert!(watches.watches()[0].changed(), "pass {} updated the cell", count);
+        }
+    }
+}
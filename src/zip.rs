@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+use std::io::{Cursor, Read};
+
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// ROM packs through the zip crate: picking the ROM out of an archive and unpacking it. Stored and deflated
+// entries, ZIP64 included, the crate checks the CRC of what it unpacks. Encrypted entries and the other
+// compression methods are refused by name
+
+// Largest entry that gets unpacked, all of XO-CHIP's 64K address space
+pub const MAX_ENTRY_SIZE: usize = 0x10000;
+
+// Entries with these extensions are taken for ROMs over anything else in the archive
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "ch10"];
+
+// An archive starts with a local file header, or with the end record when it is empty
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
+}
+
+// Files next to a ROM that are clearly something else: directories, macOS resource forks and dot files
+fn is_junk(name: &str) -> bool {
+    let base = name.rsplit('/').next().unwrap_or(name);
+    name.ends_with('/') || name.starts_with("__MACOSX/") || base.starts_with('.')
+}
+
+fn has_rom_extension(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, ext)| ROM_EXTENSIONS.iter().any(|rom| ext.eq_ignore_ascii_case(rom)))
+}
+
+// Entries that could be the ROM: the ones with a ROM extension, or when there are none every file that
+// isn't obviously something else
+fn rom_candidates(names: &[String]) -> Vec<&str> {
+    let files: Vec<&str> = names.iter().map(String::as_str).filter(|name| !is_junk(name)).collect();
+    if files.iter().any(|name| has_rom_extension(name)) {
+        files.into_iter().filter(|name| has_rom_extension(name)).collect()
+    } else {
+        files
+    }
+}
+
+// The crate's errors in the words the rest of the loader uses
+fn describe(err: ZipError) -> String {
+    match err {
+        ZipError::InvalidArchive(reason) => format!("not a ZIP archive or a corrupt one, {}", reason),
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) | ZipError::InvalidPassword => "is encrypted, which isn't supported".to_string(),
+        ZipError::CompressionMethodNotSupported(method) => format!("uses compression method {}, only stored and deflate are supported", method),
+        err => err.to_string(),
+    }
+}
+
+// The ROM inside a ZIP archive: the named entry, or the only plausible ROM in it. More than one is an
+// error listing them so the user can pick
+pub fn extract_rom(data: &[u8], name: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(describe)?;
+    let names: Vec<String> = archive.file_names().map(|name| name.map(Cow::into_owned)).collect::<Result<_, _>>().map_err(describe)?;
+    let name = match name {
+        Some(name) => name,
+        None => match rom_candidates(&names).as_slice() {
+            [name] => *name,
+            [] => return Err("archive holds no ROM".to_string()),
+            candidates => return Err(format!("archive holds {} ROMs, pick one of {}", candidates.len(), candidates.join(", "))),
+        },
+    };
+
+    let entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Err(format!("archive has no entry {}", name)),
+        Err(err) => return Err(format!("{} {}", name, describe(err))),
+    };
+    if entry.size() > MAX_ENTRY_SIZE as u64 {
+        return Err(format!("{} unpacks to {} bytes, more than the {} byte limit", name, entry.size(), MAX_ENTRY_SIZE));
+    }
+    let mut bytes = Vec::with_capacity(entry.size() as usize);
+    entry.take(MAX_ENTRY_SIZE as u64 + 1).read_to_end(&mut bytes)
+        .map_err(|err| format!("{} is corrupt, {}", name, err))?;
+    if bytes.len() > MAX_ENTRY_SIZE {                   // The directory understated the size
+        return Err(format!("{} unpacks to more than the {} byte limit", name, MAX_ENTRY_SIZE));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn squares_text() -> Vec<u8> {
+        (0..40).flat_map(|i| format!("{} squared is {}, ", i, i * i).into_bytes()).collect()
+    }
+
+    // A ZIP archive of these entries as the zip crate writes one, names ending in / are directories
+    fn archive(entries: &[(&str, CompressionMethod, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for &(name, method, bytes) in entries {
+            let options = SimpleFileOptions::default().compression_method(method);
+            if name.ends_with('/') {
+                writer.add_directory(name, options).unwrap();
+            } else {
+                writer.start_file(name, options).unwrap();
+                writer.write_all(bytes).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn stored<'a>(name: &'a str, bytes: &'a [u8]) -> (&'a str, CompressionMethod, &'a [u8]) {
+        (name, CompressionMethod::Stored, bytes)
+    }
+
+    // Where the central directory record of the first entry starts, its local header is at 0
+    fn central_record(zip: &[u8]) -> usize {
+        zip.windows(4).position(|bytes| bytes == b"PK\x01\x02").unwrap()
+    }
+
+    #[test]
+    fn a_single_stored_rom_is_extracted() {
+        let zip = archive(&[stored("pong.ch8", &[0x12, 0x00])]);
+        assert!(is_zip(&zip));
+        assert_eq!(extract_rom(&zip, None).unwrap(), [0x12, 0x00]);
+    }
+
+    #[test]
+    fn deflated_entries_unpack() {
+        let text = squares_text();
+        let zip = archive(&[("squares.ch8", CompressionMethod::Deflated, &text)]);
+        assert!(zip.len() < text.len(), "the entry was compressed");
+        assert_eq!(extract_rom(&zip, None).unwrap(), text);
+    }
+
+    #[test]
+    fn several_roms_need_a_name() {
+        let zip = archive(&[stored("a.ch8", &[1, 2]), stored("b.sc8", &[3, 4]), stored("readme.txt", b"hi")]);
+        assert_eq!(extract_rom(&zip, None).unwrap_err(), "archive holds 2 ROMs, pick one of a.ch8, b.sc8");
+        assert_eq!(extract_rom(&zip, Some("b.sc8")).unwrap(), [3, 4]);
+        assert_eq!(extract_rom(&zip, Some("c.ch8")).unwrap_err(), "archive has no entry c.ch8");
+    }
+
+    #[test]
+    fn junk_beside_a_rom_is_passed_over() {
+        let zip = archive(&[stored("games/", b""), stored("__MACOSX/._game", b"x"), stored("games/.DS_Store", b"x"), stored("games/game", &[7, 7])]);
+        assert_eq!(extract_rom(&zip, None).unwrap(), [7, 7], "without a ROM extension any real file is a candidate");
+        assert_eq!(extract_rom(&archive(&[stored("notes/", b"")]), None).unwrap_err(), "archive holds no ROM");
+    }
+
+    #[test]
+    fn corrupt_archives_are_refused() {
+        let mut zip = archive(&[stored("pong.ch8", &[0x12, 0x00])]);
+        assert!(extract_rom(&zip[..20], None).unwrap_err().starts_with("not a ZIP archive or a corrupt one"));
+
+        let data_at = 30 + 8 + u16::from_le_bytes([zip[28], zip[29]]) as usize;
+        zip[data_at] ^= 0xFF;
+        let err = extract_rom(&zip, None).unwrap_err();
+        assert!(err.starts_with("pong.ch8 is corrupt"), "{}", err);
+    }
+
+    #[test]
+    fn oversized_and_unsupported_entries_are_refused() {
+        let big = vec![0; MAX_ENTRY_SIZE + 1];
+        assert!(extract_rom(&archive(&[stored("big.ch8", &big)]), None).unwrap_err().contains("byte limit"));
+
+        // Method 12 is bzip2
+        let mut zip = archive(&[stored("bz.ch8", b"BZh")]);
+        let central = central_record(&zip);
+        zip[8] = 12;
+        zip[central + 10] = 12;
+        assert_eq!(extract_rom(&zip, None).unwrap_err(), "bz.ch8 uses compression method 12, only stored and deflate are supported");
+
+        let mut zip = archive(&[stored("pong.ch8", &[0x12, 0x00])]);
+        let central = central_record(&zip);
+        zip[6] |= 1;
+        zip[central + 8] |= 1;
+        assert_eq!(extract_rom(&zip, None).unwrap_err(), "pong.ch8 is encrypted, which isn't supported");
+    }
+}